@@ -0,0 +1,167 @@
+//! Routing what a server sends on one connection: replies to the request
+//! whose ID they carry, and streamed output and events to their own queue,
+//! so neither is lost while waiting for the other.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::io::AsyncBufReadExt as _;
+use tokio::sync::mpsc;
+
+use crate::Response;
+
+/// Where replies to each request still being listened to go, by ID. None
+/// once the connection is closed, so no one waits for a reply forever.
+type Pending = Arc<Mutex<Option<BTreeMap<u64, mpsc::UnboundedSender<Response>>>>>;
+
+/// Reads a connection in the background and sorts what arrives.
+pub(crate) struct Dispatcher {
+    pending: Pending,
+    streamed: mpsc::UnboundedReceiver<Response>,
+    next_id: u64,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Dispatcher {
+    pub(crate) fn spawn(reader: impl tokio::io::AsyncRead + Send + Unpin + 'static) -> Self {
+        let pending = Arc::new(Mutex::new(Some(BTreeMap::new())));
+        let (streamed_tx, streamed) = mpsc::unbounded_channel();
+        let task = tokio::spawn(run(reader, pending.clone(), streamed_tx));
+        Self {
+            pending,
+            streamed,
+            next_id: 1,
+            task,
+        }
+    }
+
+    /// Pick an ID for a new request and start collecting its replies.
+    /// Replies stop being collected once the receiver is dropped.
+    pub(crate) fn register(&mut self) -> (u64, mpsc::UnboundedReceiver<Response>) {
+        let id = self.next_id;
+        self.next_id += 1;
+        let (tx, rx) = mpsc::unbounded_channel();
+        if let Some(pending) = self.pending.lock().unwrap().as_mut() {
+            pending.insert(id, tx);
+        }
+        (id, rx)
+    }
+
+    /// Next streamed message, or None once the connection is closed.
+    ///
+    /// Cancel-safe.
+    pub(crate) async fn next_streamed(&mut self) -> Option<Response> {
+        self.streamed.recv().await
+    }
+}
+
+impl Drop for Dispatcher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Whether `response` is streamed rather than a reply to a request.
+fn is_streamed(response: &Response) -> bool {
+    matches!(
+        response,
+        Response::Output { .. }
+            | Response::Resized { .. }
+            | Response::PendingInput { .. }
+            | Response::Event { .. }
+            | Response::Detached { .. }
+    )
+}
+
+async fn run(
+    reader: impl tokio::io::AsyncRead + Unpin,
+    pending: Pending,
+    streamed: mpsc::UnboundedSender<Response>,
+) {
+    let mut reader = tokio::io::BufReader::new(reader);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line).await {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(e) => {
+                tracing::debug!("connection read error: {e}");
+                break;
+            }
+        }
+        let tap_protocol::Envelope {
+            request_id,
+            message,
+        } = match serde_json::from_slice::<tap_protocol::Envelope<Response>>(&line) {
+            Ok(envelope) => envelope,
+            Err(e) => {
+                tracing::debug!("skipping unreadable response: {e}");
+                continue;
+            }
+        };
+
+        let replies = {
+            let mut pending = pending.lock().unwrap();
+            let Some(pending) = pending.as_mut() else {
+                break;
+            };
+            pending.retain(|_, replies| !replies.is_closed());
+            match request_id {
+                Some(id) => pending.get(&id).cloned(),
+                None if is_streamed(&message) => None,
+                // Servers that don't echo IDs answer requests in order
+                None => pending.values().next().cloned(),
+            }
+        };
+        let delivered = match replies {
+            Some(replies) => replies.send(message),
+            None => streamed.send(message),
+        };
+        if let Err(mpsc::error::SendError(message)) = delivered {
+            tracing::debug!("dropping response nobody is waiting for: {message:?}");
+        }
+    }
+    pending.lock().unwrap().take();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_dispatch() {
+        use tokio::io::AsyncWriteExt as _;
+
+        let (client, mut server) = tokio::net::UnixStream::pair().unwrap();
+        let (reader, _writer) = client.into_split();
+        let mut dispatcher = Dispatcher::spawn(reader);
+        let (first, mut first_replies) = dispatcher.register();
+        let (second, mut second_replies) = dispatcher.register();
+
+        // Replies arrive out of order, around streamed output
+        let lines = format!(
+            "{{\"type\":\"output\",\"data\":[104,105]}}\n\
+             {{\"request_id\":{second},\"type\":\"size\",\"rows\":24,\"cols\":80}}\n\
+             {{\"type\":\"pong\"}}\n\
+             {{\"request_id\":{first},\"type\":\"ok\"}}\n"
+        );
+        server.write_all(lines.as_bytes()).await.unwrap();
+        drop(server);
+
+        assert!(matches!(
+            second_replies.recv().await,
+            Some(Response::Size { rows: 24, cols: 80 })
+        ));
+        // Without an ID, a reply goes to the oldest request
+        assert!(matches!(first_replies.recv().await, Some(Response::Pong)));
+        assert!(matches!(first_replies.recv().await, Some(Response::Ok)));
+        assert!(matches!(
+            dispatcher.next_streamed().await,
+            Some(Response::Output { data }) if data == b"hi"
+        ));
+        assert!(dispatcher.next_streamed().await.is_none());
+        assert!(first_replies.recv().await.is_none());
+        assert!(dispatcher.register().1.recv().await.is_none());
+    }
+}
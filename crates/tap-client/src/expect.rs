@@ -0,0 +1,100 @@
+//! Waiting for a pattern to show up in a session's output.
+
+/// Recent output text kept for matching against.
+const MAX_TEXT_BYTES: usize = 64 * 1024;
+
+/// Output that matched a [`crate::Client::wait_for_pattern`] pattern.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternMatch {
+    /// The text the pattern matched.
+    pub matched: String,
+    /// The whole line the match starts on, as far as it has been printed.
+    pub line: String,
+}
+
+/// Plain text of a stream of PTY output (see
+/// [`tap_protocol::ansi::StripAnsi`]), kept for matching against.
+#[derive(Debug, Default)]
+pub(crate) struct OutputText {
+    text: String,
+    strip: tap_protocol::ansi::StripAnsi,
+    /// Start of a UTF-8 character split across chunks.
+    partial: Vec<u8>,
+}
+
+impl OutputText {
+    /// Add a chunk of output; returns the offset where its text starts, for
+    /// [`Self::find`].
+    pub(crate) fn push(&mut self, data: &[u8]) -> usize {
+        let mut plain = std::mem::take(&mut self.partial);
+        self.strip.strip(data, &mut plain);
+
+        let valid = match std::str::from_utf8(&plain) {
+            Ok(_) => plain.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => plain.len(),
+        };
+        self.partial = plain.split_off(valid);
+
+        self.trim();
+        let start = self.text.len();
+        self.text.push_str(&String::from_utf8_lossy(&plain));
+        start
+    }
+
+    /// Drop the oldest text once there's too much.
+    fn trim(&mut self) {
+        if self.text.len() <= MAX_TEXT_BYTES {
+            return;
+        }
+        let mut cut = self.text.len() - MAX_TEXT_BYTES / 2;
+        while !self.text.is_char_boundary(cut) {
+            cut += 1;
+        }
+        self.text.drain(..cut);
+    }
+
+    /// The first match of `pattern` in the text added since `from`, or in
+    /// the line that text continues.
+    pub(crate) fn find(&self, pattern: &regex::Regex, from: usize) -> Option<PatternMatch> {
+        // Rescan the line the new text continues, as a match may span both
+        let line_start = self.text[..from].rfind('\n').map_or(0, |at| at + 1);
+        let found = pattern.find_at(&self.text, line_start)?;
+        let start = self.text[..found.start()]
+            .rfind('\n')
+            .map_or(0, |at| at + 1);
+        let end = self.text[found.start()..]
+            .find('\n')
+            .map_or(self.text.len(), |at| found.start() + at);
+        Some(PatternMatch {
+            matched: found.as_str().to_string(),
+            line: self.text[start..end].to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strips_escapes_across_chunks() {
+        let mut output = OutputText::default();
+        output.push(b"\x1b[1;32mok\x1b[");
+        output.push(b"0m\r\n\x1b]0;title\x07done \xe2\x9c");
+        output.push(b"\x93");
+        assert_eq!(output.text, "ok\ndone \u{2713}");
+    }
+
+    #[test]
+    fn test_find_returns_line() {
+        let pattern = regex::Regex::new(r"compil\w+ finished").unwrap();
+        let mut output = OutputText::default();
+        let from = output.push(b"building\r\n[12:00] compil");
+        assert_eq!(output.find(&pattern, from), None);
+        let from = output.push(b"ation finished in 3s\r\nnext");
+        let found = output.find(&pattern, from).unwrap();
+        assert_eq!(found.matched, "compilation finished");
+        assert_eq!(found.line, "[12:00] compilation finished in 3s");
+    }
+}
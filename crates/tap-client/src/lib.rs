@@ -1,8 +1,36 @@
 //! Client library for interacting with tap sessions.
 
-use tokio::io::{AsyncBufReadExt as _, AsyncWriteExt as _};
+mod dispatch;
+mod expect;
+mod multi;
+mod ssh;
 
-pub use tap_protocol::{Request, Response, Session, sessions_file, socket_dir, socket_path};
+use tokio::io::AsyncWriteExt as _;
+
+pub use expect::PatternMatch;
+pub use multi::{MultiSubscriber, SessionEvent};
+pub use ssh::{REMOTE_TAP_ENV, list_remote_sessions, parse_remote};
+pub use tap_protocol::{
+    CaptureEnd, Color, CursorPosition, ErrorCode, Event, EventKind, Note, PROTOCOL_VERSION,
+    ProtocolVersion, Request, Response, Screen, ScreenCell, Session, SessionTimes, sessions_file,
+    socket_dir, socket_path, validate_session_name,
+};
+
+/// Prefix of session addresses served over TCP.
+pub const TCP_SCHEME: &str = "tcp://";
+
+/// Whether `session` addresses a session on another machine, over TCP or
+/// SSH, rather than one in this machine's list.
+#[must_use]
+pub fn is_remote(session: &str) -> bool {
+    session.starts_with(TCP_SCHEME) || parse_remote(session).is_some()
+}
+
+/// How long to wait for a server to answer `Hello`.
+const HELLO_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+/// How long to wait for a server to answer `Hello` over TCP, which may
+/// cross a slow network.
+const TCP_HELLO_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -14,10 +42,43 @@ pub enum Error {
     NoSessions,
     #[error("session '{0}' not found — run `tap list` to see active sessions")]
     SessionNotFound(String),
+    #[error("session ended: {0}")]
+    SessionEnded(String),
+    #[error("already attached: {0}")]
+    AlreadyAttached(String),
+    #[error("not attached: {0}")]
+    NotAttached(String),
+    #[error("unsupported: {0}")]
+    Unsupported(String),
+    #[error("rate limited: {0}")]
+    RateLimited(String),
+    #[error("denied: {0}")]
+    Denied(String),
+    #[error("invalid pattern: {0}")]
+    Pattern(#[from] regex::Error),
+    #[error("timed out after {0:?}")]
+    TimedOut(std::time::Duration),
+    #[error("incompatible session: {0}")]
+    Incompatible(String),
     #[error("server error: {0}")]
     Server(String),
 }
 
+impl Error {
+    /// Map an error response from the server to the matching variant.
+    fn from_server(code: ErrorCode, message: String) -> Self {
+        match code {
+            ErrorCode::SessionEnded => Error::SessionEnded(message),
+            ErrorCode::AlreadyAttached => Error::AlreadyAttached(message),
+            ErrorCode::NotAttached => Error::NotAttached(message),
+            ErrorCode::Unsupported => Error::Unsupported(message),
+            ErrorCode::RateLimited => Error::RateLimited(message),
+            ErrorCode::Denied => Error::Denied(message),
+            ErrorCode::Other => Error::Server(message),
+        }
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
 
 /// List all active tap sessions.
@@ -35,22 +96,269 @@ pub fn list_sessions() -> Result<Vec<Session>> {
     Ok(sessions)
 }
 
+/// Exit code of a session that has ended, if it ended recently enough to
+/// still be in sessions.json (see `[sessions] keep_ended_secs`).
+pub fn ended_exit_code(session_id: &str) -> Result<Option<i32>> {
+    let content = std::fs::read_to_string(sessions_file()).unwrap_or_else(|_| "[]".to_string());
+    let sessions: Vec<Session> = serde_json::from_str(&content)?;
+    Ok(sessions
+        .into_iter()
+        .filter(|s| s.id == session_id && s.ended.is_some())
+        .find_map(|s| s.exit_code))
+}
+
+/// Criteria for selecting sessions in bulk (`tap list`, `tap kill`, `tap subscribe --all`).
+#[derive(Debug, Clone, Default)]
+pub struct SessionFilter {
+    /// Only sessions carrying all of these tags.
+    pub tags: Vec<String>,
+    /// Only sessions without an attached client.
+    pub detached_only: bool,
+    /// Only sessions started longer ago than this.
+    pub older_than: Option<std::time::Duration>,
+}
+
+impl SessionFilter {
+    /// Whether no criteria are set (every session matches).
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty() && !self.detached_only && self.older_than.is_none()
+    }
+
+    /// Check a session against all criteria.
+    #[must_use]
+    pub fn matches(&self, session: &Session, now: chrono::DateTime<chrono::Utc>) -> bool {
+        if self.detached_only && session.attached {
+            return false;
+        }
+        if !self.tags.iter().all(|tag| session.tags.contains(tag)) {
+            return false;
+        }
+        if let Some(older_than) = self.older_than {
+            // Sessions with an unparseable start time never count as old
+            let Ok(started) = chrono::DateTime::parse_from_rfc3339(&session.started) else {
+                return false;
+            };
+            let age = now.signed_duration_since(started);
+            if !age.to_std().is_ok_and(|age| age >= older_than) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// List active sessions matching a filter.
+pub fn list_sessions_filtered(filter: &SessionFilter) -> Result<Vec<Session>> {
+    let now = chrono::Utc::now();
+    Ok(list_sessions()?
+        .into_iter()
+        .filter(|s| filter.matches(s, now))
+        .collect())
+}
+
+/// Order `sessions` as a tree, each followed by the sessions started inside
+/// it, paired with its depth. Sessions whose parent isn't listed are roots.
+#[must_use]
+pub fn session_tree(sessions: &[Session]) -> Vec<(usize, &Session)> {
+    let listed = |id: &str| sessions.iter().any(|s| s.id == id);
+    let roots = sessions
+        .iter()
+        .filter(|s| !s.parent.as_deref().is_some_and(listed));
+    let mut tree: Vec<(usize, &Session)> = Vec::with_capacity(sessions.len());
+    // Then the rest, in case parents form a cycle
+    for root in roots.chain(sessions) {
+        let mut stack = vec![(0, root)];
+        while let Some((depth, session)) = stack.pop() {
+            if tree.iter().any(|(_, s)| s.id == session.id) {
+                continue;
+            }
+            tree.push((depth, session));
+            let children = sessions
+                .iter()
+                .rev()
+                .filter(|s| s.parent.as_deref() == Some(&session.id));
+            stack.extend(children.map(|child| (depth + 1, child)));
+        }
+    }
+    tree
+}
+
+/// Parse an age like `30s`, `15m`, `6h`, `2d` or `1w`.
+pub fn parse_age(s: &str) -> std::result::Result<std::time::Duration, String> {
+    let split = s
+        .find(|c: char| !c.is_ascii_digit())
+        .ok_or_else(|| format!("missing unit in '{s}' — expected e.g. 30s, 15m, 6h, 2d, 1w"))?;
+    let (amount, unit) = s.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("invalid amount in '{s}'"))?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("unknown unit '{unit}' — expected s, m, h, d or w")),
+    };
+    Ok(std::time::Duration::from_secs(
+        amount.saturating_mul(seconds),
+    ))
+}
+
+/// Initial screen state sent to a newly attached client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attachment {
+    /// Current scrollback content.
+    pub scrollback: String,
+    /// Cursor shape the app selected, as a DECSCUSR parameter (0 for the
+    /// terminal's default).
+    pub cursor_style: u8,
+    /// Recent raw output to replay so the terminal's scrollback has history.
+    pub tail: Vec<u8>,
+    /// Window title the app last set.
+    pub title: String,
+}
+
+/// What a command run with [`Client::run_command`] printed and how it exited.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandOutput {
+    /// The command's output as it reads on screen.
+    pub output: String,
+    pub exit_code: i32,
+}
+
+/// A report on a command started with [`Client::start_interactive_command`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandEvent {
+    /// The command seems to wait for input at `prompt`; `echo` is false
+    /// when the session's terminal isn't echoing, as for passwords.
+    Prompt { prompt: String, echo: bool },
+    /// The command finished.
+    Finished(CommandOutput),
+}
+
+/// Something a subscribed client receives.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputEvent {
+    /// Output from the session's PTY.
+    Output(Vec<u8>),
+    /// The PTY was resized.
+    Resized { rows: u16, cols: u16 },
+    /// Risky injected input will be typed after `delay_ms` unless cancelled
+    /// with [`Client::cancel_input`].
+    PendingInput {
+        id: u64,
+        data: String,
+        delay_ms: u64,
+    },
+    /// Something happened in the session, after [`Client::subscribe_events`].
+    Event(Event),
+    /// The server detached this client, e.g. because another took over
+    /// with `detach_others`.
+    Detached { reason: String },
+}
+
 /// Client for interacting with a tap session.
 pub struct Client {
-    stream: tokio::io::BufReader<tokio::net::UnixStream>,
+    writer: Box<dyn tokio::io::AsyncWrite + Send + Unpin>,
+    dispatcher: dispatch::Dispatcher,
+    /// Replies to the latest request. Some requests get more than one,
+    /// e.g. `Stalled` before `SessionEnded`.
+    replies: Option<tokio::sync::mpsc::UnboundedReceiver<Response>>,
+    /// The `ssh` carrying a connection to another machine, killed with
+    /// the client.
+    _tunnel: Option<tokio::process::Child>,
 }
 
 impl Client {
-    /// Connect to a session by ID.
+    /// Connect to a session by ID, checking that its server speaks a
+    /// compatible protocol version. A `tcp://` address connects with
+    /// [`Client::connect_tcp`] instead, and `[user@]host:session` with
+    /// [`Client::connect_ssh`].
     pub async fn connect(session_id: &str) -> Result<Self> {
+        if let Some(address) = session_id.strip_prefix(TCP_SCHEME) {
+            return Self::connect_tcp(address).await;
+        }
+        if let Some((destination, session)) = parse_remote(session_id) {
+            return Self::connect_ssh(destination, session).await;
+        }
+        let mut client = Self::connect_unchecked(session_id).await?;
+        client.hello().await?;
+        Ok(client)
+    }
+
+    /// Connect to a session served over TCP (`tap start --listen`) at
+    /// `[token@]host:port`, authenticating with the token, or with
+    /// `$TAP_AUTH_TOKEN` if the address has none.
+    pub async fn connect_tcp(address: &str) -> Result<Self> {
+        let (token, host) = match address.rsplit_once('@') {
+            Some((token, host)) => (token.to_string(), host),
+            None => {
+                let token = std::env::var(tap_protocol::AUTH_TOKEN_ENV).map_err(|_| {
+                    Error::Denied(format!(
+                        "no token for {address}; set ${} or connect to tcp://TOKEN@{address}",
+                        tap_protocol::AUTH_TOKEN_ENV
+                    ))
+                })?;
+                (token, address)
+            }
+        };
+        let stream = tokio::net::TcpStream::connect(host).await?;
+        // Keystrokes go out as they're typed
+        stream.set_nodelay(true)?;
+        let mut client = Self::from_stream(stream);
+        client
+            .handshake(Some(token), Some(TCP_HELLO_TIMEOUT))
+            .await?;
+        Ok(client)
+    }
+
+    /// Connect to a session on another machine over SSH, or to its latest
+    /// session if `session` is None. tap must be installed there too (see
+    /// [`REMOTE_TAP_ENV`]).
+    pub async fn connect_ssh(destination: &str, session: Option<&str>) -> Result<Self> {
+        let mut args = vec!["proxy"];
+        args.extend(session);
+        let mut tunnel = ssh::remote_tap(destination, &args).spawn()?;
+        let (Some(reader), Some(writer)) = (tunnel.stdout.take(), tunnel.stdin.take()) else {
+            return Err(Error::Server("ssh has no pipes".to_string()));
+        };
+        let mut client = Self::from_parts(reader, writer);
+        client._tunnel = Some(tunnel);
+        // No time limit, since SSH may be asking for a password
+        client.handshake(None, None).await?;
+        Ok(client)
+    }
+
+    /// Connect to a session by ID without checking its protocol version,
+    /// for requests that must still reach older servers (e.g. `upgrade`).
+    pub async fn connect_unchecked(session_id: &str) -> Result<Self> {
         let path = socket_path(session_id);
         if !path.exists() {
             return Err(Error::SessionNotFound(session_id.to_string()));
         }
         let stream = tokio::net::UnixStream::connect(&path).await?;
-        Ok(Self {
-            stream: tokio::io::BufReader::new(stream),
-        })
+        Ok(Self::from_stream(stream))
+    }
+
+    fn from_stream(
+        stream: impl tokio::io::AsyncRead + tokio::io::AsyncWrite + Send + 'static,
+    ) -> Self {
+        let (reader, writer) = tokio::io::split(stream);
+        Self::from_parts(reader, writer)
+    }
+
+    fn from_parts(
+        reader: impl tokio::io::AsyncRead + Send + Unpin + 'static,
+        writer: impl tokio::io::AsyncWrite + Send + Unpin + 'static,
+    ) -> Self {
+        Self {
+            writer: Box::new(writer),
+            dispatcher: dispatch::Dispatcher::spawn(reader),
+            replies: None,
+            _tunnel: None,
+        }
     }
 
     /// Connect to the most recent session.
@@ -61,31 +369,154 @@ impl Client {
     }
 
     async fn send_request(&mut self, request: &Request) -> Result<Response> {
-        let request_bytes = serde_json::to_vec(request)?;
-        self.stream.get_mut().write_all(&request_bytes).await?;
+        self.start_request(request).await?;
+        self.read_response().await
+    }
 
-        let mut line = String::new();
-        self.stream.read_line(&mut line).await?;
-        let response: Response = serde_json::from_str(&line)?;
-        Ok(response)
+    /// Send `request` under a new ID, without waiting for the reply.
+    async fn start_request(&mut self, request: &Request) -> Result<()> {
+        let (id, replies) = self.dispatcher.register();
+        self.replies = Some(replies);
+        let envelope = tap_protocol::Envelope {
+            request_id: Some(id),
+            message: request,
+        };
+        self.write(&envelope).await
+    }
+
+    /// Write one message to the server. Requests sent this way get no
+    /// reply, which is what attached connections want.
+    async fn write(&mut self, message: &impl serde::Serialize) -> Result<()> {
+        let bytes = serde_json::to_vec(message)?;
+        self.writer.write_all(&bytes).await?;
+        Ok(())
+    }
+
+    /// Next reply to the latest request.
+    ///
+    /// Cancel-safe.
+    async fn read_response(&mut self) -> Result<Response> {
+        let reply = match self.replies.as_mut() {
+            Some(replies) => replies.recv().await,
+            None => None,
+        };
+        reply.ok_or_else(|| Error::Io(std::io::ErrorKind::UnexpectedEof.into()))
     }
 
     /// Get scrollback buffer content.
     pub async fn get_scrollback(&mut self, lines: Option<usize>) -> Result<String> {
-        let response = self.send_request(&Request::GetScrollback { lines }).await?;
+        let response = self
+            .send_request(&Request::GetScrollback {
+                lines,
+                since_mark: None,
+            })
+            .await?;
+        match response {
+            Response::Scrollback { content } => Ok(content),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Get output produced since a mark set with [`Client::mark`].
+    pub async fn get_scrollback_since(
+        &mut self,
+        mark: &str,
+        lines: Option<usize>,
+    ) -> Result<String> {
+        let response = self
+            .send_request(&Request::GetScrollback {
+                lines,
+                since_mark: Some(mark.to_string()),
+            })
+            .await?;
         match response {
             Response::Scrollback { content } => Ok(content),
-            Response::Error { message } => Err(Error::Server(message)),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Insert an invisible bookmark into the session's output history.
+    pub async fn mark(&mut self, label: &str) -> Result<()> {
+        let response = self
+            .send_request(&Request::Mark {
+                label: label.to_string(),
+            })
+            .await?;
+        match response {
+            Response::Ok => Ok(()),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Add a timestamped note to the session, marking any recording in
+    /// progress too.
+    pub async fn note(&mut self, text: &str) -> Result<()> {
+        let response = self
+            .send_request(&Request::Note {
+                text: text.to_string(),
+            })
+            .await?;
+        match response {
+            Response::Ok => Ok(()),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Give the session a new ID; later connections must use `name`.
+    pub async fn rename(&mut self, name: &str) -> Result<()> {
+        let response = self
+            .send_request(&Request::Rename {
+                name: name.to_string(),
+            })
+            .await?;
+        match response {
+            Response::Ok => Ok(()),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
             _ => Err(Error::Server("unexpected response".to_string())),
         }
     }
 
-    /// Get cursor position (row, col).
-    pub async fn get_cursor(&mut self) -> Result<(usize, usize)> {
+    /// Get cursor position.
+    pub async fn get_cursor(&mut self) -> Result<CursorPosition> {
         let response = self.send_request(&Request::GetCursor).await?;
         match response {
-            Response::Cursor { row, col } => Ok((row, col)),
-            Response::Error { message } => Err(Error::Server(message)),
+            Response::Cursor { row, col, char_col } => Ok(CursorPosition { row, col, char_col }),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Get the window title the app in the session last set.
+    pub async fn get_title(&mut self) -> Result<String> {
+        let response = self.send_request(&Request::GetTitle).await?;
+        match response {
+            Response::Title { title } => Ok(title),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Get the session's working directory: where the shell last reported
+    /// being (OSC 7), or else the child process's own.
+    pub async fn get_cwd(&mut self) -> Result<String> {
+        let response = self.send_request(&Request::GetCwd).await?;
+        match response {
+            Response::Cwd { cwd } => Ok(cwd),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Get the CPU and wall-clock time used by the session's command.
+    pub async fn get_times(&mut self) -> Result<SessionTimes> {
+        let response = self.send_request(&Request::GetTimes).await?;
+        match response {
+            Response::Times { times } => Ok(times),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
             _ => Err(Error::Server("unexpected response".to_string())),
         }
     }
@@ -95,21 +526,462 @@ impl Client {
         let response = self.send_request(&Request::GetSize).await?;
         match response {
             Response::Size { rows, cols } => Ok((rows, cols)),
-            Response::Error { message } => Err(Error::Server(message)),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
             _ => Err(Error::Server("unexpected response".to_string())),
         }
     }
 
-    /// Inject input into the PTY.
-    pub async fn inject(&mut self, data: &str) -> Result<()> {
+    /// Inject input into the PTY. Returns the output sequence number to
+    /// pass to [`Client::subscribe_from`] to see everything it causes.
+    pub async fn inject(&mut self, data: &str) -> Result<u64> {
         let response = self
             .send_request(&Request::Inject {
                 data: data.to_string(),
             })
             .await?;
+        match response {
+            Response::Injected { seq } => Ok(seq),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Inject keys by name (`Enter`, `C-c`, `M-x`, `F5`, `Up`...), encoded
+    /// the way the inner app expects them. Returns the output sequence
+    /// number, as [`Client::inject`] does.
+    pub async fn send_keys(&mut self, keys: &[String]) -> Result<u64> {
+        let response = self
+            .send_request(&Request::SendKeys {
+                keys: keys.to_vec(),
+            })
+            .await?;
+        match response {
+            Response::Injected { seq } => Ok(seq),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Get the output and exit code of the `nth` most recently finished
+    /// command (1 for the last). Needs the session's shell to mark commands
+    /// with OSC 133.
+    pub async fn command_output(&mut self, nth: usize) -> Result<(String, Option<i32>)> {
+        let response = self
+            .send_request(&Request::GetCommandOutput { nth })
+            .await?;
+        match response {
+            Response::CommandOutput { output, exit_code } => Ok((output, exit_code)),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Inject input and return the output produced from then until `until`,
+    /// failing if that takes longer than `timeout`.
+    pub async fn inject_and_capture(
+        &mut self,
+        data: &str,
+        until: CaptureEnd,
+        timeout: Option<std::time::Duration>,
+    ) -> Result<String> {
+        let response = self
+            .send_request(&Request::InjectAndCapture {
+                data: data.to_string(),
+                until,
+                timeout_ms: timeout.map(|d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX)),
+            })
+            .await?;
+        match response {
+            Response::Captured { output } => Ok(output),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Type `command` into the session's shell and wait for it to finish,
+    /// giving up after `timeout` if set.
+    pub async fn run_command(
+        &mut self,
+        command: &str,
+        timeout: Option<std::time::Duration>,
+    ) -> Result<CommandOutput> {
+        let response = self
+            .send_request(&Request::RunCommand {
+                command: command.to_string(),
+                timeout_ms: timeout.map(|d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX)),
+                interactive: false,
+            })
+            .await?;
+        match response {
+            Response::CommandResult { output, exit_code } => {
+                Ok(CommandOutput { output, exit_code })
+            }
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Start `command` like [`Client::run_command`], having the server report
+    /// whenever it seems to wait for input. Read the reports and the result
+    /// with [`Client::read_command_event`]; answer prompts from another
+    /// connection (e.g. with [`Client::write_input`]).
+    pub async fn start_interactive_command(
+        &mut self,
+        command: &str,
+        timeout: Option<std::time::Duration>,
+    ) -> Result<()> {
+        let request = Request::RunCommand {
+            command: command.to_string(),
+            timeout_ms: timeout.map(|d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX)),
+            interactive: true,
+        };
+        self.start_request(&request).await
+    }
+
+    /// Read the next report on a command started with
+    /// [`Client::start_interactive_command`].
+    ///
+    /// Cancel-safe.
+    pub async fn read_command_event(&mut self) -> Result<CommandEvent> {
+        match self.read_response().await? {
+            Response::InputPrompt { prompt, echo } => Ok(CommandEvent::Prompt { prompt, echo }),
+            Response::CommandResult { output, exit_code } => {
+                Ok(CommandEvent::Finished(CommandOutput { output, exit_code }))
+            }
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Get the environment of the session's child process.
+    pub async fn get_env(&mut self) -> Result<std::collections::BTreeMap<String, String>> {
+        let response = self.send_request(&Request::GetEnv).await?;
+        match response {
+            Response::Env { vars } => Ok(vars),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Render the visible screen, with colors, as an SVG document.
+    pub async fn screenshot(&mut self) -> Result<String> {
+        let response = self.send_request(&Request::Screenshot).await?;
+        match response {
+            Response::Screenshot { svg } => Ok(svg),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Get the visible screen cell by cell, with colors and attributes, the
+    /// cursor position, whether the alternate screen is active and the title.
+    pub async fn get_screen(&mut self) -> Result<Screen> {
+        let response = self.send_request(&Request::GetScreen).await?;
+        match response {
+            Response::Screen { screen } => Ok(screen),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Get the history and screen a line per row, as copy mode shows them,
+    /// with the cursor's (line, column).
+    pub async fn get_copy_text(&mut self) -> Result<(Vec<String>, (usize, usize))> {
+        let response = self.send_request(&Request::GetCopyText).await?;
+        match response {
+            Response::CopyText { lines, line, col } => Ok((lines, (line, col))),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Bring back the main screen of a session stuck on the alternate screen.
+    pub async fn reset_alt_screen(&mut self) -> Result<()> {
+        let response = self.send_request(&Request::ResetAltScreen).await?;
+        match response {
+            Response::Ok => Ok(()),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Rebuild the session's emulated screen and have its app redraw it.
+    pub async fn resync_screen(&mut self) -> Result<()> {
+        let response = self.send_request(&Request::ResyncScreen).await?;
+        match response {
+            Response::Ok => Ok(()),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Drop the session's scrollback history, keeping its screen.
+    pub async fn clear_scrollback(&mut self) -> Result<()> {
+        let response = self.send_request(&Request::ClearScrollback).await?;
+        match response {
+            Response::Ok => Ok(()),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Signal the session's child process group; `None` hangs it up,
+    /// escalating to SIGKILL if it doesn't exit.
+    pub async fn kill(&mut self, signal: Option<i32>) -> Result<()> {
+        let response = self.send_request(&Request::Kill { signal }).await?;
+        match response {
+            Response::Ok => Ok(()),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Cancel held injected input by ID, or all of it. Returns how many
+    /// were cancelled.
+    pub async fn cancel_input(&mut self, id: Option<u64>) -> Result<usize> {
+        let response = self.send_request(&Request::CancelInput { id }).await?;
+        match response {
+            Response::Cancelled { count } => Ok(count),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Exchange protocol versions with the server, failing if it can't
+    /// serve this client. Returns the server's version.
+    pub async fn hello(&mut self) -> Result<ProtocolVersion> {
+        self.handshake(None, Some(HELLO_TIMEOUT)).await
+    }
+
+    /// Say `Hello`, with a token for servers that want one. Without a
+    /// `timeout`, a server that never answers isn't told from a slow one.
+    async fn handshake(
+        &mut self,
+        token: Option<String>,
+        timeout: Option<std::time::Duration>,
+    ) -> Result<ProtocolVersion> {
+        let request = Request::Hello {
+            version: PROTOCOL_VERSION.to_string(),
+            token,
+        };
+        let reply = self.send_request(&request);
+        let response = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, reply)
+                .await
+                .map_err(|_| timeout),
+            None => Ok(reply.await),
+        };
+        match response {
+            Ok(Ok(Response::Hello { version })) => check_server_version(Some(&version)),
+            Ok(Ok(Response::Error { code, message })) => Err(Error::from_server(code, message)),
+            Ok(Ok(_)) => Err(Error::Server("unexpected response".to_string())),
+            Ok(Err(e)) => Err(e),
+            // Servers from before `Hello` ignore it, but still answer `Ping`
+            Err(timeout) => match tokio::time::timeout(timeout, self.ping()).await {
+                Ok(Ok(())) => check_server_version(None),
+                Ok(Err(e)) => Err(e),
+                Err(_) => Err(Error::TimedOut(timeout)),
+            },
+        }
+    }
+
+    /// Check that the session's server answers.
+    pub async fn ping(&mut self) -> Result<()> {
+        let response = self.send_request(&Request::Ping).await?;
+        match response {
+            Response::Pong => Ok(()),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Ask the session to add itself back to sessions.json if it's missing.
+    pub async fn reregister(&mut self) -> Result<()> {
+        let response = self.send_request(&Request::Reregister).await?;
+        match response {
+            Response::Ok => Ok(()),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Disconnect all attached and subscribed clients of the session.
+    pub async fn detach_clients(&mut self) -> Result<()> {
+        let response = self.send_request(&Request::DetachClients).await?;
+        match response {
+            Response::Ok => Ok(()),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Start recording the session's output to an asciicast file.
+    pub async fn start_recording(&mut self, path: &std::path::Path) -> Result<()> {
+        let path = std::path::absolute(path)?;
+        let response = self
+            .send_request(&Request::StartRecording {
+                path: path.display().to_string(),
+            })
+            .await?;
+        match response {
+            Response::Ok => Ok(()),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Stop recording and return the path of the finished file.
+    pub async fn stop_recording(&mut self) -> Result<String> {
+        let response = self.send_request(&Request::StopRecording).await?;
+        match response {
+            Response::RecordingStopped { path } => Ok(path),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Hand the session over to the tap process listening on `to`.
+    pub async fn migrate(&mut self, to: &std::path::Path) -> Result<()> {
+        let to = std::path::absolute(to)?;
+        let response = self
+            .send_request(&Request::Migrate {
+                to: to.display().to_string(),
+            })
+            .await?;
+        match response {
+            Response::Ok => Ok(()),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Have the server re-exec itself as the tap binary at `exe`.
+    pub async fn upgrade(&mut self, exe: &std::path::Path) -> Result<()> {
+        let response = self
+            .send_request(&Request::Upgrade {
+                exe: exe.display().to_string(),
+            })
+            .await?;
+        match response {
+            Response::Ok => Ok(()),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Block until the session's child exits and return its exit code, or
+    /// `None` if the session can't tell, as after a migration.
+    pub async fn wait(&mut self) -> Result<Option<i32>> {
+        self.wait_watched(None, |_| {}).await
+    }
+
+    /// Like [`Client::wait`], calling `on_stall` with the idle time whenever
+    /// the child goes `stall_after` without output (the session's configured
+    /// `watchdog.stall_secs` if `None`).
+    pub async fn wait_watched(
+        &mut self,
+        stall_after: Option<std::time::Duration>,
+        mut on_stall: impl FnMut(std::time::Duration),
+    ) -> Result<Option<i32>> {
+        let mut response = self
+            .send_request(&Request::Wait {
+                stall_secs: stall_after.map(|d| d.as_secs().max(1)),
+            })
+            .await?;
+        loop {
+            match response {
+                Response::Stalled { idle_secs } => {
+                    on_stall(std::time::Duration::from_secs(idle_secs));
+                    response = self.read_response().await?;
+                }
+                Response::SessionEnded { exit_code } => return Ok(exit_code),
+                Response::Error { code, message } => {
+                    return Err(Error::from_server(code, message));
+                }
+                _ => return Err(Error::Server("unexpected response".to_string())),
+            }
+        }
+    }
+
+    /// Ask the session to open its scrollback in the editor.
+    ///
+    /// Returns `Some(path)` when the session has no terminal to open an
+    /// editor in and wrote the scrollback to a file instead.
+    pub async fn open_editor(&mut self) -> Result<Option<String>> {
+        let response = self.send_request(&Request::OpenEditor).await?;
+        match response {
+            Response::Ok => Ok(None),
+            Response::ScrollbackFile { path } => Ok(Some(path)),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Store text in a named paste buffer.
+    pub async fn set_buffer(&mut self, name: &str, data: &str) -> Result<()> {
+        let response = self
+            .send_request(&Request::SetBuffer {
+                name: name.to_string(),
+                data: data.to_string(),
+            })
+            .await?;
+        match response {
+            Response::Ok => Ok(()),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Get the contents of a named paste buffer.
+    pub async fn get_buffer(&mut self, name: &str) -> Result<String> {
+        let response = self
+            .send_request(&Request::GetBuffer {
+                name: name.to_string(),
+            })
+            .await?;
+        match response {
+            Response::Buffer { data } => Ok(data),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Get what the app last copied with OSC 52. The session only keeps it
+    /// with `[clipboard] keep` set.
+    pub async fn get_clipboard(&mut self) -> Result<String> {
+        let response = self.send_request(&Request::GetClipboard).await?;
+        match response {
+            Response::Clipboard { data } => Ok(data),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Inject the contents of a named paste buffer into the PTY.
+    pub async fn paste_buffer(&mut self, name: &str) -> Result<()> {
+        let response = self
+            .send_request(&Request::PasteBuffer {
+                name: name.to_string(),
+            })
+            .await?;
+        match response {
+            Response::Ok => Ok(()),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Paste text into the PTY the way a terminal would, bracketed if the
+    /// app enabled bracketed paste.
+    pub async fn paste(&mut self, data: &str) -> Result<()> {
+        let response = self
+            .send_request(&Request::Paste {
+                data: data.to_string(),
+            })
+            .await?;
         match response {
             Response::Ok => Ok(()),
-            Response::Error { message } => Err(Error::Server(message)),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
             _ => Err(Error::Server("unexpected response".to_string())),
         }
     }
@@ -117,57 +989,228 @@ impl Client {
     /// Subscribe to live output stream.
     /// After calling this, use `read_output()` to receive output chunks.
     pub async fn subscribe(&mut self) -> Result<()> {
-        let response = self.send_request(&Request::Subscribe).await?;
+        let response = self
+            .send_request(&Request::Subscribe { from_seq: None })
+            .await?;
         match response {
             Response::Subscribed => Ok(()),
-            Response::Error { message } => Err(Error::Server(message)),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
             _ => Err(Error::Server("unexpected response".to_string())),
         }
     }
 
-    /// Read the next output chunk after subscribing.
+    /// Subscribe to output starting from sequence number `seq` (as returned
+    /// by [`Client::inject`]), so nothing printed since is missed even if it
+    /// came before the subscription. Fails if that output is too old to
+    /// have been kept.
+    pub async fn subscribe_from(&mut self, seq: u64) -> Result<()> {
+        let response = self
+            .send_request(&Request::Subscribe {
+                from_seq: Some(seq),
+            })
+            .await?;
+        match response {
+            Response::Subscribed => Ok(()),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Stream session events of the given kinds (all if empty); read them
+    /// with [`Client::read_event`].
+    pub async fn subscribe_events(&mut self, kinds: Vec<EventKind>) -> Result<()> {
+        let response = self
+            .send_request(&Request::SubscribeEvents { kinds })
+            .await?;
+        match response {
+            Response::Ok => Ok(()),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+
+    /// Read the next output chunk after subscribing, skipping other events.
     /// Returns None if the connection is closed.
+    ///
+    /// Cancel-safe.
     pub async fn read_output(&mut self) -> Result<Option<Vec<u8>>> {
-        let mut line = String::new();
-        let n = self.stream.read_line(&mut line).await?;
-        if n == 0 {
-            return Ok(None);
+        loop {
+            match self.read_event().await? {
+                Some(OutputEvent::Output(data)) => return Ok(Some(data)),
+                Some(
+                    OutputEvent::Resized { .. }
+                    | OutputEvent::PendingInput { .. }
+                    | OutputEvent::Event(_),
+                ) => {}
+                Some(OutputEvent::Detached { .. }) | None => return Ok(None),
+            }
         }
-        let response: Response = serde_json::from_str(&line)?;
+    }
+
+    /// Read the next event after subscribing. Replies to requests made
+    /// meanwhile don't consume events, nor events replies.
+    /// Returns None if the connection is closed.
+    ///
+    /// Cancel-safe.
+    pub async fn read_event(&mut self) -> Result<Option<OutputEvent>> {
+        let Some(response) = self.dispatcher.next_streamed().await else {
+            return Ok(None);
+        };
         match response {
-            Response::Output { data } => Ok(Some(data)),
-            Response::Error { message } => Err(Error::Server(message)),
+            Response::Output { data } => Ok(Some(OutputEvent::Output(data))),
+            Response::Resized { rows, cols } => Ok(Some(OutputEvent::Resized { rows, cols })),
+            Response::Event { event } => Ok(Some(OutputEvent::Event(event))),
+            Response::PendingInput { id, data, delay_ms } => {
+                Ok(Some(OutputEvent::PendingInput { id, data, delay_ms }))
+            }
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
             Response::SessionEnded { .. } => Ok(None),
+            Response::Detached { reason } => Ok(Some(OutputEvent::Detached { reason })),
             _ => Err(Error::Server("unexpected response".to_string())),
         }
     }
 
+    /// Subscribe to output and wait until the `pattern` regex matches text
+    /// printed from now on (escape sequences stripped), failing with
+    /// [`Error::TimedOut`] after `timeout`. Returns None if the session
+    /// ends first.
+    pub async fn wait_for_pattern(
+        &mut self,
+        pattern: &str,
+        timeout: Option<std::time::Duration>,
+    ) -> Result<Option<PatternMatch>> {
+        let pattern = regex::Regex::new(pattern)?;
+        self.subscribe().await?;
+
+        let mut output = expect::OutputText::default();
+        let watch = async {
+            while let Some(data) = self.read_output().await? {
+                let from = output.push(&data);
+                if let Some(found) = output.find(&pattern, from) {
+                    return Ok(Some(found));
+                }
+            }
+            Ok(None)
+        };
+        match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, watch)
+                .await
+                .unwrap_or(Err(Error::TimedOut(timeout))),
+            None => watch.await,
+        }
+    }
+
     /// Attach to the session (take over stdin/stdout).
-    /// Returns the initial scrollback content if successful.
-    pub async fn attach(&mut self, rows: u16, cols: u16) -> Result<String> {
-        let response = self.send_request(&Request::Attach { rows, cols }).await?;
+    /// Returns the initial scrollback content and cursor shape if successful.
+    ///
+    /// With `no_resize`, the PTY keeps its current size rather than
+    /// adopting `rows` x `cols`. With `read_only`, the server drops input
+    /// from this client and doesn't resize for it.
+    pub async fn attach(
+        &mut self,
+        rows: u16,
+        cols: u16,
+        no_resize: bool,
+        read_only: bool,
+        detach_others: bool,
+    ) -> Result<Attachment> {
+        let response = self
+            .send_request(&Request::Attach {
+                rows,
+                cols,
+                no_resize,
+                read_only,
+                detach_others,
+            })
+            .await?;
         match response {
-            Response::Attached { scrollback } => Ok(scrollback),
-            Response::Error { message } => Err(Error::Server(message)),
+            Response::Attached {
+                scrollback,
+                cursor_style,
+                tail,
+                title,
+            } => Ok(Attachment {
+                scrollback,
+                cursor_style,
+                tail,
+                title,
+            }),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
             _ => Err(Error::Server("unexpected response".to_string())),
         }
     }
 
     /// Send input to the PTY (for attached clients).
     pub async fn send_input(&mut self, data: Vec<u8>) -> Result<()> {
-        let request = Request::Input { data };
-        let request_bytes = serde_json::to_vec(&request)?;
-        self.stream.get_mut().write_all(&request_bytes).await?;
-        Ok(())
+        self.write(&Request::Input { data }).await
+    }
+
+    /// Send raw input to the PTY and wait for confirmation (for non-attached clients).
+    pub async fn write_input(&mut self, data: Vec<u8>) -> Result<()> {
+        let response = self.send_request(&Request::Input { data }).await?;
+        match response {
+            Response::Ok => Ok(()),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
     }
 
     /// Resize the PTY (for attached clients).
     pub async fn resize(&mut self, rows: u16, cols: u16) -> Result<()> {
-        let request = Request::Resize { rows, cols };
-        let request_bytes = serde_json::to_vec(&request)?;
-        self.stream.get_mut().write_all(&request_bytes).await?;
-        Ok(())
+        self.write(&Request::Resize { rows, cols }).await
+    }
+
+    /// Have the session's screen drawn from scratch on this attached client.
+    pub async fn redraw(&mut self) -> Result<()> {
+        self.write(&Request::Redraw).await
     }
+
+    /// Tell the server this attached client is still here. Once sent, the
+    /// server expects one every [`tap_protocol::HEARTBEAT_INTERVAL`].
+    pub async fn heartbeat(&mut self) -> Result<()> {
+        self.write(&Request::Ping).await
+    }
+
+    /// Resize the PTY and wait for confirmation (for non-attached clients).
+    /// Returns the size applied, which attached terminals may have capped.
+    pub async fn set_size(&mut self, rows: u16, cols: u16) -> Result<(u16, u16)> {
+        let response = self.send_request(&Request::Resize { rows, cols }).await?;
+        match response {
+            Response::Size { rows, cols } => Ok((rows, cols)),
+            // Servers from before sizes were capped
+            Response::Ok => Ok((rows, cols)),
+            Response::Error { code, message } => Err(Error::from_server(code, message)),
+            _ => Err(Error::Server("unexpected response".to_string())),
+        }
+    }
+}
+
+/// Check the version a server sent back in `Hello`, if it answered at all.
+fn check_server_version(version: Option<&str>) -> Result<ProtocolVersion> {
+    let Some(version) = version else {
+        return Err(Error::Incompatible(
+            "its server predates protocol versions; restart it as this tap with `tap self-upgrade-handoff`"
+                .to_string(),
+        ));
+    };
+    let Some(server) = ProtocolVersion::parse(version) else {
+        return Err(Error::Incompatible(format!(
+            "its server speaks unknown protocol '{version}'"
+        )));
+    };
+    if PROTOCOL_VERSION.is_served_by(server) {
+        return Ok(server);
+    }
+    let message = if server > PROTOCOL_VERSION {
+        format!(
+            "its server speaks protocol {server}, newer than this tap's {PROTOCOL_VERSION}; upgrade tap"
+        )
+    } else {
+        format!(
+            "its server speaks protocol {server}, older than this tap's {PROTOCOL_VERSION}; restart it as this tap with `tap self-upgrade-handoff`"
+        )
+    };
+    Err(Error::Incompatible(message))
 }
 
 #[cfg(test)]
@@ -180,6 +1223,171 @@ mod tests {
         assert!(!dir.as_os_str().is_empty());
     }
 
+    #[test]
+    fn test_dir_resolver() {
+        use tap_protocol::Resolver as _;
+
+        let resolver = tap_protocol::DirResolver("/sandbox/tap".into());
+        assert_eq!(
+            resolver.socket_path("calm-fox"),
+            std::path::Path::new("/sandbox/tap/calm-fox.sock")
+        );
+        assert_eq!(
+            resolver.sessions_file(),
+            std::path::Path::new("/sandbox/tap/sessions.json")
+        );
+    }
+
+    #[test]
+    fn test_error_codes() {
+        let response: Response =
+            serde_json::from_str(r#"{"type":"error","code":"already_attached","message":"busy"}"#)
+                .unwrap();
+        let Response::Error { code, message } = response else {
+            panic!("expected error response");
+        };
+        assert!(matches!(
+            Error::from_server(code, message),
+            Error::AlreadyAttached(m) if m == "busy"
+        ));
+
+        // Servers without codes still parse
+        let response: Response =
+            serde_json::from_str(r#"{"type":"error","message":"oops"}"#).unwrap();
+        assert!(matches!(
+            response,
+            Response::Error {
+                code: ErrorCode::Other,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_check_server_version() {
+        let current = PROTOCOL_VERSION.to_string();
+        assert_eq!(
+            check_server_version(Some(&current)).unwrap(),
+            PROTOCOL_VERSION
+        );
+        let newer_minor = format!("{}.{}", PROTOCOL_VERSION.major, PROTOCOL_VERSION.minor + 1);
+        assert!(check_server_version(Some(&newer_minor)).is_ok());
+
+        let newer_major = format!("{}.0", PROTOCOL_VERSION.major + 1);
+        assert!(matches!(
+            check_server_version(Some(&newer_major)),
+            Err(Error::Incompatible(m)) if m.ends_with("upgrade tap")
+        ));
+        assert!(matches!(
+            check_server_version(Some("0.9")),
+            Err(Error::Incompatible(m)) if m.contains("older than")
+        ));
+        assert!(matches!(
+            check_server_version(None),
+            Err(Error::Incompatible(m)) if m.contains("predates")
+        ));
+        assert!(check_server_version(Some("one")).is_err());
+    }
+
+    fn session(attached: bool, tags: &[&str], started: &str) -> Session {
+        Session {
+            id: "test".to_string(),
+            pid: 1,
+            started: started.to_string(),
+            command: vec![],
+            attached,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            term: None,
+            colorterm: None,
+            profile: None,
+            parent: None,
+            notes: vec![],
+            cwd: None,
+            title: None,
+            ended: None,
+            exit_code: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(parse_age("30s"), Ok(std::time::Duration::from_secs(30)));
+        assert_eq!(
+            parse_age("2d"),
+            Ok(std::time::Duration::from_secs(2 * 86400))
+        );
+        assert!(parse_age("2").is_err());
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("2y").is_err());
+    }
+
+    #[test]
+    fn test_filter_empty_matches_all() {
+        let filter = SessionFilter::default();
+        assert!(filter.is_empty());
+        assert!(filter.matches(&session(true, &[], "bad"), chrono::Utc::now()));
+    }
+
+    #[test]
+    fn test_filter_tags_and_detached() {
+        let now = chrono::Utc::now();
+        let filter = SessionFilter {
+            tags: vec!["exp".to_string()],
+            detached_only: true,
+            ..Default::default()
+        };
+        let started = now.to_rfc3339();
+        assert!(filter.matches(&session(false, &["exp", "x"], &started), now));
+        assert!(!filter.matches(&session(true, &["exp"], &started), now));
+        assert!(!filter.matches(&session(false, &["other"], &started), now));
+    }
+
+    #[test]
+    fn test_filter_older_than() {
+        let now = chrono::Utc::now();
+        let filter = SessionFilter {
+            older_than: Some(std::time::Duration::from_secs(86400)),
+            ..Default::default()
+        };
+        let old = (now - chrono::Duration::days(3)).to_rfc3339();
+        let new = (now - chrono::Duration::hours(1)).to_rfc3339();
+        assert!(filter.matches(&session(false, &[], &old), now));
+        assert!(!filter.matches(&session(false, &[], &new), now));
+    }
+
+    #[test]
+    fn test_session_tree() {
+        let session = |id: &str, parent: Option<&str>| Session {
+            id: id.to_string(),
+            parent: parent.map(str::to_string),
+            ..session(false, &[], "")
+        };
+        let sessions = [
+            session("inner", Some("outer")),
+            session("outer", None),
+            session("orphan", Some("gone")),
+            session("innermost", Some("inner")),
+            session("sibling", Some("outer")),
+        ];
+        let tree: Vec<_> = session_tree(&sessions)
+            .into_iter()
+            .map(|(depth, s)| (depth, s.id.as_str()))
+            .collect();
+        assert_eq!(
+            tree,
+            [
+                (0, "outer"),
+                (1, "inner"),
+                (2, "innermost"),
+                (1, "sibling"),
+                (0, "orphan"),
+            ]
+        );
+
+        let cycle = [session("a", Some("b")), session("b", Some("a"))];
+        assert_eq!(session_tree(&cycle).len(), 2);
+    }
+
     #[test]
     fn test_list_sessions_empty() {
         // This should not panic even if no sessions exist
@@ -0,0 +1,107 @@
+//! Following the output of several sessions over one connection each.
+
+use crate::{Client, Result, SessionFilter, list_sessions_filtered};
+
+/// How often to look for newly started sessions when following them.
+const RESCAN_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Something that happened in one of the followed sessions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionEvent {
+    /// Output from the session's PTY.
+    Output(Vec<u8>),
+    /// The session ended or its connection dropped.
+    Ended,
+}
+
+/// Subscribes to every session matching a filter and merges their output.
+pub struct MultiSubscriber {
+    filter: SessionFilter,
+    follow_new: bool,
+    subscribed: std::collections::HashSet<String>,
+    active: usize,
+    tx: tokio::sync::mpsc::UnboundedSender<(String, SessionEvent)>,
+    rx: tokio::sync::mpsc::UnboundedReceiver<(String, SessionEvent)>,
+    rescan: tokio::time::Interval,
+}
+
+impl MultiSubscriber {
+    /// Subscribe to the sessions matching `filter`. With `follow_new`,
+    /// sessions started later are picked up as well.
+    pub async fn new(filter: SessionFilter, follow_new: bool) -> Result<Self> {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut rescan = tokio::time::interval(RESCAN_INTERVAL);
+        rescan.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        let mut multi = Self {
+            filter,
+            follow_new,
+            subscribed: std::collections::HashSet::new(),
+            active: 0,
+            tx,
+            rx,
+            rescan,
+        };
+        multi.subscribe_new().await?;
+        Ok(multi)
+    }
+
+    /// Number of sessions currently being followed.
+    #[must_use]
+    pub fn active(&self) -> usize {
+        self.active
+    }
+
+    /// Wait for the next event from any followed session.
+    ///
+    /// Returns None once every session has ended and new ones aren't followed.
+    pub async fn next(&mut self) -> Result<Option<(String, SessionEvent)>> {
+        loop {
+            if self.active == 0 && !self.follow_new {
+                return Ok(None);
+            }
+            tokio::select! {
+                Some((id, event)) = self.rx.recv() => {
+                    if event == SessionEvent::Ended {
+                        self.active -= 1;
+                    }
+                    return Ok(Some((id, event)));
+                }
+                _ = self.rescan.tick(), if self.follow_new => {
+                    self.subscribe_new().await?;
+                }
+            }
+        }
+    }
+
+    /// Subscribe to matching sessions not followed yet.
+    async fn subscribe_new(&mut self) -> Result<()> {
+        for session in list_sessions_filtered(&self.filter)? {
+            if self.subscribed.contains(&session.id) {
+                continue;
+            }
+            // The session may have exited since it was listed
+            let Ok(mut client) = Client::connect(&session.id).await else {
+                continue;
+            };
+            if client.subscribe().await.is_err() {
+                continue;
+            }
+
+            self.subscribed.insert(session.id.clone());
+            self.active += 1;
+            let tx = self.tx.clone();
+            tokio::spawn(async move {
+                while let Ok(Some(data)) = client.read_output().await {
+                    if tx
+                        .send((session.id.clone(), SessionEvent::Output(data)))
+                        .is_err()
+                    {
+                        return;
+                    }
+                }
+                let _ = tx.send((session.id, SessionEvent::Ended));
+            });
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+//! Reaching sessions on other machines over SSH, addressed as
+//! `[user@]host:session`. tap runs on the far end too: `tap proxy` relays
+//! the connection to a session's socket, and `tap list --json` lists them.
+
+use crate::{Error, Result, Session};
+
+/// Environment variable naming the tap program to run on remote machines,
+/// for when it isn't on the PATH of a non-interactive SSH login.
+pub const REMOTE_TAP_ENV: &str = "TAP_REMOTE_TAP";
+
+/// Split a remote session address into the SSH destination and the
+/// session, which is None for the latest one (`user@host:`). Local
+/// session IDs can't contain `:`, so anything else is local.
+#[must_use]
+pub fn parse_remote(address: &str) -> Option<(&str, Option<&str>)> {
+    let (destination, session) = address.rsplit_once(':')?;
+    if destination.is_empty() {
+        return None;
+    }
+    Some((destination, (!session.is_empty()).then_some(session)))
+}
+
+/// `tap <args>` run on `destination` over SSH, its stdio piped. SSH's own
+/// prompts and errors still reach the terminal. [`REMOTE_TAP_ENV`] goes to
+/// the remote shell as written, so `~` and variables in it expand there.
+pub(crate) fn remote_tap(destination: &str, args: &[&str]) -> tokio::process::Command {
+    let tap = std::env::var(REMOTE_TAP_ENV).unwrap_or_else(|_| "tap".to_string());
+    let remote_command = std::iter::once(tap)
+        .chain(args.iter().copied().map(shell_quote))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut command = tokio::process::Command::new("ssh");
+    command
+        .args(["-T", "--", destination, &remote_command])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::inherit())
+        .kill_on_drop(true);
+    command
+}
+
+/// List the sessions running on `destination`.
+pub async fn list_remote_sessions(destination: &str) -> Result<Vec<Session>> {
+    let output = remote_tap(destination, &["list", "--json"])
+        .stdin(std::process::Stdio::null())
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(Error::Server(format!(
+            "`tap list` on {destination} failed ({})",
+            output.status
+        )));
+    }
+    Ok(serde_json::from_slice(&output.stdout)?)
+}
+
+/// Quote `word` for the remote shell, which SSH hands the command line to.
+fn shell_quote(word: &str) -> String {
+    if !word.is_empty()
+        && word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/' | '='))
+    {
+        return word.to_string();
+    }
+    format!("'{}'", word.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_remote() {
+        assert_eq!(
+            parse_remote("ci@build-box:web"),
+            Some(("ci@build-box", Some("web")))
+        );
+        assert_eq!(parse_remote("build-box:"), Some(("build-box", None)));
+        assert_eq!(parse_remote("web"), None);
+        assert_eq!(parse_remote(":web"), None);
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("calm-fox"), "calm-fox");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("it's here"), r"'it'\''s here'");
+    }
+}
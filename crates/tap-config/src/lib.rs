@@ -5,7 +5,21 @@ use eyre::WrapErr as _;
 const DEFAULT_EDITOR_KEYBIND: &str = "Alt-e";
 const DEFAULT_DETACH_KEYBIND: &str = "Ctrl-\\";
 const DEFAULT_ESCAPE_TIMEOUT_MS: u64 = 50;
+const DEFAULT_PREFIX_TIMEOUT_MS: u64 = 1000;
+const DEFAULT_RESIZE_QUIESCENCE_MS: u64 = 30;
 const DEFAULT_EDITOR: &str = "vi";
+const DEFAULT_TERM: &str = "xterm-256color";
+const DEFAULT_PRE_BUFFER_SECS: u64 = 30;
+const DEFAULT_POST_TRIGGER_SECS: u64 = 300;
+const DEFAULT_CONFIRM_DELAY_SECS: u64 = 5;
+const DEFAULT_SCROLLBACK_LINES: usize = 10000;
+const DEFAULT_ATTACH_TAIL_BYTES: usize = 64 * 1024;
+const DEFAULT_RATE_LIMIT_BYTES_PER_SEC: u64 = 4096;
+const DEFAULT_LOG_MAX_BYTES: u64 = 10 * 1024 * 1024;
+const DEFAULT_LOG_KEEP: usize = 5;
+const DEFAULT_PERSIST_INTERVAL_SECS: u64 = 30;
+const DEFAULT_KEEP_ENDED_SECS: u64 = 3600;
+const DEFAULT_BANNER: &str = "[tap: {shell} · {id}{detached}]";
 
 /// Main configuration structure.
 #[derive(Debug, Clone, Default, serde::Deserialize, serde::Serialize)]
@@ -20,6 +34,428 @@ pub struct Config {
 
     /// Timing configuration.
     pub timing: TimingConfig,
+
+    /// Output forwarding configuration.
+    pub output: OutputConfig,
+
+    /// Terminal type configuration for the child.
+    pub terminal: TerminalConfig,
+
+    /// Where `tap share-output` uploads scrollback.
+    pub share: ShareConfig,
+
+    /// Trigger-based session recording.
+    pub record: RecordConfig,
+
+    /// Checks on input injected by other programs.
+    pub inject: InjectConfig,
+
+    /// Scrollback size limits.
+    pub scrollback: ScrollbackConfig,
+
+    /// Setup typed into new sessions' shells.
+    pub shell: ShellConfig,
+
+    /// Stall detection for `tap wait`.
+    pub watchdog: WatchdogConfig,
+
+    /// Saving sessions so `tap restore` can relaunch them after a reboot.
+    pub persist: PersistConfig,
+
+    /// Bookkeeping for ended sessions.
+    pub sessions: SessionsConfig,
+
+    /// Where sockets, sessions.json and paste buffers live.
+    pub paths: PathsConfig,
+
+    /// Clipboard contents set by apps in sessions.
+    pub clipboard: ClipboardConfig,
+
+    /// Desktop notifications for bells, exits and output patterns.
+    pub notify: NotifyConfig,
+
+    /// Shell commands to run when output matches a regex, keyed by the regex.
+    /// Commands get `$TAP_SESSION`, `$TAP_MATCH` (the matched text) and
+    /// `$TAP_MATCH_<N>` / `$TAP_MATCH_<NAME>` for capture groups.
+    pub triggers: std::collections::BTreeMap<String, String>,
+
+    /// Transformations applied to output on its way to each destination.
+    pub filters: FiltersConfig,
+
+    /// Checks and transformations applied to input on its way to the child.
+    pub input_filters: InputFiltersConfig,
+
+    /// Appending session output to log files.
+    pub logging: LoggingConfig,
+
+    /// Named sets of overrides for some sessions, chosen with
+    /// `tap start --profile` or by the command being run.
+    pub profiles: std::collections::BTreeMap<String, Profile>,
+
+    /// The line printed when a session starts: "off", "minimal", or a
+    /// template using `{shell}`, `{id}`, `{command}` and `{detached}`.
+    pub banner: Banner,
+}
+
+/// Settings applied over the rest of the config for some sessions.
+#[derive(Debug, Clone, Default, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct Profile {
+    /// Glob patterns (`*` and `?`) choosing this profile for sessions
+    /// whose command line matches, e.g. `claude*`. The program's directory
+    /// is left off before matching.
+    pub commands: Vec<String>,
+    /// Options to override, laid out as in the rest of the config.
+    #[serde(flatten)]
+    pub settings: toml::Table,
+}
+
+impl Profile {
+    /// Whether `commands` chooses this profile for `command`.
+    #[must_use]
+    pub fn matches(&self, command: &[String]) -> bool {
+        let Some((program, args)) = command.split_first() else {
+            return false;
+        };
+        let program = program.rsplit('/').next().unwrap_or(program);
+        let line = std::iter::once(program)
+            .chain(args.iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.commands
+            .iter()
+            .any(|pattern| glob_match(pattern, &line))
+    }
+}
+
+/// Match `text` against `pattern`, where `*` matches any run of characters
+/// and `?` any one.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // The last `*` seen and where in `text` it started matching, to retry
+    // with it taking one more character
+    let mut star = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    star = Some((star_p, star_t + 1));
+                    p = star_p + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// The line printed when a session starts.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(from = "String", into = "String")]
+pub enum Banner {
+    /// No banner.
+    Off,
+    /// Just the session ID.
+    Minimal,
+    /// A template; see [`Banner::render`].
+    Template(String),
+}
+
+impl Default for Banner {
+    fn default() -> Self {
+        Self::Template(DEFAULT_BANNER.to_string())
+    }
+}
+
+impl From<String> for Banner {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "off" => Self::Off,
+            "minimal" => Self::Minimal,
+            _ => Self::Template(value),
+        }
+    }
+}
+
+impl From<Banner> for String {
+    fn from(banner: Banner) -> Self {
+        match banner {
+            Banner::Off => "off".to_string(),
+            Banner::Minimal => "minimal".to_string(),
+            Banner::Template(template) => template,
+        }
+    }
+}
+
+impl Banner {
+    /// The banner for a session, `None` if it's off. Templates have
+    /// `{shell}`, `{id}` and `{command}` filled in, and `{detached}` replaced
+    /// by " (detached)" for sessions started in the background.
+    #[must_use]
+    pub fn render(
+        &self,
+        shell: &str,
+        id: &str,
+        command: &[String],
+        detached: bool,
+    ) -> Option<String> {
+        let detached = if detached { " (detached)" } else { "" };
+        match self {
+            Self::Off => None,
+            Self::Minimal => Some(format!("[{id}{detached}]")),
+            Self::Template(template) => Some(
+                template
+                    .replace("{shell}", shell)
+                    .replace("{id}", id)
+                    .replace("{command}", &command.join(" "))
+                    .replace("{detached}", detached),
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// Log every session's output to this file; `{session}` is replaced by
+    /// the session ID. Off if unset; `tap start --log-file` overrides it.
+    pub file: Option<String>,
+    /// Write plain text, without escape sequences or carriage returns.
+    pub strip_ansi: bool,
+    /// Prefix each line with the local time it was written.
+    pub timestamps: bool,
+    /// Rotate the log once it reaches this size: `session.log` becomes
+    /// `session.log.1` and so on.
+    pub max_bytes: u64,
+    /// Rotated logs to keep.
+    pub keep: usize,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            file: None,
+            strip_ansi: false,
+            timestamps: false,
+            max_bytes: DEFAULT_LOG_MAX_BYTES,
+            keep: DEFAULT_LOG_KEEP,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct InputFiltersConfig {
+    /// Filters for keys typed in the terminal the session started in, or
+    /// in one attached with `tap attach`.
+    pub local: Vec<InputFilter>,
+    /// Filters for input other programs send over the socket (`tap inject`,
+    /// `tap exec` and the like). `keybinds` and `csi-u` don't apply here.
+    pub injected: Vec<InputFilter>,
+    /// Input allowed per second by `rate-limit`, in bytes. Bursts up to a
+    /// second's worth are fine.
+    pub rate_limit_bytes_per_sec: u64,
+    /// Regexes whose matches the `deny` filter refuses.
+    pub deny: Vec<String>,
+}
+
+impl Default for InputFiltersConfig {
+    fn default() -> Self {
+        Self {
+            local: vec![InputFilter::Keybinds, InputFilter::CsiU],
+            injected: Vec::new(),
+            rate_limit_bytes_per_sec: DEFAULT_RATE_LIMIT_BYTES_PER_SEC,
+            deny: Vec::new(),
+        }
+    }
+}
+
+/// Built-in input filters, applied in the order listed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InputFilter {
+    /// Detect tap's keybinds (editor, detach, paste).
+    Keybinds,
+    /// Translate kitty keyboard protocol keys to traditional input, when
+    /// tap enabled the protocol in the outer terminal.
+    CsiU,
+    /// Refuse input beyond `input_filters.rate_limit_bytes_per_sec`.
+    RateLimit,
+    /// Refuse input matching `input_filters.deny`.
+    Deny,
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct FiltersConfig {
+    /// Filters for output stored in scrollback (and read back by
+    /// `tap scrollback`, `tap last-output` and the like).
+    pub scrollback: Vec<OutputFilter>,
+    /// Filters for output streamed to API subscribers, `tap exec` and
+    /// triggers.
+    pub broadcast: Vec<OutputFilter>,
+    /// Filters for output shown in terminals: the one the session started
+    /// in and attached clients.
+    pub display: Vec<OutputFilter>,
+    /// Regexes whose matches the `redact` filter hides.
+    pub redact: Vec<String>,
+}
+
+/// Built-in output filters, applied in the order listed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFilter {
+    /// Replace matches of `filters.redact` with `[redacted]`.
+    Redact,
+    /// Keep only the final state of wrapped progress-bar redraws.
+    CollapseProgress,
+    /// Prefix each line with the local time it was written.
+    Timestamp,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct NotifyConfig {
+    /// Send desktop notifications at all (via `osascript` on macOS,
+    /// `notify-send` elsewhere).
+    pub enabled: bool,
+    /// Notify when the app rings the bell.
+    pub bell: bool,
+    /// Notify when the session's child exits.
+    pub exit: bool,
+    /// Regexes matched against output; a match sends a notification with
+    /// the matched text, at most every few seconds per pattern.
+    pub patterns: Vec<String>,
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            bell: true,
+            exit: true,
+            patterns: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct WatchdogConfig {
+    /// Report a child that goes this many seconds without output (and
+    /// doesn't respond to a SIGWINCH nudge) to clients waiting on it.
+    /// Off if unset; `tap wait --stall-after` overrides it.
+    pub stall_secs: Option<u64>,
+    /// Shell command run on each stall, with `$TAP_SESSION` and
+    /// `$TAP_IDLE_SECS` set.
+    pub hook: Option<String>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct SessionsConfig {
+    /// Seconds an ended session stays in sessions.json with its exit code,
+    /// for `tap wait` to report after it's gone. 0 removes it right away.
+    pub keep_ended_secs: u64,
+}
+
+impl Default for SessionsConfig {
+    fn default() -> Self {
+        Self {
+            keep_ended_secs: DEFAULT_KEEP_ENDED_SECS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct ClipboardConfig {
+    /// Keep the last clipboard contents the app set with OSC 52, for
+    /// `tap copy --app`. Off by default, as they may be passwords.
+    pub keep: bool,
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct PathsConfig {
+    /// Directory for all tap state, instead of the runtime directory.
+    /// `$TAP_STATE_DIR` takes precedence.
+    pub state_dir: Option<std::path::PathBuf>,
+    /// Shell command printing the directory to use, for sandboxes where it's
+    /// only known at runtime. Ignored if `state_dir` is set.
+    pub state_dir_command: Option<String>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct PersistConfig {
+    /// Save each session's command, working directory, environment, size
+    /// and screen while it runs.
+    pub enabled: bool,
+    /// Seconds between saves.
+    pub interval_secs: u64,
+    /// Directory for saved sessions. Defaults to the data dir, e.g.
+    /// ~/.local/share/tap/saved.
+    pub dir: Option<std::path::PathBuf>,
+}
+
+impl Default for PersistConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: DEFAULT_PERSIST_INTERVAL_SECS,
+            dir: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct ShellConfig {
+    /// Shell code typed into each new session right after the child starts,
+    /// e.g. functions that use `$TAP_SESSION` or `$TAP_SOCKET` to talk to the
+    /// session they run in. Leading spaces keep it out of most shells' history.
+    /// Only sessions running an interactive shell get it, not e.g. `tap -- vim`.
+    pub init: Option<String>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct ScrollbackConfig {
+    /// Lines of history kept above the screen.
+    pub max_lines: usize,
+    /// Approximate memory cap for the history; fewer lines are kept on wide
+    /// terminals. No cap beyond `max_lines` if unset.
+    pub max_bytes: Option<usize>,
+    /// Keep only the final state of progress bars and spinners redrawn with
+    /// a carriage return, even once they wrap. Output shown to clients is
+    /// unchanged. Same as listing `collapse-progress` in `filters.scrollback`.
+    pub collapse_progress: bool,
+    /// Raw output replayed to a terminal when it attaches, so its own
+    /// scrollback holds real history above the screen. 0 disables.
+    pub attach_tail_bytes: usize,
+}
+
+impl Default for ScrollbackConfig {
+    fn default() -> Self {
+        Self {
+            max_lines: DEFAULT_SCROLLBACK_LINES,
+            max_bytes: None,
+            collapse_progress: false,
+            attach_tail_bytes: DEFAULT_ATTACH_TAIL_BYTES,
+        }
+    }
 }
 
 #[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
@@ -31,6 +467,105 @@ pub struct KeybindConfig {
     /// Keybind to detach from session.
     /// Format: "Ctrl-\\", etc.
     pub detach: String,
+    /// Keybind to paste the system clipboard into the session.
+    /// Unset by default so no key is taken from the inner app.
+    pub paste: Option<String>,
+    /// Keybind to browse, search and copy from the output in copy mode.
+    /// Unset by default, like `paste`.
+    pub copy_mode: Option<String>,
+    /// Prefix key, tmux-style (e.g. "Ctrl-a"). When set, the other keybinds
+    /// only work right after it and input otherwise passes through
+    /// untouched. Pressing the prefix twice sends it to the session.
+    pub prefix: Option<String>,
+    /// Keys that run an action when pressed after the prefix, e.g.
+    /// `d = "detach"`. Keys are single characters or chords like "Ctrl-k".
+    pub prefix_keys: std::collections::BTreeMap<String, KeyAction>,
+    /// More keybinds and their actions, e.g. `"Alt-l" = "inject:ls\n"`.
+    /// They work without the prefix unless one is set.
+    pub custom: std::collections::BTreeMap<String, KeyAction>,
+}
+
+/// An action a keybind can run, written as its name (`detach`,
+/// `copy-mode`...) or as `inject:TEXT` / `run:COMMAND`.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum KeyAction {
+    /// Open the scrollback in the editor.
+    Editor,
+    /// Detach from the session.
+    Detach,
+    /// Paste the system clipboard.
+    Paste,
+    /// Enter copy mode.
+    CopyMode,
+    /// Hang up the session's child, as `tap kill` does.
+    Kill,
+    /// Save the scrollback to a file and show where.
+    ScrollbackToFile,
+    /// Drop the history above the screen.
+    ClearScrollback,
+    /// Type this text into the session.
+    Inject(String),
+    /// Run this command with `sh -c`, with `TAP_SESSION` set.
+    Run(String),
+}
+
+impl std::str::FromStr for KeyAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Quotes are optional around the argument: `inject:"ls\n"`
+        let argument = |arg: &str| {
+            arg.strip_prefix('"')
+                .and_then(|arg| arg.strip_suffix('"'))
+                .filter(|inner| !inner.contains('"'))
+                .unwrap_or(arg)
+                .to_string()
+        };
+        if let Some(text) = s.strip_prefix("inject:") {
+            return Ok(Self::Inject(argument(text)));
+        }
+        if let Some(command) = s.strip_prefix("run:") {
+            return Ok(Self::Run(argument(command)));
+        }
+        match s {
+            "editor" => Ok(Self::Editor),
+            "detach" => Ok(Self::Detach),
+            "paste" => Ok(Self::Paste),
+            "copy-mode" => Ok(Self::CopyMode),
+            "kill" => Ok(Self::Kill),
+            "scrollback-to-file" => Ok(Self::ScrollbackToFile),
+            "clear-scrollback" => Ok(Self::ClearScrollback),
+            _ => Err(format!(
+                "unknown keybind action '{s}' — expected editor, detach, paste, copy-mode, kill, \
+                 scrollback-to-file, clear-scrollback, inject:TEXT or run:COMMAND"
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for KeyAction {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<KeyAction> for String {
+    fn from(action: KeyAction) -> Self {
+        match action {
+            KeyAction::Editor => "editor".to_string(),
+            KeyAction::Detach => "detach".to_string(),
+            KeyAction::Paste => "paste".to_string(),
+            KeyAction::CopyMode => "copy-mode".to_string(),
+            KeyAction::Kill => "kill".to_string(),
+            KeyAction::ScrollbackToFile => "scrollback-to-file".to_string(),
+            KeyAction::ClearScrollback => "clear-scrollback".to_string(),
+            KeyAction::Inject(text) => format!("inject:{text}"),
+            KeyAction::Run(command) => format!("run:{command}"),
+        }
+    }
 }
 
 #[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
@@ -38,6 +573,129 @@ pub struct KeybindConfig {
 pub struct TimingConfig {
     /// Timeout in milliseconds to distinguish ESC from Alt-key sequences.
     pub escape_timeout_ms: u64,
+    /// How long keybinds stay active after the prefix key, in milliseconds.
+    pub prefix_timeout_ms: u64,
+    /// Resize requests are applied once none have arrived for this many
+    /// milliseconds, so a window drag causes one redraw instead of dozens.
+    pub resize_quiescence_ms: u64,
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct OutputConfig {
+    /// When more than this many bytes are waiting to be written to a slow
+    /// terminal, drop them and redraw the latest screen once it catches up.
+    /// Unset means output is never dropped.
+    pub resync_backlog_bytes: Option<usize>,
+    /// Typed into the session after `tap resync` to make the app redraw,
+    /// e.g. "\u000c" (Ctrl-L) for a shell. Apps get SIGWINCH regardless.
+    pub resync_input: Option<String>,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct TerminalConfig {
+    /// TERM for the child. Falls back to the outer terminal's TERM,
+    /// then "xterm-256color".
+    pub term: Option<String>,
+    /// COLORTERM for the child. Falls back to the outer terminal's COLORTERM,
+    /// then to `truecolor` when attached to a terminal known to support it.
+    pub colorterm: Option<String>,
+    /// Print a hint on attach when this terminal's COLORTERM differs from
+    /// the one the session was started with.
+    pub colorterm_hint: bool,
+    /// Run the child in its own session with the PTY as its controlling
+    /// terminal, as a shell expects. When false the child stays in tap's
+    /// process group, so signals sent to a script wrapping tap reach it like
+    /// any other child.
+    pub job_control: bool,
+    /// Set this terminal's title to the session's while attached, putting
+    /// the old one back on detach.
+    pub set_title: bool,
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct InjectConfig {
+    /// Injected input matching one of these regexes is announced to
+    /// watchers and held before it's typed, e.g. `["rm -rf", "push --force"]`.
+    pub risky_patterns: Vec<String>,
+    /// Seconds to hold risky input, giving watchers time to `tap cancel` it.
+    pub confirm_delay_secs: u64,
+}
+
+impl Default for InjectConfig {
+    fn default() -> Self {
+        Self {
+            risky_patterns: Vec::new(),
+            confirm_delay_secs: DEFAULT_CONFIRM_DELAY_SECS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct RecordConfig {
+    /// Start recording when output matches one of these regexes,
+    /// e.g. `["panicked at"]`. Empty disables auto-recording.
+    pub triggers: Vec<String>,
+    /// Seconds of output before the trigger to include in the recording.
+    pub pre_buffer_secs: u64,
+    /// Stop recording this many seconds after the trigger (0 records until
+    /// the session ends).
+    pub post_trigger_secs: u64,
+    /// Directory for recordings. Defaults to the data dir, e.g.
+    /// ~/.local/share/tap/recordings.
+    pub dir: Option<std::path::PathBuf>,
+}
+
+impl Default for RecordConfig {
+    fn default() -> Self {
+        Self {
+            triggers: Vec::new(),
+            pre_buffer_secs: DEFAULT_PRE_BUFFER_SECS,
+            post_trigger_secs: DEFAULT_POST_TRIGGER_SECS,
+            dir: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct ShareConfig {
+    /// Paste service to upload to.
+    pub service: ShareService,
+    /// Upload endpoint for the `url` service. Output is POSTed as the
+    /// multipart form field `file` and the response body is the link.
+    pub url: Option<String>,
+    /// GitHub token for the `gist` service. Falls back to $GITHUB_TOKEN.
+    pub github_token: Option<String>,
+}
+
+/// Paste services `tap share-output` can upload to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShareService {
+    /// https://0x0.st
+    #[default]
+    #[serde(rename = "0x0")]
+    ZeroXZero,
+    /// A secret GitHub gist.
+    Gist,
+    /// A custom endpoint accepting the same uploads as 0x0.st.
+    Url,
+}
+
+impl Default for TerminalConfig {
+    fn default() -> Self {
+        Self {
+            term: None,
+            colorterm: None,
+            colorterm_hint: true,
+            job_control: true,
+            set_title: false,
+        }
+    }
 }
 
 impl Default for KeybindConfig {
@@ -45,6 +703,11 @@ impl Default for KeybindConfig {
         Self {
             editor: DEFAULT_EDITOR_KEYBIND.to_string(),
             detach: DEFAULT_DETACH_KEYBIND.to_string(),
+            paste: None,
+            copy_mode: None,
+            prefix: None,
+            prefix_keys: std::collections::BTreeMap::new(),
+            custom: std::collections::BTreeMap::new(),
         }
     }
 }
@@ -53,6 +716,8 @@ impl Default for TimingConfig {
     fn default() -> Self {
         Self {
             escape_timeout_ms: DEFAULT_ESCAPE_TIMEOUT_MS,
+            prefix_timeout_ms: DEFAULT_PREFIX_TIMEOUT_MS,
+            resize_quiescence_ms: DEFAULT_RESIZE_QUIESCENCE_MS,
         }
     }
 }
@@ -66,8 +731,36 @@ pub fn config_path() -> std::path::PathBuf {
         .join("config.toml")
 }
 
-/// Load configuration from default path, falling back to defaults if not found.
+/// Load configuration from default path, falling back to defaults if not
+/// found, with environment overrides (see [`apply_env`]) on top.
 pub fn load() -> eyre::Result<Config> {
+    apply_env(load_file()?, std::env::vars())
+}
+
+/// Load configuration for a session running `command`: the file, then
+/// `profile` or else the first profile whose `commands` match, then
+/// environment overrides. Also returns the profile applied.
+pub fn load_for_session(
+    profile: Option<&str>,
+    command: &[String],
+) -> eyre::Result<(Config, Option<String>)> {
+    let config = load_file()?;
+    let profile = match profile {
+        Some(name) => Some(name.to_string()),
+        None => config
+            .profiles
+            .iter()
+            .find(|(_, profile)| profile.matches(command))
+            .map(|(name, _)| name.clone()),
+    };
+    let config = match &profile {
+        Some(name) => with_profile(config, name)?,
+        None => config,
+    };
+    Ok((apply_env(config, std::env::vars())?, profile))
+}
+
+fn load_file() -> eyre::Result<Config> {
     let path = config_path();
     if path.exists() {
         let content = std::fs::read_to_string(&path)
@@ -80,6 +773,199 @@ pub fn load() -> eyre::Result<Config> {
     }
 }
 
+/// Apply the settings of profile `name` over the rest of `config`.
+/// Sections are merged key by key; anything else is replaced.
+pub fn with_profile(config: Config, name: &str) -> eyre::Result<Config> {
+    let settings = config
+        .profiles
+        .get(name)
+        .ok_or_else(|| eyre::eyre!("no profile named '{name}' in the config"))?
+        .settings
+        .clone();
+    let mut value = toml::Value::try_from(config).wrap_err("failed to serialize config")?;
+    merge(&mut value, toml::Value::Table(settings));
+    value
+        .try_into()
+        .wrap_err_with(|| format!("invalid settings in profile '{name}'"))
+}
+
+fn merge(base: &mut toml::Value, overlay: toml::Value) {
+    match (base, overlay) {
+        (toml::Value::Table(base), toml::Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Every option that can be set from the environment, as `section.key`,
+/// or just `key` at the top level.
+const ENV_OPTIONS: &[&str] = &[
+    "editor",
+    "keybinds.editor",
+    "keybinds.detach",
+    "keybinds.paste",
+    "keybinds.copy_mode",
+    "keybinds.prefix",
+    "keybinds.prefix_keys",
+    "keybinds.custom",
+    "timing.escape_timeout_ms",
+    "timing.prefix_timeout_ms",
+    "timing.resize_quiescence_ms",
+    "output.resync_backlog_bytes",
+    "output.resync_input",
+    "terminal.term",
+    "terminal.colorterm",
+    "terminal.colorterm_hint",
+    "terminal.job_control",
+    "terminal.set_title",
+    "share.service",
+    "share.url",
+    "share.github_token",
+    "record.triggers",
+    "record.pre_buffer_secs",
+    "record.post_trigger_secs",
+    "record.dir",
+    "inject.risky_patterns",
+    "inject.confirm_delay_secs",
+    "scrollback.max_lines",
+    "scrollback.max_bytes",
+    "scrollback.collapse_progress",
+    "scrollback.attach_tail_bytes",
+    "shell.init",
+    "watchdog.stall_secs",
+    "watchdog.hook",
+    "persist.enabled",
+    "persist.interval_secs",
+    "persist.dir",
+    "sessions.keep_ended_secs",
+    "paths.state_dir",
+    "paths.state_dir_command",
+    "clipboard.keep",
+    "notify.enabled",
+    "notify.bell",
+    "notify.exit",
+    "notify.patterns",
+    "triggers",
+    "filters.scrollback",
+    "filters.broadcast",
+    "filters.display",
+    "filters.redact",
+    "input_filters.local",
+    "input_filters.injected",
+    "input_filters.rate_limit_bytes_per_sec",
+    "input_filters.deny",
+    "logging.file",
+    "logging.strip_ansi",
+    "logging.timestamps",
+    "logging.max_bytes",
+    "logging.keep",
+    "banner",
+];
+
+/// Shorter names for a few common options, beside their full ones.
+const ENV_ALIASES: &[(&str, &str)] = &[
+    ("TAP_ESCAPE_TIMEOUT_MS", "timing.escape_timeout_ms"),
+    ("TAP_SOCKET_DIR", "paths.state_dir"),
+];
+
+/// The option an environment variable sets, if any: `TAP_<SECTION>_<KEY>`
+/// (`TAP_KEYBIND_<KEY>` for keybinds), `TAP_<KEY>` for top-level options, or
+/// one of [`ENV_ALIASES`]. Also returns how specific the name is, lowest
+/// first, for when several set the same option.
+fn env_option(name: &str) -> Option<(&'static str, u8)> {
+    if let Some(&(_, option)) = ENV_ALIASES.iter().find(|(alias, _)| *alias == name) {
+        return Some((option, 1));
+    }
+    let name = name.strip_prefix("TAP_")?.to_ascii_lowercase();
+    ENV_OPTIONS
+        .iter()
+        .find(|option| {
+            let option = option.replace('.', "_");
+            option == name
+                || name
+                    .strip_prefix("keybind_")
+                    .is_some_and(|key| option.strip_prefix("keybinds_") == Some(key))
+        })
+        .map(|&option| (option, 0))
+}
+
+/// Layer environment overrides from `vars` over `config`, e.g.
+/// `TAP_EDITOR=nvim` or `TAP_ESCAPE_TIMEOUT_MS=100`. Values are read as
+/// TOML (`TAP_NOTIFY_PATTERNS='["error"]'`), or as plain strings when that
+/// doesn't fit the option. Empty values and unknown names are ignored.
+pub fn apply_env(
+    config: Config,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> eyre::Result<Config> {
+    let mut overrides: Vec<_> = vars
+        .into_iter()
+        .filter(|(_, value)| !value.is_empty())
+        .filter_map(|(name, value)| {
+            let (option, rank) = env_option(&name)?;
+            Some((option, rank, name, value))
+        })
+        .collect();
+    if overrides.is_empty() {
+        return Ok(config);
+    }
+    // The most specific name wins when several set the same option
+    overrides.sort_by_key(|(option, rank, _, _)| (*option, *rank));
+    overrides.dedup_by_key(|(option, _, _, _)| *option);
+
+    let mut table = toml::Value::try_from(config).wrap_err("failed to serialize config")?;
+    for (option, _, name, value) in overrides {
+        let literal = toml::from_str::<toml::Table>(&format!("value = {value}"))
+            .ok()
+            .and_then(|mut parsed| parsed.remove("value"));
+        let candidates = literal
+            .into_iter()
+            .chain(std::iter::once(toml::Value::String(value.clone())));
+        let mut applied = false;
+        for candidate in candidates {
+            let mut updated = table.clone();
+            set_option(&mut updated, option, candidate);
+            if updated.clone().try_into::<Config>().is_ok() {
+                table = updated;
+                applied = true;
+                break;
+            }
+        }
+        if !applied {
+            eyre::bail!("${name}: invalid value '{value}' for {option}");
+        }
+    }
+    table
+        .try_into()
+        .wrap_err("failed to apply environment overrides")
+}
+
+fn set_option(table: &mut toml::Value, option: &str, value: toml::Value) {
+    let Some(table) = table.as_table_mut() else {
+        return;
+    };
+    match option.split_once('.') {
+        Some((section, key)) => {
+            let section = table
+                .entry(section)
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+            if let Some(section) = section.as_table_mut() {
+                section.insert(key.to_string(), value);
+            }
+        }
+        None => {
+            table.insert(option.to_string(), value);
+        }
+    }
+}
+
 /// Get the effective editor command.
 #[must_use]
 pub fn get_editor(config: &Config) -> String {
@@ -91,21 +977,72 @@ pub fn get_editor(config: &Config) -> String {
         .unwrap_or_else(|| DEFAULT_EDITOR.to_string())
 }
 
+/// Get the directory recordings are written to.
+#[must_use]
+pub fn recordings_dir(config: &Config) -> std::path::PathBuf {
+    config.record.dir.clone().unwrap_or_else(|| {
+        dirs::data_dir()
+            .unwrap_or_else(|| std::path::PathBuf::from("~/.local/share"))
+            .join("tap")
+            .join("recordings")
+    })
+}
+
+/// Get the directory sessions are saved to for `tap restore`.
+#[must_use]
+pub fn saved_sessions_dir(config: &Config) -> std::path::PathBuf {
+    config.persist.dir.clone().unwrap_or_else(|| {
+        dirs::data_dir()
+            .unwrap_or_else(|| std::path::PathBuf::from("~/.local/share"))
+            .join("tap")
+            .join("saved")
+    })
+}
+
+/// Get the TERM to give the child.
+#[must_use]
+pub fn get_term(config: &Config) -> String {
+    config
+        .terminal
+        .term
+        .clone()
+        .or_else(|| std::env::var("TERM").ok().filter(|t| !t.is_empty()))
+        .unwrap_or_else(|| DEFAULT_TERM.to_string())
+}
+
+/// Get the COLORTERM to give the child, if any.
+#[must_use]
+pub fn get_colorterm(config: &Config) -> Option<String> {
+    config
+        .terminal
+        .colorterm
+        .clone()
+        .or_else(|| std::env::var("COLORTERM").ok().filter(|c| !c.is_empty()))
+}
+
 /// Parsed keybind representation.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Keybind {
     Alt(char),
     Ctrl(char),
+    /// A key without modifiers, for use after the prefix.
+    Key(char),
 }
 
 impl Keybind {
-    /// Parse a keybind string like "Alt-e" or "Ctrl-e" or "Ctrl-\\".
+    /// Parse a keybind string like "Alt-e" or "Ctrl-e" or "Ctrl-\\", or a
+    /// single character for a plain key.
     pub fn parse(s: &str) -> eyre::Result<Self> {
         // Handle special case of Ctrl-\ (backslash)
         if s == "Ctrl-\\" || s == "ctrl-\\" {
             return Ok(Keybind::Ctrl('\\'));
         }
 
+        let mut chars = s.chars();
+        if let (Some(key), None) = (chars.next(), chars.next()) {
+            return Ok(Keybind::Key(key));
+        }
+
         let parts: Vec<&str> = s.split('-').collect();
         if parts.len() != 2 {
             eyre::bail!("invalid keybind format '{s}' — expected 'Alt-<key>' or 'Ctrl-<key>'");
@@ -152,6 +1089,11 @@ impl Keybind {
                     None
                 }
             }
+            Keybind::Key(c) => {
+                let mut buf = [0; 4];
+                let encoded = c.encode_utf8(&mut buf).as_bytes();
+                bytes.starts_with(encoded).then_some(encoded.len())
+            }
         }
     }
 
@@ -164,15 +1106,16 @@ impl Keybind {
         const MIN_KITTY_SEQ_LEN: usize = 4;
         const ALT_MODIFIER: u32 = 3;
         const CTRL_MODIFIER: u32 = 5;
+        const NO_MODIFIER: u32 = 1;
 
         // Must start with CSI (ESC [)
         if bytes.len() < MIN_KITTY_SEQ_LEN || bytes[0] != CSI_ESC || bytes[1] != CSI_BRACKET {
             return None;
         }
 
-        // Find the 'u' terminator
-        let u_pos = bytes.iter().position(|&b| b == KITTY_TERMINATOR)?;
-        if u_pos < 3 {
+        // Find the final byte, which must be the 'u' terminator
+        let u_pos = bytes[2..].iter().position(|b| (0x40..=0x7e).contains(b))? + 2;
+        if bytes[u_pos] != KITTY_TERMINATOR || u_pos < 3 {
             return None;
         }
 
@@ -184,8 +1127,7 @@ impl Keybind {
         let modifiers: u32 = parts.get(1).and_then(|s| s.parse().ok()).unwrap_or(1);
 
         let expected_char = match self {
-            Keybind::Alt(c) => *c,
-            Keybind::Ctrl(c) => *c,
+            Keybind::Alt(c) | Keybind::Ctrl(c) | Keybind::Key(c) => *c,
         };
 
         if codepoint != expected_char as u32 {
@@ -196,6 +1138,7 @@ impl Keybind {
         let matches = match self {
             Keybind::Alt(_) => modifiers == ALT_MODIFIER,
             Keybind::Ctrl(_) => modifiers == CTRL_MODIFIER,
+            Keybind::Key(_) => modifiers == NO_MODIFIER,
         };
 
         if matches { Some(u_pos + 1) } else { None }
@@ -218,6 +1161,163 @@ mod tests {
         assert_eq!(kb, Keybind::Ctrl('c'));
     }
 
+    #[test]
+    fn test_keybind_key() {
+        let kb = Keybind::parse("[").unwrap();
+        assert_eq!(kb, Keybind::Key('['));
+        assert_eq!(kb.matches(b"["), Some(1));
+        assert_eq!(kb.matches(b"\x1b[91u"), Some(5));
+        assert_eq!(kb.matches(b"\x1b[91;5u"), None);
+        assert!(Keybind::parse("dd").is_err());
+    }
+
+    #[test]
+    fn test_key_action_parse() {
+        assert_eq!("copy-mode".parse(), Ok(KeyAction::CopyMode));
+        assert_eq!(
+            "inject:\"ls\n\"".parse(),
+            Ok(KeyAction::Inject("ls\n".to_string()))
+        );
+        assert_eq!(
+            "run:notify-send done".parse(),
+            Ok(KeyAction::Run("notify-send done".to_string()))
+        );
+        assert!("explode".parse::<KeyAction>().is_err());
+
+        let config: Config = toml::from_str(
+            r#"
+            [keybinds.custom]
+            "Alt-l" = "inject:ls\n"
+            "Alt-k" = "clear-scrollback"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.keybinds.custom["Alt-l"],
+            KeyAction::Inject("ls\n".to_string())
+        );
+    }
+
+    #[test]
+    fn test_env_option() {
+        assert_eq!(env_option("TAP_EDITOR"), Some(("editor", 0)));
+        assert_eq!(
+            env_option("TAP_KEYBINDS_EDITOR"),
+            Some(("keybinds.editor", 0))
+        );
+        assert_eq!(
+            env_option("TAP_KEYBIND_EDITOR"),
+            Some(("keybinds.editor", 0))
+        );
+        assert_eq!(
+            env_option("TAP_ESCAPE_TIMEOUT_MS"),
+            Some(("timing.escape_timeout_ms", 1))
+        );
+        assert_eq!(env_option("TAP_SOCKET_DIR"), Some(("paths.state_dir", 1)));
+        // Set inside sessions, not options
+        assert_eq!(env_option("TAP_SESSION"), None);
+        assert_eq!(env_option("TAP_SOCKET"), None);
+        // Keys need their section unless aliased
+        assert_eq!(env_option("TAP_ENABLED"), None);
+        assert_eq!(env_option("TAP_MAX_LINES"), None);
+        assert_eq!(env_option("EDITOR"), None);
+    }
+
+    #[test]
+    fn test_env_options_cover_config() {
+        let config = toml::Value::try_from(Config::default()).unwrap();
+        for (key, value) in config.as_table().unwrap() {
+            match value.as_table() {
+                Some(section) if key != "triggers" => {
+                    for field in section.keys() {
+                        let option = format!("{key}.{field}");
+                        assert!(ENV_OPTIONS.contains(&option.as_str()), "{option}");
+                    }
+                }
+                _ => assert!(ENV_OPTIONS.contains(&key.as_str()), "{key}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_apply_env() {
+        let vars = [
+            ("TAP_EDITOR", "nvim -f"),
+            ("TAP_KEYBIND_DETACH", "Ctrl-q"),
+            ("TAP_ESCAPE_TIMEOUT_MS", "100"),
+            ("TAP_TIMING_ESCAPE_TIMEOUT_MS", "200"),
+            ("TAP_NOTIFY_PATTERNS", r#"["error", "done"]"#),
+            ("TAP_BANNER", "minimal"),
+            ("TAP_SHELL_INIT", "42"),
+            ("TAP_PREFIX", ""),
+            ("TAP_SESSION", "calm-fox"),
+        ]
+        .map(|(name, value)| (name.to_string(), value.to_string()));
+        let config = apply_env(Config::default(), vars).unwrap();
+        assert_eq!(config.editor.as_deref(), Some("nvim -f"));
+        assert_eq!(config.keybinds.detach, "Ctrl-q");
+        assert_eq!(config.timing.escape_timeout_ms, 200);
+        assert_eq!(config.notify.patterns, ["error", "done"]);
+        assert_eq!(config.banner, Banner::Minimal);
+        // Read as a string when a number doesn't fit
+        assert_eq!(config.shell.init.as_deref(), Some("42"));
+        assert_eq!(config.keybinds.prefix, None);
+
+        let vars = [("TAP_SCROLLBACK_MAX_LINES".to_string(), "lots".to_string())];
+        let err = apply_env(Config::default(), vars).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("$TAP_SCROLLBACK_MAX_LINES: invalid value")
+        );
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("claude*", "claude --resume"));
+        assert!(glob_match("*vim", "nvim"));
+        assert!(glob_match("py?hon *", "python app.py"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("claude*", "claudia"));
+        assert!(!glob_match("vim", "nvim"));
+    }
+
+    #[test]
+    fn test_profiles() {
+        let config: Config = toml::from_str(
+            r#"
+            editor = "vi"
+
+            [scrollback]
+            max_lines = 500
+            collapse_progress = true
+
+            [profiles.agent]
+            editor = "nvim"
+            scrollback.max_lines = 50000
+
+            [profiles.claude]
+            commands = ["claude*"]
+            keybinds.detach = "Ctrl-q"
+            "#,
+        )
+        .unwrap();
+
+        let agent = with_profile(config.clone(), "agent").unwrap();
+        assert_eq!(agent.editor.as_deref(), Some("nvim"));
+        assert_eq!(agent.scrollback.max_lines, 50000);
+        // Merged, not replaced
+        assert!(agent.scrollback.collapse_progress);
+        assert_eq!(agent.keybinds.detach, DEFAULT_DETACH_KEYBIND);
+        assert!(with_profile(config.clone(), "missing").is_err());
+
+        let claude = &config.profiles["claude"];
+        assert!(claude.matches(&["/usr/local/bin/claude".to_string()]));
+        assert!(!claude.matches(&["bash".to_string()]));
+        let claude = with_profile(config, "claude").unwrap();
+        assert_eq!(claude.keybinds.detach, "Ctrl-q");
+        assert_eq!(claude.editor.as_deref(), Some("vi"));
+    }
+
     #[test]
     fn test_keybind_matches_alt() {
         let kb = Keybind::Alt('e');
@@ -241,6 +1341,23 @@ mod tests {
         assert_eq!(config.timing.escape_timeout_ms, DEFAULT_ESCAPE_TIMEOUT_MS);
     }
 
+    #[test]
+    fn test_term_override() {
+        let mut config = Config::default();
+        config.terminal.term = Some("screen-256color".to_string());
+        config.terminal.colorterm = Some("truecolor".to_string());
+        assert_eq!(get_term(&config), "screen-256color");
+        assert_eq!(get_colorterm(&config).as_deref(), Some("truecolor"));
+    }
+
+    #[test]
+    fn test_share_service_parse() {
+        let config: Config = toml::from_str("[share]\nservice = \"0x0\"").unwrap();
+        assert_eq!(config.share.service, ShareService::ZeroXZero);
+        let config: Config = toml::from_str("[share]\nservice = \"gist\"").unwrap();
+        assert_eq!(config.share.service, ShareService::Gist);
+    }
+
     #[test]
     fn test_ctrl_e_end_to_end() {
         // Simulate what happens with "Ctrl-e" from config
@@ -291,4 +1408,41 @@ mod tests {
         let kb = Keybind::parse(&config.keybinds.detach).unwrap();
         assert_eq!(kb, Keybind::Ctrl('\\'));
     }
+
+    #[test]
+    fn test_banner() {
+        let command = vec!["zsh".to_string(), "-i".to_string()];
+        let config = Config::default();
+        assert_eq!(
+            config
+                .banner
+                .render("zsh", "calm-fox", &command, true)
+                .as_deref(),
+            Some("[tap: zsh · calm-fox (detached)]")
+        );
+
+        let config: Config = toml::from_str("banner = \"off\"").unwrap();
+        assert_eq!(
+            config.banner.render("zsh", "calm-fox", &command, false),
+            None
+        );
+
+        let config: Config = toml::from_str("banner = \"minimal\"").unwrap();
+        assert_eq!(
+            config
+                .banner
+                .render("zsh", "calm-fox", &command, false)
+                .as_deref(),
+            Some("[calm-fox]")
+        );
+
+        let config: Config = toml::from_str("banner = \"{id}: {command}\"").unwrap();
+        assert_eq!(
+            config
+                .banner
+                .render("zsh", "calm-fox", &command, false)
+                .as_deref(),
+            Some("calm-fox: zsh -i")
+        );
+    }
 }
@@ -8,7 +8,7 @@ use std::path::Path;
 /// Known editor types with their argument formats.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EditorKind {
-    /// vim, nvim, vi: `+{line}` before file
+    /// vim, nvim, vi: `+{line}` (or `+call cursor({line},{byte_col})`) before file
     Vim,
     /// VSCode, Cursor: `-g {file}:{line}:{col}`
     VsCode,
@@ -16,7 +16,7 @@ pub enum EditorKind {
     Nano,
     /// emacs: `+{line}:{col}` before file
     Emacs,
-    /// helix: `{file}:{line}`
+    /// helix: `{file}:{line}:{col}`
     Helix,
     /// Unknown editor, no line number support
     Unknown,
@@ -46,17 +46,33 @@ impl EditorKind {
 pub struct Position {
     /// 1-indexed line number
     pub line: usize,
-    /// 1-indexed column number (optional)
+    /// 1-indexed column number in characters (optional)
     pub col: Option<usize>,
+    /// 1-indexed column number in bytes, for editors that count those
+    /// (falls back to `col`)
+    pub byte_col: Option<usize>,
 }
 
 impl Position {
     pub fn new(line: usize, col: Option<usize>) -> Self {
-        Self { line, col }
+        Self {
+            line,
+            col,
+            byte_col: None,
+        }
     }
 
     pub fn line(line: usize) -> Self {
-        Self { line, col: None }
+        Self::new(line, None)
+    }
+
+    /// Set the column in bytes as well.
+    #[must_use]
+    pub fn with_byte_col(self, byte_col: usize) -> Self {
+        Self {
+            byte_col: Some(byte_col),
+            ..self
+        }
     }
 }
 
@@ -79,8 +95,13 @@ pub fn build_editor_args(
 
     match kind {
         EditorKind::Vim => {
-            // vim +42 file.txt
-            (vec![format!("+{}", pos.line)], file_str)
+            // vim +42 file.txt, or vim '+call cursor(42,10)' file.txt with a
+            // column (cursor() counts bytes)
+            let arg = match pos.byte_col.or(pos.col) {
+                Some(col) => format!("+call cursor({},{col})", pos.line),
+                None => format!("+{}", pos.line),
+            };
+            (vec![arg], file_str)
         }
         EditorKind::VsCode => {
             // code -g file.txt:42:10
@@ -107,8 +128,12 @@ pub fn build_editor_args(
             (vec![arg], file_str)
         }
         EditorKind::Helix => {
-            // hx file.txt:42
-            (vec![], format!("{file_str}:{}", pos.line))
+            // hx file.txt:42:10
+            let file = match pos.col {
+                Some(col) => format!("{file_str}:{}:{col}", pos.line),
+                None => format!("{file_str}:{}", pos.line),
+            };
+            (vec![], file)
         }
         EditorKind::Unknown => (vec![], file_str),
     }
@@ -155,6 +180,20 @@ mod tests {
         assert_eq!(file, "/tmp/test.txt");
     }
 
+    #[test]
+    fn test_vim_args_with_column() {
+        let pos = Position::new(42, Some(3)).with_byte_col(7);
+        let (args, _) = build_editor_args("nvim", Path::new("/tmp/test.txt"), Some(pos));
+        assert_eq!(args, vec!["+call cursor(42,7)"]);
+
+        let (args, _) = build_editor_args(
+            "vim",
+            Path::new("/tmp/test.txt"),
+            Some(Position::new(42, Some(3))),
+        );
+        assert_eq!(args, vec!["+call cursor(42,3)"]);
+    }
+
     #[test]
     fn test_vscode_args() {
         let (args, file) = build_editor_args(
@@ -172,6 +211,13 @@ mod tests {
             build_editor_args("hx", Path::new("/tmp/test.txt"), Some(Position::line(42)));
         assert!(args.is_empty());
         assert_eq!(file, "/tmp/test.txt:42");
+
+        let (_, file) = build_editor_args(
+            "hx",
+            Path::new("/tmp/test.txt"),
+            Some(Position::new(42, Some(5)).with_byte_col(9)),
+        );
+        assert_eq!(file, "/tmp/test.txt:42:5");
     }
 
     #[test]
@@ -0,0 +1,51 @@
+//! Plain text of PTY output, for the server's logs and filters and for
+//! clients matching against output.
+
+/// Reduces a stream of output to plain text: escape sequences, carriage
+/// returns and other control characters besides newline and tab are
+/// dropped. Sequences split across chunks are handled.
+#[derive(Debug, Default, Clone)]
+pub struct StripAnsi {
+    state: State,
+}
+
+/// Where the stripper is within an escape sequence.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum State {
+    #[default]
+    Text,
+    /// After ESC.
+    Escape,
+    /// Inside a CSI sequence, up to its final byte.
+    Csi,
+    /// Inside an OSC/DCS/APC string, up to BEL or ST.
+    String,
+    /// ESC inside a string, maybe the start of ST.
+    StringEscape,
+}
+
+impl StripAnsi {
+    /// Append the plain text of a chunk of output to `out`.
+    pub fn strip(&mut self, data: &[u8], out: &mut Vec<u8>) {
+        for &byte in data {
+            self.state = match (self.state, byte) {
+                (State::Text, 0x1b) => State::Escape,
+                (State::Text, b'\n' | b'\t' | 0x20..=0x7e | 0x80..=0xff) => {
+                    out.push(byte);
+                    State::Text
+                }
+                (State::Text, _) => State::Text,
+                (State::Escape, b'[') => State::Csi,
+                (State::Escape, b']' | b'P' | b'_' | b'^' | b'X') => State::String,
+                (State::Escape, _) => State::Text,
+                (State::Csi, 0x40..=0x7e) => State::Text,
+                (State::Csi, _) => State::Csi,
+                (State::String, 0x07) => State::Text,
+                (State::String, 0x1b) => State::StringEscape,
+                (State::String, _) => State::String,
+                (State::StringEscape, b'\\') => State::Text,
+                (State::StringEscape, _) => State::String,
+            };
+        }
+    }
+}
@@ -1,5 +1,7 @@
 //! Shared protocol types for tap terminal sessions.
 
+pub mod ansi;
+
 /// Session metadata stored in sessions.json.
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct Session {
@@ -10,6 +12,182 @@ pub struct Session {
     /// Whether a client is currently attached to this session.
     #[serde(default)]
     pub attached: bool,
+    /// User-assigned tags for selecting sessions in bulk.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// TERM the child was started with.
+    #[serde(default)]
+    pub term: Option<String>,
+    /// COLORTERM the child was started with.
+    #[serde(default)]
+    pub colorterm: Option<String>,
+    /// The config profile the session was started with.
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// The session this one was started inside of, if any.
+    #[serde(default)]
+    pub parent: Option<String>,
+    /// Notes added with `tap note`, oldest first.
+    #[serde(default)]
+    pub notes: Vec<Note>,
+    /// Working directory last reported by the shell (OSC 7).
+    #[serde(default)]
+    pub cwd: Option<String>,
+    /// Window title the app last set (OSC 0/2).
+    #[serde(default)]
+    pub title: Option<String>,
+    /// When the session ended (RFC 3339), for ended sessions kept for
+    /// `[sessions] keep_ended_secs`.
+    #[serde(default)]
+    pub ended: Option<String>,
+    /// The command's exit code, once the session has ended.
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+}
+
+/// A timestamped note attached to a session.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Note {
+    /// When the note was added (RFC 3339).
+    pub time: String,
+    pub text: String,
+}
+
+/// Cursor position reported by a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct CursorPosition {
+    /// 0-indexed screen row.
+    pub row: usize,
+    /// 0-indexed column in terminal cells.
+    pub col: usize,
+    /// 0-indexed column in characters.
+    pub char_col: usize,
+}
+
+/// CPU and wall-clock time used by a session's command, as returned for
+/// [`Request::GetTimes`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct SessionTimes {
+    /// Time since the command started, or until it exited.
+    pub wall_ms: u64,
+    /// CPU time in user mode, including child processes waited for.
+    pub user_ms: u64,
+    /// CPU time in the kernel, including child processes waited for.
+    pub sys_ms: u64,
+    /// Whether the command has exited, making the times final.
+    pub exited: bool,
+}
+
+/// The visible screen of a session, as returned for [`Request::GetScreen`].
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Screen {
+    pub rows: u16,
+    pub cols: u16,
+    /// Cells row by row, `cols` to a row.
+    pub cells: Vec<Vec<ScreenCell>>,
+    /// 0-indexed cursor row.
+    pub cursor_row: u16,
+    /// 0-indexed cursor column in cells.
+    pub cursor_col: u16,
+    /// Whether the app is showing the alternate screen.
+    pub alternate_screen: bool,
+    /// Window title the app last set.
+    pub title: String,
+}
+
+/// One cell of a [`Screen`]. Attributes that are off and default colors
+/// are left out of the JSON.
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ScreenCell {
+    /// Text in the cell (empty for blank cells and the second half of wide
+    /// characters).
+    pub text: String,
+    #[serde(default, skip_serializing_if = "Color::is_default")]
+    pub fg: Color,
+    #[serde(default, skip_serializing_if = "Color::is_default")]
+    pub bg: Color,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub bold: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub italic: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub underline: bool,
+    /// Foreground and background swapped, as for highlighted items.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub inverse: bool,
+    /// The cell holds a character two cells wide.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub wide: bool,
+}
+
+/// Color of a [`ScreenCell`]'s foreground or background.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Color {
+    /// The terminal's default color.
+    #[default]
+    Default,
+    /// An entry in the 256-color palette.
+    Indexed(u8),
+    /// A true color value.
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    #[must_use]
+    pub fn is_default(&self) -> bool {
+        *self == Self::Default
+    }
+}
+
+/// Version of the protocol spoken over session sockets. Minor versions
+/// only add requests, responses and optional fields; anything an older
+/// peer would misread bumps the major version.
+pub const PROTOCOL_VERSION: ProtocolVersion = ProtocolVersion { major: 1, minor: 2 };
+
+/// A `major.minor` protocol version, exchanged with `Hello`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ProtocolVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl ProtocolVersion {
+    /// Parse `major.minor`.
+    #[must_use]
+    pub fn parse(version: &str) -> Option<Self> {
+        let (major, minor) = version.split_once('.')?;
+        Some(Self {
+            major: major.parse().ok()?,
+            minor: minor.parse().ok()?,
+        })
+    }
+
+    /// Whether a server speaking `server` understands everything a client
+    /// speaking this version may send: the same major version, and at
+    /// least this minor one.
+    #[must_use]
+    pub fn is_served_by(self, server: Self) -> bool {
+        server.major == self.major && server.minor >= self.minor
+    }
+}
+
+impl std::fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// A request or response with the ID that pairs them. Clients number
+/// their requests and servers echo the number on every reply, so replies
+/// can be told apart from output streamed on the same connection. Streamed
+/// messages, and requests sent while attached, have no ID.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct Envelope<T> {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<u64>,
+    #[serde(flatten)]
+    pub message: T,
 }
 
 /// Client requests to the server.
@@ -17,26 +195,178 @@ pub struct Session {
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum Request {
     /// Get the last N lines from scrollback buffer.
-    GetScrollback { lines: Option<usize> },
+    GetScrollback {
+        lines: Option<usize>,
+        /// Only return output since this mark (see `Mark`).
+        #[serde(default)]
+        since_mark: Option<String>,
+    },
+    /// Insert an invisible bookmark into the output history.
+    Mark { label: String },
+    /// Add a timestamped note to the session's entry in sessions.json, and
+    /// a marker to any recording in progress.
+    Note { text: String },
+    /// Give the session a new ID, moving its socket and sessions.json entry.
+    /// Programs in the session keep the old `$TAP_SESSION`.
+    Rename { name: String },
+    /// Sent first by clients to exchange protocol versions (see
+    /// [`PROTOCOL_VERSION`]); answered with `Hello`. Connections over
+    /// TCP must send it first, with the session's token.
+    Hello {
+        version: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        token: Option<String>,
+    },
+    /// Check that the server is responsive; answered with `Pong`. Attached
+    /// clients send it as a heartbeat instead, and get no answer.
+    Ping,
+    /// Add this session back to sessions.json if its entry is missing, e.g.
+    /// after the file was lost or damaged.
+    Reregister,
     /// Get current cursor position.
     GetCursor,
-    /// Inject input into the PTY.
+    /// Inject input into the PTY. Input matching the session's risky
+    /// patterns is held and announced with `PendingInput` first.
     Inject { data: String },
+    /// Inject keys by name (`Enter`, `C-c`, `M-x`, `F5`, `Up`...), encoded
+    /// for the inner app's cursor key mode and kitty keyboard flags. Names
+    /// that aren't keys are typed as literal text.
+    SendKeys { keys: Vec<String> },
+    /// Stream `Event`s of the given kinds (all kinds if empty); answered
+    /// with `Ok` first.
+    SubscribeEvents {
+        #[serde(default)]
+        kinds: Vec<EventKind>,
+    },
+    /// Get the output of the `nth` most recently finished command (1 for the
+    /// last), as split by shell integration marks (OSC 133).
+    GetCommandOutput {
+        #[serde(default = "default_nth")]
+        nth: usize,
+    },
+    /// Inject input and answer with the output produced from then until
+    /// `until` (`Captured`), all in one round trip. With `timeout_ms`, gives
+    /// up with an error if `until` hasn't happened by then.
+    InjectAndCapture {
+        data: String,
+        until: CaptureEnd,
+        #[serde(default)]
+        timeout_ms: Option<u64>,
+    },
+    /// Type a command into the session's shell and answer with its output
+    /// and exit status (`CommandResult`) once it finishes. Held like
+    /// `Inject` if risky. With `interactive`, `InputPrompt` is sent
+    /// meanwhile whenever the command seems to wait for input.
+    RunCommand {
+        command: String,
+        #[serde(default)]
+        timeout_ms: Option<u64>,
+        #[serde(default)]
+        interactive: bool,
+    },
+    /// Cancel held injected input by ID, or all of it.
+    CancelInput {
+        #[serde(default)]
+        id: Option<u64>,
+    },
     /// Get terminal size.
     GetSize,
-    /// Subscribe to live output.
-    Subscribe,
+    /// Get the window title the app last set.
+    GetTitle,
+    /// Get the session's working directory.
+    GetCwd,
+    /// Get the CPU and wall-clock time used by the session's command.
+    GetTimes,
+    /// Subscribe to live output. With `from_seq` (e.g. from `Injected`),
+    /// output broadcast since that sequence number is sent first.
+    Subscribe {
+        #[serde(default)]
+        from_seq: Option<u64>,
+    },
     /// Attach to the session (take over stdin/stdout).
     Attach {
         /// Terminal rows.
         rows: u16,
         /// Terminal columns.
         cols: u16,
+        /// Keep the PTY at its current size instead of resizing to the client.
+        #[serde(default)]
+        no_resize: bool,
+        /// Only watch: input from this client is dropped and it doesn't
+        /// resize the PTY.
+        #[serde(default)]
+        read_only: bool,
+        /// Detach every other attached client first (e.g. one whose
+        /// terminal died), sending each [`Response::Detached`].
+        #[serde(default)]
+        detach_others: bool,
     },
     /// Send input from attached client to PTY.
     Input { data: Vec<u8> },
-    /// Resize the PTY from attached client.
+    /// Resize the PTY from attached client. From any other client, answered
+    /// with the size applied, which is no bigger than the smallest attached
+    /// terminal that sizes the session.
     Resize { rows: u16, cols: u16 },
+    /// Get the environment of the child process.
+    GetEnv,
+    /// Render the visible screen, with colors, as an SVG image.
+    Screenshot,
+    /// Get the visible screen as a grid of cells with their attributes.
+    GetScreen,
+    /// Switch back to the main screen when an app left the session stuck on
+    /// the alternate screen; attached clients switch too.
+    ResetAltScreen,
+    /// Rebuild the emulated screen from scratch and ask the app to redraw,
+    /// when output it couldn't follow left it wrong.
+    ResyncScreen,
+    /// Drop the scrollback history above the screen.
+    ClearScrollback,
+    /// Get the history and screen as copy mode shows them, a line per row.
+    GetCopyText,
+    /// Have the screen drawn from scratch for this attached client, e.g.
+    /// after it covered the screen with copy mode.
+    Redraw,
+    /// Store text in a named paste buffer shared by all sessions.
+    SetBuffer { name: String, data: String },
+    /// Get the contents of a named paste buffer.
+    GetBuffer { name: String },
+    /// Get what the app last copied with OSC 52 (kept with `[clipboard] keep`).
+    GetClipboard,
+    /// Inject the contents of a named paste buffer into the PTY.
+    PasteBuffer { name: String },
+    /// Paste text into the PTY, in bracketed paste markers if the app
+    /// enabled that mode (`CSI ?2004h`).
+    Paste { data: String },
+    /// Signal the child (its whole process group under job control). Without
+    /// a signal, the child is hung up and killed if it's still running after
+    /// a grace period.
+    Kill {
+        #[serde(default)]
+        signal: Option<i32>,
+    },
+    /// Block until the child process exits; answered with `SessionEnded`.
+    /// With `stall_secs` (or `watchdog.stall_secs` configured), `Stalled` is
+    /// sent meanwhile whenever the child goes that long without output.
+    Wait {
+        #[serde(default)]
+        stall_secs: Option<u64>,
+    },
+    /// Open the scrollback in the editor, as the editor keybind does.
+    /// Without a local terminal, the scrollback is written to a file instead.
+    OpenEditor,
+    /// Disconnect all attached and subscribed clients, leaving the child running.
+    DetachClients,
+    /// Record all further output to an asciicast v2 file at `path`, which
+    /// should be absolute.
+    StartRecording { path: String },
+    /// Stop the recording started with `StartRecording`.
+    StopRecording,
+    /// Hand the session over to the tap process listening on the Unix
+    /// socket at `to` (see `tap receive`). This server exits afterwards.
+    Migrate { to: String },
+    /// Replace the server with the tap binary at `exe`, keeping the session
+    /// (see `tap self-upgrade-handoff`).
+    Upgrade { exe: String },
 }
 
 /// Server responses.
@@ -46,42 +376,362 @@ pub enum Response {
     /// Scrollback buffer content.
     Scrollback { content: String },
     /// Cursor position.
-    Cursor { row: usize, col: usize },
+    Cursor {
+        row: usize,
+        /// Column in terminal cells.
+        col: usize,
+        /// Column as a character index into the line (differs from `col`
+        /// when the line contains wide characters).
+        #[serde(default)]
+        char_col: usize,
+    },
     /// Terminal size.
     Size { rows: u16, cols: u16 },
+    /// The window title the app last set (empty if none).
+    Title { title: String },
+    /// The session's working directory.
+    Cwd { cwd: String },
+    /// CPU and wall-clock time used so far.
+    Times { times: SessionTimes },
     /// Live output data (for subscribed clients).
     Output { data: Vec<u8> },
     /// Subscription confirmed.
     Subscribed,
+    /// Input was queued for the PTY (`Inject`, `SendKeys`). Output it causes
+    /// is at or after output sequence number `seq`; subscribe from there to
+    /// be sure of seeing it.
+    Injected { seq: u64 },
+    /// The PTY was resized (for subscribed clients).
+    Resized { rows: u16, cols: u16 },
+    /// Risky injected input is held and will be typed after `delay_ms`
+    /// unless cancelled (for subscribed and attached clients).
+    PendingInput {
+        id: u64,
+        data: String,
+        delay_ms: u64,
+    },
+    /// Number of held inputs cancelled.
+    Cancelled { count: usize },
     /// Attach confirmed - client now owns stdin/stdout.
     Attached {
         /// Current scrollback content for initial display.
         scrollback: String,
+        /// Cursor shape the app selected, as a DECSCUSR parameter (0 for
+        /// the terminal's default).
+        #[serde(default)]
+        cursor_style: u8,
+        /// Recent raw output, for the attaching terminal to replay into its
+        /// own scrollback before drawing `scrollback`. Empty on the
+        /// alternate screen.
+        #[serde(default)]
+        tail: Vec<u8>,
+        /// Window title the app last set.
+        #[serde(default)]
+        title: String,
+    },
+    /// Child process environment.
+    Env {
+        vars: std::collections::BTreeMap<String, String>,
+    },
+    /// Rendered screen image.
+    Screenshot { svg: String },
+    /// The visible screen, cell by cell.
+    Screen { screen: Screen },
+    /// History and screen for copy mode, with the cursor's line and column.
+    CopyText {
+        lines: Vec<String>,
+        line: usize,
+        col: usize,
     },
-    /// Session has ended (child process exited).
-    SessionEnded { exit_code: i32 },
+    /// Scrollback written to a file (editor requested without a terminal).
+    ScrollbackFile { path: String },
+    /// Named paste buffer contents.
+    Buffer { data: String },
+    /// What the app last copied with OSC 52.
+    Clipboard { data: String },
+    /// Recording stopped; the file it was written to.
+    RecordingStopped { path: String },
+    /// Something happened in the session (after `SubscribeEvents`).
+    Event { event: Event },
+    /// One command's output, and its exit code if the shell reported it.
+    CommandOutput {
+        output: String,
+        exit_code: Option<i32>,
+    },
+    /// Output captured by `InjectAndCapture`, as it reads on screen.
+    Captured { output: String },
+    /// Output and exit status of a `RunCommand`.
+    CommandResult { output: String, exit_code: i32 },
+    /// An interactive `RunCommand` stopped at what looks like a prompt for
+    /// input, on the line `prompt`. `echo` is false when the terminal isn't
+    /// echoing input, as when reading a password.
+    InputPrompt { prompt: String, echo: bool },
+    /// Session has ended (child process exited). No exit code if it
+    /// couldn't be observed, as after a migration.
+    SessionEnded { exit_code: Option<i32> },
+    /// This attached client was detached by the server, e.g. because
+    /// another attached with `detach_others`. The connection closes next.
+    Detached { reason: String },
+    /// The child produced no output for `idle_secs`, even after a nudge,
+    /// while a client was waiting on it.
+    Stalled { idle_secs: u64 },
+    /// Answer to `Hello`, with the server's protocol version.
+    Hello { version: String },
+    /// Answer to `Ping`.
+    Pong,
     /// Success.
     Ok,
     /// Error.
-    Error { message: String },
+    Error {
+        /// What kind of failure this is, for callers to match on.
+        #[serde(default)]
+        code: ErrorCode,
+        message: String,
+    },
+}
+
+/// Something that happened in a session, streamed after
+/// [`Request::SubscribeEvents`].
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Event {
+    /// The app rang the terminal bell.
+    Bell,
+    /// The app set the window title (OSC 0/2).
+    Title { title: String },
+    /// The child exited, with its exit code if it could be observed.
+    Exit { exit_code: Option<i32> },
+    /// The PTY was resized.
+    Resize { rows: u16, cols: u16 },
+    /// A client attached; `clients` are attached now.
+    Attach { clients: usize },
+    /// A client detached; `clients` are still attached.
+    Detach { clients: usize },
+    /// The app switched to (`active`) or back from the alternate screen.
+    AltScreen { active: bool },
+    /// The scrollback editor couldn't be started or exited with an error.
+    EditorFailed { command: String, message: String },
+}
+
+/// Kinds of [`Event`], for choosing which to subscribe to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Bell,
+    Title,
+    Exit,
+    Resize,
+    Attach,
+    Detach,
+    AltScreen,
+    EditorFailed,
+}
+
+impl Event {
+    #[must_use]
+    pub fn kind(&self) -> EventKind {
+        match self {
+            Self::Bell => EventKind::Bell,
+            Self::Title { .. } => EventKind::Title,
+            Self::Exit { .. } => EventKind::Exit,
+            Self::Resize { .. } => EventKind::Resize,
+            Self::Attach { .. } => EventKind::Attach,
+            Self::Detach { .. } => EventKind::Detach,
+            Self::AltScreen { .. } => EventKind::AltScreen,
+            Self::EditorFailed { .. } => EventKind::EditorFailed,
+        }
+    }
+}
+
+const fn default_nth() -> usize {
+    1
+}
+
+/// When [`Request::InjectAndCapture`] stops capturing.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum CaptureEnd {
+    /// Once the captured text matches this regex; output ends with the match.
+    /// A match is looked for in the lines each new chunk of output touches.
+    Regex { pattern: String },
+    /// When the shell marks its next prompt (OSC 133;A, as emitted by shell
+    /// integration); output ends before the mark.
+    PromptMark,
+    /// After this many milliseconds.
+    Timeout { ms: u64 },
+}
+
+/// Kind of failure reported in [`Response::Error`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// The session's child has exited.
+    SessionEnded,
+    /// The connection is already attached.
+    AlreadyAttached,
+    /// The request needs an attached client.
+    NotAttached,
+    /// The request isn't supported by this server or platform.
+    Unsupported,
+    /// Too many requests; try again later.
+    RateLimited,
+    /// The client isn't allowed to make this request.
+    Denied,
+    /// Any other failure; see the message. Codes added by newer servers
+    /// read as this.
+    #[default]
+    #[serde(other)]
+    Other,
+}
+
+/// How often attached clients send `Ping`. The server drops an attached
+/// client that has pinged before but then goes quiet for a few intervals,
+/// so a vanished client doesn't hold the session forever.
+pub const HEARTBEAT_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Environment variable holding the token for sessions served over TCP,
+/// on both ends. Sessions don't pass it on to their commands.
+pub const AUTH_TOKEN_ENV: &str = "TAP_AUTH_TOKEN";
+
+/// Environment variable that relocates all tap state to one directory.
+pub const STATE_DIR_ENV: &str = "TAP_STATE_DIR";
+
+/// Decides where tap keeps its state: session sockets, the sessions index
+/// and paste buffers. Install one with [`set_resolver`] to move all of it,
+/// e.g. into a sandbox's shared directory or a test fixture's temp dir.
+pub trait Resolver: Send + Sync {
+    /// Directory holding sockets, the sessions index and paste buffers.
+    fn socket_dir(&self) -> std::path::PathBuf;
+
+    /// Socket for a session ID.
+    fn socket_path(&self, session_id: &str) -> std::path::PathBuf {
+        self.socket_dir().join(format!("{session_id}.sock"))
+    }
+
+    /// The sessions index.
+    fn sessions_file(&self) -> std::path::PathBuf {
+        self.socket_dir().join("sessions.json")
+    }
+
+    /// Directory holding named paste buffers.
+    fn buffers_dir(&self) -> std::path::PathBuf {
+        self.socket_dir().join("buffers")
+    }
+}
+
+/// `$TAP_STATE_DIR` if set, else the runtime directory, `~/.tap` or
+/// `/tmp/tap`, whichever exists first.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultResolver;
+
+impl Resolver for DefaultResolver {
+    fn socket_dir(&self) -> std::path::PathBuf {
+        if let Some(dir) = std::env::var_os(STATE_DIR_ENV).filter(|dir| !dir.is_empty()) {
+            return std::path::PathBuf::from(dir);
+        }
+        dirs::runtime_dir()
+            .or_else(|| dirs::home_dir().map(|h| h.join(".tap")))
+            .unwrap_or_else(|| std::path::PathBuf::from("/tmp/tap"))
+    }
+}
+
+/// Everything under one fixed directory.
+#[derive(Debug, Clone)]
+pub struct DirResolver(pub std::path::PathBuf);
+
+impl DirResolver {
+    /// Ask a shell command (e.g. a sandbox helper or daemon client) for the
+    /// directory: the first line it prints.
+    pub fn from_command(command: &str) -> std::io::Result<Self> {
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .stderr(std::process::Stdio::inherit())
+            .output()?;
+        if !output.status.success() {
+            return Err(std::io::Error::other(format!(
+                "`{command}` failed with {}",
+                output.status
+            )));
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let dir = stdout.lines().next().map(str::trim).unwrap_or_default();
+        if dir.is_empty() {
+            return Err(std::io::Error::other(format!(
+                "`{command}` printed no directory"
+            )));
+        }
+        Ok(Self(std::path::PathBuf::from(dir)))
+    }
+}
+
+impl Resolver for DirResolver {
+    fn socket_dir(&self) -> std::path::PathBuf {
+        self.0.clone()
+    }
+}
+
+static RESOLVER: std::sync::RwLock<Option<std::sync::Arc<dyn Resolver>>> =
+    std::sync::RwLock::new(None);
+
+/// Use `resolver` for every path lookup in this process from now on.
+pub fn set_resolver(resolver: impl Resolver + 'static) {
+    *RESOLVER
+        .write()
+        .unwrap_or_else(std::sync::PoisonError::into_inner) = Some(std::sync::Arc::new(resolver));
+}
+
+/// The installed resolver, or [`DefaultResolver`].
+#[must_use]
+pub fn resolver() -> std::sync::Arc<dyn Resolver> {
+    RESOLVER
+        .read()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .clone()
+        .unwrap_or_else(|| std::sync::Arc::new(DefaultResolver))
 }
 
 /// Get the socket directory path.
 #[must_use]
 pub fn socket_dir() -> std::path::PathBuf {
-    dirs::runtime_dir()
-        .or_else(|| dirs::home_dir().map(|h| h.join(".tap")))
-        .unwrap_or_else(|| std::path::PathBuf::from("/tmp/tap"))
+    resolver().socket_dir()
 }
 
 /// Get socket path for a session ID.
 #[must_use]
 pub fn socket_path(session_id: &str) -> std::path::PathBuf {
-    socket_dir().join(format!("{session_id}.sock"))
+    resolver().socket_path(session_id)
+}
+
+/// Check that `name` can be used as a session ID: it names the socket file,
+/// so it must be a plain file name.
+pub fn validate_session_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("session name is empty".to_string());
+    }
+    if name.starts_with('.') || name.starts_with('-') {
+        return Err(format!(
+            "session name '{name}' can't start with '{}'",
+            &name[..1]
+        ));
+    }
+    if let Some(c) = name
+        .chars()
+        .find(|c| !(c.is_alphanumeric() || matches!(c, '-' | '_' | '.')))
+    {
+        return Err(format!("session name '{name}' can't contain {c:?}"));
+    }
+    Ok(())
 }
 
 /// Get the sessions index file path.
 #[must_use]
 pub fn sessions_file() -> std::path::PathBuf {
-    socket_dir().join("sessions.json")
+    resolver().sessions_file()
+}
+
+/// Get the directory holding named paste buffers.
+#[must_use]
+pub fn buffers_dir() -> std::path::PathBuf {
+    resolver().buffers_dir()
 }
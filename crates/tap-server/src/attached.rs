@@ -0,0 +1,164 @@
+//! Remote clients attached to the session.
+//!
+//! Any number of clients may attach at once. Each gets its own output
+//! channel, and the PTY is sized to the smallest of the clients that asked
+//! to resize it, so every attached terminal can show the whole screen.
+
+/// Sender for PTY output to one attached client.
+pub type OutputSender = tokio::sync::mpsc::UnboundedSender<Vec<u8>>;
+
+struct AttachedClient {
+    output_tx: OutputSender,
+    /// Terminal size, or `None` for clients that attached without resizing.
+    size: Option<(u16, u16)>,
+}
+
+/// All currently attached clients.
+#[derive(Default)]
+pub struct AttachedClients {
+    next_id: u64,
+    clients: std::collections::HashMap<u64, AttachedClient>,
+}
+
+impl AttachedClients {
+    /// Register a client, returning its ID.
+    pub fn add(&mut self, output_tx: OutputSender, size: Option<(u16, u16)>) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.clients.insert(id, AttachedClient { output_tx, size });
+        crate::events::emit(tap_protocol::Event::Attach {
+            clients: self.clients.len(),
+        });
+        id
+    }
+
+    /// Forget a client, closing its output channel.
+    pub fn remove(&mut self, id: u64) {
+        if self.clients.remove(&id).is_some() {
+            crate::events::emit(tap_protocol::Event::Detach {
+                clients: self.clients.len(),
+            });
+        }
+    }
+
+    /// Forget every client, closing their output channels. Returns how
+    /// many there were.
+    pub fn remove_all(&mut self) -> usize {
+        let ids: Vec<u64> = self.clients.keys().copied().collect();
+        for &id in &ids {
+            self.remove(id);
+        }
+        ids.len()
+    }
+
+    /// Record a client's new terminal size. Clients that attached without
+    /// resizing keep not taking part in sizing.
+    pub fn set_size(&mut self, id: u64, rows: u16, cols: u16) {
+        if let Some(client) = self.clients.get_mut(&id)
+            && client.size.is_some()
+        {
+            client.size = Some((rows, cols));
+        }
+    }
+
+    /// Smallest rows and columns across clients that size the PTY.
+    pub fn smallest_size(&self) -> Option<(u16, u16)> {
+        self.clients
+            .values()
+            .filter_map(|client| client.size)
+            .reduce(|(rows, cols), (r, c)| (rows.min(r), cols.min(c)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.is_empty()
+    }
+
+    /// Send output to every attached client.
+    pub fn broadcast(&self, data: &[u8]) {
+        for client in self.clients.values() {
+            let _ = client.output_tx.send(data.to_vec());
+        }
+    }
+
+    /// Send output to one client.
+    pub fn send(&self, id: u64, data: Vec<u8>) {
+        if let Some(client) = self.clients.get(&id) {
+            let _ = client.output_tx.send(data);
+        }
+    }
+
+    /// Resize the PTY to fit every client that sizes it.
+    pub fn apply_smallest_size(&self) {
+        if let Some((rows, cols)) = self.smallest_size() {
+            crate::resize::request(rows, cols);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sender() -> OutputSender {
+        tokio::sync::mpsc::unbounded_channel().0
+    }
+
+    #[test]
+    fn test_smallest_size() {
+        let mut clients = AttachedClients::default();
+        assert_eq!(clients.smallest_size(), None);
+
+        let big = clients.add(sender(), Some((50, 200)));
+        let small = clients.add(sender(), Some((30, 220)));
+        clients.add(sender(), None);
+        assert_eq!(clients.smallest_size(), Some((30, 200)));
+
+        clients.set_size(big, 60, 100);
+        assert_eq!(clients.smallest_size(), Some((30, 100)));
+
+        clients.remove(small);
+        assert_eq!(clients.smallest_size(), Some((60, 100)));
+        assert!(!clients.is_empty());
+    }
+
+    #[test]
+    fn test_no_resize_clients_stay_out_of_sizing() {
+        let mut clients = AttachedClients::default();
+        let viewer = clients.add(sender(), None);
+        clients.set_size(viewer, 10, 10);
+        assert_eq!(clients.smallest_size(), None);
+    }
+
+    #[test]
+    fn test_broadcast_reaches_every_client() {
+        let mut clients = AttachedClients::default();
+        let (tx_a, mut rx_a) = tokio::sync::mpsc::unbounded_channel();
+        let (tx_b, mut rx_b) = tokio::sync::mpsc::unbounded_channel();
+        clients.add(tx_a, None);
+        let b = clients.add(tx_b, None);
+
+        clients.broadcast(b"hi");
+        assert_eq!(rx_a.try_recv().unwrap(), b"hi");
+        assert_eq!(rx_b.try_recv().unwrap(), b"hi");
+
+        clients.remove(b);
+        assert!(rx_b.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_remove_all() {
+        let mut clients = AttachedClients::default();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        clients.add(tx, Some((24, 80)));
+        clients.add(sender(), None);
+
+        assert_eq!(clients.remove_all(), 2);
+        assert!(clients.is_empty());
+        assert_eq!(clients.smallest_size(), None);
+        // The client's output loop sees its channel close
+        assert!(matches!(
+            rx.try_recv(),
+            Err(tokio::sync::mpsc::error::TryRecvError::Disconnected)
+        ));
+    }
+}
@@ -0,0 +1,247 @@
+//! Terminal emulator backends.
+//!
+//! [`crate::scrollback::ScrollbackBuffer`] talks to the emulator only through
+//! [`TerminalBackend`], so alternatives to vt100 (e.g. wezterm-term or
+//! alacritty_terminal, for scrollback row access, rewrap or hyperlinks) can be
+//! added behind a feature flag without touching callers.
+
+/// Color of a cell's foreground or background.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CellColor {
+    /// The terminal's default color.
+    #[default]
+    Default,
+    /// An entry in the 256-color palette.
+    Indexed(u8),
+    /// A true color value.
+    Rgb(u8, u8, u8),
+}
+
+/// A screen cell with its text attributes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StyledCell {
+    /// Text in the cell (empty for blank cells and wide-char continuations).
+    pub contents: String,
+    pub fg: CellColor,
+    pub bg: CellColor,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub inverse: bool,
+    /// The cell holds a character two cells wide.
+    pub wide: bool,
+}
+
+impl From<CellColor> for tap_protocol::Color {
+    fn from(color: CellColor) -> Self {
+        match color {
+            CellColor::Default => Self::Default,
+            CellColor::Indexed(idx) => Self::Indexed(idx),
+            CellColor::Rgb(r, g, b) => Self::Rgb(r, g, b),
+        }
+    }
+}
+
+impl From<StyledCell> for tap_protocol::ScreenCell {
+    fn from(cell: StyledCell) -> Self {
+        Self {
+            text: cell.contents,
+            fg: cell.fg.into(),
+            bg: cell.bg.into(),
+            bold: cell.bold,
+            italic: cell.italic,
+            underline: cell.underline,
+            inverse: cell.inverse,
+            wide: cell.wide,
+        }
+    }
+}
+
+/// A terminal emulator that tracks screen state from PTY output.
+pub trait TerminalBackend: Send + Sync {
+    /// Create an emulator with the given screen size and scrollback length.
+    fn new(rows: u16, cols: u16, scrollback_len: usize) -> Self
+    where
+        Self: Sized;
+
+    /// Feed PTY output into the emulator.
+    fn process(&mut self, data: &[u8]);
+
+    /// Screen size as (rows, cols).
+    fn size(&self) -> (u16, u16);
+
+    /// Change the screen size, as the PTY's size changed.
+    fn set_size(&mut self, rows: u16, cols: u16);
+
+    /// Plain-text contents of the visible screen (the alternate screen when active).
+    fn contents(&self) -> String;
+
+    /// Plain text of the history scrolled off the top followed by the
+    /// visible screen, a string per row, and how many rows are history.
+    fn rows_with_history(&mut self) -> (Vec<String>, usize);
+
+    /// Cursor position as (row, col) in cells.
+    fn cursor_position(&self) -> (u16, u16);
+
+    /// Number of characters in the cells of `row` before cell column `col`.
+    fn char_col_at(&self, row: u16, col: u16) -> usize;
+
+    /// Number of UTF-8 bytes in the cells of `row` before cell column `col`.
+    fn byte_col_at(&self, row: u16, col: u16) -> usize;
+
+    /// Cells of the visible screen, row by row.
+    fn styled_rows(&self) -> Vec<Vec<StyledCell>>;
+
+    /// Whether the app enabled bracketed paste mode.
+    fn bracketed_paste(&self) -> bool;
+
+    /// Whether the app switched cursor keys to application mode (DECCKM).
+    fn application_cursor(&self) -> bool;
+
+    /// Window title the app last set.
+    fn title(&self) -> &str;
+
+    /// Times the app has rung the bell.
+    fn bell_count(&self) -> usize;
+
+    /// Whether the app is showing the alternate screen.
+    fn alternate_screen(&self) -> bool;
+
+    /// Escape sequences that redraw the visible screen, cursor and modes from scratch.
+    fn state_formatted(&self) -> Vec<u8>;
+}
+
+#[cfg(feature = "vt100")]
+pub type Backend = Vt100Backend;
+
+#[cfg(not(feature = "vt100"))]
+compile_error!("tap-server needs a terminal backend feature enabled (e.g. `vt100`)");
+
+/// Backend using the `vt100` crate.
+#[cfg(feature = "vt100")]
+pub struct Vt100Backend {
+    parser: vt100::Parser,
+}
+
+#[cfg(feature = "vt100")]
+impl TerminalBackend for Vt100Backend {
+    fn new(rows: u16, cols: u16, scrollback_len: usize) -> Self {
+        Self {
+            parser: vt100::Parser::new(rows, cols, scrollback_len),
+        }
+    }
+
+    fn process(&mut self, data: &[u8]) {
+        self.parser.process(data);
+    }
+
+    fn size(&self) -> (u16, u16) {
+        self.parser.screen().size()
+    }
+
+    fn set_size(&mut self, rows: u16, cols: u16) {
+        self.parser.set_size(rows, cols);
+    }
+
+    fn contents(&self) -> String {
+        self.parser.screen().contents()
+    }
+
+    fn title(&self) -> &str {
+        self.parser.screen().title()
+    }
+
+    fn bell_count(&self) -> usize {
+        self.parser.screen().audible_bell_count()
+    }
+
+    fn alternate_screen(&self) -> bool {
+        self.parser.screen().alternate_screen()
+    }
+
+    fn rows_with_history(&mut self) -> (Vec<String>, usize) {
+        let (rows, cols) = self.size();
+        // Scroll back a screenful at a time, reading the rows that come into view
+        self.parser.set_scrollback(usize::MAX);
+        let mut offset = self.parser.screen().scrollback();
+        let mut text = Vec::with_capacity(offset + usize::from(rows));
+        while offset > 0 {
+            self.parser.set_scrollback(offset);
+            let count = offset.min(usize::from(rows));
+            text.extend(self.parser.screen().rows(0, cols).take(count));
+            offset -= count;
+        }
+        self.parser.set_scrollback(0);
+        let history = text.len();
+        text.extend(self.parser.screen().rows(0, cols));
+        (text, history)
+    }
+
+    fn cursor_position(&self) -> (u16, u16) {
+        self.parser.screen().cursor_position()
+    }
+
+    fn char_col_at(&self, row: u16, col: u16) -> usize {
+        let screen = self.parser.screen();
+        (0..col)
+            .filter_map(|c| screen.cell(row, c))
+            .filter(|cell| !cell.is_wide_continuation())
+            .map(|cell| cell.contents().chars().count().max(1))
+            .sum()
+    }
+
+    fn byte_col_at(&self, row: u16, col: u16) -> usize {
+        let screen = self.parser.screen();
+        (0..col)
+            .filter_map(|c| screen.cell(row, c))
+            .filter(|cell| !cell.is_wide_continuation())
+            .map(|cell| cell.contents().len().max(1))
+            .sum()
+    }
+
+    fn styled_rows(&self) -> Vec<Vec<StyledCell>> {
+        fn color(color: vt100::Color) -> CellColor {
+            match color {
+                vt100::Color::Default => CellColor::Default,
+                vt100::Color::Idx(idx) => CellColor::Indexed(idx),
+                vt100::Color::Rgb(r, g, b) => CellColor::Rgb(r, g, b),
+            }
+        }
+
+        let screen = self.parser.screen();
+        let (rows, cols) = screen.size();
+        (0..rows)
+            .map(|row| {
+                (0..cols)
+                    .map(|col| {
+                        let Some(cell) = screen.cell(row, col) else {
+                            return StyledCell::default();
+                        };
+                        StyledCell {
+                            contents: cell.contents(),
+                            fg: color(cell.fgcolor()),
+                            bg: color(cell.bgcolor()),
+                            bold: cell.bold(),
+                            italic: cell.italic(),
+                            underline: cell.underline(),
+                            inverse: cell.inverse(),
+                            wide: cell.is_wide(),
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn bracketed_paste(&self) -> bool {
+        self.parser.screen().bracketed_paste()
+    }
+
+    fn application_cursor(&self) -> bool {
+        self.parser.screen().application_cursor()
+    }
+
+    fn state_formatted(&self) -> Vec<u8> {
+        self.parser.screen().state_formatted()
+    }
+}
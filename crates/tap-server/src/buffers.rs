@@ -0,0 +1,55 @@
+//! Named paste buffers shared between sessions.
+//!
+//! Each buffer is a file in [`tap_protocol::buffers_dir`], so any session can
+//! paste what another one stored.
+
+use eyre::WrapErr as _;
+
+/// Resolve a buffer name to its file path, rejecting names that would escape
+/// the buffers directory.
+fn buffer_path(name: &str) -> eyre::Result<std::path::PathBuf> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\', '\0']) {
+        eyre::bail!("invalid buffer name '{name}'");
+    }
+    Ok(tap_protocol::buffers_dir().join(name))
+}
+
+/// Store `data` in the named buffer, replacing any previous contents.
+pub fn set(name: &str, data: &str) -> eyre::Result<()> {
+    let path = buffer_path(name)?;
+    let dir = tap_protocol::buffers_dir();
+    std::fs::create_dir_all(&dir)
+        .wrap_err_with(|| format!("failed to create buffers directory {}", dir.display()))?;
+    std::fs::write(&path, data).wrap_err_with(|| format!("failed to write buffer '{name}'"))
+}
+
+/// Read the contents of the named buffer.
+pub fn get(name: &str) -> eyre::Result<String> {
+    let path = buffer_path(name)?;
+    match std::fs::read_to_string(&path) {
+        Ok(data) => Ok(data),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            eyre::bail!("buffer '{name}' does not exist")
+        }
+        Err(e) => Err(e).wrap_err_with(|| format!("failed to read buffer '{name}'")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffer_path_valid() {
+        let path = buffer_path("clip").unwrap();
+        assert_eq!(path, tap_protocol::buffers_dir().join("clip"));
+    }
+
+    #[test]
+    fn test_buffer_path_rejects_traversal() {
+        assert!(buffer_path("").is_err());
+        assert!(buffer_path("..").is_err());
+        assert!(buffer_path("../sessions.json").is_err());
+        assert!(buffer_path("a/b").is_err());
+    }
+}
@@ -0,0 +1,113 @@
+//! Protocol state of a single client connection.
+//!
+//! A connection starts idle, answering one-shot requests. `Subscribe` makes it
+//! receive live output alongside its responses, and `Attach` hands it the
+//! session's input and output for good. Attaching is allowed from either of
+//! the earlier states; nothing leaves the attached state.
+
+/// Where a client connection is in its lifecycle.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ClientState {
+    /// Request/response only.
+    #[default]
+    Idle,
+    /// Receiving live output as `Output` lines.
+    Subscribed,
+    /// Driving the session's input and receiving its output.
+    Attached,
+}
+
+/// A request that isn't allowed in the connection's current state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IllegalTransition {
+    pub from: ClientState,
+    pub request: &'static str,
+}
+
+impl std::fmt::Display for IllegalTransition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let state = match self.from {
+            ClientState::Idle => "idle",
+            ClientState::Subscribed => "subscribed",
+            ClientState::Attached => "attached",
+        };
+        write!(f, "cannot {} while {state}", self.request)
+    }
+}
+
+impl IllegalTransition {
+    /// Error code to report the rejected request with.
+    pub fn code(&self) -> tap_protocol::ErrorCode {
+        match self.from {
+            ClientState::Attached => tap_protocol::ErrorCode::AlreadyAttached,
+            ClientState::Idle | ClientState::Subscribed => tap_protocol::ErrorCode::Other,
+        }
+    }
+
+    /// The rejection as a protocol response.
+    pub fn to_response(&self) -> tap_protocol::Response {
+        tap_protocol::Response::Error {
+            code: self.code(),
+            message: self.to_string(),
+        }
+    }
+}
+
+impl ClientState {
+    /// State after a `Subscribe` request.
+    pub fn subscribe(self) -> Result<Self, IllegalTransition> {
+        match self {
+            ClientState::Idle => Ok(ClientState::Subscribed),
+            ClientState::Subscribed | ClientState::Attached => Err(IllegalTransition {
+                from: self,
+                request: "subscribe",
+            }),
+        }
+    }
+
+    /// State after an `Attach` request.
+    pub fn attach(self) -> Result<Self, IllegalTransition> {
+        match self {
+            ClientState::Idle | ClientState::Subscribed => Ok(ClientState::Attached),
+            ClientState::Attached => Err(IllegalTransition {
+                from: self,
+                request: "attach",
+            }),
+        }
+    }
+
+    /// Whether broadcast output should be sent to this connection as
+    /// `Output` lines.
+    pub fn receives_broadcast(self) -> bool {
+        self == ClientState::Subscribed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idle_transitions() {
+        assert_eq!(ClientState::Idle.subscribe(), Ok(ClientState::Subscribed));
+        assert_eq!(ClientState::Idle.attach(), Ok(ClientState::Attached));
+        assert!(!ClientState::Idle.receives_broadcast());
+    }
+
+    #[test]
+    fn test_subscribed_transitions() {
+        assert_eq!(ClientState::Subscribed.attach(), Ok(ClientState::Attached));
+        let err = ClientState::Subscribed.subscribe().unwrap_err();
+        assert_eq!(err.to_string(), "cannot subscribe while subscribed");
+        assert_eq!(err.code(), tap_protocol::ErrorCode::Other);
+        assert!(ClientState::Subscribed.receives_broadcast());
+    }
+
+    #[test]
+    fn test_attached_is_final() {
+        let err = ClientState::Attached.attach().unwrap_err();
+        assert_eq!(err.code(), tap_protocol::ErrorCode::AlreadyAttached);
+        assert!(ClientState::Attached.subscribe().is_err());
+        assert!(!ClientState::Attached.receives_broadcast());
+    }
+}
@@ -0,0 +1,288 @@
+//! Pasting the system clipboard into the child, and copying to it.
+//!
+//! The clipboard is read with the platform's paste tool when one is
+//! available, and otherwise requested from the outer terminal via OSC 52.
+//! Copy mode copies through the outer terminal, which works over SSH;
+//! `tap copy` tries the platform's copy tool first. Clipboard contents the
+//! app sets with OSC 52 reach attached terminals with the rest of the
+//! output, and can be kept for fetching later (`[clipboard] keep`).
+
+use base64::Engine as _;
+
+/// OSC 52 query for the clipboard selection.
+pub const OSC52_QUERY: &[u8] = b"\x1b]52;c;?\x07";
+
+const OSC52_PREFIX: &[u8] = b"\x1b]52;";
+/// Longest unfinished OSC 52 sequence carried over between chunks.
+const MAX_PARTIAL_LEN: usize = 1024 * 1024;
+const BRACKETED_PASTE_START: &[u8] = b"\x1b[200~";
+const BRACKETED_PASTE_END: &[u8] = b"\x1b[201~";
+
+/// Paste tools to try, in order, with their arguments.
+#[cfg(target_os = "macos")]
+const PASTE_COMMANDS: &[&[&str]] = &[&["pbpaste"]];
+#[cfg(not(target_os = "macos"))]
+const PASTE_COMMANDS: &[&[&str]] = &[
+    &["wl-paste", "--no-newline"],
+    &["xclip", "-selection", "clipboard", "-o"],
+    &["xsel", "--clipboard", "--output"],
+];
+
+/// Copy tools to try, in order, with their arguments.
+#[cfg(target_os = "macos")]
+const COPY_COMMANDS: &[&[&str]] = &[&["pbcopy"]];
+#[cfg(not(target_os = "macos"))]
+const COPY_COMMANDS: &[&[&str]] = &[
+    &["wl-copy"],
+    &["xclip", "-selection", "clipboard", "-i"],
+    &["xsel", "--clipboard", "--input"],
+];
+
+/// Read the system clipboard, or `None` if no paste tool succeeded.
+pub fn read_system_clipboard() -> Option<Vec<u8>> {
+    PASTE_COMMANDS.iter().find_map(|command| {
+        let output = std::process::Command::new(command[0])
+            .args(&command[1..])
+            .stdin(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        tracing::debug!("read clipboard with {}", command[0]);
+        Some(output.stdout)
+    })
+}
+
+/// Set the system clipboard, returning whether a copy tool succeeded.
+pub fn write_system_clipboard(contents: &[u8]) -> bool {
+    use std::io::Write as _;
+
+    COPY_COMMANDS.iter().any(|command| {
+        let Ok(mut child) = std::process::Command::new(command[0])
+            .args(&command[1..])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+        else {
+            return false;
+        };
+        let written = child
+            .stdin
+            .take()
+            .is_some_and(|mut stdin| stdin.write_all(contents).is_ok());
+        let copied = child.wait().is_ok_and(|status| status.success()) && written;
+        if copied {
+            tracing::debug!("copied to clipboard with {}", command[0]);
+        }
+        copied
+    })
+}
+
+/// OSC 52 sequence asking the outer terminal to set the clipboard.
+pub fn osc52_copy(contents: &[u8]) -> Vec<u8> {
+    let mut out = OSC52_PREFIX.to_vec();
+    out.extend_from_slice(b"c;");
+    out.extend_from_slice(
+        base64::engine::general_purpose::STANDARD
+            .encode(contents)
+            .as_bytes(),
+    );
+    out.push(0x07);
+    out
+}
+
+/// Find an OSC 52 reply in terminal input.
+///
+/// Returns the decoded clipboard contents and the input with the reply
+/// removed, or `None` if the input holds no complete reply.
+pub fn take_osc52_reply(input: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let (range, data) = find_osc52(input)?;
+    let contents = base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .unwrap_or_default();
+
+    let mut rest = input[..range.start].to_vec();
+    rest.extend_from_slice(&input[range.end..]);
+    Some((contents, rest))
+}
+
+/// The first complete OSC 52 sequence in `input`: where it is, and its
+/// base64 data (or `?` for a query).
+fn find_osc52(input: &[u8]) -> Option<(std::ops::Range<usize>, &[u8])> {
+    let start = find(input, OSC52_PREFIX)?;
+    let body_start = start + OSC52_PREFIX.len();
+    let body = &input[body_start..];
+    let (end, terminator_len) = match (body.iter().position(|&b| b == 0x07), find(body, b"\x1b\\"))
+    {
+        (Some(bel), Some(st)) if st < bel => (st, 2),
+        (Some(bel), _) => (bel, 1),
+        (None, Some(st)) => (st, 2),
+        (None, None) => return None,
+    };
+
+    // Payload is "<selection>;<base64>"
+    let payload = &body[..end];
+    let data = payload
+        .iter()
+        .position(|&b| b == b';')
+        .map(|semicolon| &payload[semicolon + 1..])
+        .unwrap_or_default();
+    Some((start..body_start + end + terminator_len, data))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// The clipboard contents the app last set with OSC 52, kept (when enabled)
+/// for `tap copy --app` in case no terminal was attached to take them.
+#[derive(Debug, Default)]
+pub struct AppClipboard {
+    keep: bool,
+    contents: Option<Vec<u8>>,
+    /// Start of a sequence split across chunks.
+    partial: Vec<u8>,
+}
+
+impl AppClipboard {
+    pub const fn new() -> Self {
+        Self {
+            keep: false,
+            contents: None,
+            partial: Vec::new(),
+        }
+    }
+
+    /// Start or stop keeping what the app copies.
+    pub fn set_keep(&mut self, keep: bool) {
+        self.keep = keep;
+        if !keep {
+            self.contents = None;
+            self.partial.clear();
+        }
+    }
+
+    /// What the app last copied.
+    pub fn get(&self) -> Option<&[u8]> {
+        self.contents.as_deref()
+    }
+
+    /// Scan a chunk of PTY output for clipboard sets.
+    pub fn process(&mut self, data: &[u8]) {
+        if !self.keep {
+            return;
+        }
+        let joined;
+        let mut rest = if self.partial.is_empty() {
+            data
+        } else {
+            self.partial.extend_from_slice(data);
+            joined = std::mem::take(&mut self.partial);
+            &joined
+        };
+
+        while let Some((range, data)) = find_osc52(rest) {
+            if data != b"?"
+                && let Ok(contents) = base64::engine::general_purpose::STANDARD.decode(data)
+            {
+                self.contents = Some(contents);
+            }
+            rest = &rest[range.end..];
+        }
+
+        // Hold back an unfinished sequence, or what may start one
+        if let Some(start) = find(rest, OSC52_PREFIX) {
+            if rest.len() - start <= MAX_PARTIAL_LEN {
+                self.partial = rest[start..].to_vec();
+            }
+            return;
+        }
+        let tail = (1..=OSC52_PREFIX.len().min(rest.len()))
+            .rev()
+            .find(|&len| OSC52_PREFIX.starts_with(&rest[rest.len() - len..]))
+            .unwrap_or(0);
+        self.partial = rest[rest.len() - tail..].to_vec();
+    }
+}
+
+/// Prepare clipboard contents for writing to the PTY the way a terminal
+/// would: newlines become carriage returns, and the text is wrapped in
+/// bracketed paste markers when the inner app enabled that mode.
+pub fn paste_bytes(contents: &[u8], bracketed: bool) -> Vec<u8> {
+    let mut text = Vec::with_capacity(contents.len() + 12);
+    let mut iter = contents.iter().peekable();
+    while let Some(&b) = iter.next() {
+        match b {
+            b'\r' if iter.peek() == Some(&&b'\n') => {}
+            b'\n' => text.push(b'\r'),
+            _ => text.push(b),
+        }
+    }
+
+    if !bracketed {
+        return text;
+    }
+
+    // Strip end markers so pasted text can't break out of the paste
+    while let Some(pos) = text
+        .windows(BRACKETED_PASTE_END.len())
+        .position(|w| w == BRACKETED_PASTE_END)
+    {
+        text.drain(pos..pos + BRACKETED_PASTE_END.len());
+    }
+
+    let mut out = BRACKETED_PASTE_START.to_vec();
+    out.extend_from_slice(&text);
+    out.extend_from_slice(BRACKETED_PASTE_END);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_osc52_reply() {
+        let input = b"a\x1b]52;c;aGVsbG8=\x07b";
+        let (contents, rest) = take_osc52_reply(input).unwrap();
+        assert_eq!(contents, b"hello");
+        assert_eq!(rest, b"ab");
+
+        let (contents, rest) = take_osc52_reply(b"\x1b]52;c;aGk=\x1b\\").unwrap();
+        assert_eq!(contents, b"hi");
+        assert!(rest.is_empty());
+
+        assert!(take_osc52_reply(b"plain input").is_none());
+
+        let (contents, _) = take_osc52_reply(&osc52_copy(b"copied")).unwrap();
+        assert_eq!(contents, b"copied");
+    }
+
+    #[test]
+    fn test_app_clipboard() {
+        let mut clipboard = AppClipboard::new();
+        clipboard.process(b"\x1b]52;c;aGk=\x07");
+        assert_eq!(clipboard.get(), None);
+
+        clipboard.set_keep(true);
+        clipboard.process(b"vim\x1b]52;c;aGVs");
+        clipboard.process(b"bG8=\x1b\\ \x1b]5");
+        assert_eq!(clipboard.get(), Some(&b"hello"[..]));
+        clipboard.process(b"2;c;?\x07");
+        assert_eq!(clipboard.get(), Some(&b"hello"[..]));
+    }
+
+    #[test]
+    fn test_paste_bytes() {
+        assert_eq!(paste_bytes(b"a\r\nb\nc", false), b"a\rb\rc");
+        assert_eq!(
+            paste_bytes(b"x\x1b[201~y", true),
+            b"\x1b[200~xy\x1b[201~".to_vec()
+        );
+    }
+}
@@ -0,0 +1,181 @@
+//! Splitting output into commands using shell integration marks.
+//!
+//! Shells set up for semantic prompts (OSC 133, as used by iTerm2, kitty,
+//! WezTerm and others) mark where a command's output starts (`133;C`) and
+//! where it finished (`133;D;<exit code>`). The raw output between them is
+//! kept for the last few commands, so one command's output can be read on
+//! its own.
+
+/// Finished commands kept.
+const MAX_COMMANDS: usize = 100;
+/// Output kept per command; the start of longer output is dropped.
+const MAX_COMMAND_BYTES: usize = 1024 * 1024;
+/// Longest OSC 133 sequence carried over between chunks.
+const MAX_PARTIAL_LEN: usize = 64;
+
+const OSC_133: &[u8] = b"\x1b]133;";
+
+/// A finished command's raw output and exit status.
+#[derive(Debug, Clone)]
+pub struct Command {
+    pub output: Vec<u8>,
+    pub exit_code: Option<i32>,
+}
+
+#[derive(Debug, Default)]
+pub struct CommandLog {
+    /// Output of the running command, since its `133;C`.
+    running: Option<Vec<u8>>,
+    finished: std::collections::VecDeque<Command>,
+    /// Start of a mark split across chunks.
+    partial: Vec<u8>,
+}
+
+impl CommandLog {
+    pub const fn new() -> Self {
+        Self {
+            running: None,
+            finished: std::collections::VecDeque::new(),
+            partial: Vec::new(),
+        }
+    }
+
+    /// Scan a chunk of PTY output for command boundaries.
+    pub fn process(&mut self, data: &[u8]) {
+        let joined;
+        let bytes = if self.partial.is_empty() {
+            data
+        } else {
+            self.partial.extend_from_slice(data);
+            joined = std::mem::take(&mut self.partial);
+            &joined
+        };
+
+        let mut i = 0;
+        while let Some(offset) = find(&bytes[i..], OSC_133) {
+            let start = i + offset;
+            let body_start = start + OSC_133.len();
+            let Some((body_len, terminator_len)) = terminator(&bytes[body_start..]) else {
+                self.append(&bytes[i..start]);
+                if bytes.len() - start <= MAX_PARTIAL_LEN {
+                    self.partial = bytes[start..].to_vec();
+                } else {
+                    self.append(&bytes[start..]);
+                }
+                return;
+            };
+            self.append(&bytes[i..start]);
+            self.mark(&bytes[body_start..body_start + body_len]);
+            i = body_start + body_len + terminator_len;
+        }
+
+        // Hold back a trailing ESC that may start the next mark
+        let tail = (1..=OSC_133.len().min(bytes.len() - i))
+            .rev()
+            .find(|&len| OSC_133.starts_with(&bytes[bytes.len() - len..]))
+            .unwrap_or(0);
+        self.append(&bytes[i..bytes.len() - tail]);
+        self.partial = bytes[bytes.len() - tail..].to_vec();
+    }
+
+    fn append(&mut self, data: &[u8]) {
+        if let Some(output) = &mut self.running {
+            output.extend_from_slice(data);
+            if output.len() > MAX_COMMAND_BYTES {
+                let excess = output.len() - MAX_COMMAND_BYTES;
+                output.drain(..excess);
+            }
+        }
+    }
+
+    /// Handle a mark's parameters, e.g. `C` or `D;0`.
+    fn mark(&mut self, params: &[u8]) {
+        let mut params = params.split(|&b| b == b';');
+        match params.next() {
+            Some(b"C") => self.running = Some(Vec::new()),
+            Some(b"D") => {
+                let Some(output) = self.running.take() else {
+                    return;
+                };
+                let exit_code = params
+                    .next()
+                    .and_then(|code| std::str::from_utf8(code).ok())
+                    .and_then(|code| code.parse().ok());
+                self.finished.push_back(Command { output, exit_code });
+                if self.finished.len() > MAX_COMMANDS {
+                    self.finished.pop_front();
+                }
+            }
+            // A new prompt without a `D` ends the command all the same
+            Some(b"A") => {
+                if self.running.is_some() {
+                    self.mark(b"D");
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The `nth` most recently finished command, counting from 1.
+    pub fn nth_last(&self, nth: usize) -> Option<&Command> {
+        let index = self.finished.len().checked_sub(nth.max(1))?;
+        self.finished.get(index)
+    }
+}
+
+/// Length of the OSC body in `bytes` and of its BEL or ST terminator.
+fn terminator(bytes: &[u8]) -> Option<(usize, usize)> {
+    let end = bytes.iter().position(|&b| b == 0x07 || b == 0x1b)?;
+    match bytes[end] {
+        0x07 => Some((end, 1)),
+        _ if bytes.get(end + 1) == Some(&b'\\') => Some((end, 2)),
+        _ => None,
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_splits_commands() {
+        let mut log = CommandLog::new();
+        log.process(b"\x1b]133;A\x07$ ls\r\n\x1b]133;C\x07a  b\r\n\x1b]133;D;0\x07");
+        log.process(b"\x1b]133;A\x07$ false\r\n\x1b]133;C\x1b\\\x1b]133;D;1\x1b\\");
+
+        let last = log.nth_last(1).unwrap();
+        assert_eq!(last.output, b"");
+        assert_eq!(last.exit_code, Some(1));
+        let first = log.nth_last(2).unwrap();
+        assert_eq!(first.output, b"a  b\r\n");
+        assert_eq!(first.exit_code, Some(0));
+        assert!(log.nth_last(3).is_none());
+    }
+
+    #[test]
+    fn test_mark_split_across_chunks() {
+        let mut log = CommandLog::new();
+        log.process(b"\x1b]133;C\x07out\x1b");
+        log.process(b"]13");
+        log.process(b"3;D;2");
+        log.process(b"\x07");
+
+        let last = log.nth_last(1).unwrap();
+        assert_eq!(last.output, b"out");
+        assert_eq!(last.exit_code, Some(2));
+    }
+
+    #[test]
+    fn test_prompt_ends_command() {
+        let mut log = CommandLog::new();
+        log.process(b"\x1b]133;C\x07out\r\n\x1b]133;A\x07$ ");
+        assert_eq!(log.nth_last(1).unwrap().output, b"out\r\n");
+        assert_eq!(log.nth_last(1).unwrap().exit_code, None);
+    }
+}
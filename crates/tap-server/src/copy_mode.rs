@@ -0,0 +1,531 @@
+//! Copy mode: a frozen snapshot of the session's output shown in place of
+//! the live screen, with vi-style movement, search (`/`, `?`, `n`, `N`) and
+//! selection (`v`, `V`). Yanking (`y` or Enter) copies the selection to the
+//! outer terminal's clipboard over OSC 52 and returns to the live screen.
+
+/// A key pressed in copy mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Key {
+    Char(char),
+    Ctrl(char),
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    Enter,
+    Escape,
+    Backspace,
+}
+
+/// Split terminal input into keys, dropping escape sequences for keys copy
+/// mode doesn't use.
+fn keys(input: &[u8]) -> Vec<Key> {
+    let text = String::from_utf8_lossy(input);
+    let mut chars = text.chars().peekable();
+    let mut keys = Vec::new();
+    while let Some(c) = chars.next() {
+        let key = match c {
+            '\x1b' if matches!(chars.peek(), Some('[' | 'O')) => {
+                chars.next();
+                let mut params = String::new();
+                let mut end = None;
+                for c in chars.by_ref() {
+                    if c.is_ascii_digit() || c == ';' {
+                        params.push(c);
+                    } else {
+                        end = Some(c);
+                        break;
+                    }
+                }
+                match (end, params.as_str()) {
+                    (Some('A'), _) => Key::Up,
+                    (Some('B'), _) => Key::Down,
+                    (Some('C'), _) => Key::Right,
+                    (Some('D'), _) => Key::Left,
+                    (Some('H'), _) | (Some('~'), "1" | "7") => Key::Home,
+                    (Some('F'), _) | (Some('~'), "4" | "8") => Key::End,
+                    (Some('~'), "5") => Key::PageUp,
+                    (Some('~'), "6") => Key::PageDown,
+                    _ => continue,
+                }
+            }
+            '\x1b' => Key::Escape,
+            '\r' | '\n' => Key::Enter,
+            '\x7f' | '\x08' => Key::Backspace,
+            '\0'..='\x1f' => Key::Ctrl(char::from(c as u8 | 0x60)),
+            c => Key::Char(c),
+        };
+        keys.push(key);
+    }
+    keys
+}
+
+/// What to do after a key in copy mode.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Outcome {
+    /// Still in copy mode; redraw it.
+    Stay,
+    /// Back to the live screen.
+    Exit,
+    /// Back to the live screen, copying this text.
+    Yank(String),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Selection {
+    /// Where the selection started, as (line, column).
+    anchor: (usize, usize),
+    /// Whole lines (`V`) rather than characters (`v`).
+    lines: bool,
+}
+
+/// Copy mode's view of a snapshot.
+pub struct CopyMode {
+    lines: Vec<Vec<char>>,
+    rows: usize,
+    cols: usize,
+    /// Cursor as (line, column) into `lines`.
+    cursor: (usize, usize),
+    /// First line on screen.
+    top: usize,
+    selection: Option<Selection>,
+    /// Last search, and whether it went forward.
+    search: Option<(Vec<char>, bool)>,
+    /// Search being typed after `/` or `?`.
+    prompt: Option<(String, bool)>,
+    /// Shown in the status line until the next key.
+    message: Option<String>,
+}
+
+impl CopyMode {
+    /// Copy mode over `lines`, drawn on a screen of `size` (rows, cols) with
+    /// the last line at the bottom, the way output usually sits.
+    pub fn new(lines: Vec<String>, cursor: (usize, usize), size: (u16, u16)) -> Self {
+        let mut lines: Vec<Vec<char>> = lines.iter().map(|line| line.chars().collect()).collect();
+        if lines.is_empty() {
+            lines.push(Vec::new());
+        }
+        let rows = usize::from(size.0.max(2));
+        let mut mode = Self {
+            top: lines.len().saturating_sub(rows - 1),
+            lines,
+            rows,
+            cols: usize::from(size.1.max(1)),
+            cursor: (0, 0),
+            selection: None,
+            search: None,
+            prompt: None,
+            message: None,
+        };
+        mode.move_to(cursor.0, cursor.1);
+        mode
+    }
+
+    /// Snapshot the session's output.
+    pub(crate) fn enter() -> Self {
+        let mut scrollback = crate::SCROLLBACK.write();
+        let (lines, cursor) = scrollback.copy_text();
+        Self::new(lines, cursor, scrollback.size())
+    }
+
+    /// Fit a screen resized to `size` (rows, cols), keeping the cursor in
+    /// view.
+    pub fn resize(&mut self, size: (u16, u16)) {
+        self.rows = usize::from(size.0.max(2));
+        self.cols = usize::from(size.1.max(1));
+        self.top = self.top.min(self.lines.len().saturating_sub(self.rows - 1));
+        let (line, col) = self.cursor;
+        self.move_to(line, col);
+    }
+
+    /// Handle typed input.
+    pub fn handle(&mut self, input: &[u8]) -> Outcome {
+        let input = crate::kitty::translate_all_csi_u(input);
+        for key in keys(&input) {
+            self.message = None;
+            if self.prompt.is_some() {
+                self.prompt_key(key);
+                continue;
+            }
+            match self.key(key) {
+                Outcome::Stay => {}
+                outcome => return outcome,
+            }
+        }
+        Outcome::Stay
+    }
+
+    fn key(&mut self, key: Key) -> Outcome {
+        let page = self.rows - 1;
+        let (line, col) = self.cursor;
+        match key {
+            Key::Char('h') | Key::Left | Key::Backspace => {
+                self.move_to(line, col.saturating_sub(1))
+            }
+            Key::Char('l') | Key::Right => self.move_to(line, col + 1),
+            Key::Char('k') | Key::Up => self.move_to(line.saturating_sub(1), col),
+            Key::Char('j') | Key::Down => self.move_to(line + 1, col),
+            Key::Char('0') | Key::Home => self.move_to(line, 0),
+            Key::Char('^') => {
+                let first = self.lines[line]
+                    .iter()
+                    .position(|c| !c.is_whitespace())
+                    .unwrap_or(0);
+                self.move_to(line, first);
+            }
+            Key::Char('$') | Key::End => self.move_to(line, usize::MAX),
+            Key::Char('w') => self.next_word(),
+            Key::Char('b') => self.previous_word(),
+            Key::Char('g') => self.move_to(0, 0),
+            Key::Char('G') => self.move_to(usize::MAX, 0),
+            Key::Ctrl('u') => self.move_to(line.saturating_sub(page / 2), col),
+            Key::Ctrl('d') => self.move_to(line + page / 2, col),
+            Key::Ctrl('b') | Key::PageUp => self.move_to(line.saturating_sub(page), col),
+            Key::Ctrl('f') | Key::PageDown => self.move_to(line + page, col),
+            Key::Char(c @ ('v' | 'V')) => {
+                let lines = c == 'V';
+                self.selection = match self.selection {
+                    Some(selection) if selection.lines == lines => None,
+                    Some(selection) => Some(Selection { lines, ..selection }),
+                    None => Some(Selection {
+                        anchor: self.cursor,
+                        lines,
+                    }),
+                };
+            }
+            Key::Char('/') => self.prompt = Some((String::new(), true)),
+            Key::Char('?') => self.prompt = Some((String::new(), false)),
+            Key::Char('n') => self.search_next(false),
+            Key::Char('N') => self.search_next(true),
+            Key::Char('y') | Key::Enter => return self.yank(),
+            Key::Escape if self.selection.is_some() => self.selection = None,
+            Key::Char('q') | Key::Escape | Key::Ctrl('c') => return Outcome::Exit,
+            _ => {}
+        }
+        Outcome::Stay
+    }
+
+    fn prompt_key(&mut self, key: Key) {
+        let Some((mut text, forward)) = self.prompt.take() else {
+            return;
+        };
+        match key {
+            Key::Char(c) => text.push(c),
+            Key::Backspace if !text.is_empty() => {
+                text.pop();
+            }
+            Key::Enter => {
+                // An empty search repeats the last one in the new direction
+                match (text.is_empty(), &mut self.search) {
+                    (true, Some((_, last))) => *last = forward,
+                    (true, None) => {}
+                    (false, _) => self.search = Some((text.chars().collect(), forward)),
+                }
+                self.search_next(false);
+                return;
+            }
+            Key::Escape | Key::Ctrl('c') | Key::Backspace => return,
+            _ => {}
+        }
+        self.prompt = Some((text, forward));
+    }
+
+    /// Move the cursor, keeping it on the text and on screen.
+    fn move_to(&mut self, line: usize, col: usize) {
+        let line = line.min(self.lines.len() - 1);
+        let col = col.min(self.lines[line].len().saturating_sub(1));
+        self.cursor = (line, col);
+
+        let height = self.rows - 1;
+        if line < self.top {
+            self.top = line;
+        } else if line >= self.top + height {
+            self.top = line + 1 - height;
+        }
+    }
+
+    /// To the start of the next whitespace-separated word.
+    fn next_word(&mut self) {
+        let (mut line, mut col) = self.cursor;
+        let mut in_word = self.lines[line]
+            .get(col)
+            .is_some_and(|c| !c.is_whitespace());
+        loop {
+            col += 1;
+            if col >= self.lines[line].len() {
+                if line + 1 >= self.lines.len() {
+                    return;
+                }
+                line += 1;
+                col = 0;
+                in_word = false;
+            }
+            match self.lines[line].get(col) {
+                Some(c) if !c.is_whitespace() => {
+                    if !in_word {
+                        self.move_to(line, col);
+                        return;
+                    }
+                }
+                _ => in_word = false,
+            }
+        }
+    }
+
+    /// To the start of this word, or of the one before if already there.
+    fn previous_word(&mut self) {
+        let (mut line, mut col) = self.cursor;
+        let mut seen_word = false;
+        loop {
+            if col == 0 {
+                if seen_word || line == 0 {
+                    break;
+                }
+                line -= 1;
+                col = self.lines[line].len();
+                continue;
+            }
+            if self.lines[line][col - 1].is_whitespace() {
+                if seen_word {
+                    break;
+                }
+            } else {
+                seen_word = true;
+            }
+            col -= 1;
+        }
+        self.move_to(line, col);
+    }
+
+    /// Jump to the next match of the last search, or the previous one if
+    /// `reverse`. Searches wrap around and ignore case unless the pattern
+    /// has capitals.
+    fn search_next(&mut self, reverse: bool) {
+        let Some((pattern, forward)) = &self.search else {
+            self.message = Some("no previous search".to_string());
+            return;
+        };
+        let found = if *forward != reverse {
+            self.find_forward(pattern)
+        } else {
+            self.find_backward(pattern)
+        };
+        match found {
+            Some((line, col)) => self.move_to(line, col),
+            None => {
+                self.message = Some(format!("not found: {}", pattern.iter().collect::<String>()));
+            }
+        }
+    }
+
+    fn find_forward(&self, pattern: &[char]) -> Option<(usize, usize)> {
+        let (line, col) = self.cursor;
+        let count = self.lines.len();
+        (0..=count).find_map(|i| {
+            let line = (line + i) % count;
+            let from = if i == 0 { col + 1 } else { 0 };
+            find_in(&self.lines[line], pattern, from).map(|col| (line, col))
+        })
+    }
+
+    fn find_backward(&self, pattern: &[char]) -> Option<(usize, usize)> {
+        let (line, col) = self.cursor;
+        let count = self.lines.len();
+        (0..=count).find_map(|i| {
+            let line = (line + count - i % count) % count;
+            let before = if i == 0 { col } else { usize::MAX };
+            rfind_in(&self.lines[line], pattern, before).map(|col| (line, col))
+        })
+    }
+
+    fn yank(&mut self) -> Outcome {
+        let Some(selection) = self.selection else {
+            self.message = Some("nothing selected (v to start)".to_string());
+            return Outcome::Stay;
+        };
+        let (start, end) = ordered(selection.anchor, self.cursor);
+        let text: Vec<String> = (start.0..=end.0)
+            .map(|line| {
+                let chars = &self.lines[line];
+                if selection.lines {
+                    return chars.iter().collect();
+                }
+                let to = if line == end.0 {
+                    (end.1 + 1).min(chars.len())
+                } else {
+                    chars.len()
+                };
+                let from = if line == start.0 { start.1.min(to) } else { 0 };
+                chars[from..to].iter().collect()
+            })
+            .collect();
+        Outcome::Yank(text.join("\n"))
+    }
+
+    fn selected(&self, line: usize, col: usize) -> bool {
+        let Some(selection) = self.selection else {
+            return false;
+        };
+        let (start, end) = ordered(selection.anchor, self.cursor);
+        if selection.lines {
+            (start.0..=end.0).contains(&line)
+        } else {
+            (start..=end).contains(&(line, col))
+        }
+    }
+
+    fn status(&self) -> String {
+        if let Some((text, forward)) = &self.prompt {
+            return format!("{}{text}", if *forward { '/' } else { '?' });
+        }
+        let mode = match self.selection {
+            Some(selection) if selection.lines => " (line select)",
+            Some(_) => " (select)",
+            None => "",
+        };
+        let position = format!(
+            "[copy mode{mode}] {}/{}",
+            self.cursor.0 + 1,
+            self.lines.len()
+        );
+        match &self.message {
+            Some(message) => format!("{position}  {message}"),
+            None => position,
+        }
+    }
+
+    /// Escape sequences that draw copy mode over the whole screen, with a
+    /// status line at the bottom.
+    pub fn render(&self) -> Vec<u8> {
+        let mut out = String::from("\x1b[?25l\x1b[0m");
+        for row in 0..self.rows - 1 {
+            out.push_str(&format!("\x1b[{};1H\x1b[2K", row + 1));
+            let line = self.top + row;
+            let Some(chars) = self.lines.get(line) else {
+                continue;
+            };
+            let mut inverse = false;
+            for (col, &c) in chars.iter().take(self.cols).enumerate() {
+                let selected = self.selected(line, col);
+                if selected != inverse {
+                    out.push_str(if selected { "\x1b[7m" } else { "\x1b[27m" });
+                    inverse = selected;
+                }
+                out.push(c);
+            }
+            if inverse {
+                out.push_str("\x1b[27m");
+            }
+        }
+
+        let status: String = self.status().chars().take(self.cols).collect();
+        out.push_str(&format!(
+            "\x1b[{};1H\x1b[2K\x1b[7m{status}\x1b[0m",
+            self.rows
+        ));
+
+        let (row, col) = match &self.prompt {
+            Some((text, _)) => (self.rows, text.chars().count() + 1),
+            None => (self.cursor.0 - self.top + 1, self.cursor.1),
+        };
+        out.push_str(&format!(
+            "\x1b[{row};{}H\x1b[?25h",
+            col.min(self.cols - 1) + 1
+        ));
+        out.into_bytes()
+    }
+}
+
+/// Positions `a` and `b` in document order.
+fn ordered(a: (usize, usize), b: (usize, usize)) -> ((usize, usize), (usize, usize)) {
+    if a <= b { (a, b) } else { (b, a) }
+}
+
+fn matches_at(line: &[char], pattern: &[char], col: usize) -> bool {
+    let ignore_case = !pattern.iter().any(|c| c.is_uppercase());
+    line[col..col + pattern.len()]
+        .iter()
+        .zip(pattern)
+        .all(|(a, b)| a == b || (ignore_case && a.to_lowercase().eq(b.to_lowercase())))
+}
+
+/// Column of the first match in `line` at or after `from`.
+fn find_in(line: &[char], pattern: &[char], from: usize) -> Option<usize> {
+    if pattern.is_empty() || line.len() < pattern.len() {
+        return None;
+    }
+    (from..=line.len() - pattern.len()).find(|&col| matches_at(line, pattern, col))
+}
+
+/// Column of the last match in `line` starting before `before`.
+fn rfind_in(line: &[char], pattern: &[char], before: usize) -> Option<usize> {
+    if pattern.is_empty() || line.len() < pattern.len() {
+        return None;
+    }
+    let last = line.len() - pattern.len();
+    (0..before.min(last + 1))
+        .rev()
+        .find(|&col| matches_at(line, pattern, col))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mode() -> CopyMode {
+        let lines = [
+            "$ cargo build",
+            "error: oops",
+            "$ cargo test",
+            "Error: again",
+            "$",
+        ];
+        CopyMode::new(
+            lines.iter().map(|line| line.to_string()).collect(),
+            (4, 0),
+            (3, 20),
+        )
+    }
+
+    #[test]
+    fn test_search_and_yank() {
+        let mut mode = mode();
+        assert_eq!(mode.handle(b"?error\r"), Outcome::Stay);
+        assert_eq!(mode.cursor, (3, 0));
+        assert_eq!(mode.handle(b"n"), Outcome::Stay);
+        assert_eq!(mode.cursor, (1, 0));
+        // Capitals make the search case-sensitive
+        mode.handle(b"/Error\r");
+        assert_eq!(mode.cursor, (3, 0));
+
+        mode.handle(b"gwv$");
+        assert_eq!(mode.handle(b"y"), Outcome::Yank("cargo build".to_string()));
+
+        mode.handle(b"\x1bjVj");
+        assert_eq!(
+            mode.handle(b"\r"),
+            Outcome::Yank("error: oops\n$ cargo test".to_string())
+        );
+        assert_eq!(mode.handle(b"\x1b\x1b"), Outcome::Exit);
+    }
+
+    #[test]
+    fn test_keeps_cursor_on_screen() {
+        let mut mode = mode();
+        assert_eq!(mode.top, 3);
+        mode.handle(b"\x1b[A\x1b[A");
+        assert_eq!((mode.cursor.0, mode.top), (2, 2));
+        mode.handle(b"G");
+        assert_eq!((mode.cursor.0, mode.top), (4, 3));
+
+        // A taller screen shows everything, a shorter one still the cursor
+        mode.resize((10, 20));
+        assert_eq!(mode.top, 0);
+        mode.resize((2, 20));
+        assert_eq!((mode.cursor.0, mode.top), (4, 4));
+    }
+}
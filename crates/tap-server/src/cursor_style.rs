@@ -0,0 +1,138 @@
+//! Tracking the cursor shape apps select with DECSCUSR (`CSI Ps SP q`).
+//!
+//! vt100 ignores DECSCUSR, so the last shape is picked out of the output here
+//! and replayed to terminals that attach later.
+
+/// Restores the terminal's own configured cursor shape.
+pub const RESET: &[u8] = b"\x1b[0 q";
+
+/// Longest DECSCUSR sequence we recognize (`ESC [ Ps Ps SP q`).
+const MAX_SEQUENCE_LEN: usize = 6;
+
+/// The cursor shape last selected by the app.
+#[derive(Debug, Default)]
+pub struct CursorStyle {
+    /// DECSCUSR parameter; 0 is the terminal's default shape.
+    style: u8,
+    /// Start of a sequence split across output chunks.
+    partial: Vec<u8>,
+}
+
+enum Parsed {
+    /// A DECSCUSR sequence with its parameter and length.
+    Complete(u8, usize),
+    /// Could still become a DECSCUSR sequence with more bytes.
+    Incomplete,
+    Invalid,
+}
+
+/// Parse a DECSCUSR sequence at the start of `bytes` (which begins with ESC).
+fn parse(bytes: &[u8]) -> Parsed {
+    let mut i = 1;
+    match bytes.get(i) {
+        None => return Parsed::Incomplete,
+        Some(b'[') => i += 1,
+        Some(_) => return Parsed::Invalid,
+    }
+    let digits_start = i;
+    while let Some(b) = bytes.get(i)
+        && b.is_ascii_digit()
+    {
+        i += 1;
+    }
+    if i - digits_start > 2 {
+        return Parsed::Invalid;
+    }
+    match (bytes.get(i), bytes.get(i + 1)) {
+        (None, _) | (Some(b' '), None) => Parsed::Incomplete,
+        (Some(b' '), Some(b'q')) => {
+            let style = std::str::from_utf8(&bytes[digits_start..i])
+                .ok()
+                .and_then(|digits| digits.parse().ok())
+                .unwrap_or(0);
+            Parsed::Complete(style, i + 2)
+        }
+        _ => Parsed::Invalid,
+    }
+}
+
+impl CursorStyle {
+    pub const fn new() -> Self {
+        Self {
+            style: 0,
+            partial: Vec::new(),
+        }
+    }
+
+    /// Scan a chunk of PTY output for cursor shape changes.
+    pub fn process(&mut self, data: &[u8]) {
+        let joined;
+        let bytes = if self.partial.is_empty() {
+            data
+        } else {
+            self.partial.extend_from_slice(data);
+            joined = std::mem::take(&mut self.partial);
+            &joined
+        };
+
+        let mut i = 0;
+        while let Some(offset) = bytes[i..].iter().position(|&b| b == 0x1b) {
+            let start = i + offset;
+            match parse(&bytes[start..]) {
+                Parsed::Complete(style, len) => {
+                    self.style = style;
+                    i = start + len;
+                }
+                Parsed::Incomplete if bytes.len() - start < MAX_SEQUENCE_LEN => {
+                    self.partial = bytes[start..].to_vec();
+                    return;
+                }
+                Parsed::Incomplete | Parsed::Invalid => i = start + 1,
+            }
+        }
+    }
+
+    /// The DECSCUSR parameter last set (0 for the default shape).
+    pub fn style(&self) -> u8 {
+        self.style
+    }
+
+    /// Escape sequence selecting the current shape.
+    pub fn sequence(&self) -> Vec<u8> {
+        format!("\x1b[{} q", self.style).into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracks_last_style() {
+        let mut cursor = CursorStyle::new();
+        assert_eq!(cursor.style(), 0);
+
+        cursor.process(b"vim\x1b[6 q\x1b[2J");
+        assert_eq!(cursor.style(), 6);
+        assert_eq!(cursor.sequence(), b"\x1b[6 q");
+
+        cursor.process(b"\x1b[2 qx\x1b[ q");
+        assert_eq!(cursor.style(), 0);
+    }
+
+    #[test]
+    fn test_split_sequence() {
+        let mut cursor = CursorStyle::new();
+        cursor.process(b"abc\x1b[");
+        cursor.process(b"5");
+        cursor.process(b" q");
+        assert_eq!(cursor.style(), 5);
+    }
+
+    #[test]
+    fn test_ignores_other_sequences() {
+        let mut cursor = CursorStyle::new();
+        cursor.process(b"\x1b[5 p\x1b[5q\x1b[123 q\x1b]0;title\x07");
+        assert_eq!(cursor.style(), 0);
+    }
+}
@@ -0,0 +1,141 @@
+//! Tracking the working directory shells report with OSC 7
+//! (`ESC ] 7 ; file://host/path ST`), so new sessions can be opened where
+//! an existing one is.
+
+/// Longest OSC 7 sequence carried over between chunks.
+const MAX_PARTIAL_LEN: usize = 4096;
+
+const OSC_7: &[u8] = b"\x1b]7;";
+
+/// The working directory last reported by the shell.
+#[derive(Debug, Default)]
+pub struct WorkingDir {
+    cwd: Option<String>,
+    /// Start of a report split across chunks.
+    partial: Vec<u8>,
+}
+
+impl WorkingDir {
+    pub const fn new() -> Self {
+        Self {
+            cwd: None,
+            partial: Vec::new(),
+        }
+    }
+
+    /// The last reported directory.
+    pub fn get(&self) -> Option<&str> {
+        self.cwd.as_deref()
+    }
+
+    /// Scan a chunk of PTY output for directory reports.
+    pub fn process(&mut self, data: &[u8]) {
+        let joined;
+        let bytes = if self.partial.is_empty() {
+            data
+        } else {
+            self.partial.extend_from_slice(data);
+            joined = std::mem::take(&mut self.partial);
+            &joined
+        };
+
+        let mut i = 0;
+        while let Some(offset) = find(&bytes[i..], OSC_7) {
+            let start = i + offset;
+            let body_start = start + OSC_7.len();
+            let Some((body_len, terminator_len)) = terminator(&bytes[body_start..]) else {
+                if bytes.len() - start <= MAX_PARTIAL_LEN {
+                    self.partial = bytes[start..].to_vec();
+                }
+                return;
+            };
+            if let Some(path) = parse_file_url(&bytes[body_start..body_start + body_len])
+                && self.cwd.as_ref() != Some(&path)
+            {
+                self.cwd = Some(path);
+                crate::SESSION_INFO_CHANGED.notify_one();
+            }
+            i = body_start + body_len + terminator_len;
+        }
+
+        // Hold back a trailing ESC that may start the next report
+        let tail = (1..=OSC_7.len().min(bytes.len() - i))
+            .rev()
+            .find(|&len| OSC_7.starts_with(&bytes[bytes.len() - len..]))
+            .unwrap_or(0);
+        self.partial = bytes[bytes.len() - tail..].to_vec();
+    }
+}
+
+/// The path in a `file://host/path` URL, percent-decoded. The host is
+/// ignored: it names the machine the shell runs on, which is this one.
+fn parse_file_url(url: &[u8]) -> Option<String> {
+    let rest = url.strip_prefix(b"file://")?;
+    let path = &rest[rest.iter().position(|&b| b == b'/')?..];
+
+    let mut decoded = Vec::with_capacity(path.len());
+    let mut i = 0;
+    while i < path.len() {
+        let hex = path
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (path[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Length of the OSC body in `bytes` and of its BEL or ST terminator.
+fn terminator(bytes: &[u8]) -> Option<(usize, usize)> {
+    let end = bytes.iter().position(|&b| b == 0x07 || b == 0x1b)?;
+    match bytes[end] {
+        0x07 => Some((end, 1)),
+        _ if bytes.get(end + 1) == Some(&b'\\') => Some((end, 2)),
+        _ => None,
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// The session's working directory: the last one the shell reported, or
+/// else the child's own.
+pub fn current() -> Option<String> {
+    if let Some(cwd) = crate::SCROLLBACK.read().cwd() {
+        return Some(cwd.to_string());
+    }
+    let pid = *crate::CHILD_PID.get()?;
+    let cwd = crate::procenv::read_process_cwd(pid).ok()?;
+    cwd.to_str().map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracks_reports() {
+        let mut cwd = WorkingDir::new();
+        cwd.process(b"$ cd src\r\n\x1b]7;file://host/home/me/my%20src\x07$ ");
+        assert_eq!(cwd.get(), Some("/home/me/my src"));
+
+        cwd.process(b"\x1b]7;file://host/tm");
+        cwd.process(b"p\x1b\\");
+        assert_eq!(cwd.get(), Some("/tmp"));
+
+        cwd.process(b"\x1b]7;not a url\x07");
+        assert_eq!(cwd.get(), Some("/tmp"));
+    }
+}
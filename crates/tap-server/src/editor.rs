@@ -27,18 +27,18 @@ pub fn open_scrollback_in_editor(
         .wrap_err("failed to flush temporary file")?;
     let temp_path = temp_file.path().to_owned();
 
+    // Parse editor command
+    let parts: Vec<&str> = editor_cmd.split_whitespace().collect();
+    let (cmd, args) = parts
+        .split_first()
+        .ok_or_else(|| eyre::eyre!("empty editor command — set $EDITOR or configure tap"))?;
+
     // Restore terminal to cooked mode if we have original termios
     let stdin_fd = unsafe { BorrowedFd::borrow_raw(nix::libc::STDIN_FILENO) };
     if let Some(termios) = orig_termios {
         let _ = nix::sys::termios::tcsetattr(stdin_fd, nix::sys::termios::SetArg::TCSANOW, termios);
     }
 
-    // Parse editor command and spawn
-    let parts: Vec<&str> = editor_cmd.split_whitespace().collect();
-    let (cmd, args) = parts
-        .split_first()
-        .ok_or_else(|| eyre::eyre!("empty editor command — set $EDITOR or configure tap"))?;
-
     // Build editor arguments with position support
     let (pos_args, file_arg) = tap_editor::build_editor_args(cmd, &temp_path, cursor_pos);
 
@@ -47,13 +47,12 @@ pub fn open_scrollback_in_editor(
     command.args(pos_args);
     command.arg(&file_arg);
 
-    let status = command
-        .status()
-        .wrap_err_with(|| format!("failed to spawn editor '{cmd}'"))?;
-
-    if !status.success() {
-        tracing::warn!("editor exited with status: {status}");
-    }
+    // Raw mode is restored below whether or not the editor ran
+    let result = match command.status() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(eyre::eyre!("editor '{editor_cmd}' exited with {status}")),
+        Err(e) => Err(eyre::eyre!("failed to start editor '{editor_cmd}': {e}")),
+    };
 
     // Restore raw mode
     let stdin_fd = unsafe { BorrowedFd::borrow_raw(nix::libc::STDIN_FILENO) };
@@ -64,5 +63,22 @@ pub fn open_scrollback_in_editor(
         .wrap_err("failed to restore raw terminal mode")?;
 
     // Temp file is automatically deleted when temp_file drops
-    Ok(())
+    result
+}
+
+/// Write scrollback content to a temporary file that outlives this process.
+/// Used when there is no local terminal to open an editor in.
+pub fn dump_scrollback_to_file(scrollback_content: &str) -> eyre::Result<std::path::PathBuf> {
+    let mut temp_file = tempfile::Builder::new()
+        .prefix("tap-scrollback-")
+        .suffix(".txt")
+        .tempfile()
+        .wrap_err("failed to create temporary file for scrollback")?;
+    temp_file
+        .write_all(scrollback_content.as_bytes())
+        .wrap_err("failed to write scrollback to temporary file")?;
+    let (_, path) = temp_file
+        .keep()
+        .wrap_err("failed to persist scrollback file")?;
+    Ok(path)
 }
@@ -0,0 +1,83 @@
+//! Keeping ended sessions in sessions.json for a while, with their exit
+//! codes, so `tap wait` can report how a session ended after it's gone.
+
+/// How long ended sessions are kept (`[sessions] keep_ended_secs`).
+static RETENTION: std::sync::OnceLock<chrono::Duration> = std::sync::OnceLock::new();
+
+/// Configure how long ended sessions are kept.
+pub(crate) fn init(config: &tap_config::SessionsConfig) {
+    let secs = i64::try_from(config.keep_ended_secs).unwrap_or(i64::MAX);
+    let _ = RETENTION.set(chrono::Duration::try_seconds(secs).unwrap_or(chrono::Duration::MAX));
+}
+
+fn retention() -> chrono::Duration {
+    RETENTION
+        .get()
+        .copied()
+        .unwrap_or_else(chrono::Duration::zero)
+}
+
+/// Whether a sessions.json entry is for a session that has ended.
+pub(crate) fn is_ended(entry: &serde_json::Value) -> bool {
+    entry.get("ended").is_some()
+}
+
+/// Mark `session_id`'s entry ended with `exit_code` (null if unknown), or
+/// remove it if ended sessions aren't kept, and drop ended entries past the
+/// retention window.
+pub(crate) fn record(
+    sessions: &mut Vec<serde_json::Value>,
+    session_id: &str,
+    exit_code: Option<i32>,
+) {
+    let now = chrono::Utc::now();
+    let retention = retention();
+    for entry in sessions.iter_mut() {
+        if entry.get("id").and_then(|v| v.as_str()) == Some(session_id) && !is_ended(entry) {
+            entry["ended"] = serde_json::json!(now.to_rfc3339());
+            entry["exit_code"] = serde_json::json!(exit_code);
+            entry["attached"] = serde_json::json!(false);
+        }
+    }
+    prune(sessions, now, retention);
+}
+
+/// Drop entries that ended more than `retention` before `now`.
+pub(crate) fn prune(
+    sessions: &mut Vec<serde_json::Value>,
+    now: chrono::DateTime<chrono::Utc>,
+    retention: chrono::Duration,
+) {
+    sessions.retain(|entry| {
+        let Some(ended) = entry.get("ended") else {
+            return true;
+        };
+        ended
+            .as_str()
+            .and_then(|ended| chrono::DateTime::parse_from_rfc3339(ended).ok())
+            .is_some_and(|ended| now - ended.with_timezone(&chrono::Utc) < retention)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prune() {
+        let now = chrono::DateTime::parse_from_rfc3339("2024-01-02T00:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let mut sessions = vec![
+            serde_json::json!({ "id": "live" }),
+            serde_json::json!({ "id": "recent", "ended": "2024-01-01T23:30:00+00:00", "exit_code": 0 }),
+            serde_json::json!({ "id": "old", "ended": "2024-01-01T12:00:00+00:00", "exit_code": 1 }),
+        ];
+        prune(&mut sessions, now, chrono::Duration::hours(1));
+        let ids: Vec<_> = sessions.iter().map(|s| s["id"].as_str().unwrap()).collect();
+        assert_eq!(ids, ["live", "recent"]);
+
+        prune(&mut sessions, now, chrono::Duration::zero());
+        assert_eq!(sessions.len(), 1);
+    }
+}
@@ -0,0 +1,69 @@
+//! Structured session events for `Request::SubscribeEvents`.
+
+static EVENTS: std::sync::LazyLock<tokio::sync::broadcast::Sender<tap_protocol::Event>> =
+    std::sync::LazyLock::new(|| tokio::sync::broadcast::channel(64).0);
+
+/// Tell event subscribers something happened.
+pub fn emit(event: tap_protocol::Event) {
+    let _ = EVENTS.send(event);
+}
+
+/// One client's event stream, limited to the kinds it asked for.
+pub struct Subscription {
+    rx: tokio::sync::broadcast::Receiver<tap_protocol::Event>,
+    kinds: Vec<tap_protocol::EventKind>,
+}
+
+impl Subscription {
+    pub fn new(kinds: Vec<tap_protocol::EventKind>) -> Self {
+        Self {
+            rx: EVENTS.subscribe(),
+            kinds,
+        }
+    }
+
+    async fn next(&mut self) -> tap_protocol::Event {
+        loop {
+            match self.rx.recv().await {
+                Ok(event) if self.kinds.is_empty() || self.kinds.contains(&event.kind()) => {
+                    return event;
+                }
+                Ok(_) => {}
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::debug!("event subscriber lagged, skipped {skipped} events");
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => {
+                    std::future::pending::<()>().await;
+                }
+            }
+        }
+    }
+}
+
+/// The next event for `subscription`, or never without one.
+pub async fn next(subscription: Option<&mut Subscription>) -> tap_protocol::Event {
+    match subscription {
+        Some(subscription) => subscription.next().await,
+        None => std::future::pending().await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_filters_kinds() {
+        let mut subscription = Subscription::new(vec![tap_protocol::EventKind::Title]);
+        emit(tap_protocol::Event::Bell);
+        emit(tap_protocol::Event::Title {
+            title: "vim".to_string(),
+        });
+        assert_eq!(
+            next(Some(&mut subscription)).await,
+            tap_protocol::Event::Title {
+                title: "vim".to_string()
+            }
+        );
+    }
+}
@@ -0,0 +1,439 @@
+//! Running one command in a session's shell and capturing just its output,
+//! and capturing the output that follows injected input.
+//!
+//! The command is typed into the shell between two `printf`s that print
+//! markers unique to the run; its output is whatever the PTY produces
+//! between them, and the exit status rides along in the closing marker.
+//! Marker text is assembled by `printf` so the shell's echo of the typed
+//! line never matches. Needs a POSIX-style shell (sh, bash, zsh).
+//!
+//! Interactive runs also watch for the command stopping at a prompt for
+//! input (a password, a `[y/N]` confirmation) and report it, so the client
+//! can answer instead of waiting out the timeout.
+
+/// Output kept while waiting for a command to finish.
+const MAX_CAPTURE_BYTES: usize = 16 * 1024 * 1024;
+/// How long output must pause on a prompt-like line before it's reported.
+const PROMPT_IDLE: std::time::Duration = std::time::Duration::from_millis(300);
+
+static NEXT_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1);
+
+/// Run `command` in the session's shell and wait for its output and exit
+/// status, giving up after `timeout` if one is set. With `prompts`, sends
+/// `InputPrompt` there whenever the command seems to wait for input.
+pub async fn run(
+    command: &str,
+    timeout: Option<std::time::Duration>,
+    prompts: Option<tokio::sync::mpsc::UnboundedSender<tap_protocol::Response>>,
+    output_rx: tokio::sync::broadcast::Receiver<Vec<u8>>,
+    input_tx: &crate::InputSender,
+) -> tap_protocol::Response {
+    let id = format!(
+        "{}x{}",
+        std::process::id(),
+        NEXT_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+    );
+    let output_rx = crate::sequence::subscribe(&output_rx);
+    if input_tx.send(wrap(command, &id).into_bytes()).is_err() {
+        return session_ended();
+    }
+
+    let end_marker = format!("__tap_end_{id}_").into_bytes();
+    let watch = prompts.map(|tx| PromptWatch {
+        id: id.clone(),
+        tx,
+        checked_at: None,
+    });
+    let mut result = None;
+    let captured = capture(output_rx, timeout, watch, |raw, scan_from| {
+        // Only look for the end where the latest chunk could complete it
+        let scan_from = scan_from.saturating_sub(end_marker.len());
+        if find(&raw[scan_from..], &end_marker).is_some()
+            && let Some((output, exit_code)) = extract(raw, &id)
+        {
+            result = Some((output.to_vec(), exit_code));
+            return Some(raw.len());
+        }
+        None
+    })
+    .await;
+
+    match captured {
+        Ok(_) => {
+            let (output, exit_code) = result.expect("set when the capture ends");
+            tap_protocol::Response::CommandResult {
+                output: render(&output),
+                exit_code,
+            }
+        }
+        Err(CaptureError::TimedOut(_)) => error("timed out waiting for the command to finish"),
+        Err(e) => error(e.message()),
+    }
+}
+
+/// Inject `data` and capture the output from then until `until`.
+pub async fn inject_and_capture(
+    data: Vec<u8>,
+    until: &tap_protocol::CaptureEnd,
+    timeout: Option<std::time::Duration>,
+    output_rx: tokio::sync::broadcast::Receiver<Vec<u8>>,
+    input_tx: &crate::InputSender,
+) -> tap_protocol::Response {
+    let pattern = match until {
+        tap_protocol::CaptureEnd::Regex { pattern } => match regex::Regex::new(pattern) {
+            Ok(pattern) => Some(pattern),
+            Err(e) => return error(format!("invalid pattern: {e}")),
+        },
+        _ => None,
+    };
+    let output_rx = crate::sequence::subscribe(&output_rx);
+    if input_tx.send(data).is_err() {
+        return session_ended();
+    }
+
+    let captured = match until {
+        tap_protocol::CaptureEnd::Timeout { ms } => {
+            let window = std::time::Duration::from_millis(*ms);
+            match capture(output_rx, Some(window), None, |_, _| None).await {
+                Err(CaptureError::TimedOut(raw)) => Ok(render(&raw)),
+                Err(e) => Err(e),
+                Ok(_) => unreachable!("capture without an end only stops on error"),
+            }
+        }
+        tap_protocol::CaptureEnd::PromptMark => {
+            capture(output_rx, timeout, None, |raw, scan_from| {
+                let scan_from = scan_from.saturating_sub(PROMPT_MARK.len());
+                find(&raw[scan_from..], PROMPT_MARK).map(|at| scan_from + at)
+            })
+            .await
+            .map(|raw| render(&raw))
+        }
+        tap_protocol::CaptureEnd::Regex { .. } => {
+            let pattern = pattern.expect("compiled above");
+            // Search only the lines the latest chunk touched, rather than
+            // rendering everything again for each chunk
+            capture(output_rx, timeout, None, |raw, scan_from| {
+                let line_start = raw[..scan_from]
+                    .iter()
+                    .rposition(|&b| b == b'\n')
+                    .map_or(0, |newline| newline + 1);
+                pattern
+                    .is_match(&render(&raw[line_start..]))
+                    .then_some(raw.len())
+            })
+            .await
+            .map(|raw| {
+                let text = render(&raw);
+                let end = pattern.find(&text).map_or(text.len(), |found| found.end());
+                text[..end].to_string()
+            })
+        }
+    };
+
+    match captured {
+        Ok(output) => tap_protocol::Response::Captured { output },
+        Err(e) => error(e.message()),
+    }
+}
+
+/// Shell integration's mark for the start of a prompt (OSC 133;A).
+const PROMPT_MARK: &[u8] = b"\x1b]133;A";
+
+enum CaptureError {
+    /// Gave up waiting; what was captured so far.
+    TimedOut(Vec<u8>),
+    Other(&'static str),
+}
+
+impl CaptureError {
+    fn message(&self) -> &'static str {
+        match self {
+            Self::TimedOut(_) => "timed out waiting for the output to end",
+            Self::Other(message) => message,
+        }
+    }
+}
+
+/// Reports a running command stopping at a prompt for input.
+struct PromptWatch {
+    /// ID of the run, whose begin marker starts the command's output.
+    id: String,
+    tx: tokio::sync::mpsc::UnboundedSender<tap_protocol::Response>,
+    /// Output length when last checked, so a pause that's gone on without
+    /// new output isn't checked (or its prompt reported) again.
+    checked_at: Option<usize>,
+}
+
+impl PromptWatch {
+    /// Output has paused; report it if it stopped on a prompt.
+    fn check(&mut self, raw: &[u8]) {
+        if self.checked_at == Some(raw.len()) {
+            return;
+        }
+        self.checked_at = Some(raw.len());
+        let begin = format!("__tap_begin_{}__", self.id);
+        let Some(start) = find(raw, begin.as_bytes()) else {
+            return;
+        };
+        let echo = echoing();
+        let Some(prompt) = prompt_line(&raw[start + begin.len()..], echo) else {
+            return;
+        };
+        let _ = self
+            .tx
+            .send(tap_protocol::Response::InputPrompt { prompt, echo });
+    }
+}
+
+/// The line output stopped on if it looks like the command is asking for
+/// input: it ends like a question or a `[y/N]` choice, or the terminal
+/// stopped echoing (as programs do to read passwords).
+fn prompt_line(raw: &[u8], echo: bool) -> Option<String> {
+    // The cursor sits on a fresh line
+    if raw[raw.trim_ascii_end().len()..].contains(&b'\n') {
+        return None;
+    }
+    let line_start = raw
+        .iter()
+        .rposition(|&b| b == b'\n')
+        .map_or(0, |newline| newline + 1);
+    let output = render(&raw[line_start..]);
+    let line = output.lines().last()?.trim_end();
+    if line.is_empty() {
+        return None;
+    }
+    let lower = line.to_ascii_lowercase();
+    let asks = line.ends_with(':')
+        || line.ends_with('?')
+        || ["[y/n]", "(y/n)", "[yes/no]", "(yes/no)"]
+            .iter()
+            .any(|choice| lower.ends_with(choice));
+    (asks || !echo).then(|| line.to_string())
+}
+
+/// Whether the PTY echoes input, as it does unless a program reading a
+/// password turned it off.
+fn echoing() -> bool {
+    let Some(&master_fd) = crate::MASTER_FD.get() else {
+        return true;
+    };
+    let fd = unsafe { std::os::fd::BorrowedFd::borrow_raw(master_fd) };
+    match nix::sys::termios::tcgetattr(fd) {
+        Ok(termios) => termios
+            .local_flags
+            .contains(nix::sys::termios::LocalFlags::ECHO),
+        Err(_) => true,
+    }
+}
+
+/// Collect output until `end` returns where in the collected bytes it ends,
+/// given them and the offset where the latest chunk starts. `output_rx`
+/// comes with the sequence number it starts at, so output it lags behind on
+/// can be picked up from the replay log. With `prompts`, pauses in the
+/// output are checked for prompts.
+async fn capture(
+    (mut output_rx, mut seq): (tokio::sync::broadcast::Receiver<Vec<u8>>, u64),
+    timeout: Option<std::time::Duration>,
+    mut prompts: Option<PromptWatch>,
+    mut end: impl FnMut(&[u8], usize) -> Option<usize>,
+) -> Result<Vec<u8>, CaptureError> {
+    let mut raw = Vec::new();
+    let collect = async {
+        loop {
+            let received = if let Some(watch) = &mut prompts {
+                match tokio::time::timeout(PROMPT_IDLE, output_rx.recv()).await {
+                    Ok(received) => received,
+                    Err(_) => {
+                        watch.check(&raw);
+                        continue;
+                    }
+                }
+            } else {
+                output_rx.recv().await
+            };
+            let data = match received {
+                Ok(data) => data,
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {
+                    match crate::sequence::subscribe_from(&output_rx, seq) {
+                        Ok((resubscribed, missed)) => {
+                            output_rx = resubscribed;
+                            missed
+                        }
+                        Err(_) => return Err("output came too fast to capture"),
+                    }
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => {
+                    return Err("session ended");
+                }
+            };
+            seq += data.len() as u64;
+            let scan_from = raw.len();
+            raw.extend_from_slice(&data);
+            if let Some(len) = end(&raw, scan_from) {
+                raw.truncate(len);
+                return Ok(());
+            }
+            if raw.len() > MAX_CAPTURE_BYTES {
+                return Err("too much output to capture");
+            }
+        }
+    };
+
+    let result = match timeout {
+        Some(timeout) => {
+            let outcome = tokio::time::timeout(timeout, collect).await;
+            match outcome {
+                Ok(result) => result,
+                Err(_) => return Err(CaptureError::TimedOut(raw)),
+            }
+        }
+        None => collect.await,
+    };
+    match result {
+        Ok(()) => Ok(raw),
+        Err(message) => Err(CaptureError::Other(message)),
+    }
+}
+
+/// Raw output as plain text at the PTY's width.
+fn render(raw: &[u8]) -> String {
+    let cols = crate::MASTER_FD
+        .get()
+        .map_or(80, |&fd| crate::resize::current_size(fd).1);
+    crate::scrollback::render_text(raw, cols)
+}
+
+fn error(message: impl Into<String>) -> tap_protocol::Response {
+    tap_protocol::Response::Error {
+        code: tap_protocol::ErrorCode::Other,
+        message: message.into(),
+    }
+}
+
+fn session_ended() -> tap_protocol::Response {
+    tap_protocol::Response::Error {
+        code: tap_protocol::ErrorCode::SessionEnded,
+        message: "session ended".to_string(),
+    }
+}
+
+/// The line typed into the shell for `command`. The leading space keeps it
+/// out of history in shells set to ignore such lines.
+fn wrap(command: &str, id: &str) -> String {
+    let quoted = command.replace('\'', r"'\''");
+    format!(
+        " printf '__tap_%s_%s__\\n' begin {id}; eval '{quoted}'; printf '__tap_%s_%s_%s__\\n' end {id} \"$?\"\n"
+    )
+}
+
+/// The output between a run's markers and its exit status, once the closing
+/// marker has arrived.
+fn extract<'a>(raw: &'a [u8], id: &str) -> Option<(&'a [u8], i32)> {
+    let begin = format!("__tap_begin_{id}__");
+    let start = find(raw, begin.as_bytes())? + begin.len();
+    let mut rest = &raw[start..];
+    for newline in [&b"\r\n"[..], b"\n"] {
+        if let Some(stripped) = rest.strip_prefix(newline) {
+            rest = stripped;
+            break;
+        }
+    }
+
+    let end = format!("__tap_end_{id}_");
+    let output_len = find(rest, end.as_bytes())?;
+    let status = &rest[output_len + end.len()..];
+    let status_len = find(status, b"__")?;
+    let exit_code = std::str::from_utf8(&status[..status_len])
+        .ok()?
+        .parse()
+        .ok()?;
+    Some((&rest[..output_len], exit_code))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_output_between_markers() {
+        let raw =
+            b"$  printf '__tap_%s_%s__\\n' begin 1x1; eval 'ls'; printf ... end 1x1 \"$?\"\r\n\
+            __tap_begin_1x1__\r\nCargo.toml\r\nsrc\r\n__tap_end_1x1_0__\r\n$ ";
+        let (output, exit_code) = extract(raw, "1x1").unwrap();
+        assert_eq!(output, b"Cargo.toml\r\nsrc\r\n");
+        assert_eq!(exit_code, 0);
+    }
+
+    #[test]
+    fn test_extract_waits_for_status() {
+        assert!(extract(b"__tap_begin_2x1__\r\nout", "2x1").is_none());
+        assert!(extract(b"__tap_begin_2x1__\r\nout__tap_end_2x1_1", "2x1").is_none());
+        let (output, exit_code) =
+            extract(b"__tap_begin_2x1__\r\nout__tap_end_2x1_101__", "2x1").unwrap();
+        assert_eq!(output, b"out");
+        assert_eq!(exit_code, 101);
+    }
+
+    async fn capture_reply(until: tap_protocol::CaptureEnd, output: &[u8]) -> String {
+        let (output_tx, output_rx) = tokio::sync::broadcast::channel(16);
+        let (input_tx, mut input_rx) = tokio::sync::mpsc::unbounded_channel();
+        let capture = inject_and_capture(b"ls\r".to_vec(), &until, None, output_rx, &input_tx);
+        let feed = async {
+            assert_eq!(input_rx.recv().await.unwrap(), b"ls\r");
+            output_tx.send(output.to_vec()).unwrap();
+        };
+        match tokio::join!(capture, feed).0 {
+            tap_protocol::Response::Captured { output } => output,
+            other => panic!("unexpected response: {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_capture_until_prompt_mark() {
+        let output = capture_reply(
+            tap_protocol::CaptureEnd::PromptMark,
+            b"ls\r\na  b\r\n\x1b]133;A\x07$ ",
+        )
+        .await;
+        assert_eq!(output, "ls\na  b");
+    }
+
+    #[tokio::test]
+    async fn test_capture_until_regex() {
+        let until = tap_protocol::CaptureEnd::Regex {
+            pattern: "b+".to_string(),
+        };
+        let output = capture_reply(until, b"ls\r\na  bb\r\n$ ").await;
+        assert_eq!(output, "ls\na  bb");
+    }
+
+    #[test]
+    fn test_prompt_line() {
+        let prompt = prompt_line(b"\r\nRemoving 3 files\r\nProceed? [y/N] ", true);
+        assert_eq!(prompt.as_deref(), Some("Proceed? [y/N]"));
+        assert_eq!(
+            prompt_line(b"[sudo] password for me: ", true).as_deref(),
+            Some("[sudo] password for me:")
+        );
+        assert_eq!(
+            prompt_line(b"Enter PIN", false).as_deref(),
+            Some("Enter PIN")
+        );
+        assert_eq!(prompt_line(b"Compiling tap", true), None);
+        assert_eq!(prompt_line(b"done\r\n", false), None);
+    }
+
+    #[test]
+    fn test_wrap_quotes_command() {
+        let line = wrap("echo 'hi' # done", "1x2");
+        assert!(line.contains(r"eval 'echo '\''hi'\'' # done';"));
+        assert!(!line.contains("__tap_begin_1x2__"));
+        assert!(!line.contains("__tap_end_1x2_"));
+    }
+}
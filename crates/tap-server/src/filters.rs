@@ -0,0 +1,234 @@
+//! Output filters applied between reading the PTY and each destination.
+//!
+//! Each destination — scrollback, the broadcast to API subscribers, and
+//! terminals displaying the session — has its own [`Pipeline`] of
+//! [`OutputFilter`]s configured in `[filters]`. Filters keep state across
+//! chunks, so each pipeline sees the whole output stream in order.
+
+pub use tap_protocol::ansi::StripAnsi;
+
+use crate::progress::ProgressFilter;
+
+/// A transformation of the output stream.
+pub trait OutputFilter: Send {
+    /// Rewrite a chunk of output for a terminal `cols` wide.
+    fn process(&mut self, data: &[u8], cols: u16) -> Vec<u8>;
+}
+
+impl OutputFilter for ProgressFilter {
+    fn process(&mut self, data: &[u8], cols: u16) -> Vec<u8> {
+        ProgressFilter::process(self, data, cols)
+    }
+}
+
+/// Hides matches of secret-looking patterns.
+///
+/// Chunks are filtered one at a time, so a match split across two reads
+/// of the PTY isn't caught.
+pub struct Redact {
+    patterns: Vec<regex::bytes::Regex>,
+}
+
+impl OutputFilter for Redact {
+    fn process(&mut self, data: &[u8], _cols: u16) -> Vec<u8> {
+        let mut data = data.to_vec();
+        for pattern in &self.patterns {
+            if pattern.is_match(&data) {
+                data = pattern.replace_all(&data, &b"[redacted]"[..]).into_owned();
+            }
+        }
+        data
+    }
+}
+
+/// Prefixes each line with the local time it started.
+pub struct Timestamp {
+    at_line_start: bool,
+}
+
+impl Timestamp {
+    pub const fn new() -> Self {
+        Self {
+            at_line_start: true,
+        }
+    }
+}
+
+impl OutputFilter for Timestamp {
+    fn process(&mut self, data: &[u8], _cols: u16) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len());
+        for &byte in data {
+            if self.at_line_start && !matches!(byte, b'\r' | b'\n') {
+                out.extend_from_slice(
+                    chrono::Local::now()
+                        .format("[%H:%M:%S] ")
+                        .to_string()
+                        .as_bytes(),
+                );
+                self.at_line_start = false;
+            }
+            if byte == b'\n' {
+                self.at_line_start = true;
+            }
+            out.push(byte);
+        }
+        out
+    }
+}
+
+impl OutputFilter for StripAnsi {
+    fn process(&mut self, data: &[u8], _cols: u16) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len());
+        self.strip(data, &mut out);
+        out
+    }
+}
+
+/// Filters applied in order to output bound for one destination.
+#[derive(Default)]
+pub struct Pipeline {
+    filters: Vec<Box<dyn OutputFilter>>,
+}
+
+impl Pipeline {
+    pub const fn new(filters: Vec<Box<dyn OutputFilter>>) -> Self {
+        Self { filters }
+    }
+
+    /// Build the pipeline `kinds` describes.
+    pub fn from_config(
+        kinds: &[tap_config::OutputFilter],
+        config: &tap_config::FiltersConfig,
+    ) -> eyre::Result<Self> {
+        let filters = kinds
+            .iter()
+            .map(|kind| -> eyre::Result<Box<dyn OutputFilter>> {
+                Ok(match kind {
+                    tap_config::OutputFilter::Redact => {
+                        let patterns = config
+                            .redact
+                            .iter()
+                            .map(|pattern| {
+                                regex::bytes::Regex::new(pattern).map_err(|e| {
+                                    eyre::eyre!("invalid redact pattern '{pattern}': {e}")
+                                })
+                            })
+                            .collect::<eyre::Result<_>>()?;
+                        Box::new(Redact { patterns })
+                    }
+                    tap_config::OutputFilter::CollapseProgress => Box::new(ProgressFilter::new()),
+                    tap_config::OutputFilter::Timestamp => Box::new(Timestamp::new()),
+                })
+            })
+            .collect::<eyre::Result<_>>()?;
+        Ok(Self::new(filters))
+    }
+
+    /// Run a chunk through every filter, borrowing it if there are none.
+    pub fn process<'a>(&mut self, data: &'a [u8], cols: u16) -> std::borrow::Cow<'a, [u8]> {
+        let mut data = std::borrow::Cow::Borrowed(data);
+        for filter in &mut self.filters {
+            data = std::borrow::Cow::Owned(filter.process(&data, cols));
+        }
+        data
+    }
+
+    fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+}
+
+/// Pipelines for the destinations outside scrollback, which keeps its own.
+struct Sinks {
+    broadcast: Pipeline,
+    display: Pipeline,
+}
+
+static SINKS: parking_lot::Mutex<Option<Sinks>> = parking_lot::Mutex::new(None);
+
+/// Set up pipelines from `[filters]`, returning the one for scrollback.
+pub fn init(config: &tap_config::Config) -> eyre::Result<Pipeline> {
+    let filters = &config.filters;
+    let mut scrollback = filters.scrollback.clone();
+    if config.scrollback.collapse_progress
+        && !scrollback.contains(&tap_config::OutputFilter::CollapseProgress)
+    {
+        scrollback.push(tap_config::OutputFilter::CollapseProgress);
+    }
+
+    *SINKS.lock() = Some(Sinks {
+        broadcast: Pipeline::from_config(&filters.broadcast, filters)?,
+        display: Pipeline::from_config(&filters.display, filters)?,
+    });
+    Pipeline::from_config(&scrollback, filters)
+}
+
+/// Output as streamed to API subscribers.
+pub fn broadcast(data: Vec<u8>) -> Vec<u8> {
+    apply(data, |sinks| &mut sinks.broadcast)
+}
+
+/// Output as shown in terminals.
+pub fn display(data: Vec<u8>) -> Vec<u8> {
+    apply(data, |sinks| &mut sinks.display)
+}
+
+fn apply(data: Vec<u8>, pipeline: impl FnOnce(&mut Sinks) -> &mut Pipeline) -> Vec<u8> {
+    let mut sinks = SINKS.lock();
+    let Some(pipeline) = sinks.as_mut().map(pipeline) else {
+        return data;
+    };
+    if pipeline.is_empty() {
+        return data;
+    }
+    let cols = crate::SCROLLBACK.read().size().1;
+    pipeline.process(&data, cols).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pipeline_in_order() {
+        let config = tap_config::FiltersConfig {
+            redact: vec![r"ghp_[A-Za-z0-9]+".to_string()],
+            ..Default::default()
+        };
+        let mut pipeline = Pipeline::from_config(
+            &[
+                tap_config::OutputFilter::Redact,
+                tap_config::OutputFilter::Timestamp,
+            ],
+            &config,
+        )
+        .unwrap();
+
+        let out = pipeline.process(b"token ghp_abc123\r\nnext", 80);
+        let out = String::from_utf8(out.into_owned()).unwrap();
+        let lines: Vec<_> = out.split("\r\n").collect();
+        assert!(lines[0].starts_with('[') && lines[0].ends_with("] token [redacted]"));
+        assert!(lines[1].ends_with("] next"));
+
+        // Mid-line, so no timestamp
+        assert_eq!(pipeline.process(b" more", 80), &b" more"[..]);
+    }
+
+    #[test]
+    fn test_strip_ansi_across_chunks() {
+        let mut strip = StripAnsi::default();
+        let mut out = strip.process(b"\x1b[1;31mred\x1b[", 80);
+        out.extend(strip.process(b"0m\r\n\x1b]0;title\x1b", 80));
+        out.extend(strip.process(b"\\done\x07\n", 80));
+        assert_eq!(out, b"red\ndone\n");
+    }
+
+    #[test]
+    fn test_empty_pipeline_borrows() {
+        let mut pipeline = Pipeline::default();
+        assert!(matches!(
+            pipeline.process(b"abc", 80),
+            std::borrow::Cow::Borrowed(b"abc")
+        ));
+    }
+}
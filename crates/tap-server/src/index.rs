@@ -0,0 +1,139 @@
+//! Keeping sessions.json intact. Content that doesn't parse as a list of
+//! session entries is backed up rather than overwritten, and whatever
+//! entries can still be read are kept. Each server also remembers its own
+//! entry so `tap repair-index` can put it back.
+
+use std::path::Path;
+
+/// This server's entry as last written, for [`reregister`].
+static OWN_ENTRY: parking_lot::Mutex<Option<serde_json::Value>> = parking_lot::Mutex::new(None);
+
+fn is_entry(value: &serde_json::Value) -> bool {
+    value
+        .get("id")
+        .and_then(serde_json::Value::as_str)
+        .is_some()
+}
+
+/// Parse the index, recovering what it can from damaged content.
+pub(crate) fn parse(content: &str, path: &Path) -> Vec<serde_json::Value> {
+    if content.trim().is_empty() {
+        return Vec::new();
+    }
+    let problem = match serde_json::from_str::<Vec<serde_json::Value>>(content) {
+        Ok(sessions) if sessions.iter().all(is_entry) => return sessions,
+        Ok(_) => "it has entries without an id".to_string(),
+        Err(e) => e.to_string(),
+    };
+
+    let sessions = recover(content);
+    let backup = path.with_extension("json.bak");
+    match std::fs::write(&backup, content) {
+        Ok(()) => tracing::error!(
+            "{} is corrupt ({problem}); kept {} readable entries and saved the original to {}",
+            path.display(),
+            sessions.len(),
+            backup.display()
+        ),
+        Err(e) => tracing::error!(
+            "{} is corrupt ({problem}); kept {} readable entries, but failed to save the original to {}: {e}",
+            path.display(),
+            sessions.len(),
+            backup.display()
+        ),
+    }
+    sessions
+}
+
+/// Every object in `content` that reads as a session entry, skipping over
+/// anything that doesn't parse.
+fn recover(content: &str) -> Vec<serde_json::Value> {
+    let mut sessions = Vec::new();
+    let mut pos = 0;
+    while let Some(offset) = content[pos..].find('{') {
+        let start = pos + offset;
+        let mut stream =
+            serde_json::Deserializer::from_str(&content[start..]).into_iter::<serde_json::Value>();
+        match stream.next() {
+            Some(Ok(value)) => {
+                pos = start + stream.byte_offset();
+                if is_entry(&value) {
+                    sessions.push(value);
+                }
+            }
+            _ => pos = start + 1,
+        }
+    }
+    sessions
+}
+
+/// Remember this server's entry from the index it's about to write.
+pub(crate) fn remember(sessions: &[serde_json::Value]) {
+    let pid = u64::from(std::process::id());
+    let own = sessions.iter().find(|entry| {
+        entry.get("pid").and_then(serde_json::Value::as_u64) == Some(pid)
+            && !crate::ended::is_ended(entry)
+    });
+    if let Some(own) = own {
+        *OWN_ENTRY.lock() = Some(own.clone());
+    }
+}
+
+/// Put this server's entry back in the index if it's missing.
+pub(crate) fn reregister() -> eyre::Result<()> {
+    let Some(mut entry) = OWN_ENTRY.lock().clone() else {
+        eyre::bail!("session hasn't registered yet");
+    };
+    entry["id"] = serde_json::json!(crate::SESSION_ID.read().clone());
+    let pid = u64::from(std::process::id());
+    crate::modify_sessions_file(&tap_protocol::sessions_file(), |sessions| {
+        let present = sessions
+            .iter()
+            .any(|s| s.get("pid").and_then(serde_json::Value::as_u64) == Some(pid));
+        if !present {
+            sessions.push(entry);
+        }
+    })?;
+    // Bring the working directory and title up to date
+    crate::SESSION_INFO_CHANGED.notify_one();
+    Ok(())
+}
+
+/// Drop entries for running sessions other than `live`, returning how many
+/// were dropped. Ended sessions are left to expire on their own.
+pub fn retain_live(live: &[String]) -> eyre::Result<usize> {
+    let mut dropped = 0;
+    crate::modify_sessions_file(&tap_protocol::sessions_file(), |sessions| {
+        let before = sessions.len();
+        sessions.retain(|entry| {
+            crate::ended::is_ended(entry)
+                || entry
+                    .get("id")
+                    .and_then(serde_json::Value::as_str)
+                    .is_some_and(|id| live.iter().any(|live| live == id))
+        });
+        dropped = before - sessions.len();
+    })?;
+    Ok(dropped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recover() {
+        // A write cut off partway through the second entry
+        let content = r#"[
+  { "id": "calm-fox", "pid": 1, "notes": [{ "time": "t", "text": "hi" }] },
+  { "id": "bold-owl", "pid": 2, "comm"#;
+        let ids: Vec<_> = recover(content)
+            .iter()
+            .map(|s| s["id"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(ids, ["calm-fox"]);
+
+        let content = r#"[{ "id": "a" }, garbage, { "id": "b", "pid": 3 }]"#;
+        assert_eq!(recover(content).len(), 2);
+    }
+}
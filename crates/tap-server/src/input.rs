@@ -1,18 +1,43 @@
-//! Input processing with keybind detection.
+//! Input filter pipelines.
+//!
+//! Input passes through a chain of [`InputFilter`] stages on its way to the
+//! child: keys typed locally through `input_filters.local` (keybind
+//! detection, CSI u translation), and input injected over the socket through
+//! `input_filters.injected` (rate limiting, deny patterns). Each stage can
+//! pass input on, possibly rewritten, fire a keybind, hold it back for more,
+//! or refuse it.
 
 const ESC_BYTE: u8 = 0x1b;
 
-/// Input processor state machine for detecting keybinds.
-pub struct InputProcessor {
-    keybinds: Vec<(tap_config::Keybind, KeybindAction)>,
-    escape_timeout: std::time::Duration,
-    pending_escape: bool,
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum KeybindAction {
     OpenEditor,
     Detach,
+    PasteClipboard,
+    CopyMode,
+    Kill,
+    ScrollbackToFile,
+    ClearScrollback,
+    /// Write these bytes to the PTY.
+    Inject(Vec<u8>),
+    /// Run a shell command.
+    Run(String),
+}
+
+impl From<tap_config::KeyAction> for KeybindAction {
+    fn from(action: tap_config::KeyAction) -> Self {
+        match action {
+            tap_config::KeyAction::Editor => Self::OpenEditor,
+            tap_config::KeyAction::Detach => Self::Detach,
+            tap_config::KeyAction::Paste => Self::PasteClipboard,
+            tap_config::KeyAction::CopyMode => Self::CopyMode,
+            tap_config::KeyAction::Kill => Self::Kill,
+            tap_config::KeyAction::ScrollbackToFile => Self::ScrollbackToFile,
+            tap_config::KeyAction::ClearScrollback => Self::ClearScrollback,
+            tap_config::KeyAction::Inject(text) => Self::Inject(text.into_bytes()),
+            tap_config::KeyAction::Run(command) => Self::Run(command),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -23,37 +48,122 @@ pub enum InputResult {
     Action(KeybindAction),
     /// Need more input (waiting for escape timeout).
     NeedMore,
+    /// A filter refused the input, for this reason.
+    Rejected(String),
 }
 
-impl InputProcessor {
+/// One stage of an input pipeline.
+pub trait InputFilter: Send {
+    /// Filter a chunk of input.
+    fn process(&mut self, bytes: Vec<u8>) -> InputResult;
+
+    /// How long held-back input waits for more, if any is held.
+    fn pending_timeout(&self) -> Option<std::time::Duration> {
+        None
+    }
+
+    /// Release input held back past its timeout.
+    fn timeout(&mut self) -> InputResult {
+        InputResult::Passthrough(Vec::new())
+    }
+}
+
+/// Detects keybinds, holding a lone ESC briefly in case it starts an Alt key.
+///
+/// With a prefix key configured, keybinds are only detected in the input
+/// right after it; all other input passes through as is. The prefix also
+/// enables `prefix_keys`, plain keys that only act after it.
+pub struct Keybinds {
+    keybinds: Vec<(tap_config::Keybind, KeybindAction)>,
+    escape_timeout: std::time::Duration,
+    pending_escape: bool,
+    prefix: Option<tap_config::Keybind>,
+    prefix_timeout: std::time::Duration,
+    /// When the prefix was last pressed, while keybinds are active.
+    armed_at: Option<std::time::Instant>,
+}
+
+impl Keybinds {
     pub fn new(config: &tap_config::Config) -> eyre::Result<Self> {
-        let mut keybinds = Vec::new();
+        let has_prefix = config.keybinds.prefix.is_some();
+        if !config.keybinds.prefix_keys.is_empty() && !has_prefix {
+            eyre::bail!("keybinds.prefix_keys needs keybinds.prefix to be set");
+        }
 
-        let editor_keybind = tap_config::Keybind::parse(&config.keybinds.editor)?;
-        keybinds.push((editor_keybind, KeybindAction::OpenEditor));
+        // Earlier entries win when a key is bound twice
+        let mut bindings: Vec<(&str, tap_config::KeyAction)> = Vec::new();
+        for (key, action) in &config.keybinds.prefix_keys {
+            bindings.push((key, action.clone()));
+        }
+        for (key, action) in &config.keybinds.custom {
+            bindings.push((key, action.clone()));
+        }
+        bindings.push((&config.keybinds.editor, tap_config::KeyAction::Editor));
+        bindings.push((&config.keybinds.detach, tap_config::KeyAction::Detach));
+        if let Some(paste) = &config.keybinds.paste {
+            bindings.push((paste, tap_config::KeyAction::Paste));
+        }
+        if let Some(copy_mode) = &config.keybinds.copy_mode {
+            bindings.push((copy_mode, tap_config::KeyAction::CopyMode));
+        }
 
-        let detach_keybind = tap_config::Keybind::parse(&config.keybinds.detach)?;
-        keybinds.push((detach_keybind, KeybindAction::Detach));
+        let mut keybinds = Vec::with_capacity(bindings.len());
+        for (key, action) in bindings {
+            let keybind = tap_config::Keybind::parse(key)?;
+            // A plain key would take that letter from everything typed
+            if matches!(keybind, tap_config::Keybind::Key(_)) && !has_prefix {
+                eyre::bail!(
+                    "keybind '{key}' needs a modifier (e.g. 'Alt-{key}') or keybinds.prefix"
+                );
+            }
+            keybinds.push((keybind, action.into()));
+        }
+
+        let prefix = config
+            .keybinds
+            .prefix
+            .as_deref()
+            .map(tap_config::Keybind::parse)
+            .transpose()?;
 
         Ok(Self {
             keybinds,
             escape_timeout: std::time::Duration::from_millis(config.timing.escape_timeout_ms),
             pending_escape: false,
+            prefix,
+            prefix_timeout: std::time::Duration::from_millis(config.timing.prefix_timeout_ms),
+            armed_at: None,
         })
     }
 
-    #[must_use]
-    pub fn escape_timeout(&self) -> std::time::Duration {
-        self.escape_timeout
+    /// Whether the prefix was pressed recently enough for this input to be
+    /// checked for keybinds. Each press covers one chunk of input.
+    fn take_armed(&mut self) -> bool {
+        self.armed_at
+            .take()
+            .is_some_and(|at| at.elapsed() <= self.prefix_timeout)
     }
 
-    #[must_use]
-    pub fn has_pending_escape(&self) -> bool {
-        self.pending_escape
+    /// Pass input through untouched unless it starts with the prefix, which
+    /// is swallowed and arms the keybinds.
+    fn wait_for_prefix(&mut self, bytes: Vec<u8>) -> InputResult {
+        let Some(consumed) = self
+            .prefix
+            .as_ref()
+            .and_then(|prefix| prefix.matches(&bytes))
+        else {
+            return InputResult::Passthrough(bytes);
+        };
+        self.armed_at = Some(std::time::Instant::now());
+        if consumed < bytes.len() {
+            return self.process(bytes[consumed..].to_vec());
+        }
+        InputResult::Passthrough(Vec::new())
     }
+}
 
-    /// Process input bytes, returning what action to take.
-    pub fn process(&mut self, bytes: &[u8]) -> InputResult {
+impl InputFilter for Keybinds {
+    fn process(&mut self, bytes: Vec<u8>) -> InputResult {
         tracing::debug!("Input bytes: {:?} (hex: {:02x?})", bytes, bytes);
 
         if bytes.is_empty() {
@@ -64,16 +174,29 @@ impl InputProcessor {
             return InputResult::Passthrough(vec![]);
         }
 
+        if self.prefix.is_some() && !self.pending_escape && !self.take_armed() {
+            return self.wait_for_prefix(bytes);
+        }
+
         // Check if we have a pending escape and new input
         let effective_bytes = if self.pending_escape {
             self.pending_escape = false;
             let mut v = vec![ESC_BYTE];
-            v.extend_from_slice(bytes);
+            v.extend_from_slice(&bytes);
             v
         } else {
-            bytes.to_vec()
+            bytes
         };
 
+        // The prefix pressed twice is sent on
+        if self
+            .prefix
+            .as_ref()
+            .is_some_and(|prefix| prefix.matches(&effective_bytes).is_some())
+        {
+            return InputResult::Passthrough(effective_bytes);
+        }
+
         // Check for keybind matches
         for (keybind, action) in &self.keybinds {
             tracing::debug!(
@@ -86,11 +209,11 @@ impl InputProcessor {
                 // If there are remaining bytes after the keybind, we'd need to handle them
                 // For now, assume keybinds consume all input in that read
                 if consumed == effective_bytes.len() {
-                    return InputResult::Action(*action);
+                    return InputResult::Action(action.clone());
                 }
                 // Partial match with trailing bytes - trigger action, remaining bytes are lost
                 // This is acceptable for our use case
-                return InputResult::Action(*action);
+                return InputResult::Action(action.clone());
             }
         }
 
@@ -103,8 +226,11 @@ impl InputProcessor {
         InputResult::Passthrough(effective_bytes)
     }
 
-    /// Called when escape timeout expires.
-    pub fn timeout_escape(&mut self) -> InputResult {
+    fn pending_timeout(&self) -> Option<std::time::Duration> {
+        self.pending_escape.then_some(self.escape_timeout)
+    }
+
+    fn timeout(&mut self) -> InputResult {
         if self.pending_escape {
             self.pending_escape = false;
             InputResult::Passthrough(vec![ESC_BYTE])
@@ -114,6 +240,265 @@ impl InputProcessor {
     }
 }
 
+/// Translates kitty keyboard protocol keys to traditional terminal input.
+pub struct CsiU;
+
+impl InputFilter for CsiU {
+    fn process(&mut self, bytes: Vec<u8>) -> InputResult {
+        let translated = crate::kitty::translate_all_csi_u(&bytes);
+        if translated != bytes {
+            tracing::debug!("translated CSI u: {:02x?} -> {:02x?}", bytes, translated);
+        }
+        InputResult::Passthrough(translated)
+    }
+}
+
+/// Refuses input once more than a second's worth of the allowed rate has
+/// arrived without time to recover (a token bucket).
+pub struct RateLimit {
+    bytes_per_sec: u64,
+    available: f64,
+    refilled: std::time::Instant,
+}
+
+impl RateLimit {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        Self {
+            bytes_per_sec,
+            available: bytes_per_sec as f64,
+            refilled: std::time::Instant::now(),
+        }
+    }
+}
+
+impl InputFilter for RateLimit {
+    fn process(&mut self, bytes: Vec<u8>) -> InputResult {
+        let now = std::time::Instant::now();
+        let rate = self.bytes_per_sec as f64;
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.available = (self.available + elapsed * rate).min(rate);
+        self.refilled = now;
+
+        let len = bytes.len() as f64;
+        if len > self.available {
+            return InputResult::Rejected(format!(
+                "input rate limit of {} bytes/s exceeded",
+                self.bytes_per_sec
+            ));
+        }
+        self.available -= len;
+        InputResult::Passthrough(bytes)
+    }
+}
+
+/// Refuses input matching any of a set of patterns.
+pub struct Deny {
+    patterns: Vec<regex::bytes::Regex>,
+}
+
+impl Deny {
+    pub fn new(patterns: &[String]) -> eyre::Result<Self> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| {
+                regex::bytes::Regex::new(pattern)
+                    .map_err(|e| eyre::eyre!("invalid deny pattern '{pattern}': {e}"))
+            })
+            .collect::<eyre::Result<_>>()?;
+        Ok(Self { patterns })
+    }
+}
+
+impl InputFilter for Deny {
+    fn process(&mut self, bytes: Vec<u8>) -> InputResult {
+        match self
+            .patterns
+            .iter()
+            .find(|pattern| pattern.is_match(&bytes))
+        {
+            Some(pattern) => {
+                InputResult::Rejected(format!("input matches denied pattern '{pattern}'"))
+            }
+            None => InputResult::Passthrough(bytes),
+        }
+    }
+}
+
+/// A chain of input filters.
+pub struct InputProcessor {
+    stages: Vec<(tap_config::InputFilter, Box<dyn InputFilter>)>,
+}
+
+impl InputProcessor {
+    /// The pipeline for keys typed locally.
+    pub fn new(config: &tap_config::Config) -> eyre::Result<Self> {
+        Self::from_config(&config.input_filters.local, config)
+    }
+
+    /// The pipeline for input injected over the socket.
+    pub fn injected(config: &tap_config::Config) -> eyre::Result<Self> {
+        let kinds = &config.input_filters.injected;
+        if let Some(kind) = kinds.iter().find(|kind| {
+            matches!(
+                kind,
+                tap_config::InputFilter::Keybinds | tap_config::InputFilter::CsiU
+            )
+        }) {
+            eyre::bail!("input filter {kind:?} only applies to local input");
+        }
+        Self::from_config(kinds, config)
+    }
+
+    /// The pipeline for keys typed into terminals attached with `tap attach`,
+    /// past the stages the client runs itself (keybinds, CSI u).
+    pub fn attached(config: &tap_config::Config) -> eyre::Result<Self> {
+        let kinds: Vec<_> = config
+            .input_filters
+            .local
+            .iter()
+            .copied()
+            .filter(|kind| {
+                !matches!(
+                    kind,
+                    tap_config::InputFilter::Keybinds | tap_config::InputFilter::CsiU
+                )
+            })
+            .collect();
+        Self::from_config(&kinds, config)
+    }
+
+    fn from_config(
+        kinds: &[tap_config::InputFilter],
+        config: &tap_config::Config,
+    ) -> eyre::Result<Self> {
+        let stages = kinds
+            .iter()
+            .map(|&kind| -> eyre::Result<_> {
+                let stage: Box<dyn InputFilter> = match kind {
+                    tap_config::InputFilter::Keybinds => Box::new(Keybinds::new(config)?),
+                    tap_config::InputFilter::CsiU => Box::new(CsiU),
+                    tap_config::InputFilter::RateLimit => Box::new(RateLimit::new(
+                        config.input_filters.rate_limit_bytes_per_sec,
+                    )),
+                    tap_config::InputFilter::Deny => {
+                        Box::new(Deny::new(&config.input_filters.deny)?)
+                    }
+                };
+                Ok((kind, stage))
+            })
+            .collect::<eyre::Result<_>>()?;
+        Ok(Self { stages })
+    }
+
+    /// Drop a stage, e.g. one that turned out not to apply to this terminal.
+    pub fn remove(&mut self, kind: tap_config::InputFilter) {
+        self.stages.retain(|(stage_kind, _)| *stage_kind != kind);
+    }
+
+    /// How long until held-back input should be released.
+    #[must_use]
+    pub fn escape_timeout(&self) -> std::time::Duration {
+        self.stages
+            .iter()
+            .filter_map(|(_, stage)| stage.pending_timeout())
+            .min()
+            .unwrap_or_default()
+    }
+
+    #[must_use]
+    pub fn has_pending_escape(&self) -> bool {
+        self.stages
+            .iter()
+            .any(|(_, stage)| stage.pending_timeout().is_some())
+    }
+
+    /// Process input bytes, returning what action to take.
+    #[cfg_attr(
+        feature = "trace",
+        tracing::instrument(level = "debug", skip_all, fields(bytes = bytes.len()))
+    )]
+    pub fn process(&mut self, bytes: &[u8]) -> InputResult {
+        self.run_from(0, bytes.to_vec())
+    }
+
+    /// Called when escape timeout expires.
+    pub fn timeout_escape(&mut self) -> InputResult {
+        let Some(index) = self
+            .stages
+            .iter()
+            .position(|(_, stage)| stage.pending_timeout().is_some())
+        else {
+            return InputResult::Passthrough(vec![]);
+        };
+        match self.stages[index].1.timeout() {
+            InputResult::Passthrough(bytes) => self.run_from(index + 1, bytes),
+            other => other,
+        }
+    }
+
+    fn run_from(&mut self, start: usize, mut bytes: Vec<u8>) -> InputResult {
+        for (_, stage) in &mut self.stages[start..] {
+            match stage.process(bytes) {
+                InputResult::Passthrough(next) => bytes = next,
+                other => return other,
+            }
+        }
+        InputResult::Passthrough(bytes)
+    }
+}
+
+/// The session's pipeline for injected input.
+static INJECTED: parking_lot::Mutex<Option<InputProcessor>> = parking_lot::Mutex::new(None);
+/// The session's pipeline for input from attached terminals.
+static ATTACHED: parking_lot::Mutex<Option<InputProcessor>> = parking_lot::Mutex::new(None);
+
+/// Set up the pipelines for input arriving over the socket.
+pub fn init_injected(config: &tap_config::Config) -> eyre::Result<()> {
+    *INJECTED.lock() = Some(InputProcessor::injected(config)?);
+    *ATTACHED.lock() = Some(InputProcessor::attached(config)?);
+    Ok(())
+}
+
+/// Run input injected over the socket through its filters, returning what
+/// to write to the PTY or why it was refused.
+pub fn filter_injected(data: Vec<u8>) -> Result<Vec<u8>, String> {
+    filter(&INJECTED, data)
+}
+
+/// Run injected input through [`filter_injected`], then hold it if it looks
+/// risky (see [`crate::pending`]), returning what to write to the PTY or why
+/// it won't be.
+pub async fn accept_injected(data: Vec<u8>) -> Result<Vec<u8>, String> {
+    let data = filter_injected(data)?;
+    if !crate::pending::hold(&String::from_utf8_lossy(&data)).await {
+        return Err("input was cancelled".to_string());
+    }
+    Ok(data)
+}
+
+/// Run keys typed into an attached terminal through their filters, like
+/// [`filter_injected`].
+pub fn filter_attached(data: Vec<u8>) -> Result<Vec<u8>, String> {
+    filter(&ATTACHED, data)
+}
+
+fn filter(
+    pipeline: &parking_lot::Mutex<Option<InputProcessor>>,
+    data: Vec<u8>,
+) -> Result<Vec<u8>, String> {
+    let mut pipeline = pipeline.lock();
+    let Some(processor) = pipeline.as_mut() else {
+        return Ok(data);
+    };
+    match processor.run_from(0, data) {
+        InputResult::Passthrough(bytes) => Ok(bytes),
+        InputResult::Rejected(reason) => Err(reason),
+        InputResult::Action(_) | InputResult::NeedMore => {
+            unreachable!("keybinds don't apply to input over the socket")
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -177,6 +562,23 @@ mod tests {
         assert!(!proc.has_pending_escape());
     }
 
+    #[test]
+    fn test_detach_legacy_and_kitty() {
+        let mut proc = default_processor();
+        // Ctrl-\ as a control byte, and as kitty encodes it (codepoint 92, Ctrl)
+        for input in [&[0x1c][..], &b"\x1b[92;5u"[..]] {
+            assert!(
+                matches!(
+                    proc.process(input),
+                    InputResult::Action(KeybindAction::Detach)
+                ),
+                "{input:02x?} should detach"
+            );
+        }
+        // Plain backslash passes through
+        assert!(matches!(proc.process(b"\x1b[92u"), InputResult::Passthrough(b) if b == b"\\"));
+    }
+
     #[test]
     fn test_escape_then_other_key() {
         let mut proc = default_processor();
@@ -188,6 +590,174 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_csi_u_translated_after_keybinds() {
+        let mut proc = default_processor();
+        // Ctrl-a in kitty encoding
+        match proc.process(b"\x1b[97;5u") {
+            InputResult::Passthrough(bytes) => assert_eq!(bytes, [0x01]),
+            other => panic!("Expected passthrough, got {other:?}"),
+        }
+        proc.remove(tap_config::InputFilter::CsiU);
+        match proc.process(b"\x1b[97;5u") {
+            InputResult::Passthrough(bytes) => assert_eq!(bytes, b"\x1b[97;5u"),
+            other => panic!("Expected passthrough, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_injected_rate_limit_and_deny() {
+        let mut config = tap_config::Config::default();
+        config.input_filters.injected = vec![
+            tap_config::InputFilter::Deny,
+            tap_config::InputFilter::RateLimit,
+        ];
+        config.input_filters.deny = vec![r"rm -rf /\s".to_string()];
+        config.input_filters.rate_limit_bytes_per_sec = 10;
+        let mut proc = InputProcessor::injected(&config).unwrap();
+
+        assert!(matches!(
+            proc.process(b"rm -rf / \r"),
+            InputResult::Rejected(_)
+        ));
+        assert!(matches!(proc.process(b"ls\r"), InputResult::Passthrough(_)));
+        assert!(matches!(
+            proc.process(b"echo hello\r"),
+            InputResult::Rejected(_)
+        ));
+
+        config.input_filters.injected = vec![tap_config::InputFilter::Keybinds];
+        assert!(InputProcessor::injected(&config).is_err());
+    }
+
+    #[test]
+    fn test_attached_skips_client_stages() {
+        let mut config = tap_config::Config::default();
+        config.input_filters.local = vec![
+            tap_config::InputFilter::Keybinds,
+            tap_config::InputFilter::CsiU,
+            tap_config::InputFilter::Deny,
+        ];
+        config.input_filters.deny = vec![r"rm -rf /\s".to_string()];
+        let mut proc = InputProcessor::attached(&config).unwrap();
+
+        assert!(matches!(
+            proc.process(b"rm -rf / \r"),
+            InputResult::Rejected(_)
+        ));
+        // Keybinds were the client's to act on
+        match proc.process(b"\x1c") {
+            InputResult::Passthrough(bytes) => assert_eq!(bytes, b"\x1c"),
+            other => panic!("expected passthrough, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_prefix_arms_keybinds() {
+        let mut config = tap_config::Config::default();
+        config.keybinds.prefix = Some("Ctrl-a".to_string());
+        let mut proc = InputProcessor::new(&config).unwrap();
+
+        // Without the prefix everything passes straight through
+        assert!(matches!(proc.process(&[0x1c]), InputResult::Passthrough(b) if b == [0x1c]));
+        assert!(
+            matches!(proc.process(&[ESC_BYTE]), InputResult::Passthrough(b) if b == [ESC_BYTE])
+        );
+        assert!(!proc.has_pending_escape());
+
+        assert!(matches!(proc.process(&[0x01]), InputResult::Passthrough(b) if b.is_empty()));
+        assert!(matches!(
+            proc.process(&[0x1c]),
+            InputResult::Action(KeybindAction::Detach)
+        ));
+        assert!(matches!(proc.process(b"\x1be"), InputResult::Passthrough(b) if b == b"\x1be"));
+
+        // Prefix, then an Alt key split across reads
+        proc.process(&[0x01]);
+        assert!(matches!(proc.process(&[ESC_BYTE]), InputResult::NeedMore));
+        assert!(matches!(
+            proc.process(b"e"),
+            InputResult::Action(KeybindAction::OpenEditor)
+        ));
+
+        // Prefix twice sends it
+        proc.process(&[0x01]);
+        assert!(matches!(proc.process(&[0x01]), InputResult::Passthrough(b) if b == [0x01]));
+    }
+
+    #[test]
+    fn test_prefix_keys() {
+        let mut config = tap_config::Config::default();
+        config.keybinds.prefix_keys = [
+            ("d".to_string(), tap_config::KeyAction::Detach),
+            ("[".to_string(), tap_config::KeyAction::CopyMode),
+            ("Ctrl-k".to_string(), tap_config::KeyAction::Kill),
+        ]
+        .into();
+        assert!(InputProcessor::new(&config).is_err());
+
+        config.keybinds.prefix = Some("Ctrl-a".to_string());
+        let mut proc = InputProcessor::new(&config).unwrap();
+        assert!(matches!(proc.process(b"d"), InputResult::Passthrough(b) if b == b"d"));
+        proc.process(&[0x01]);
+        assert!(matches!(
+            proc.process(b"d"),
+            InputResult::Action(KeybindAction::Detach)
+        ));
+        proc.process(&[0x01]);
+        assert!(matches!(
+            proc.process(b"["),
+            InputResult::Action(KeybindAction::CopyMode)
+        ));
+        assert!(matches!(proc.process(b"["), InputResult::Passthrough(b) if b == b"["));
+        assert!(matches!(
+            proc.process(&[0x01, 0x0b]),
+            InputResult::Action(KeybindAction::Kill)
+        ));
+        // Built-in keybinds still work after the prefix
+        proc.process(&[0x01]);
+        assert!(matches!(
+            proc.process(&[0x1c]),
+            InputResult::Action(KeybindAction::Detach)
+        ));
+    }
+
+    #[test]
+    fn test_custom_keybinds() {
+        let mut config = tap_config::Config::default();
+        config.keybinds.custom = [
+            (
+                "Alt-l".to_string(),
+                tap_config::KeyAction::Inject("ls\n".to_string()),
+            ),
+            (
+                "Ctrl-g".to_string(),
+                tap_config::KeyAction::Run("notify-send hi".to_string()),
+            ),
+        ]
+        .into();
+        let mut proc = InputProcessor::new(&config).unwrap();
+        assert!(matches!(
+            proc.process(b"\x1bl"),
+            InputResult::Action(KeybindAction::Inject(data)) if data == b"ls\n"
+        ));
+        assert!(matches!(
+            proc.process(&[0x07]),
+            InputResult::Action(KeybindAction::Run(command)) if command == "notify-send hi"
+        ));
+        assert!(matches!(
+            proc.process(b"\x1be"),
+            InputResult::Action(KeybindAction::OpenEditor)
+        ));
+
+        // Plain keys only work after a prefix
+        config
+            .keybinds
+            .custom
+            .insert("x".to_string(), tap_config::KeyAction::ClearScrollback);
+        assert!(InputProcessor::new(&config).is_err());
+    }
+
     #[test]
     fn test_ctrl_e_triggers_action() {
         let mut config = tap_config::Config::default();
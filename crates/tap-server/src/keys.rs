@@ -0,0 +1,229 @@
+//! Encoding symbolic key names (`Enter`, `C-c`, `M-x`, `F5`, `S-Up`...) into
+//! the bytes a terminal would send for them.
+//!
+//! Names follow tmux: modifiers are `C-` (ctrl), `M-` (alt) and `S-` (shift)
+//! prefixes, and a name that isn't a key is sent as literal text. The
+//! encoding depends on the inner app's modes: arrows differ in application
+//! cursor mode, and apps that enabled the kitty keyboard protocol get
+//! unambiguous CSI u sequences for Escape and modified keys.
+
+/// Kitty keyboard flag asking for unambiguous escape codes.
+const DISAMBIGUATE: u32 = 1;
+
+/// Inner app modes that change how keys are encoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyModes {
+    /// Cursor keys send `ESC O` instead of `ESC [` (DECCKM).
+    pub application_cursor: bool,
+    /// Kitty keyboard protocol flags the app enabled.
+    pub kitty_flags: u32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Modifiers {
+    shift: bool,
+    alt: bool,
+    ctrl: bool,
+}
+
+impl Modifiers {
+    fn any(self) -> bool {
+        self.shift || self.alt || self.ctrl
+    }
+
+    /// The modifier parameter of CSI sequences.
+    fn param(self) -> u8 {
+        1 + u8::from(self.shift) + 2 * u8::from(self.alt) + 4 * u8::from(self.ctrl)
+    }
+}
+
+/// Keys with their own escape sequences.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Special {
+    /// `CSI <final>`, or `SS3 <final>` in application cursor mode.
+    Cursor(u8),
+    /// `CSI <number> ~`.
+    Tilde(u8),
+    /// `SS3 <final>` (F1-F4).
+    Function(u8),
+}
+
+fn special(name: &str) -> Option<Special> {
+    let key = match name.to_ascii_lowercase().as_str() {
+        "up" => Special::Cursor(b'A'),
+        "down" => Special::Cursor(b'B'),
+        "right" => Special::Cursor(b'C'),
+        "left" => Special::Cursor(b'D'),
+        "home" => Special::Cursor(b'H'),
+        "end" => Special::Cursor(b'F'),
+        "insert" | "ic" => Special::Tilde(2),
+        "delete" | "dc" => Special::Tilde(3),
+        "pageup" | "pgup" | "ppage" => Special::Tilde(5),
+        "pagedown" | "pgdn" | "npage" => Special::Tilde(6),
+        "f1" => Special::Function(b'P'),
+        "f2" => Special::Function(b'Q'),
+        "f3" => Special::Function(b'R'),
+        "f4" => Special::Function(b'S'),
+        "f5" => Special::Tilde(15),
+        "f6" => Special::Tilde(17),
+        "f7" => Special::Tilde(18),
+        "f8" => Special::Tilde(19),
+        "f9" => Special::Tilde(20),
+        "f10" => Special::Tilde(21),
+        "f11" => Special::Tilde(23),
+        "f12" => Special::Tilde(24),
+        _ => return None,
+    };
+    Some(key)
+}
+
+/// Keys that produce a single character, by codepoint.
+fn text_key(name: &str) -> Option<char> {
+    let key = match name.to_ascii_lowercase().as_str() {
+        "enter" | "return" => '\r',
+        "tab" => '\t',
+        "escape" | "esc" => '\x1b',
+        "space" => ' ',
+        "backspace" | "bspace" => '\x7f',
+        _ => {
+            let mut chars = name.chars();
+            let c = chars.next()?;
+            return chars.next().is_none().then_some(c);
+        }
+    };
+    Some(key)
+}
+
+/// Split `C-M-x` style modifier prefixes off a key name.
+fn split_modifiers(mut name: &str) -> (Modifiers, &str) {
+    let mut modifiers = Modifiers::default();
+    while name.len() > 2 && name.as_bytes()[1] == b'-' {
+        match name.as_bytes()[0] {
+            b'C' | b'c' => modifiers.ctrl = true,
+            b'M' | b'm' => modifiers.alt = true,
+            b'S' | b's' => modifiers.shift = true,
+            _ => break,
+        }
+        name = &name[2..];
+    }
+    (modifiers, name)
+}
+
+/// Encode one key name, or return literal text for a name that isn't a key.
+pub fn encode(name: &str, modes: KeyModes) -> Result<Vec<u8>, String> {
+    let (modifiers, base) = split_modifiers(name);
+    if let Some(key) = special(base) {
+        return Ok(encode_special(key, modifiers, modes));
+    }
+    match text_key(base) {
+        Some(c) => encode_char(c, modifiers, modes)
+            .ok_or_else(|| format!("can't send {name}: no encoding for that key combination")),
+        None if modifiers.any() => Err(format!("unknown key '{base}' in '{name}'")),
+        None => Ok(name.as_bytes().to_vec()),
+    }
+}
+
+fn encode_special(key: Special, modifiers: Modifiers, modes: KeyModes) -> Vec<u8> {
+    let param = modifiers.param();
+    match key {
+        Special::Cursor(end) if modifiers.any() => format!("\x1b[1;{param}{}", end as char),
+        Special::Cursor(end) if modes.application_cursor => format!("\x1bO{}", end as char),
+        Special::Cursor(end) => format!("\x1b[{}", end as char),
+        Special::Tilde(number) if modifiers.any() => format!("\x1b[{number};{param}~"),
+        Special::Tilde(number) => format!("\x1b[{number}~"),
+        Special::Function(end) if modifiers.any() => format!("\x1b[1;{param}{}", end as char),
+        Special::Function(end) => format!("\x1bO{}", end as char),
+    }
+    .into_bytes()
+}
+
+fn encode_char(c: char, modifiers: Modifiers, modes: KeyModes) -> Option<Vec<u8>> {
+    let kitty = modes.kitty_flags & DISAMBIGUATE != 0;
+    let codepoint = u32::from(c.to_ascii_lowercase());
+
+    if kitty && (c == '\x1b' || modifiers.alt || modifiers.ctrl) {
+        return Some(format!("\x1b[{codepoint};{}u", modifiers.param()).into_bytes());
+    }
+    if kitty && modifiers.shift && matches!(c, '\r' | '\t' | '\x7f') {
+        return Some(format!("\x1b[{codepoint};{}u", modifiers.param()).into_bytes());
+    }
+
+    let mut out = Vec::new();
+    if modifiers.alt {
+        out.push(0x1b);
+    }
+    match c {
+        '\t' if modifiers.shift => out.extend_from_slice(b"\x1b[Z"),
+        _ if modifiers.ctrl => out.push(ctrl_byte(c)?),
+        _ if modifiers.shift => out.extend(c.to_uppercase().to_string().bytes()),
+        _ => out.extend(c.to_string().bytes()),
+    }
+    Some(out)
+}
+
+/// The control character Ctrl plus `c` sends, if any.
+fn ctrl_byte(c: char) -> Option<u8> {
+    match c {
+        'a'..='z' | 'A'..='Z' => Some(c.to_ascii_lowercase() as u8 & 0x1f),
+        '@' | ' ' | '2' => Some(0x00),
+        '[' | '3' => Some(0x1b),
+        '\\' | '4' => Some(0x1c),
+        ']' | '5' => Some(0x1d),
+        '^' | '6' => Some(0x1e),
+        '_' | '-' | '7' => Some(0x1f),
+        '?' | '8' | '\x7f' => Some(0x7f),
+        '\r' | '\t' | '\x1b' => Some(c as u8),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(names: &[&str], modes: KeyModes) -> Vec<u8> {
+        names
+            .iter()
+            .flat_map(|name| encode(name, modes).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_legacy_encoding() {
+        let modes = KeyModes::default();
+        assert_eq!(keys(&["C-c", "Up", "Enter"], modes), b"\x03\x1b[A\r");
+        assert_eq!(keys(&["M-x", "Escape", "S-Tab"], modes), b"\x1bx\x1b\x1b[Z");
+        assert_eq!(
+            keys(&["C-Left", "F1", "F12", "PageUp"], modes),
+            b"\x1b[1;5D\x1bOP\x1b[24~\x1b[5~"
+        );
+        assert_eq!(keys(&["ls -la"], modes), b"ls -la");
+    }
+
+    #[test]
+    fn test_application_cursor() {
+        let modes = KeyModes {
+            application_cursor: true,
+            ..KeyModes::default()
+        };
+        assert_eq!(keys(&["Up", "Home"], modes), b"\x1bOA\x1bOH");
+    }
+
+    #[test]
+    fn test_kitty_encoding() {
+        let modes = KeyModes {
+            kitty_flags: 1,
+            ..KeyModes::default()
+        };
+        assert_eq!(
+            keys(&["C-c", "Escape", "M-x", "Enter", "a"], modes),
+            b"\x1b[99;5u\x1b[27;1u\x1b[120;3u\ra"
+        );
+    }
+
+    #[test]
+    fn test_unknown_modified_key() {
+        assert!(encode("C-Bogus", KeyModes::default()).is_err());
+        assert!(encode("C-%", KeyModes::default()).is_err());
+    }
+}
@@ -1,8 +1,9 @@
 //! Kitty keyboard protocol handling.
 //!
 //! This module translates kitty keyboard protocol CSI u sequences to traditional
-//! terminal input. We always translate because PTYs don't emulate kitty protocol
-//! negotiation, so inner apps may not actually parse kitty input even if they
+//! terminal input. Whenever tap pushed kitty flags to the outer terminal we
+//! translate, because PTYs don't emulate kitty protocol negotiation, so inner
+//! apps may not actually parse kitty input even if they
 //! send enable sequences.
 
 /// Translate a kitty CSI u sequence to traditional terminal input.
@@ -13,8 +14,9 @@ pub fn translate_csi_u_to_traditional(data: &[u8]) -> Option<(Vec<u8>, usize)> {
         return None;
     }
 
-    let u_pos = data.iter().position(|&b| b == b'u')?;
-    if u_pos < 3 {
+    // The sequence ends at the first final byte, which must be 'u'
+    let u_pos = data[2..].iter().position(|b| (0x40..=0x7e).contains(b))? + 2;
+    if data[u_pos] != b'u' || u_pos < 3 {
         return None;
     }
 
@@ -175,6 +177,108 @@ pub fn translate_all_csi_u(data: &[u8]) -> Vec<u8> {
     result
 }
 
+/// Longest flags sequence carried over between output chunks.
+const MAX_FLAGS_SEQUENCE_LEN: usize = 16;
+
+/// The kitty keyboard flags the inner app asked for, tracked from its output
+/// (`CSI > flags u` pushes, `CSI < n u` pops, `CSI = flags ; mode u` sets).
+#[derive(Debug, Default)]
+pub struct KeyboardFlags {
+    stack: Vec<u32>,
+    /// Start of a sequence split across output chunks.
+    partial: Vec<u8>,
+}
+
+impl KeyboardFlags {
+    pub const fn new() -> Self {
+        Self {
+            stack: Vec::new(),
+            partial: Vec::new(),
+        }
+    }
+
+    /// Current flags; 0 when the app hasn't enabled the protocol.
+    pub fn flags(&self) -> u32 {
+        self.stack.last().copied().unwrap_or(0)
+    }
+
+    /// Scan a chunk of output for flag changes.
+    pub fn process(&mut self, data: &[u8]) {
+        let joined;
+        let bytes = if self.partial.is_empty() {
+            data
+        } else {
+            self.partial.extend_from_slice(data);
+            joined = std::mem::take(&mut self.partial);
+            joined.as_slice()
+        };
+
+        let mut i = 0;
+        while let Some(offset) = bytes[i..].iter().position(|&b| b == 0x1b) {
+            let start = i + offset;
+            let rest = &bytes[start..];
+            match rest.get(1) {
+                None => {
+                    self.partial = rest.to_vec();
+                    return;
+                }
+                Some(b'[') => {}
+                Some(_) => {
+                    i = start + 1;
+                    continue;
+                }
+            }
+            let Some(end) = rest[2..].iter().position(|b| (0x40..=0x7e).contains(b)) else {
+                if rest.len() <= MAX_FLAGS_SEQUENCE_LEN {
+                    self.partial = rest.to_vec();
+                }
+                return;
+            };
+            let sequence = &rest[..end + 3];
+            self.apply(sequence);
+            i = start + sequence.len();
+        }
+    }
+
+    fn apply(&mut self, sequence: &[u8]) {
+        let Some(body) = sequence
+            .strip_prefix(b"\x1b[")
+            .and_then(|body| body.strip_suffix(b"u"))
+        else {
+            return;
+        };
+        let Some((&kind, params)) = body.split_first() else {
+            return;
+        };
+        let mut params = std::str::from_utf8(params)
+            .unwrap_or_default()
+            .split(';')
+            .map(|param| param.parse::<u32>().ok());
+        let first = params.next().flatten();
+        match kind {
+            b'>' => self.stack.push(first.unwrap_or(0)),
+            b'<' => {
+                let count = first.unwrap_or(1) as usize;
+                self.stack.truncate(self.stack.len().saturating_sub(count));
+            }
+            b'=' => {
+                let flags = first.unwrap_or(0);
+                let current = self.flags();
+                let updated = match params.next().flatten().unwrap_or(1) {
+                    2 => current | flags,
+                    3 => current & !flags,
+                    _ => flags,
+                };
+                match self.stack.last_mut() {
+                    Some(top) => *top = updated,
+                    None => self.stack.push(updated),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -213,6 +317,13 @@ mod tests {
         assert_eq!(consumed, input.len());
     }
 
+    #[test]
+    fn test_other_csi_followed_by_u() {
+        // Ctrl+Up then a typed 'u' isn't a CSI u sequence
+        assert_eq!(translate_csi_u_to_traditional(b"\x1b[1;5Au"), None);
+        assert_eq!(translate_all_csi_u(b"\x1b[1;5Au"), b"\x1b[1;5Au");
+    }
+
     #[test]
     fn test_translate_enter() {
         let input = b"\x1b[13u";
@@ -221,6 +332,18 @@ mod tests {
         assert_eq!(consumed, input.len());
     }
 
+    #[test]
+    fn test_keyboard_flags() {
+        let mut flags = KeyboardFlags::new();
+        flags.process(b"\x1b[?u\x1b[>1u");
+        assert_eq!(flags.flags(), 1);
+        flags.process(b"\x1b[=8;2");
+        flags.process(b"u\x1b[1;5A");
+        assert_eq!(flags.flags(), 9);
+        flags.process(b"\x1b[<u");
+        assert_eq!(flags.flags(), 0);
+    }
+
     #[test]
     fn test_translate_all() {
         let input = b"hello\x1b[99;5uworld";
@@ -1,10 +1,71 @@
 //! PTY wrapper server library for terminal introspection.
 
+/// Log a pipeline event with its sequence number and the stage's running
+/// byte total (see `trace`). Compiled out without the `trace` feature.
+macro_rules! trace_pipeline {
+    ($stage:ident, $bytes:expr $(, $field:ident = $value:expr)* $(,)?) => {
+        #[cfg(feature = "trace")]
+        {
+            let bytes: usize = $bytes;
+            tracing::debug!(
+                seq = crate::trace::next_seq(),
+                stage = stringify!($stage),
+                bytes,
+                total = crate::trace::$stage.add(bytes)
+                $(, $field = $value)*
+            );
+        }
+        #[cfg(not(feature = "trace"))]
+        {
+            let _: usize = $bytes;
+        }
+    };
+}
+
+mod attached;
+pub mod backend;
+mod buffers;
+mod client_state;
+pub mod clipboard;
+mod commands;
+pub mod copy_mode;
+mod cursor_style;
+mod cwd;
 mod editor;
+mod ended;
+mod events;
+mod exec;
+mod filters;
+pub mod index;
 pub mod input;
-mod kitty;
+mod keys;
+pub mod kitty;
+pub mod migrate;
+mod notify;
+mod osc;
+mod pending;
+pub mod persist;
+mod probe;
+mod procenv;
+mod progress;
+mod pty_writer;
+mod recording;
+pub mod remote;
+mod resize;
+mod resync;
+mod screenshot;
 pub mod scrollback;
-
+mod sequence;
+mod session_log;
+pub mod status;
+mod stdout_forwarder;
+mod times;
+#[cfg(feature = "trace")]
+mod trace;
+mod triggers;
+mod watchdog;
+
+use std::io::IsTerminal as _;
 use std::os::fd::{AsRawFd as _, BorrowedFd, FromRawFd as _};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -18,6 +79,15 @@ const DEFAULT_SHELL: &str = "/bin/sh";
 const HUMAN_ID_WORDS: usize = 3;
 const BROADCAST_CHANNEL_SIZE: usize = 1024;
 const IO_BUFFER_SIZE: usize = 4096;
+/// Bytes of child output handled before yielding to other tasks, so large
+/// bursts (e.g. `cat big.json`) can't starve stdin or the socket server.
+const OUTPUT_BUDGET_BYTES: usize = 64 * 1024;
+/// How long a child may ignore SIGHUP after `tap kill` before it is killed.
+const KILL_GRACE_PERIOD: std::time::Duration = std::time::Duration::from_secs(5);
+/// Heartbeats an attached client may miss before it's dropped.
+const HEARTBEAT_MISSES: u32 = 3;
+/// How often to check whether a slow terminal has caught up.
+const RESYNC_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
 
 /// Atomically modify the sessions file with exclusive locking.
 fn modify_sessions_file(
@@ -41,13 +111,10 @@ fn modify_sessions_file(
     file.read_to_string(&mut content)
         .wrap_err("failed to read sessions file")?;
 
-    let mut sessions: Vec<serde_json::Value> = if content.is_empty() {
-        Vec::new()
-    } else {
-        serde_json::from_str(&content).unwrap_or_default()
-    };
+    let mut sessions = index::parse(&content, path);
 
     f(&mut sessions);
+    index::remember(&sessions);
 
     file.set_len(0)
         .wrap_err("failed to truncate sessions file")?;
@@ -60,9 +127,166 @@ fn modify_sessions_file(
     Ok(())
 }
 
+/// Append a timestamped note to this session's entry in sessions.json.
+fn add_note(text: &str) -> eyre::Result<()> {
+    let note = serde_json::to_value(tap_protocol::Note {
+        time: chrono::Utc::now().to_rfc3339(),
+        text: text.to_string(),
+    })?;
+    let mut found = false;
+    modify_sessions_file(&tap_protocol::sessions_file(), |sessions| {
+        let Some(entry) = sessions
+            .iter_mut()
+            .find(|s| s.get("pid").and_then(|v| v.as_u64()) == Some(u64::from(std::process::id())))
+        else {
+            return;
+        };
+        match entry
+            .get_mut("notes")
+            .and_then(|notes| notes.as_array_mut())
+        {
+            Some(notes) => notes.push(note),
+            None => entry["notes"] = serde_json::json!([note]),
+        }
+        found = true;
+    })?;
+    eyre::ensure!(found, "session is missing from sessions.json");
+    Ok(())
+}
+
+/// Whether another live session already uses `name`: its socket answers,
+/// or its sessions.json entry names a running process.
+fn name_taken(name: &str, sessions: &[serde_json::Value]) -> bool {
+    if std::os::unix::net::UnixStream::connect(tap_protocol::socket_path(name)).is_ok() {
+        return true;
+    }
+    sessions.iter().any(|s| {
+        s.get("id").and_then(|v| v.as_str()) == Some(name)
+            && !ended::is_ended(s)
+            && s.get("pid")
+                .and_then(|v| v.as_u64())
+                .filter(|&pid| pid != u64::from(std::process::id()))
+                .and_then(|pid| i32::try_from(pid).ok())
+                .is_some_and(|pid| {
+                    nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid), None).is_ok()
+                })
+    })
+}
+
+/// Give this session a new ID, moving its socket and sessions.json entry
+/// under the sessions file lock.
+fn rename_session(name: &str) -> eyre::Result<()> {
+    tap_protocol::validate_session_name(name).map_err(|e| eyre::eyre!(e))?;
+    let old = SESSION_ID.read().clone();
+    if name == old {
+        return Ok(());
+    }
+    let mut result = Ok(());
+    modify_sessions_file(&tap_protocol::sessions_file(), |sessions| {
+        if name_taken(name, sessions) {
+            result = Err(eyre::eyre!("session '{name}' already exists"));
+            return;
+        }
+        if let Err(e) = std::fs::rename(
+            tap_protocol::socket_path(&old),
+            tap_protocol::socket_path(name),
+        ) {
+            result = Err(eyre::eyre!("failed to move socket: {e}"));
+            return;
+        }
+        // Entries left behind by dead sessions of that name
+        sessions.retain(|s| s.get("id").and_then(|v| v.as_str()) != Some(name));
+        for s in sessions.iter_mut() {
+            if s.get("pid").and_then(|v| v.as_u64()) == Some(u64::from(std::process::id())) {
+                s["id"] = serde_json::json!(name);
+            }
+        }
+        *SESSION_ID.write() = name.to_string();
+    })?;
+    result
+}
+
+/// Woken when the working directory or title changes, to update
+/// sessions.json.
+static SESSION_INFO_CHANGED: tokio::sync::Notify = tokio::sync::Notify::const_new();
+
+/// Keep this session's `cwd` and `title` in sessions.json up to date, for
+/// `tap list`.
+fn spawn_session_info_updater() {
+    tokio::spawn(async {
+        loop {
+            SESSION_INFO_CHANGED.notified().await;
+            let (cwd, title) = {
+                let scrollback = SCROLLBACK.read();
+                (scrollback.cwd().map(str::to_string), scrollback.title())
+            };
+            let pid = u64::from(std::process::id());
+            let result = modify_sessions_file(&tap_protocol::sessions_file(), |sessions| {
+                if let Some(entry) = sessions
+                    .iter_mut()
+                    .find(|s| s.get("pid").and_then(|v| v.as_u64()) == Some(pid))
+                {
+                    entry["cwd"] = serde_json::json!(cwd);
+                    entry["title"] = serde_json::json!(title);
+                }
+            });
+            if let Err(e) = result {
+                tracing::debug!("failed to update session info: {e}");
+            }
+        }
+    });
+}
+
+/// This session's ID, which `tap rename` can change while it runs.
+static SESSION_ID: parking_lot::RwLock<String> = parking_lot::RwLock::new(String::new());
 static SCROLLBACK: parking_lot::RwLock<scrollback::ScrollbackBuffer> =
     parking_lot::RwLock::new(scrollback::ScrollbackBuffer::new());
 static MASTER_FD: std::sync::OnceLock<i32> = std::sync::OnceLock::new();
+static CHILD_PID: std::sync::OnceLock<i32> = std::sync::OnceLock::new();
+/// Whether the child leads its own session and process group (see
+/// `TerminalConfig::job_control`).
+static JOB_CONTROL: AtomicBool = AtomicBool::new(true);
+/// The session socket's listener, kept open across in-place upgrades.
+static LISTENER_FD: std::sync::OnceLock<i32> = std::sync::OnceLock::new();
+/// Whether the session's own terminal is driving it (not detached).
+static LOCAL_TERMINAL: AtomicBool = AtomicBool::new(false);
+/// Signals the local I/O loop to open the scrollback editor.
+static OPEN_EDITOR: tokio::sync::Notify = tokio::sync::Notify::const_new();
+/// Tells attached and subscribed clients to disconnect.
+static DETACH_CLIENTS: std::sync::LazyLock<tokio::sync::broadcast::Sender<()>> =
+    std::sync::LazyLock::new(|| tokio::sync::broadcast::channel(1).0);
+/// Exit code of the child, set once it has exited; `None` inside if the
+/// child was adopted and its exit code couldn't be observed.
+static EXIT_CODE: std::sync::OnceLock<Option<i32>> = std::sync::OnceLock::new();
+/// Whether the child was received from another tap process (`tap receive`),
+/// so this process isn't its parent and can't reap it.
+static ADOPTED: AtomicBool = AtomicBool::new(false);
+/// How often to check whether an adopted child is still running.
+const ADOPTED_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+/// Wakes clients blocked in `Request::Wait` once `EXIT_CODE` is set.
+static SESSION_EXITED: tokio::sync::Notify = tokio::sync::Notify::const_new();
+
+/// Record the child's exit code and wake any waiting clients.
+fn record_exit(code: Option<i32>) {
+    if EXIT_CODE.set(code).is_ok() {
+        events::emit(tap_protocol::Event::Exit { exit_code: code });
+        notify::exited(code);
+    }
+    SESSION_EXITED.notify_waiters();
+}
+
+/// Wait until the session's child has exited and return its exit code, if
+/// it could be observed.
+pub async fn wait_for_exit() -> Option<i32> {
+    loop {
+        // Created before checking so a concurrent notify_waiters() isn't missed
+        let exited = SESSION_EXITED.notified();
+        if let Some(&code) = EXIT_CODE.get() {
+            return code;
+        }
+        exited.await;
+    }
+}
 
 /// Configuration for starting a server session.
 #[derive(Debug, Clone, Default)]
@@ -73,6 +297,30 @@ pub struct ServerConfig {
     pub session_id: Option<String>,
     /// Start detached (no terminal attached).
     pub detached: bool,
+    /// Tags recorded in sessions.json for bulk selection.
+    pub tags: Vec<String>,
+    /// Record all output to this asciicast file.
+    pub record: Option<std::path::PathBuf>,
+    /// Append all output to this log file, overriding `[logging] file`.
+    pub log_file: Option<std::path::PathBuf>,
+    /// Serve a session received from another tap process instead of
+    /// starting a new one. Implies `detached`.
+    pub adopt: Option<migrate::Handoff>,
+    /// Don't print the startup banner.
+    pub quiet: bool,
+    /// Apply this profile from the config instead of choosing one by
+    /// command.
+    pub profile: Option<String>,
+    /// Extra environment variables for the child.
+    pub env: Vec<(String, String)>,
+    /// The session this one is started inside of, recorded in
+    /// sessions.json.
+    pub parent: Option<String>,
+    /// Relaunch a saved session: its size, with its old screen shown above
+    /// the new output.
+    pub restore: Option<persist::SavedSession>,
+    /// Also serve the session over TCP, to clients with the token.
+    pub listen: Option<remote::Listen>,
 }
 
 fn setup_terminal(fd: BorrowedFd<'_>) -> nix::Result<nix::sys::termios::Termios> {
@@ -111,69 +359,287 @@ fn set_window_size_raw(fd: i32, rows: u16, cols: u16) {
     set_window_size(fd, &ws);
 }
 
-/// Channel for sending input to the PTY from attached clients.
-type InputSender = tokio::sync::mpsc::UnboundedSender<Vec<u8>>;
-type InputReceiver = tokio::sync::mpsc::UnboundedReceiver<Vec<u8>>;
+use pty_writer::InputSender;
+
+/// Remove an attached client and refit the PTY to those remaining.
+async fn detach_client(attached_client: &Mutex<attached::AttachedClients>, client_id: u64) {
+    let mut attached = attached_client.lock().await;
+    attached.remove(client_id);
+    attached.apply_smallest_size();
+}
 
-/// Shared state for attached client.
-struct AttachedClient {
-    /// Sender for PTY output to the attached client.
-    output_tx: tokio::sync::mpsc::UnboundedSender<Vec<u8>>,
+/// Write a reply to a request, echoing its ID if the client sent one.
+async fn write_reply<W: tokio::io::AsyncWrite + Unpin>(
+    stream: &mut W,
+    request_id: Option<u64>,
+    response: &tap_protocol::Response,
+) -> std::io::Result<()> {
+    let envelope = tap_protocol::Envelope {
+        request_id,
+        message: response,
+    };
+    let mut line = serde_json::to_vec(&envelope).map_err(std::io::Error::other)?;
+    line.push(b'\n');
+    stream.write_all(&line).await
+}
+
+/// Write one response line to a client.
+async fn write_response<W: tokio::io::AsyncWrite + Unpin>(
+    stream: &mut W,
+    response: &tap_protocol::Response,
+) -> std::io::Result<()> {
+    let mut line = serde_json::to_vec(response).map_err(std::io::Error::other)?;
+    line.push(b'\n');
+    stream.write_all(&line).await
+}
+
+/// A request received from a client, or why it couldn't be read along with
+/// its ID if it had one.
+type Received =
+    Result<tap_protocol::Envelope<tap_protocol::Request>, (Option<u64>, serde_json::Error)>;
+
+/// The next request on a client connection, reading until `unread` holds a
+/// whole one: requests aren't delimited, and one read may hold several or
+/// end partway through one. `None` once the client hangs up. Cancel-safe,
+/// as whatever was read stays in `unread`.
+async fn read_request<S: tokio::io::AsyncRead + Unpin>(
+    stream: &mut S,
+    unread: &mut bytes::BytesMut,
+) -> std::io::Result<Option<Received>> {
+    loop {
+        // Parsed as any JSON first, so a request this server doesn't know
+        // is skipped on its own rather than losing what follows it
+        let mut values =
+            serde_json::Deserializer::from_slice(unread).into_iter::<serde_json::Value>();
+        match values.next() {
+            Some(Ok(value)) => {
+                let consumed = values.byte_offset();
+                bytes::Buf::advance(unread, consumed);
+                let request_id = value.get("request_id").and_then(serde_json::Value::as_u64);
+                return Ok(Some(
+                    serde_json::from_value(value).map_err(|e| (request_id, e)),
+                ));
+            }
+            Some(Err(e)) if !e.is_eof() => {
+                // No telling where the next request starts
+                unread.clear();
+                return Ok(Some(Err((None, e))));
+            }
+            // Partway through a request, or nothing but whitespace
+            _ => {}
+        }
+        let n = stream.read_buf(unread).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        trace_pipeline!(REQUEST, n);
+    }
+}
+
+/// Write text to the PTY as a paste, bracketed if the app asked for that,
+/// so editors and REPLs don't auto-indent or run it line by line.
+async fn paste(data: Vec<u8>, input_tx: &InputSender) -> tap_protocol::Response {
+    match input::accept_injected(data).await {
+        Err(reason) => tap_protocol::Response::Error {
+            code: tap_protocol::ErrorCode::Denied,
+            message: reason,
+        },
+        Ok(data) => {
+            let bracketed = SCROLLBACK.read().bracketed_paste();
+            if input_tx
+                .send(clipboard::paste_bytes(&data, bracketed))
+                .is_ok()
+            {
+                tap_protocol::Response::Ok
+            } else {
+                tap_protocol::Response::Error {
+                    code: tap_protocol::ErrorCode::SessionEnded,
+                    message: "session ended".to_string(),
+                }
+            }
+        }
+    }
 }
 
 /// Handle JSON protocol clients (scrollback queries, inject, etc.).
-async fn handle_json_client(
-    mut stream: tokio::net::UnixStream,
+#[cfg_attr(
+    feature = "trace",
+    tracing::instrument(level = "debug", skip_all, fields(client = trace::next_seq()))
+)]
+async fn handle_json_client<S>(
+    mut stream: S,
     output_rx: tokio::sync::broadcast::Receiver<Vec<u8>>,
     input_tx: InputSender,
-    attached_client: Arc<Mutex<Option<AttachedClient>>>,
+    attached_client: Arc<Mutex<attached::AttachedClients>>,
     session_ended: Arc<AtomicBool>,
-) {
-    let mut buf = bytes::BytesMut::with_capacity(IO_BUFFER_SIZE);
+    token: Option<Arc<str>>,
+) where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    // Read but not yet parsed
+    let mut unread = bytes::BytesMut::with_capacity(IO_BUFFER_SIZE);
     let mut output_rx = output_rx;
+    let mut detach_rx = DETACH_CLIENTS.subscribe();
+    let mut resized_rx = resize::subscribe();
+    let mut pending_rx = pending::subscribe();
+    let mut events: Option<events::Subscription> = None;
+    let mut state = client_state::ClientState::Idle;
+    let mut authenticated = token.is_none();
+    let hello_deadline = tokio::time::Instant::now() + remote::HELLO_TIMEOUT;
 
     loop {
-        buf.clear();
-
         if session_ended.load(Ordering::Relaxed) {
-            let response = tap_protocol::Response::SessionEnded { exit_code: 0 };
-            let response_bytes = serde_json::to_vec(&response).unwrap();
-            let _ = stream.write_all(&response_bytes).await;
-            let _ = stream.write_all(b"\n").await;
+            let exit_code = EXIT_CODE.get().copied().flatten();
+            let response = tap_protocol::Response::SessionEnded { exit_code };
+            let _ = write_response(&mut stream, &response).await;
             break;
         }
 
         tokio::select! {
-            result = stream.read_buf(&mut buf) => {
+            result = read_request(&mut stream, &mut unread) => {
                 match result {
-                    Ok(0) => break,
-                    Ok(_) => {
-                        let request: tap_protocol::Request = match serde_json::from_slice(&buf) {
-                            Ok(r) => r,
-                            Err(e) => {
-                                tracing::warn!("invalid request: {e}");
-                                continue;
+                    Ok(None) => break,
+                    Ok(Some(Err((request_id, e)))) => {
+                        tracing::warn!("invalid request: {e}");
+                        if !authenticated {
+                            break;
+                        }
+                        // Valid JSON this server doesn't understand, e.g. from a newer client
+                        let code = if e.is_data() { tap_protocol::ErrorCode::Unsupported } else { tap_protocol::ErrorCode::Other };
+                        let response = tap_protocol::Response::Error { code, message: format!("invalid request: {e}") };
+                        if write_reply(&mut stream, request_id, &response).await.is_err() {
+                            break;
+                        }
+                    }
+                    Ok(Some(Ok(tap_protocol::Envelope { request_id, message: request }))) => {
+                        if let Some(token) = token.as_deref().filter(|_| !authenticated) {
+                            if !remote::authenticates(&request, token) {
+                                tracing::warn!("refusing unauthenticated TCP client");
+                                let response = tap_protocol::Response::Error { code: tap_protocol::ErrorCode::Denied, message: "authentication failed".to_string() };
+                                let _ = write_reply(&mut stream, request_id, &response).await;
+                                break;
                             }
-                        };
+                            authenticated = true;
+                        }
 
+                        let mut handed_off = false;
+                        let mut upgrade_to = None;
+                        let mut replay = Vec::new();
                         let response = match request {
-                            tap_protocol::Request::GetScrollback { lines } => {
+                            tap_protocol::Request::GetScrollback { lines, since_mark } => {
                                 let scrollback = SCROLLBACK.read();
-                                let content = scrollback.get_lines(lines);
-                                tap_protocol::Response::Scrollback { content }
+                                match since_mark {
+                                    Some(label) => match scrollback.get_lines_since_mark(&label, lines) {
+                                        Some(content) => tap_protocol::Response::Scrollback { content },
+                                        None => tap_protocol::Response::Error { code: tap_protocol::ErrorCode::Other, message: format!("no mark named '{label}'") },
+                                    },
+                                    None => {
+                                        let content = scrollback.get_lines(lines);
+                                        tap_protocol::Response::Scrollback { content }
+                                    }
+                                }
+                            }
+                            tap_protocol::Request::Mark { label } => {
+                                SCROLLBACK.write().mark(&label);
+                                tap_protocol::Response::Ok
+                            }
+                            tap_protocol::Request::Note { text } => match add_note(&text) {
+                                Ok(()) => {
+                                    recording::annotate(&text);
+                                    tap_protocol::Response::Ok
+                                }
+                                Err(e) => tap_protocol::Response::Error { code: tap_protocol::ErrorCode::Other, message: format!("{e:#}") },
+                            },
+                            tap_protocol::Request::Rename { name } => match rename_session(&name) {
+                                Ok(()) => tap_protocol::Response::Ok,
+                                Err(e) => tap_protocol::Response::Error { code: tap_protocol::ErrorCode::Other, message: format!("{e:#}") },
+                            },
+                            tap_protocol::Request::Hello { version, .. } => {
+                                let compatible = tap_protocol::ProtocolVersion::parse(&version)
+                                    .is_some_and(|client| client.is_served_by(tap_protocol::PROTOCOL_VERSION));
+                                if !compatible {
+                                    tracing::info!("client speaks protocol {version}, this server {}", tap_protocol::PROTOCOL_VERSION);
+                                }
+                                tap_protocol::Response::Hello { version: tap_protocol::PROTOCOL_VERSION.to_string() }
                             }
+                            tap_protocol::Request::Ping => tap_protocol::Response::Pong,
+                            tap_protocol::Request::Reregister => match index::reregister() {
+                                Ok(()) => tap_protocol::Response::Ok,
+                                Err(e) => tap_protocol::Response::Error { code: tap_protocol::ErrorCode::Other, message: e.to_string() },
+                            },
+                            tap_protocol::Request::CancelInput { id } => tap_protocol::Response::Cancelled {
+                                count: pending::cancel(id),
+                            },
                             tap_protocol::Request::GetCursor => {
                                 let scrollback = SCROLLBACK.read();
                                 let (row, col) = scrollback.cursor_position();
-                                tap_protocol::Response::Cursor { row, col }
+                                let char_col = scrollback.cursor_char_col();
+                                tap_protocol::Response::Cursor { row, col, char_col }
                             }
-                            tap_protocol::Request::Inject { data } => {
-                                if input_tx.send(data.into_bytes()).is_ok() {
-                                    tap_protocol::Response::Ok
-                                } else {
-                                    tap_protocol::Response::Error { message: "session ended".to_string() }
+                            tap_protocol::Request::Inject { data } => match input::accept_injected(data.into_bytes()).await {
+                                Err(reason) => tap_protocol::Response::Error { code: tap_protocol::ErrorCode::Denied, message: reason },
+                                Ok(data) => {
+                                    // Output caused by the input can only be read after this
+                                    let seq = sequence::current();
+                                    if input_tx.send(data).is_ok() {
+                                        tap_protocol::Response::Injected { seq }
+                                    } else {
+                                        tap_protocol::Response::Error { code: tap_protocol::ErrorCode::SessionEnded, message: "session ended".to_string() }
+                                    }
+                                }
+                            },
+                            tap_protocol::Request::SendKeys { keys: names } => {
+                                let modes = SCROLLBACK.read().key_modes();
+                                match names.iter().map(|name| keys::encode(name, modes)).collect::<Result<Vec<_>, _>>() {
+                                    Err(message) => tap_protocol::Response::Error { code: tap_protocol::ErrorCode::Other, message },
+                                    Ok(encoded) => match input::accept_injected(encoded.concat()).await {
+                                        Err(reason) => tap_protocol::Response::Error { code: tap_protocol::ErrorCode::Denied, message: reason },
+                                        Ok(data) => {
+                                            let seq = sequence::current();
+                                            if input_tx.send(data).is_ok() {
+                                                tap_protocol::Response::Injected { seq }
+                                            } else {
+                                                tap_protocol::Response::Error { code: tap_protocol::ErrorCode::SessionEnded, message: "session ended".to_string() }
+                                            }
+                                        }
+                                    },
                                 }
                             }
+                            tap_protocol::Request::SubscribeEvents { kinds } => {
+                                events = Some(events::Subscription::new(kinds));
+                                tap_protocol::Response::Ok
+                            }
+                            tap_protocol::Request::GetCommandOutput { nth } => match SCROLLBACK.read().command_output(nth) {
+                                Some((output, exit_code)) => tap_protocol::Response::CommandOutput { output, exit_code },
+                                None => tap_protocol::Response::Error { code: tap_protocol::ErrorCode::Other, message: format!("no output recorded for command {nth} back; needs shell integration marking commands (OSC 133)") },
+                            },
+                            tap_protocol::Request::InjectAndCapture { data, until, timeout_ms } => match input::accept_injected(data.into_bytes()).await {
+                                Err(reason) => tap_protocol::Response::Error { code: tap_protocol::ErrorCode::Denied, message: reason },
+                                Ok(data) => {
+                                    let timeout = timeout_ms.map(std::time::Duration::from_millis);
+                                    exec::inject_and_capture(data, &until, timeout, output_rx.resubscribe(), &input_tx).await
+                                }
+                            },
+                            tap_protocol::Request::RunCommand { command, timeout_ms, interactive } => match input::accept_injected(command.into_bytes()).await {
+                                Err(reason) => tap_protocol::Response::Error { code: tap_protocol::ErrorCode::Denied, message: reason },
+                                Ok(command) => {
+                                    let command = String::from_utf8_lossy(&command).into_owned();
+                                    let timeout = timeout_ms.map(std::time::Duration::from_millis);
+                                    let (prompt_tx, mut prompt_rx) = tokio::sync::mpsc::unbounded_channel();
+                                    let run = exec::run(&command, timeout, interactive.then_some(prompt_tx), output_rx.resubscribe(), &input_tx);
+                                    tokio::pin!(run);
+                                    loop {
+                                        tokio::select! {
+                                            response = &mut run => break response,
+                                            Some(prompt) = prompt_rx.recv() => {
+                                                if write_reply(&mut stream, request_id, &prompt).await.is_err() {
+                                                    return;
+                                                }
+                                            }
+                                        }
+                                    }
+                                }
+                            },
                             tap_protocol::Request::GetSize => {
                                 if let Some(&master_fd) = MASTER_FD.get() {
                                     let mut ws: nix::pty::Winsize = unsafe { std::mem::zeroed() };
@@ -185,132 +651,411 @@ async fn handle_json_client(
                                         cols: ws.ws_col,
                                     }
                                 } else {
-                                    tap_protocol::Response::Error { message: "no master FD".to_string() }
+                                    tap_protocol::Response::Error { code: tap_protocol::ErrorCode::Other, message: "no master FD".to_string() }
                                 }
                             }
-                            tap_protocol::Request::Subscribe => {
-                                tap_protocol::Response::Subscribed
+                            tap_protocol::Request::GetTimes => tap_protocol::Response::Times { times: times::current() },
+                            tap_protocol::Request::GetTitle => tap_protocol::Response::Title { title: SCROLLBACK.read().title() },
+                            tap_protocol::Request::GetCwd => match cwd::current() {
+                                Some(cwd) => tap_protocol::Response::Cwd { cwd },
+                                None => tap_protocol::Response::Error { code: tap_protocol::ErrorCode::Other, message: "working directory is unknown".to_string() },
+                            },
+                            tap_protocol::Request::Subscribe { from_seq } => match state.subscribe() {
+                                Ok(next) => {
+                                    let subscribed = match from_seq {
+                                        None => Ok(None),
+                                        Some(seq) => sequence::subscribe_from(&output_rx, seq).map(Some),
+                                    };
+                                    match subscribed {
+                                        Ok(from) => {
+                                            state = next;
+                                            // Only detach requests from now on apply
+                                            detach_rx = detach_rx.resubscribe();
+                                            if let Some((rx, output)) = from {
+                                                output_rx = rx;
+                                                replay = output;
+                                            }
+                                            tap_protocol::Response::Subscribed
+                                        }
+                                        Err(message) => tap_protocol::Response::Error { code: tap_protocol::ErrorCode::Other, message },
+                                    }
+                                }
+                                Err(e) => e.to_response(),
+                            },
+                            tap_protocol::Request::DetachClients => {
+                                let _ = DETACH_CLIENTS.send(());
+                                tap_protocol::Response::Ok
                             }
-                            tap_protocol::Request::Attach { rows, cols } => {
-                                // Check if already attached
-                                let mut attached = attached_client.lock().await;
-                                if attached.is_some() {
-                                    tap_protocol::Response::Error { message: "session already has attached client".to_string() }
+                            tap_protocol::Request::Migrate { to } => {
+                                if LOCAL_TERMINAL.load(Ordering::Relaxed) {
+                                    tap_protocol::Response::Error { code: tap_protocol::ErrorCode::Unsupported, message: "session is running in a terminal; start it detached to migrate it".to_string() }
                                 } else {
-                                    // Set up attached client
-                                    let (client_output_tx, mut client_output_rx) = tokio::sync::mpsc::unbounded_channel();
-                                    *attached = Some(AttachedClient { output_tx: client_output_tx });
-                                    drop(attached);
-
-                                    // Resize PTY to client's terminal size
-                                    if let Some(&master_fd) = MASTER_FD.get() {
-                                        set_window_size_raw(master_fd, rows, cols);
+                                    match migrate_session(std::path::Path::new(&to)) {
+                                        Ok(()) => {
+                                            handed_off = true;
+                                            tap_protocol::Response::Ok
+                                        }
+                                        Err(e) => tap_protocol::Response::Error { code: tap_protocol::ErrorCode::Other, message: format!("{e:#}") },
                                     }
+                                }
+                            }
+                            tap_protocol::Request::Upgrade { exe } => {
+                                use std::os::unix::fs::PermissionsExt as _;
 
-                                    // Get current scrollback for initial display
-                                    let scrollback = SCROLLBACK.read().get_lines(None);
+                                if LOCAL_TERMINAL.load(Ordering::Relaxed) {
+                                    tap_protocol::Response::Error { code: tap_protocol::ErrorCode::Unsupported, message: "session is running in a terminal; start it detached to upgrade it".to_string() }
+                                } else if !std::fs::metadata(&exe).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0) {
+                                    tap_protocol::Response::Error { code: tap_protocol::ErrorCode::Other, message: format!("{exe} isn't an executable") }
+                                } else {
+                                    upgrade_to = Some(std::path::PathBuf::from(exe));
+                                    tap_protocol::Response::Ok
+                                }
+                            }
+                            tap_protocol::Request::StartRecording { path } => {
+                                match recording::start(std::path::Path::new(&path)) {
+                                    Ok(()) => tap_protocol::Response::Ok,
+                                    Err(e) => tap_protocol::Response::Error {
+                                        code: tap_protocol::ErrorCode::Other,
+                                        message: e.to_string(),
+                                    },
+                                }
+                            }
+                            tap_protocol::Request::StopRecording => match recording::stop() {
+                                Some(path) => tap_protocol::Response::RecordingStopped {
+                                    path: path.display().to_string(),
+                                },
+                                None => tap_protocol::Response::Error {
+                                    code: tap_protocol::ErrorCode::Other,
+                                    message: "not recording".to_string(),
+                                },
+                            },
+                            tap_protocol::Request::Attach { rows, cols, no_resize, read_only, detach_others } => {
+                                if let Err(e) = state.attach() {
+                                    e.to_response()
+                                } else {
+                                    // Set up attached client; the PTY fits the smallest
+                                    // attached terminal unless this one opted out
+                                    let (client_output_tx, mut client_output_rx) = tokio::sync::mpsc::unbounded_channel();
+                                    let size = (!no_resize && !read_only).then_some((rows, cols));
+                                    let client_id = {
+                                        let mut attached = attached_client.lock().await;
+                                        if detach_others {
+                                            let detached = attached.remove_all();
+                                            tracing::debug!("detached {detached} other client(s)");
+                                        }
+                                        let client_id = attached.add(client_output_tx, size);
+                                        attached.apply_smallest_size();
+                                        client_id
+                                    };
+                                    detach_rx = detach_rx.resubscribe();
+
+                                    // Get current scrollback and cursor shape for initial display
+                                    let (scrollback, cursor_style, tail, title) = {
+                                        let scrollback = SCROLLBACK.read();
+                                        (scrollback.get_lines(None), scrollback.cursor_style(), scrollback.recent_output(), scrollback.title())
+                                    };
 
                                     // Send attach response
-                                    let response = tap_protocol::Response::Attached { scrollback };
-                                    let response_bytes = serde_json::to_vec(&response).unwrap();
-                                    if stream.write_all(&response_bytes).await.is_err() {
-                                        let mut attached = attached_client.lock().await;
-                                        *attached = None;
-                                        break;
-                                    }
-                                    if stream.write_all(b"\n").await.is_err() {
-                                        let mut attached = attached_client.lock().await;
-                                        *attached = None;
+                                    let response = tap_protocol::Response::Attached { scrollback, cursor_style, tail, title };
+                                    if write_reply(&mut stream, request_id, &response).await.is_err() {
+                                        detach_client(&attached_client, client_id).await;
                                         break;
                                     }
 
-                                    // Now switch to binary I/O mode for this client
+                                    // Now switch to binary I/O mode for this client. Output
+                                    // from a prior subscription stops here; it continues
+                                    // through the attached client channel below.
                                     // Split stream for bidirectional communication
-                                    let (mut read_half, mut write_half) = stream.into_split();
+                                    let (mut read_half, mut write_half) = tokio::io::split(stream);
 
-                                    // Forward input from client to PTY
+                                    // Forward input from client to PTY. Clients that send
+                                    // heartbeats are dropped once they stop.
                                     let input_tx_clone = input_tx.clone();
                                     let attached_client_clone = attached_client.clone();
                                     let session_ended_clone = session_ended.clone();
-                                    tokio::spawn(async move {
+                                    let last_heard: Arc<parking_lot::Mutex<Option<std::time::Instant>>> = Arc::default();
+                                    let last_heard_clone = last_heard.clone();
+                                    // Requests sent right behind the attach
+                                    let unread = unread.split().to_vec();
+                                    let reader = tokio::spawn(async move {
                                         let mut buf = vec![0u8; IO_BUFFER_SIZE];
-                                        loop {
-                                            if session_ended_clone.load(Ordering::Relaxed) {
-                                                break;
-                                            }
-                                            match read_half.read(&mut buf).await {
-                                                Ok(0) => break,
-                                                Ok(n) => {
-                                                    // Parse as protocol message first
-                                                    if let Ok(request) = serde_json::from_slice::<tap_protocol::Request>(&buf[..n]) {
-                                                        match request {
-                                                            tap_protocol::Request::Input { data } => {
+                                        // Requests aren't delimited, and one read may hold
+                                        // several or end partway through one
+                                        let mut pending = unread;
+                                        'read: loop {
+                                            let mut requests = serde_json::Deserializer::from_slice(&pending)
+                                                .into_iter::<tap_protocol::Request>();
+                                            let mut consumed = 0;
+                                            while let Some(request) = requests.next() {
+                                                let request = match request {
+                                                    Ok(request) => request,
+                                                    Err(e) if e.is_eof() => break,
+                                                    Err(e) => {
+                                                        tracing::debug!("dropping unreadable request from attached client: {e}");
+                                                        consumed = pending.len();
+                                                        break;
+                                                    }
+                                                };
+                                                consumed = requests.byte_offset();
+                                                match request {
+                                                    tap_protocol::Request::Input { .. } if read_only => {
+                                                        tracing::debug!("dropping input from read-only client");
+                                                    }
+                                                    tap_protocol::Request::Input { data } => {
+                                                        osc::cache_color_replies(&data);
+                                                        match input::filter_attached(data) {
+                                                            Ok(data) => {
                                                                 if input_tx_clone.send(data).is_err() {
-                                                                    break;
+                                                                    break 'read;
                                                                 }
                                                             }
-                                                            tap_protocol::Request::Resize { rows, cols } => {
-                                                                if let Some(&master_fd) = MASTER_FD.get() {
-                                                                    set_window_size_raw(master_fd, rows, cols);
-                                                                }
-                                                            }
-                                                            _ => {}
+                                                            Err(reason) => tracing::debug!("dropped input: {reason}"),
                                                         }
                                                     }
+                                                    tap_protocol::Request::Resize { rows, cols } => {
+                                                        let mut attached = attached_client_clone.lock().await;
+                                                        attached.set_size(client_id, rows, cols);
+                                                        attached.apply_smallest_size();
+                                                    }
+                                                    tap_protocol::Request::Ping => {
+                                                        *last_heard_clone.lock() = Some(std::time::Instant::now());
+                                                    }
+                                                    tap_protocol::Request::Redraw => {
+                                                        // Under the lock PTY output is pushed under, so the
+                                                        // screen holds exactly the output sent before it
+                                                        let attached = attached_client_clone.lock().await;
+                                                        attached.send(client_id, SCROLLBACK.read().screen_formatted());
+                                                    }
+                                                    other => {
+                                                        tracing::debug!("ignoring request while attached: {other:?}");
+                                                    }
                                                 }
-                                                Err(_) => break,
                                             }
+                                            pending.drain(..consumed);
+
+                                            if session_ended_clone.load(Ordering::Relaxed) {
+                                                break;
+                                            }
+                                            let n = match read_half.read(&mut buf).await {
+                                                Ok(0) | Err(_) => break,
+                                                Ok(n) => n,
+                                            };
+                                            if let Some(heard) = last_heard_clone.lock().as_mut() {
+                                                *heard = std::time::Instant::now();
+                                            }
+                                            pending.extend_from_slice(&buf[..n]);
                                         }
-                                        // Client disconnected - clear attached state
-                                        let mut attached = attached_client_clone.lock().await;
-                                        *attached = None;
+                                        // Client disconnected
+                                        detach_client(&attached_client_clone, client_id).await;
                                     });
 
                                     // Forward output from PTY to client
-                                    loop {
-                                        tokio::select! {
-                                            Some(data) = client_output_rx.recv() => {
-                                                let response = tap_protocol::Response::Output { data };
-                                                let response_bytes = serde_json::to_vec(&response).unwrap();
-                                                if write_half.write_all(&response_bytes).await.is_err() {
-                                                    break;
+                                    let forward = async {
+                                        loop {
+                                            tokio::select! {
+                                                data = client_output_rx.recv() => {
+                                                    // Closed once this client is forgotten: it left,
+                                                    // or another attached with `detach_others`
+                                                    let Some(data) = data else {
+                                                        if !reader.is_finished() {
+                                                            let response = tap_protocol::Response::Detached {
+                                                                reason: "another client took over".to_string(),
+                                                            };
+                                                            let _ = write_response(&mut write_half, &response).await;
+                                                        }
+                                                        break;
+                                                    };
+                                                    let response = tap_protocol::Response::Output { data };
+                                                    if write_response(&mut write_half, &response).await.is_err() {
+                                                        break;
+                                                    }
+                                                }
+                                                Ok(pending) = pending_rx.recv() => {
+                                                    let response = tap_protocol::Response::PendingInput {
+                                                        id: pending.id,
+                                                        data: pending.data,
+                                                        delay_ms: pending.delay_ms,
+                                                    };
+                                                    if write_response(&mut write_half, &response).await.is_err() {
+                                                        break;
+                                                    }
                                                 }
-                                                if write_half.write_all(b"\n").await.is_err() {
+                                                Ok(()) = detach_rx.recv() => {
+                                                    tracing::debug!("detaching attached client on request");
                                                     break;
                                                 }
+                                                else => break,
+                                            }
+                                        }
+                                    };
+                                    // Also ends a write blocked on a client that stopped reading
+                                    let heartbeat_timeout = tap_protocol::HEARTBEAT_INTERVAL * HEARTBEAT_MISSES;
+                                    let watchdog = async {
+                                        let mut check = tokio::time::interval(tap_protocol::HEARTBEAT_INTERVAL);
+                                        loop {
+                                            check.tick().await;
+                                            if last_heard.lock().is_some_and(|heard| heard.elapsed() > heartbeat_timeout) {
+                                                break;
                                             }
-                                            else => break,
+                                        }
+                                    };
+                                    tokio::select! {
+                                        () = forward => {}
+                                        () = watchdog => {
+                                            tracing::info!("dropping attached client {client_id}: no heartbeat for {heartbeat_timeout:?}");
                                         }
                                     }
+                                    reader.abort();
+                                    detach_client(&attached_client, client_id).await;
 
-                                    // Session ended or client disconnected
+                                    // Session ended, client disconnected or was detached
                                     return;
                                 }
                             }
                             tap_protocol::Request::Input { data } => {
                                 // Direct input (for non-attached clients)
-                                if input_tx.send(data).is_ok() {
-                                    tap_protocol::Response::Ok
-                                } else {
-                                    tap_protocol::Response::Error { message: "session ended".to_string() }
+                                match input::accept_injected(data).await {
+                                    Err(reason) => tap_protocol::Response::Error { code: tap_protocol::ErrorCode::Denied, message: reason },
+                                    Ok(data) if input_tx.send(data).is_ok() => tap_protocol::Response::Ok,
+                                    Ok(_) => tap_protocol::Response::Error { code: tap_protocol::ErrorCode::SessionEnded, message: "session ended".to_string() },
                                 }
                             }
                             tap_protocol::Request::Resize { rows, cols } => {
-                                if let Some(&master_fd) = MASTER_FD.get() {
-                                    set_window_size_raw(master_fd, rows, cols);
+                                if MASTER_FD.get().is_some() {
+                                    // No bigger than attached terminals can show
+                                    let (rows, cols) = match attached_client.lock().await.smallest_size() {
+                                        Some((max_rows, max_cols)) => (rows.min(max_rows), cols.min(max_cols)),
+                                        None => (rows, cols),
+                                    };
+                                    resize::request(rows, cols);
+                                    tap_protocol::Response::Size { rows, cols }
+                                } else {
+                                    tap_protocol::Response::Error { code: tap_protocol::ErrorCode::Other, message: "no master FD".to_string() }
+                                }
+                            }
+                            tap_protocol::Request::Screenshot => {
+                                let rows = SCROLLBACK.read().styled_rows();
+                                tap_protocol::Response::Screenshot {
+                                    svg: screenshot::render_svg(&rows),
+                                }
+                            }
+                            tap_protocol::Request::GetScreen => tap_protocol::Response::Screen {
+                                screen: SCROLLBACK.read().screen(),
+                            },
+                            tap_protocol::Request::ResyncScreen => match resync::resync(&input_tx) {
+                                Ok(()) => tap_protocol::Response::Ok,
+                                Err(message) => tap_protocol::Response::Error { code: tap_protocol::ErrorCode::Other, message },
+                            },
+                            tap_protocol::Request::ClearScrollback => {
+                                SCROLLBACK.write().clear_history();
+                                tap_protocol::Response::Ok
+                            }
+                            tap_protocol::Request::GetCopyText => {
+                                let (lines, (line, col)) = SCROLLBACK.write().copy_text();
+                                tap_protocol::Response::CopyText { lines, line, col }
+                            }
+                            tap_protocol::Request::Redraw => tap_protocol::Response::Error { code: tap_protocol::ErrorCode::NotAttached, message: "only attached clients can ask for a redraw".to_string() },
+                            tap_protocol::Request::ResetAltScreen => {
+                                if SCROLLBACK.write().reset_alt() {
+                                    attached_client.lock().await.broadcast(scrollback::LEAVE_ALT_SCREEN);
+                                }
+                                tap_protocol::Response::Ok
+                            }
+                            tap_protocol::Request::GetEnv => {
+                                if let Some(&child_pid) = CHILD_PID.get() {
+                                    match procenv::read_process_env(child_pid) {
+                                        Ok(vars) => tap_protocol::Response::Env { vars },
+                                        Err(e) => tap_protocol::Response::Error {
+                                            code: if e.kind() == std::io::ErrorKind::Unsupported {
+                                                tap_protocol::ErrorCode::Unsupported
+                                            } else {
+                                                tap_protocol::ErrorCode::Other
+                                            },
+                                            message: format!("failed to read child environment: {e}"),
+                                        },
+                                    }
+                                } else {
+                                    tap_protocol::Response::Error { code: tap_protocol::ErrorCode::Other, message: "no child process".to_string() }
+                                }
+                            }
+                            tap_protocol::Request::OpenEditor => {
+                                if LOCAL_TERMINAL.load(Ordering::Relaxed) {
+                                    OPEN_EDITOR.notify_one();
                                     tap_protocol::Response::Ok
                                 } else {
-                                    tap_protocol::Response::Error { message: "no master FD".to_string() }
+                                    let content = SCROLLBACK.read().get_lines(None);
+                                    match editor::dump_scrollback_to_file(&content) {
+                                        Ok(path) => tap_protocol::Response::ScrollbackFile {
+                                            path: path.display().to_string(),
+                                        },
+                                        Err(e) => tap_protocol::Response::Error { code: tap_protocol::ErrorCode::Other, message: format!("{e:#}") },
+                                    }
                                 }
                             }
+                            tap_protocol::Request::Kill { signal } => kill_child(signal),
+                            tap_protocol::Request::Wait { stall_secs } => {
+                                let exit_code = match watchdog::stall_after(stall_secs) {
+                                    None => wait_for_exit().await,
+                                    Some(stall_after) => {
+                                        let mut reported = None;
+                                        loop {
+                                            tokio::select! {
+                                                exit_code = wait_for_exit() => break exit_code,
+                                                idle_secs = watchdog::next_stall(stall_after, &mut reported) => {
+                                                    let stalled = tap_protocol::Response::Stalled { idle_secs };
+                                                    if write_reply(&mut stream, request_id, &stalled).await.is_err() {
+                                                        return;
+                                                    }
+                                                }
+                                            }
+                                        }
+                                    }
+                                };
+                                tap_protocol::Response::SessionEnded { exit_code }
+                            }
+                            tap_protocol::Request::SetBuffer { name, data } => {
+                                match buffers::set(&name, &data) {
+                                    Ok(()) => tap_protocol::Response::Ok,
+                                    Err(e) => tap_protocol::Response::Error { code: tap_protocol::ErrorCode::Other, message: format!("{e:#}") },
+                                }
+                            }
+                            tap_protocol::Request::GetClipboard => match SCROLLBACK.read().app_clipboard() {
+                                Some(data) => tap_protocol::Response::Clipboard { data: String::from_utf8_lossy(data).into_owned() },
+                                None => tap_protocol::Response::Error {
+                                    code: tap_protocol::ErrorCode::Other,
+                                    message: "the app hasn't copied anything (or [clipboard] keep is off)".to_string(),
+                                },
+                            },
+                            tap_protocol::Request::GetBuffer { name } => {
+                                match buffers::get(&name) {
+                                    Ok(data) => tap_protocol::Response::Buffer { data },
+                                    Err(e) => tap_protocol::Response::Error { code: tap_protocol::ErrorCode::Other, message: format!("{e:#}") },
+                                }
+                            }
+                            tap_protocol::Request::PasteBuffer { name } => {
+                                match buffers::get(&name) {
+                                    Ok(data) => paste(data.into_bytes(), &input_tx).await,
+                                    Err(e) => tap_protocol::Response::Error { code: tap_protocol::ErrorCode::Other, message: format!("{e:#}") },
+                                }
+                            }
+                            tap_protocol::Request::Paste { data } => paste(data.into_bytes(), &input_tx).await,
                         };
 
-                        let response_bytes = serde_json::to_vec(&response).unwrap();
-                        if stream.write_all(&response_bytes).await.is_err() {
+                        if write_reply(&mut stream, request_id, &response).await.is_err() {
                             break;
                         }
-                        if stream.write_all(b"\n").await.is_err() {
-                            break;
+                        if !replay.is_empty() {
+                            let output = tap_protocol::Response::Output { data: replay };
+                            if write_response(&mut stream, &output).await.is_err() {
+                                break;
+                            }
+                        }
+                        if handed_off {
+                            migrate::HANDED_OFF.notify_one();
+                        }
+                        if let Some(exe) = upgrade_to {
+                            let e = upgrade_session(&exe);
+                            tracing::error!("upgrade failed: {e:#}");
                         }
                     }
                     Err(e) => {
@@ -319,15 +1064,41 @@ async fn handle_json_client(
                     }
                 }
             }
-            result = output_rx.recv() => {
+            () = tokio::time::sleep_until(hello_deadline), if !authenticated => {
+                tracing::warn!("dropping TCP client that didn't authenticate in time");
+                break;
+            }
+            Ok(()) = detach_rx.recv(), if state == client_state::ClientState::Subscribed => {
+                tracing::debug!("detaching subscribed client on request");
+                break;
+            }
+            Ok(pending) = pending_rx.recv(), if state.receives_broadcast() => {
+                let response = tap_protocol::Response::PendingInput {
+                    id: pending.id,
+                    data: pending.data,
+                    delay_ms: pending.delay_ms,
+                };
+                if write_response(&mut stream, &response).await.is_err() {
+                    break;
+                }
+            }
+            event = events::next(events.as_mut()) => {
+                let response = tap_protocol::Response::Event { event };
+                if write_response(&mut stream, &response).await.is_err() {
+                    break;
+                }
+            }
+            Ok((rows, cols)) = resized_rx.recv(), if state.receives_broadcast() => {
+                let response = tap_protocol::Response::Resized { rows, cols };
+                if write_response(&mut stream, &response).await.is_err() {
+                    break;
+                }
+            }
+            result = output_rx.recv(), if state.receives_broadcast() => {
                 match result {
                     Ok(data) => {
                         let response = tap_protocol::Response::Output { data };
-                        let response_bytes = serde_json::to_vec(&response).unwrap();
-                        if stream.write_all(&response_bytes).await.is_err() {
-                            break;
-                        }
-                        if stream.write_all(b"\n").await.is_err() {
+                        if write_response(&mut stream, &response).await.is_err() {
                             break;
                         }
                     }
@@ -336,60 +1107,546 @@ async fn handle_json_client(
                 }
             }
         }
-    }
+    }
+}
+
+async fn run_socket_server(
+    socket_path: std::path::PathBuf,
+    output_tx: tokio::sync::broadcast::Sender<Vec<u8>>,
+    input_tx: InputSender,
+    attached_client: Arc<Mutex<attached::AttachedClients>>,
+    session_ended: Arc<AtomicBool>,
+    inherited: Option<std::os::fd::OwnedFd>,
+) -> std::io::Result<()> {
+    let std_listener = match inherited {
+        Some(listener) => std::os::unix::net::UnixListener::from(listener),
+        None => {
+            let _ = std::fs::remove_file(&socket_path);
+            std::os::unix::net::UnixListener::bind(&socket_path)?
+        }
+    };
+    std_listener.set_nonblocking(true)?;
+    let _ = LISTENER_FD.set(std_listener.as_raw_fd());
+    let listener = tokio::net::UnixListener::from_std(std_listener)?;
+
+    tracing::info!("listening on {}", socket_path.display());
+
+    loop {
+        if session_ended.load(Ordering::Relaxed) {
+            break Ok(());
+        }
+
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tracing::debug!("client connected");
+                let output_rx = output_tx.subscribe();
+                let input_tx = input_tx.clone();
+                let attached_client = attached_client.clone();
+                let session_ended = session_ended.clone();
+                tokio::spawn(handle_json_client(
+                    stream,
+                    output_rx,
+                    input_tx,
+                    attached_client,
+                    session_ended,
+                    None,
+                ));
+            }
+            Err(e) => {
+                tracing::error!("accept error: {e}");
+            }
+        }
+    }
+}
+
+/// Accept clients over TCP, each of which must authenticate with `token`.
+async fn run_tcp_server(
+    listener: tokio::net::TcpListener,
+    token: Arc<str>,
+    output_tx: tokio::sync::broadcast::Sender<Vec<u8>>,
+    input_tx: InputSender,
+    attached_client: Arc<Mutex<attached::AttachedClients>>,
+    session_ended: Arc<AtomicBool>,
+) {
+    let clients = Arc::new(tokio::sync::Semaphore::new(remote::MAX_CLIENTS));
+    loop {
+        if session_ended.load(Ordering::Relaxed) {
+            break;
+        }
+
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let Ok(permit) = clients.clone().try_acquire_owned() else {
+                    tracing::warn!("refusing TCP client from {peer}: too many connected");
+                    continue;
+                };
+                tracing::debug!("TCP client connected from {peer}");
+                // Keystrokes go out as they're typed
+                let _ = stream.set_nodelay(true);
+                let client = handle_json_client(
+                    stream,
+                    output_tx.subscribe(),
+                    input_tx.clone(),
+                    attached_client.clone(),
+                    session_ended.clone(),
+                    Some(token.clone()),
+                );
+                tokio::spawn(async move {
+                    client.await;
+                    drop(permit);
+                });
+            }
+            Err(e) => {
+                tracing::error!("TCP accept error: {e}");
+                // Failures like running out of descriptors repeat until
+                // something closes
+                tokio::time::sleep(remote::ACCEPT_BACKOFF).await;
+            }
+        }
+    }
+}
+
+/// Fork and exec `command` on the PTY `slave`. With `job_control` the child
+/// gets its own session with the PTY as controlling terminal; otherwise it
+/// stays in tap's process group.
+fn spawn_child(
+    master: &std::os::fd::OwnedFd,
+    slave: std::os::fd::OwnedFd,
+    command: &[String],
+    term: &str,
+    colorterm: Option<&str>,
+    env: &[(&str, &std::ffi::OsStr)],
+    job_control: bool,
+) -> eyre::Result<nix::unistd::Pid> {
+    use std::os::unix::ffi::OsStrExt as _;
+
+    // Everything exec needs is prepared before forking: between fork and
+    // exec, the child of a multithreaded process may only make
+    // async-signal-safe calls, which allocating or changing the
+    // environment aren't
+    let envp = child_env(term, colorterm, env)?;
+    let path = envp
+        .iter()
+        .find_map(|var| var.to_bytes().strip_prefix(b"PATH="))
+        .map(std::ffi::OsStr::from_bytes);
+    let program = std::ffi::CString::new(
+        find_program(&command[0], path)
+            .into_os_string()
+            .into_encoded_bytes(),
+    )
+    .wrap_err("command contains a NUL byte")?;
+    let argv = command
+        .iter()
+        .map(|arg| std::ffi::CString::new(arg.as_str()))
+        .collect::<Result<Vec<_>, _>>()
+        .wrap_err("command contains a NUL byte")?;
+    let exec_failed = format!("tap: failed to run {}\r\n", command[0]);
+
+    match unsafe { nix::unistd::fork() } {
+        Ok(nix::unistd::ForkResult::Child) => {
+            unsafe {
+                nix::libc::close(master.as_raw_fd());
+            }
+
+            if job_control {
+                nix::unistd::setsid().expect("setsid failed");
+
+                // Set controlling terminal
+                unsafe {
+                    nix::libc::ioctl(slave.as_raw_fd(), nix::libc::TIOCSCTTY as _, 0);
+                }
+            }
+
+            // Dup slave to stdin/stdout/stderr using libc directly
+            let slave_raw = slave.as_raw_fd();
+            unsafe {
+                nix::libc::dup2(slave_raw, nix::libc::STDIN_FILENO);
+                nix::libc::dup2(slave_raw, nix::libc::STDOUT_FILENO);
+                nix::libc::dup2(slave_raw, nix::libc::STDERR_FILENO);
+            }
+
+            if slave_raw > 2 {
+                drop(slave);
+            }
+
+            let _ = nix::unistd::execve(&program, &argv, &envp);
+            unsafe {
+                nix::libc::write(
+                    nix::libc::STDERR_FILENO,
+                    exec_failed.as_ptr().cast(),
+                    exec_failed.len(),
+                );
+                nix::libc::_exit(127)
+            }
+        }
+        // Slave is closed in the parent on return
+        Ok(nix::unistd::ForkResult::Parent { child }) => Ok(child),
+        Err(e) => Err(eyre::eyre!("fork failed: {e}")),
+    }
+}
+
+/// Whether `command` starts an interactive shell: a known shell, given
+/// no script or `-c` code to run.
+fn is_interactive_shell(command: &[String]) -> bool {
+    let Some((program, args)) = command.split_first() else {
+        return false;
+    };
+    let name = std::path::Path::new(program)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(program);
+    let known = matches!(
+        name.trim_start_matches('-'),
+        "sh" | "bash"
+            | "zsh"
+            | "fish"
+            | "nu"
+            | "nushell"
+            | "dash"
+            | "ksh"
+            | "mksh"
+            | "yash"
+            | "tcsh"
+            | "csh"
+            | "elvish"
+            | "xonsh"
+    );
+    known && args.iter().all(|arg| arg.starts_with('-') && arg != "-c")
+}
+
+/// The child's environment: tap's own, with `TERM`, `COLORTERM` and `env`
+/// set and the TCP token left out.
+fn child_env(
+    term: &str,
+    colorterm: Option<&str>,
+    env: &[(&str, &std::ffi::OsStr)],
+) -> eyre::Result<Vec<std::ffi::CString>> {
+    use std::os::unix::ffi::OsStringExt as _;
+
+    let mut vars: Vec<(std::ffi::OsString, std::ffi::OsString)> = std::env::vars_os()
+        .filter(|(key, _)| key != "COLORTERM" && key != tap_protocol::AUTH_TOKEN_ENV)
+        .collect();
+    let mut set = |key: &str, value: &std::ffi::OsStr| {
+        vars.retain(|(existing, _)| existing != key);
+        vars.push((key.into(), value.to_owned()));
+    };
+    set("TERM", term.as_ref());
+    if let Some(colorterm) = colorterm {
+        set("COLORTERM", colorterm.as_ref());
+    }
+    for &(key, value) in env {
+        set(key, value);
+    }
+    vars.into_iter()
+        .map(|(key, value)| {
+            let mut var = key.into_vec();
+            var.push(b'=');
+            var.extend(value.into_vec());
+            std::ffi::CString::new(var)
+        })
+        .collect::<Result<_, _>>()
+        .wrap_err("environment variable contains a NUL byte")
+}
+
+/// Where `program` is, searching `path` like execvp() would. Left as is
+/// if it's not found, for exec to report.
+fn find_program(program: &str, path: Option<&std::ffi::OsStr>) -> std::path::PathBuf {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    if program.contains('/') {
+        return program.into();
+    }
+    let path = path.unwrap_or("/usr/bin:/bin".as_ref());
+    std::env::split_paths(path)
+        .map(|dir| dir.join(program))
+        .find(|candidate| {
+            candidate
+                .metadata()
+                .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+        })
+        .unwrap_or_else(|| program.into())
+}
+
+/// The session's state for handing it over, and its PTY master.
+fn handoff_state() -> eyre::Result<(migrate::SessionState, i32)> {
+    let (Some(&child_pid), Some(&master_fd)) = (CHILD_PID.get(), MASTER_FD.get()) else {
+        eyre::bail!("session has no child");
+    };
+    let mut entry = None;
+    modify_sessions_file(&tap_protocol::sessions_file(), |sessions| {
+        entry = sessions
+            .iter()
+            .find(|s| s.get("pid").and_then(|v| v.as_u64()) == Some(u64::from(std::process::id())))
+            .cloned();
+    })?;
+    let entry = entry.ok_or_else(|| eyre::eyre!("session is missing from sessions.json"))?;
+    let state = migrate::SessionState {
+        entry,
+        child_pid,
+        scrollback: SCROLLBACK.read().get_lines(None),
+        listen: remote::addr(),
+        adopted: ADOPTED.load(Ordering::Relaxed),
+    };
+    Ok((state, master_fd))
+}
+
+/// Hand the session to the tap process listening on `to`.
+fn migrate_session(to: &std::path::Path) -> eyre::Result<()> {
+    let (state, master_fd) = handoff_state()?;
+    migrate::send(to, &state, master_fd)
+}
+
+/// Replace this server with `exe`, keeping the session. Only returns on failure.
+fn upgrade_session(exe: &std::path::Path) -> eyre::Report {
+    let Some(&listener_fd) = LISTENER_FD.get() else {
+        return eyre::eyre!("server isn't listening");
+    };
+    match handoff_state() {
+        Ok((state, master_fd)) => migrate::exec(exe, &state, master_fd, listener_fd),
+        Err(e) => e,
+    }
+}
+
+/// Reap the child and record its exit, once however many paths get here.
+/// An adopted child is only waited out, with no exit code.
+fn reap_child(child: nix::unistd::Pid) -> Option<i32> {
+    static REAPING: parking_lot::Mutex<()> = parking_lot::Mutex::new(());
+    let _reaping = REAPING.lock();
+    if let Some(&code) = EXIT_CODE.get() {
+        return code;
+    }
+    let code = if ADOPTED.load(Ordering::Relaxed) {
+        wait_until_gone(child);
+        None
+    } else {
+        Some(wait_for_child(child))
+    };
+    record_exit(code);
+    code
+}
+
+/// Wait for a child this process isn't the parent of to exit. It can't be
+/// waited on, so poll whether it's still there.
+fn wait_until_gone(child: nix::unistd::Pid) {
+    // EPERM means the PID now belongs to someone else's process
+    while nix::sys::signal::kill(child, None).is_ok() {
+        std::thread::sleep(ADOPTED_POLL_INTERVAL);
+    }
+}
+
+fn wait_for_child(child: nix::unistd::Pid) -> i32 {
+    loop {
+        // wait4 rather than waitpid, for the CPU time `tap time` reports
+        let mut status = 0;
+        let mut usage: nix::libc::rusage = unsafe { std::mem::zeroed() };
+        if unsafe { nix::libc::wait4(child.as_raw(), &mut status, 0, &mut usage) } == -1 {
+            match nix::errno::Errno::last() {
+                nix::errno::Errno::EINTR => continue,
+                _ => return 1,
+            }
+        }
+        times::finish(&usage);
+        if nix::libc::WIFEXITED(status) {
+            return nix::libc::WEXITSTATUS(status);
+        }
+        if nix::libc::WIFSIGNALED(status) {
+            return 128 + nix::libc::WTERMSIG(status);
+        }
+    }
+}
+
+/// Open the scrollback in the editor with the cursor at the terminal's cursor.
+fn open_editor_at_cursor(editor_cmd: &str, orig_termios: Option<&nix::sys::termios::Termios>) {
+    let scrollback = SCROLLBACK.read();
+    let scrollback_content = scrollback.get_lines(None);
+    let (cursor_row, _) = scrollback.cursor_position();
+    // Editors count columns in characters (or bytes), not terminal cells
+    let cursor_col = scrollback.cursor_char_col();
+    let cursor_byte_col = scrollback.cursor_byte_col();
+
+    let total_lines = scrollback_content.lines().count();
+    let viewport_height = 24;
+    let cursor_line = total_lines.saturating_sub(viewport_height) + cursor_row + 1;
+
+    drop(scrollback);
+
+    if let Err(e) = editor::open_scrollback_in_editor(
+        &scrollback_content,
+        editor_cmd,
+        orig_termios,
+        Some(
+            tap_editor::Position::new(cursor_line, Some(cursor_col + 1))
+                .with_byte_col(cursor_byte_col + 1),
+        ),
+    ) {
+        tracing::error!("failed to open editor: {e:#}");
+        // Otherwise nothing shows why the editor didn't open
+        let message = format!("{e:#}");
+        if let Some(line) = status::styled(&format!("[tap: {message}]"), &std::io::stderr()) {
+            eprint!("\r\n{line}\r\n");
+        }
+        events::emit(tap_protocol::Event::EditorFailed {
+            command: editor_cmd.to_string(),
+            message,
+        });
+    }
+}
+
+/// Run a keybind's shell command in the background.
+fn run_keybind_command(command: &str) {
+    tracing::debug!("running keybind command: {command}");
+    let spawned = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("TAP_SESSION", SESSION_ID.read().as_str())
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn();
+    match spawned {
+        Ok(mut child) => {
+            tokio::spawn(async move {
+                let _ = child.wait().await;
+            });
+        }
+        Err(e) => tracing::warn!("failed to run keybind command '{command}': {e}"),
+    }
+}
+
+/// Resize the PTY and the emulator whenever the local terminal is resized.
+async fn follow_local_window_size(mut sigwinch: tokio::signal::unix::Signal) {
+    while sigwinch.recv().await.is_some() {
+        let ws = get_window_size();
+        resize::request(ws.ws_row, ws.ws_col);
+    }
+}
+
+/// On SIGTERM, hang up the child as `Request::Kill` does by default.
+async fn hang_up_child_on_sigterm(child_pid: nix::unistd::Pid) {
+    let mut sigterm = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+    {
+        Ok(sigterm) => sigterm,
+        Err(e) => {
+            tracing::warn!("failed to install SIGTERM handler: {e}");
+            return;
+        }
+    };
+    sigterm.recv().await;
+
+    tracing::debug!("received SIGTERM, hanging up child");
+    // Most likely a shutdown: leave the session for `tap restore`
+    persist::keep();
+    hang_up_child(child_pid).await;
 }
 
-async fn run_socket_server(
-    socket_path: std::path::PathBuf,
-    output_tx: tokio::sync::broadcast::Sender<Vec<u8>>,
-    input_tx: InputSender,
-    attached_client: Arc<Mutex<Option<AttachedClient>>>,
-    session_ended: Arc<AtomicBool>,
-) -> std::io::Result<()> {
-    let _ = std::fs::remove_file(&socket_path);
-    let std_listener = std::os::unix::net::UnixListener::bind(&socket_path)?;
-    std_listener.set_nonblocking(true)?;
-    let listener = tokio::net::UnixListener::from_std(std_listener)?;
+/// Send SIGHUP to the child's process group, escalating to SIGKILL if it
+/// ignores the hangup.
+async fn hang_up_child(child_pid: nix::unistd::Pid) {
+    let _ = signal_child(child_pid, nix::sys::signal::Signal::SIGHUP);
 
-    tracing::info!("listening on {}", socket_path.display());
+    tokio::time::sleep(KILL_GRACE_PERIOD).await;
+    if EXIT_CODE.get().is_none() {
+        tracing::debug!("child ignored SIGHUP, killing it");
+        let _ = signal_child(child_pid, nix::sys::signal::Signal::SIGKILL);
+    }
+}
 
-    loop {
-        if session_ended.load(Ordering::Relaxed) {
-            break Ok(());
-        }
+/// Send `signal` to the child. Under job control the child leads its own
+/// process group, so the whole group gets it; otherwise the group is tap's
+/// own and only the child is signalled.
+fn signal_child(child_pid: nix::unistd::Pid, signal: nix::sys::signal::Signal) -> nix::Result<()> {
+    if JOB_CONTROL.load(Ordering::Relaxed) {
+        nix::sys::signal::killpg(child_pid, signal)
+    } else {
+        nix::sys::signal::kill(child_pid, signal)
+    }
+}
 
-        match listener.accept().await {
-            Ok((stream, _)) => {
-                tracing::debug!("client connected");
-                let output_rx = output_tx.subscribe();
-                let input_tx = input_tx.clone();
-                let attached_client = attached_client.clone();
-                let session_ended = session_ended.clone();
-                tokio::spawn(handle_json_client(
-                    stream,
-                    output_rx,
-                    input_tx,
-                    attached_client,
-                    session_ended,
-                ));
+/// Pass SIGTSTP and SIGCONT on to the child, so stopping and resuming tap
+/// stops and resumes the command it wraps.
+async fn forward_stop_signals(child_pid: nix::unistd::Pid) {
+    use tokio::signal::unix::{SignalKind, signal};
+
+    let (Ok(mut tstp), Ok(mut cont)) = (
+        signal(SignalKind::from_raw(nix::libc::SIGTSTP)),
+        signal(SignalKind::from_raw(nix::libc::SIGCONT)),
+    ) else {
+        tracing::warn!("failed to install SIGTSTP/SIGCONT handlers");
+        return;
+    };
+    loop {
+        tokio::select! {
+            Some(()) = tstp.recv() => {
+                tracing::debug!("received SIGTSTP, stopping child");
+                let _ = signal_child(child_pid, nix::sys::signal::Signal::SIGTSTP);
+                // The handler replaced the default action, so stop explicitly
+                let _ = nix::sys::signal::raise(nix::sys::signal::Signal::SIGSTOP);
             }
-            Err(e) => {
-                tracing::error!("accept error: {e}");
+            Some(()) = cont.recv() => {
+                let _ = signal_child(child_pid, nix::sys::signal::Signal::SIGCONT);
             }
+            else => break,
         }
     }
 }
 
-fn wait_for_child(child: nix::unistd::Pid) -> i32 {
-    loop {
-        match nix::sys::wait::waitpid(child, None) {
-            Ok(nix::sys::wait::WaitStatus::Exited(_, code)) => return code,
-            Ok(nix::sys::wait::WaitStatus::Signaled(_, sig, _)) => return 128 + sig as i32,
-            Ok(_) => continue,
-            Err(nix::errno::Errno::EINTR) => continue,
-            Err(_) => return 1,
+/// Handle `Request::Kill`. Once the child exits the session's socket and
+/// sessions.json entry are cleaned up, even if something it started keeps
+/// the PTY open and the session's I/O loop running.
+fn kill_child(signal: Option<i32>) -> tap_protocol::Response {
+    let Some(&child_pid) = CHILD_PID.get() else {
+        return tap_protocol::Response::Error {
+            code: tap_protocol::ErrorCode::Other,
+            message: "no child process".to_string(),
+        };
+    };
+    let child_pid = nix::unistd::Pid::from_raw(child_pid);
+    tokio::spawn(clean_up_once_killed(child_pid));
+
+    let Some(signal) = signal else {
+        tokio::spawn(hang_up_child(child_pid));
+        return tap_protocol::Response::Ok;
+    };
+    let signal = match nix::sys::signal::Signal::try_from(signal) {
+        Ok(signal) => signal,
+        Err(e) => {
+            return tap_protocol::Response::Error {
+                code: tap_protocol::ErrorCode::Other,
+                message: format!("invalid signal {signal}: {e}"),
+            };
         }
+    };
+    match signal_child(child_pid, signal) {
+        Ok(()) => tap_protocol::Response::Ok,
+        Err(e) => tap_protocol::Response::Error {
+            code: tap_protocol::ErrorCode::Other,
+            message: format!("failed to send {signal} to child: {e}"),
+        },
+    }
+}
+
+/// Remove the session's socket and mark it ended in sessions.json once the
+/// child has exited, as the I/O loop does when the PTY closes.
+async fn clean_up_once_killed(child_pid: nix::unistd::Pid) {
+    static WATCHING: AtomicBool = AtomicBool::new(false);
+    if WATCHING.swap(true, Ordering::Relaxed) {
+        return;
     }
+    let Ok(exit_code) = tokio::task::spawn_blocking(move || reap_child(child_pid)).await else {
+        return;
+    };
+    persist::discard();
+    let session_id = SESSION_ID.read().clone();
+    let _ = std::fs::remove_file(tap_protocol::socket_path(&session_id));
+    let _ = modify_sessions_file(&tap_protocol::sessions_file(), |sessions| {
+        ended::record(sessions, &session_id, exit_code);
+    });
+}
+
+/// Generate a human-readable session ID.
+#[must_use]
+pub fn generate_session_id() -> String {
+    human_id::gen_id(HUMAN_ID_WORDS)
 }
 
 /// Result of running in attached mode.
@@ -402,22 +1659,9 @@ pub enum RunResult {
 
 /// Run the PTY server with the given configuration.
 pub async fn run(config: ServerConfig) -> eyre::Result<RunResult> {
-    // Load tap config for keybinds
-    let tap_config = tap_config::load().wrap_err("failed to load tap configuration")?;
-    let mut input_processor =
-        input::InputProcessor::new(&tap_config).wrap_err("failed to initialize input processor")?;
-    let editor_cmd = tap_config::get_editor(&tap_config);
-
-    let session_id = config
-        .session_id
-        .unwrap_or_else(|| human_id::gen_id(HUMAN_ID_WORDS));
-
-    let socket_dir = tap_protocol::socket_dir();
-    std::fs::create_dir_all(&socket_dir)
-        .wrap_err_with(|| format!("failed to create socket directory {}", socket_dir.display()))?;
-    let socket_path = tap_protocol::socket_path(&session_id);
-
-    let command = if config.command.is_empty() {
+    let command = if let Some(handoff) = &config.adopt {
+        handoff.command()
+    } else if config.command.is_empty() {
         let shell = std::env::var("SHELL").unwrap_or_else(|_| DEFAULT_SHELL.to_string());
         // Force login/interactive mode for shells that need it to load config
         if shell.ends_with("/nu") || shell.ends_with("/nushell") {
@@ -431,112 +1675,270 @@ pub async fn run(config: ServerConfig) -> eyre::Result<RunResult> {
         config.command.clone()
     };
 
+    // Load tap config for keybinds, with the session's profile. A migrated
+    // or restored session keeps the one it had.
+    let profile = config
+        .profile
+        .clone()
+        .or_else(|| config.adopt.as_ref().and_then(migrate::Handoff::profile))
+        .or_else(|| {
+            config
+                .restore
+                .as_ref()
+                .and_then(|saved| saved.profile.clone())
+        });
+    let (tap_config, profile) = tap_config::load_for_session(profile.as_deref(), &command)
+        .wrap_err("failed to load tap configuration")?;
+    let mut input_processor =
+        input::InputProcessor::new(&tap_config).wrap_err("failed to initialize input processor")?;
+    let editor_cmd = tap_config::get_editor(&tap_config);
+    *SCROLLBACK.write() = scrollback::ScrollbackBuffer::with_limits(
+        tap_config.scrollback.max_lines,
+        tap_config.scrollback.max_bytes,
+    );
+    SCROLLBACK
+        .write()
+        .set_filters(filters::init(&tap_config).wrap_err("invalid output filters")?);
+    SCROLLBACK
+        .write()
+        .set_tail_limit(tap_config.scrollback.attach_tail_bytes);
+    SCROLLBACK
+        .write()
+        .set_keep_clipboard(tap_config.clipboard.keep);
+
+    let detached = config.detached || config.adopt.is_some();
+    let session_id = match &config.adopt {
+        Some(handoff) => handoff.session_id().to_string(),
+        None => config.session_id.unwrap_or_else(generate_session_id),
+    };
+    SESSION_ID.write().clone_from(&session_id);
+
+    let socket_dir = tap_protocol::socket_dir();
+    std::fs::create_dir_all(&socket_dir)
+        .wrap_err_with(|| format!("failed to create socket directory {}", socket_dir.display()))?;
+    let socket_path = tap_protocol::socket_path(&session_id);
+    let listen = match (
+        &config.listen,
+        config.adopt.as_ref().and_then(migrate::Handoff::listen),
+    ) {
+        (Some(listen), _) => Some(listen.clone()),
+        (None, Some(addr)) => {
+            let listen = remote::Listen::from_env(addr);
+            if listen.is_none() {
+                tracing::warn!(
+                    "not serving on {addr}: ${} isn't set",
+                    tap_protocol::AUTH_TOKEN_ENV
+                );
+            }
+            listen
+        }
+        (None, None) => None,
+    };
+    let tcp_listener = match listen {
+        Some(listen) => {
+            let listener = remote::bind(&listen, config.adopt.is_some())
+                .await
+                .wrap_err_with(|| format!("failed to listen on {}", listen.addr))?;
+            Some((listener, Arc::<str>::from(listen.token)))
+        }
+        None => None,
+    };
+
+    // Set the child's terminal type explicitly rather than inheriting blindly
+    let term = tap_config::get_term(&tap_config);
+    let colorterm = tap_config::get_colorterm(&tap_config)
+        .or_else(|| (!detached && probe::truecolor()).then(|| "truecolor".to_string()));
+
     // Write session info (with file locking for concurrent access)
     let sessions_file = tap_protocol::sessions_file();
     let session_id_clone = session_id.clone();
     let command_clone = command.clone();
-    modify_sessions_file(&sessions_file, |sessions| {
-        sessions.push(serde_json::json!({
+
+    // A migrated child keeps the mode it was started with
+    let job_control = config.adopt.as_ref().map_or(
+        tap_config.terminal.job_control,
+        migrate::Handoff::job_control,
+    );
+    JOB_CONTROL.store(job_control, Ordering::Relaxed);
+
+    // A migrated child keeps its start time
+    times::start(
+        config
+            .adopt
+            .as_ref()
+            .and_then(|handoff| handoff.entry()["started"].as_str().map(str::to_string))
+            .and_then(|started| chrono::DateTime::parse_from_rfc3339(&started).ok())
+            .map_or_else(chrono::Utc::now, |started| {
+                started.with_timezone(&chrono::Utc)
+            }),
+    );
+
+    let mut taken = false;
+    modify_sessions_file(&sessions_file, |sessions| match &config.adopt {
+        Some(handoff) => {
+            sessions.retain(|s| s.get("id").and_then(|v| v.as_str()) != Some(&session_id_clone));
+            sessions.push(handoff.entry());
+        }
+        None if name_taken(&session_id_clone, sessions) => taken = true,
+        None => {
+            // An ended session of the same name makes way
+            sessions.retain(|s| s.get("id").and_then(|v| v.as_str()) != Some(&session_id_clone));
+            sessions.push(serde_json::json!({
             "id": session_id_clone,
             "pid": std::process::id(),
             "started": chrono::Utc::now().to_rfc3339(),
             "command": command_clone,
-            "attached": !config.detached,
-        }));
+            "attached": !detached,
+            "tags": config.tags,
+            "term": term,
+            "colorterm": colorterm,
+            "job_control": job_control,
+            "profile": profile,
+            "parent": config.parent,
+            }));
+        }
     })?;
+    eyre::ensure!(!taken, "session '{session_id}' already exists");
 
-    // Open PTY using openpty
-    let ws = if config.detached {
-        // Default size for detached sessions
-        nix::pty::Winsize {
-            ws_row: 24,
-            ws_col: 80,
-            ws_xpixel: 0,
-            ws_ypixel: 0,
-        }
+    let (master, child_pid) = if let Some(handoff) = &config.adopt {
+        let master = handoff.master().wrap_err("failed to take over PTY")?;
+        ADOPTED.store(handoff.adopted(), Ordering::Relaxed);
+        (master, handoff.child_pid())
     } else {
-        get_window_size()
+        // Open PTY using openpty
+        let ws = if detached {
+            // Default size for detached sessions, or the saved one
+            let (rows, cols) = config
+                .restore
+                .as_ref()
+                .map_or((24, 80), |saved| (saved.rows, saved.cols));
+            nix::pty::Winsize {
+                ws_row: rows,
+                ws_col: cols,
+                ws_xpixel: 0,
+                ws_ypixel: 0,
+            }
+        } else {
+            get_window_size()
+        };
+        let nix::pty::OpenptyResult { master, slave } =
+            nix::pty::openpty(Some(&ws), None).map_err(|e| eyre::eyre!("openpty failed: {e}"))?;
+        // Programs in the session can find their own server
+        let mut env: Vec<(&str, &std::ffi::OsStr)> = config
+            .env
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_ref()))
+            .collect();
+        env.extend([
+            ("TAP_SESSION", session_id.as_ref()),
+            ("TAP_SESSION_ID", session_id.as_ref()),
+            ("TAP_SOCKET", socket_path.as_os_str()),
+        ]);
+        let child_pid = spawn_child(
+            &master,
+            slave,
+            &command,
+            &term,
+            colorterm.as_deref(),
+            &env,
+            job_control,
+        )?;
+        (master, child_pid)
     };
-    let nix::pty::OpenptyResult { master, slave } =
-        nix::pty::openpty(Some(&ws), None).map_err(|e| eyre::eyre!("openpty failed: {e}"))?;
 
     let master_raw_fd = master.as_raw_fd();
 
+    // Captured output wraps like the real terminal
+    {
+        let (rows, cols) = resize::current_size(master_raw_fd);
+        SCROLLBACK.write().resize(rows, cols);
+    }
+    if let Some(handoff) = &config.adopt {
+        // Replay the old server's scrollback so queries keep working
+        SCROLLBACK
+            .write()
+            .push(handoff.scrollback().replace('\n', "\r\n").as_bytes());
+    }
+    if let Some(saved) = &config.restore {
+        SCROLLBACK.write().push(&persist::seed(saved));
+    }
+
     // Store master FD for signal handler
     MASTER_FD
         .set(master_raw_fd)
         .map_err(|_| eyre::eyre!("failed to set MASTER_FD — was run() called multiple times?"))?;
 
-    // Set up SIGWINCH handler (only if attached)
-    if !config.detached {
-        unsafe {
-            extern "C" fn handle_sigwinch(_: nix::libc::c_int) {
-                if let Some(&master_fd) = MASTER_FD.get() {
-                    let ws = get_window_size();
-                    set_window_size(master_fd, &ws);
-                }
-            }
-            nix::sys::signal::signal(
-                nix::sys::signal::Signal::SIGWINCH,
-                nix::sys::signal::SigHandler::Handler(handle_sigwinch),
-            )
-            .map_err(|e| eyre::eyre!("failed to set SIGWINCH handler: {e}"))?;
-        }
+    pending::init(&tap_config.inject)?;
+    ended::init(&tap_config.sessions);
+    resync::init(&tap_config.output);
+    input::init_injected(&tap_config).wrap_err("invalid input filters")?;
+    watchdog::init(&tap_config.watchdog);
+    recording::init(recording::AutoRecord::new(
+        &tap_config.record,
+        tap_config::recordings_dir(&tap_config),
+    )?);
+    if let Some(path) = &config.record {
+        recording::start(path)?;
+    }
+    session_log::init(config.log_file.as_deref(), &tap_config.logging, &session_id)?;
+
+    resize::spawn(
+        master_raw_fd,
+        std::time::Duration::from_millis(tap_config.timing.resize_quiescence_ms),
+    );
+
+    // Follow the local terminal's size (only if attached)
+    if !detached {
+        let sigwinch =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::window_change())
+                .map_err(|e| eyre::eyre!("failed to set SIGWINCH handler: {e}"))?;
+        tokio::spawn(follow_local_window_size(sigwinch));
     }
 
-    // Fork child process
-    let child_pid = match unsafe { nix::unistd::fork() } {
-        Ok(nix::unistd::ForkResult::Child) => {
-            drop(master);
-
-            nix::unistd::setsid().expect("setsid failed");
-
-            // Set controlling terminal
-            unsafe {
-                nix::libc::ioctl(slave.as_raw_fd(), nix::libc::TIOCSCTTY as _, 0);
-            }
-
-            // Dup slave to stdin/stdout/stderr using libc directly
-            let slave_raw = slave.as_raw_fd();
-            unsafe {
-                nix::libc::dup2(slave_raw, nix::libc::STDIN_FILENO);
-                nix::libc::dup2(slave_raw, nix::libc::STDOUT_FILENO);
-                nix::libc::dup2(slave_raw, nix::libc::STDERR_FILENO);
-            }
-
-            if slave_raw > 2 {
-                drop(slave);
-            }
-
-            let c_cmd: Vec<std::ffi::CString> = command
-                .iter()
-                .map(|s| std::ffi::CString::new(s.as_str()).unwrap())
-                .collect();
-
-            nix::unistd::execvp(&c_cmd[0], &c_cmd).expect("execvp failed");
-            unreachable!()
-        }
-        Ok(nix::unistd::ForkResult::Parent { child }) => child,
-        Err(e) => {
-            return Err(eyre::eyre!("fork failed: {e}"));
-        }
-    };
+    // Store child PID for environment queries
+    let _ = CHILD_PID.set(child_pid.as_raw());
+    persist::init(&tap_config, child_pid.as_raw());
+    spawn_session_info_updater();
 
-    // Close slave in parent
-    drop(slave);
+    // `tap kill` sends SIGTERM: hang up the child so the session winds down
+    // and cleans up like any other exit
+    tokio::spawn(hang_up_child_on_sigterm(child_pid));
+    tokio::spawn(forward_stop_signals(child_pid));
 
     // Set up broadcast channel for output
     let (output_tx, _) = tokio::sync::broadcast::channel::<Vec<u8>>(BROADCAST_CHANNEL_SIZE);
-
-    // Set up input channel
-    let (input_tx, mut input_rx): (InputSender, InputReceiver) =
-        tokio::sync::mpsc::unbounded_channel();
+    notify::init(&tap_config.notify, output_tx.subscribe())?;
+    triggers::init(&tap_config.triggers, output_tx.subscribe())?;
+
+    // All writes to the PTY go through one ordered queue
+    let input_tx =
+        pty_writer::spawn(master_raw_fd).wrap_err("failed to start PTY writer thread")?;
+
+    // The shell reads this once it's done with its rc files. Anything else
+    // would take it for keystrokes
+    if config.adopt.is_none()
+        && is_interactive_shell(&command)
+        && let Some(init) = &tap_config.shell.init
+    {
+        let mut init = init.clone();
+        if !init.ends_with('\n') {
+            init.push('\n');
+        }
+        let _ = input_tx.send(init.into_bytes());
+    }
 
     // Attached client state
-    let attached_client: Arc<Mutex<Option<AttachedClient>>> = Arc::new(Mutex::new(None));
+    let attached_client: Arc<Mutex<attached::AttachedClients>> =
+        Arc::new(Mutex::new(attached::AttachedClients::default()));
     let session_ended = Arc::new(AtomicBool::new(false));
 
-    // Start server
+    // Start server, on the listener from before an upgrade if there is one
+    let inherited_listener = config
+        .adopt
+        .as_ref()
+        .and_then(migrate::Handoff::listener)
+        .transpose()
+        .wrap_err("failed to take over socket listener")?;
     let server_output_tx = output_tx.clone();
     let server_socket_path = socket_path.clone();
     let server_input_tx = input_tx.clone();
@@ -549,21 +1951,43 @@ pub async fn run(config: ServerConfig) -> eyre::Result<RunResult> {
             server_input_tx,
             server_attached_client,
             server_session_ended,
+            inherited_listener,
         )
         .await
         {
             tracing::error!("server error: {e}");
         }
     });
+    if let Some((listener, token)) = tcp_listener {
+        tracing::info!("listening on {}", listener.local_addr()?);
+        tokio::spawn(run_tcp_server(
+            listener,
+            token,
+            output_tx.clone(),
+            input_tx.clone(),
+            attached_client.clone(),
+            session_ended.clone(),
+        ));
+    }
 
     let shell_name = std::path::Path::new(&command[0])
         .file_name()
         .and_then(|s| s.to_str())
         .unwrap_or(&command[0]);
+    // Only announce the session to a person typing into it
+    let banner = if config.quiet || !std::io::stdin().is_terminal() {
+        None
+    } else {
+        tap_config
+            .banner
+            .render(shell_name, &session_id, &command, detached)
+    };
 
     // If starting detached, fork to background and return
-    if config.detached {
-        println!("\x1b[2m[tap: {shell_name} · {session_id} (detached)]\x1b[0m");
+    if detached {
+        if let Some(banner) = &banner {
+            status::print(banner);
+        }
 
         // Run PTY I/O loop in background
         let master_file =
@@ -574,21 +1998,17 @@ pub async fn run(config: ServerConfig) -> eyre::Result<RunResult> {
         let attached_client_clone = attached_client.clone();
         let session_ended_clone = session_ended.clone();
         let sessions_file_clone = sessions_file.clone();
-        let session_id_clone = session_id.clone();
-        let socket_path_clone = socket_path.clone();
+        let input_tx_clone = input_tx.clone();
 
         tokio::spawn(async move {
             run_pty_loop_detached(
                 master_file,
-                master_raw_fd,
-                input_rx,
+                input_tx_clone,
                 output_tx_clone,
                 attached_client_clone,
                 session_ended_clone,
                 child_pid,
                 sessions_file_clone,
-                session_id_clone,
-                socket_path_clone,
             )
             .await;
         });
@@ -606,8 +2026,19 @@ pub async fn run(config: ServerConfig) -> eyre::Result<RunResult> {
         }
     };
 
+    let capabilities = if orig_termios.is_some() {
+        let (capabilities, early_input) = probe::probe(stdin_fd);
+        // Keystrokes typed while probing
+        if !early_input.is_empty() {
+            let _ = input_tx.send(early_input);
+        }
+        Some(capabilities)
+    } else {
+        None
+    };
+
     // Enable Kitty keyboard protocol for proper Alt-key detection
-    let keyboard_enhanced = if orig_termios.is_some() {
+    let keyboard_enhanced = if capabilities.is_some_and(|c| c.kitty_keyboard) {
         let mut stdout = std::io::stdout();
         match execute!(
             stdout,
@@ -628,7 +2059,14 @@ pub async fn run(config: ServerConfig) -> eyre::Result<RunResult> {
         false
     };
 
-    println!("\x1b[2m[tap: {shell_name} · {session_id}]\x1b[0m");
+    // Keys only arrive as CSI u with kitty flags pushed
+    if !keyboard_enhanced {
+        input_processor.remove(tap_config::InputFilter::CsiU);
+    }
+
+    if let Some(banner) = &banner {
+        status::print(banner);
+    }
 
     // Main I/O loop
     let mut master_file =
@@ -637,83 +2075,133 @@ pub async fn run(config: ServerConfig) -> eyre::Result<RunResult> {
     std::mem::forget(master);
 
     let mut stdin = tokio::io::stdin();
-    let mut stdout = tokio::io::stdout();
+    let mut stdout_forwarder =
+        stdout_forwarder::StdoutForwarder::spawn(tap_config.output.resync_backlog_bytes);
 
     let mut master_buf = vec![0u8; IO_BUFFER_SIZE];
     let mut stdin_buf = vec![0u8; IO_BUFFER_SIZE];
 
     let mut detached = false;
+    let mut awaiting_clipboard = false;
+    // The system clipboard, read off the loop since paste tools can be slow
+    let (clipboard_tx, mut clipboard_rx) = tokio::sync::mpsc::unbounded_channel();
+    // While set, output is kept from the screen, which shows copy mode
+    let mut copy_mode: Option<copy_mode::CopyMode> = None;
+    let mut resized_rx = resize::subscribe();
+    let mut output_since_yield = 0;
+    // Kept across iterations: a sleep made anew each time would never
+    // finish while output keeps another arm ready
+    let mut resync_poll = tokio::time::interval(RESYNC_POLL_INTERVAL);
+    resync_poll.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    LOCAL_TERMINAL.store(true, Ordering::Relaxed);
     let exit_code = loop {
+        // Poll input first so keystrokes (e.g. Ctrl-C) reach the child
+        // promptly even while it floods output.
         tokio::select! {
-            result = master_file.read(&mut master_buf) => {
-                match result {
-                    Ok(0) => break 0,
-                    Ok(n) => {
-                        let data = master_buf[..n].to_vec();
-
-                        // Update scrollback
-                        SCROLLBACK.write().push(&data);
+            biased;
 
-                        // Broadcast to subscribers
-                        let _ = output_tx.send(data.clone());
-
-                        // Write to stdout
-                        if stdout.write_all(&data).await.is_err() {
-                            break 1;
-                        }
-                        let _ = stdout.flush().await;
-                    }
-                    Err(e) => {
-                        tracing::debug!("master read error: {e}");
-                        break 0;
-                    }
-                }
-            }
             result = stdin.read(&mut stdin_buf) => {
                 match result {
                     Ok(0) => break 0,
                     Ok(n) => {
-                        let input_bytes = &stdin_buf[..n];
+                        trace_pipeline!(STDIN, n);
+                        let mut input_bytes = &stdin_buf[..n];
                         tracing::debug!("stdin received {} bytes: {:02x?}", n, input_bytes);
+                        osc::cache_color_replies(input_bytes);
+
+                        // Swallow the terminal's answer to our OSC 52 query
+                        let remaining;
+                        if awaiting_clipboard
+                            && let Some((contents, rest)) = clipboard::take_osc52_reply(input_bytes)
+                        {
+                            awaiting_clipboard = false;
+                            let bracketed = SCROLLBACK.read().bracketed_paste();
+                            if input_tx.send(clipboard::paste_bytes(&contents, bracketed)).is_err() {
+                                break 1;
+                            }
+                            remaining = rest;
+                            if remaining.is_empty() {
+                                continue;
+                            }
+                            input_bytes = &remaining;
+                        }
+
+                        if let Some(mode) = &mut copy_mode {
+                            let screen = match mode.handle(input_bytes) {
+                                copy_mode::Outcome::Stay => mode.render(),
+                                outcome => {
+                                    copy_mode = None;
+                                    let mut screen = match outcome {
+                                        copy_mode::Outcome::Yank(text) => clipboard::osc52_copy(text.as_bytes()),
+                                        _ => Vec::new(),
+                                    };
+                                    screen.extend(SCROLLBACK.read().screen_formatted());
+                                    screen
+                                }
+                            };
+                            if !stdout_forwarder.send(screen) {
+                                break 1;
+                            }
+                            continue;
+                        }
+
                         match input_processor.process(input_bytes) {
                             input::InputResult::Passthrough(bytes) => {
-                                if !bytes.is_empty() {
-                                    // Always translate CSI u sequences to traditional terminal input.
-                                    let translated = kitty::translate_all_csi_u(&bytes);
-                                    if translated != bytes {
-                                        tracing::debug!(
-                                            "translated CSI u: {:02x?} -> {:02x?}",
-                                            bytes,
-                                            translated
-                                        );
-                                    }
-
-                                    let fd = unsafe { BorrowedFd::borrow_raw(master_raw_fd) };
-                                    if nix::unistd::write(fd, &translated).is_err() {
-                                        break 1;
-                                    }
+                                if !bytes.is_empty() && input_tx.send(bytes).is_err() {
+                                    break 1;
                                 }
                             }
+                            input::InputResult::Rejected(reason) => {
+                                tracing::debug!("dropped input: {reason}");
+                            }
                             input::InputResult::Action(input::KeybindAction::OpenEditor) => {
                                 tracing::debug!("OpenEditor action triggered!");
-                                let scrollback = SCROLLBACK.read();
-                                let scrollback_content = scrollback.get_lines(None);
-                                let (cursor_row, cursor_col) = scrollback.cursor_position();
-
-                                let total_lines = scrollback_content.lines().count();
-                                let viewport_height = 24;
-                                let cursor_line =
-                                    total_lines.saturating_sub(viewport_height) + cursor_row + 1;
-
-                                drop(scrollback);
-
-                                if let Err(e) = editor::open_scrollback_in_editor(
-                                    &scrollback_content,
-                                    &editor_cmd,
-                                    orig_termios.as_ref(),
-                                    Some(tap_editor::Position::new(cursor_line, Some(cursor_col + 1))),
-                                ) {
-                                    tracing::error!("failed to open editor: {e}");
+                                open_editor_at_cursor(&editor_cmd, orig_termios.as_ref());
+                            }
+                            input::InputResult::Action(input::KeybindAction::PasteClipboard) => {
+                                tracing::debug!("PasteClipboard action triggered!");
+                                let clipboard_tx = clipboard_tx.clone();
+                                tokio::task::spawn_blocking(move || {
+                                    let _ = clipboard_tx.send(clipboard::read_system_clipboard());
+                                });
+                            }
+                            input::InputResult::Action(input::KeybindAction::CopyMode) => {
+                                tracing::debug!("CopyMode action triggered!");
+                                let mode = copy_mode::CopyMode::enter();
+                                if !stdout_forwarder.send(mode.render()) {
+                                    break 1;
+                                }
+                                copy_mode = Some(mode);
+                            }
+                            input::InputResult::Action(input::KeybindAction::Inject(data)) => {
+                                if input_tx.send(data).is_err() {
+                                    break 1;
+                                }
+                            }
+                            input::InputResult::Action(input::KeybindAction::Run(command)) => {
+                                run_keybind_command(&command);
+                            }
+                            input::InputResult::Action(input::KeybindAction::ScrollbackToFile) => {
+                                let content = SCROLLBACK.read().get_lines(None);
+                                let message = match editor::dump_scrollback_to_file(&content) {
+                                    Ok(path) => format!("[tap: scrollback saved to {}]", path.display()),
+                                    Err(e) => format!("[tap: {e:#}]"),
+                                };
+                                if let Some(line) = status::styled(&message, &std::io::stderr()) {
+                                    eprint!("\r\n{line}\r\n");
+                                }
+                            }
+                            input::InputResult::Action(input::KeybindAction::ClearScrollback) => {
+                                SCROLLBACK.write().clear_history();
+                                // And this terminal's own scrollback
+                                if !stdout_forwarder.send(scrollback::CLEAR_SAVED_LINES.to_vec()) {
+                                    break 1;
+                                }
+                            }
+                            input::InputResult::Action(input::KeybindAction::Kill) => {
+                                tracing::debug!("Kill action triggered!");
+                                if let tap_protocol::Response::Error { message, .. } = kill_child(None) {
+                                    tracing::warn!("failed to kill session: {message}");
                                 }
                             }
                             input::InputResult::Action(input::KeybindAction::Detach) => {
@@ -732,23 +2220,96 @@ pub async fn run(config: ServerConfig) -> eyre::Result<RunResult> {
                     }
                 }
             }
-            Some(data) = input_rx.recv() => {
-                // Input from socket clients
-                let fd = unsafe { BorrowedFd::borrow_raw(master_raw_fd) };
-                let _ = nix::unistd::write(fd, &data);
-            }
             _ = tokio::time::sleep(input_processor.escape_timeout()), if input_processor.has_pending_escape() => {
                 if let input::InputResult::Passthrough(bytes) = input_processor.timeout_escape()
                     && !bytes.is_empty()
                 {
-                    let translated = kitty::translate_all_csi_u(&bytes);
-                    let fd = unsafe { BorrowedFd::borrow_raw(master_raw_fd) };
-                    let _ = nix::unistd::write(fd, &translated);
+                    let _ = input_tx.send(bytes);
+                }
+            }
+            Some(contents) = clipboard_rx.recv() => {
+                match contents {
+                    Some(contents) => {
+                        let bracketed = SCROLLBACK.read().bracketed_paste();
+                        if input_tx.send(clipboard::paste_bytes(&contents, bracketed)).is_err() {
+                            break 1;
+                        }
+                    }
+                    None if probe::cached().is_some_and(|c| !c.osc52) => {
+                        tracing::debug!("no paste tool and the terminal can't answer OSC 52");
+                    }
+                    None => {
+                        // No paste tool; ask the outer terminal instead
+                        awaiting_clipboard = true;
+                        if !stdout_forwarder.send(clipboard::OSC52_QUERY.to_vec()) {
+                            break 1;
+                        }
+                    }
+                }
+            }
+            Ok(size) = resized_rx.recv(), if copy_mode.is_some() => {
+                if let Some(mode) = &mut copy_mode {
+                    mode.resize(size);
+                    if !stdout_forwarder.send(mode.render()) {
+                        break 1;
+                    }
+                }
+            }
+            _ = resync_poll.tick(), if stdout_forwarder.is_resyncing() && copy_mode.is_none() => {
+                if !stdout_forwarder.try_resync(|| SCROLLBACK.read().screen_formatted()) {
+                    break 1;
+                }
+            }
+            () = OPEN_EDITOR.notified() => {
+                tracing::debug!("OpenEditor requested over socket");
+                open_editor_at_cursor(&editor_cmd, orig_termios.as_ref());
+            }
+            result = master_file.read(&mut master_buf) => {
+                match result {
+                    Ok(0) => break 0,
+                    Ok(n) => {
+                        trace_pipeline!(PTY_READ, n);
+                        let data = master_buf[..n].to_vec();
+
+                        // Update scrollback
+                        SCROLLBACK.write().push(&data);
+                        recording::record_output(&data);
+                        session_log::record_output(&data);
+                        watchdog::record_output();
+
+                        // Broadcast to subscribers
+                        sequence::broadcast(&output_tx, filters::broadcast(data.clone()));
+
+                        // Write to stdout
+                        if copy_mode.is_none() && !stdout_forwarder.send(filters::display(data)) {
+                            break 1;
+                        }
+
+                        output_since_yield += n;
+                        if output_since_yield >= OUTPUT_BUDGET_BYTES {
+                            output_since_yield = 0;
+                            tokio::task::yield_now().await;
+                        }
+                    }
+                    Err(e) => {
+                        tracing::debug!("master read error: {e}");
+                        break 0;
+                    }
                 }
             }
         }
     };
 
+    LOCAL_TERMINAL.store(false, Ordering::Relaxed);
+    stdout_forwarder.finish().await;
+
+    // Don't leave the app's cursor shape behind
+    {
+        let mut stdout = std::io::stdout();
+        let _ = std::io::Write::write_all(&mut stdout, cursor_style::RESET);
+        let _ = std::io::Write::flush(&mut stdout);
+    }
+
     // Disable Kitty keyboard protocol
     if keyboard_enhanced {
         let mut stdout = std::io::stdout();
@@ -762,6 +2323,10 @@ pub async fn run(config: ServerConfig) -> eyre::Result<RunResult> {
         restore_terminal(stdin_fd, termios);
     }
 
+    // `tap rename` may have moved the session while it ran
+    let session_id = SESSION_ID.read().clone();
+    let socket_path = tap_protocol::socket_path(&session_id);
+
     if detached {
         // Update session to show detached
         let _ = modify_sessions_file(&sessions_file, |sessions| {
@@ -772,28 +2337,24 @@ pub async fn run(config: ServerConfig) -> eyre::Result<RunResult> {
             }
         });
 
-        println!("\n\x1b[2m[detached from {session_id}]\x1b[0m");
+        status::print(&format!("\n[detached from {session_id}]"));
 
         // Continue PTY server in background
         let output_tx_clone = output_tx.clone();
         let attached_client_clone = attached_client.clone();
         let session_ended_clone = session_ended.clone();
         let sessions_file_clone = sessions_file.clone();
-        let session_id_clone = session_id.clone();
-        let socket_path_clone = socket_path.clone();
+        let input_tx_clone = input_tx.clone();
 
         tokio::spawn(async move {
             run_pty_loop_detached(
                 master_file,
-                master_raw_fd,
-                input_rx,
+                input_tx_clone,
                 output_tx_clone,
                 attached_client_clone,
                 session_ended_clone,
                 child_pid,
                 sessions_file_clone,
-                session_id_clone,
-                socket_path_clone,
             )
             .await;
         });
@@ -801,17 +2362,20 @@ pub async fn run(config: ServerConfig) -> eyre::Result<RunResult> {
         return Ok(RunResult::Detached { session_id });
     }
 
-    // Clean up socket and session entry
+    // Clean up socket
     let _ = std::fs::remove_file(&socket_path);
 
-    // Remove session from sessions.json (with file locking)
+    // Wait for child
+    let final_code = reap_child(child_pid);
+    persist::discard();
+
+    // Leave the exit code in sessions.json for `tap wait`
     let _ = modify_sessions_file(&sessions_file, |sessions| {
-        sessions.retain(|s| s.get("id").and_then(|v| v.as_str()) != Some(&session_id));
+        ended::record(sessions, &session_id, final_code);
     });
 
-    // Wait for child
-    let final_code = wait_for_child(child_pid);
-
+    // Only an adopted child has no exit code, and those are served detached
+    let final_code = final_code.unwrap_or(exit_code);
     if final_code == 0 && exit_code == 0 {
         Ok(RunResult::Exited(0))
     } else {
@@ -822,59 +2386,80 @@ pub async fn run(config: ServerConfig) -> eyre::Result<RunResult> {
 /// Run the PTY I/O loop in detached mode (no local terminal).
 async fn run_pty_loop_detached(
     mut master_file: tokio::fs::File,
-    master_raw_fd: i32,
-    mut input_rx: InputReceiver,
+    input_tx: InputSender,
     output_tx: tokio::sync::broadcast::Sender<Vec<u8>>,
-    attached_client: Arc<Mutex<Option<AttachedClient>>>,
+    attached_client: Arc<Mutex<attached::AttachedClients>>,
     session_ended: Arc<AtomicBool>,
     child_pid: nix::unistd::Pid,
     sessions_file: std::path::PathBuf,
-    session_id: String,
-    socket_path: std::path::PathBuf,
 ) {
     let mut master_buf = vec![0u8; IO_BUFFER_SIZE];
+    let mut output_since_yield = 0;
 
     loop {
-        tokio::select! {
-            result = master_file.read(&mut master_buf) => {
-                match result {
-                    Ok(0) => break,
-                    Ok(n) => {
-                        let data = master_buf[..n].to_vec();
-
-                        // Update scrollback
-                        SCROLLBACK.write().push(&data);
-
-                        // Broadcast to subscribers
-                        let _ = output_tx.send(data.clone());
-
-                        // Send to attached client if any
-                        if let Some(client) = attached_client.lock().await.as_ref() {
-                            let _ = client.output_tx.send(data);
-                        }
-                    }
-                    Err(e) => {
-                        tracing::debug!("master read error: {e}");
-                        break;
+        let result = tokio::select! {
+            result = master_file.read(&mut master_buf) => result,
+            () = migrate::HANDED_OFF.notified() => {
+                // The child and socket now belong to the receiving process.
+                // The child is still running, so there's no exit to record
+                // and clients still connected here just lose the connection
+                tracing::info!("session handed off");
+                std::process::exit(0);
+            }
+        };
+        match result {
+            Ok(0) => break,
+            Ok(n) => {
+                trace_pipeline!(PTY_READ, n, detached = true);
+                let data = master_buf[..n].to_vec();
+
+                // Update scrollback, holding the attached clients so a redraw
+                // for one can't fall between this and sending it the output
+                let attached = attached_client.lock().await;
+                SCROLLBACK.write().push(&data);
+                recording::record_output(&data);
+                session_log::record_output(&data);
+                watchdog::record_output();
+
+                // Broadcast to subscribers
+                sequence::broadcast(&output_tx, filters::broadcast(data.clone()));
+
+                // Send to attached clients if any; otherwise nothing will
+                // answer color queries, so reply from the cache
+                if !attached.is_empty() {
+                    attached.broadcast(&filters::display(data.clone()));
+                } else {
+                    let replies = osc::answer_color_queries(&data);
+                    if !replies.is_empty() {
+                        let _ = input_tx.send(replies);
                     }
                 }
+                drop(attached);
+
+                output_since_yield += n;
+                if output_since_yield >= OUTPUT_BUDGET_BYTES {
+                    output_since_yield = 0;
+                    tokio::task::yield_now().await;
+                }
             }
-            Some(data) = input_rx.recv() => {
-                let fd = unsafe { BorrowedFd::borrow_raw(master_raw_fd) };
-                let _ = nix::unistd::write(fd, &data);
+            Err(e) => {
+                tracing::debug!("master read error: {e}");
+                break;
             }
         }
     }
 
+    // Reap the child first so waiting clients get its exit code
+    let exit_code = reap_child(child_pid);
+    persist::discard();
+
     // Mark session as ended
     session_ended.store(true, Ordering::Relaxed);
 
-    // Clean up socket and session entry
-    let _ = std::fs::remove_file(&socket_path);
+    // Clean up socket, leaving the exit code in sessions.json for `tap wait`
+    let session_id = SESSION_ID.read().clone();
+    let _ = std::fs::remove_file(tap_protocol::socket_path(&session_id));
     let _ = modify_sessions_file(&sessions_file, |sessions| {
-        sessions.retain(|s| s.get("id").and_then(|v| v.as_str()) != Some(&session_id));
+        ended::record(sessions, &session_id, exit_code);
     });
-
-    // Wait for child
-    let _ = wait_for_child(child_pid);
 }
@@ -0,0 +1,353 @@
+//! Handing a detached session over to another tap process.
+//!
+//! The sending server passes its PTY master over a Unix socket with
+//! `SCM_RIGHTS`, followed by the session's entry from sessions.json and its
+//! scrollback, then exits without touching the child. The receiving process
+//! serves the session from then on, so sessions survive upgrading tap.
+//!
+//! A server can also upgrade in place by exec()ing a new tap binary that
+//! inherits the PTY master, the socket listener and the session state. The
+//! process (and so the child's parent) stays the same.
+
+use std::io::{Read as _, Seek as _, Write as _};
+use std::os::fd::{AsRawFd as _, FromRawFd as _};
+
+/// Acknowledgement sent by the receiver once it has taken over.
+const ACK: &[u8] = b"ok";
+/// How long either side waits on the other once they're connected.
+const TRANSFER_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// Everything but the PTY master needed to keep serving a session.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub(crate) struct SessionState {
+    /// The session's sessions.json entry.
+    pub entry: serde_json::Value,
+    pub child_pid: i32,
+    pub scrollback: String,
+    /// Address the session is also served on over TCP. Its token isn't
+    /// handed over; the receiver takes it from its own environment.
+    #[serde(default)]
+    pub listen: Option<std::net::SocketAddr>,
+    /// Whether the sending server had itself received the session, so
+    /// isn't the child's parent either.
+    #[serde(default)]
+    pub adopted: bool,
+}
+
+/// A session received from another tap process.
+#[derive(Debug, Clone)]
+pub struct Handoff {
+    state: SessionState,
+    master: std::sync::Arc<std::os::fd::OwnedFd>,
+    /// Socket listener inherited across an in-place upgrade.
+    listener: Option<std::sync::Arc<std::os::fd::OwnedFd>>,
+}
+
+impl Handoff {
+    /// ID of the received session.
+    #[must_use]
+    pub fn session_id(&self) -> &str {
+        self.state.entry["id"].as_str().unwrap_or_default()
+    }
+
+    pub(crate) fn child_pid(&self) -> nix::unistd::Pid {
+        nix::unistd::Pid::from_raw(self.state.child_pid)
+    }
+
+    pub(crate) fn command(&self) -> Vec<String> {
+        serde_json::from_value(self.state.entry["command"].clone()).unwrap_or_default()
+    }
+
+    /// The sessions.json entry, pointing at this process.
+    pub(crate) fn entry(&self) -> serde_json::Value {
+        let mut entry = self.state.entry.clone();
+        entry["pid"] = std::process::id().into();
+        entry
+    }
+
+    /// Whether the child was started with job control.
+    pub(crate) fn job_control(&self) -> bool {
+        self.state.entry["job_control"].as_bool().unwrap_or(true)
+    }
+
+    /// The config profile the session was started with.
+    pub(crate) fn profile(&self) -> Option<String> {
+        self.state.entry["profile"].as_str().map(str::to_string)
+    }
+
+    /// Whether this process isn't the child's parent, so can't reap it:
+    /// always after a migration, and after an in-place upgrade of a server
+    /// that had received the session.
+    pub(crate) const fn adopted(&self) -> bool {
+        self.listener.is_none() || self.state.adopted
+    }
+
+    /// Address the session was served on over TCP.
+    pub(crate) const fn listen(&self) -> Option<std::net::SocketAddr> {
+        self.state.listen
+    }
+
+    pub(crate) fn scrollback(&self) -> &str {
+        &self.state.scrollback
+    }
+
+    /// Duplicate the PTY master for this process to own.
+    pub(crate) fn master(&self) -> std::io::Result<std::os::fd::OwnedFd> {
+        self.master.try_clone()
+    }
+
+    /// Duplicate the inherited socket listener, if there is one.
+    pub(crate) fn listener(&self) -> Option<std::io::Result<std::os::fd::OwnedFd>> {
+        self.listener.as_ref().map(|listener| listener.try_clone())
+    }
+}
+
+/// Send the session to the tap process listening on `to` and wait until it
+/// has taken over.
+pub(crate) fn send(to: &std::path::Path, state: &SessionState, master_fd: i32) -> eyre::Result<()> {
+    let mut stream = std::os::unix::net::UnixStream::connect(to)
+        .map_err(|e| eyre::eyre!("failed to connect to {}: {e}", to.display()))?;
+    stream.set_read_timeout(Some(TRANSFER_TIMEOUT))?;
+    stream.set_write_timeout(Some(TRANSFER_TIMEOUT))?;
+
+    nix::sys::socket::sendmsg::<()>(
+        stream.as_raw_fd(),
+        &[std::io::IoSlice::new(b"m")],
+        &[nix::sys::socket::ControlMessage::ScmRights(&[master_fd])],
+        nix::sys::socket::MsgFlags::empty(),
+        None,
+    )
+    .map_err(|e| eyre::eyre!("failed to send PTY: {e}"))?;
+
+    serde_json::to_writer(&mut stream, state)?;
+    stream.shutdown(std::net::Shutdown::Write)?;
+
+    let mut ack = Vec::new();
+    stream.read_to_end(&mut ack)?;
+    if ack != ACK {
+        eyre::bail!("receiver did not take over the session");
+    }
+    Ok(())
+}
+
+/// Listen on `path` for one session sent with [`send`], giving up if none
+/// arrives within `timeout`.
+pub fn receive(
+    path: &std::path::Path,
+    timeout: Option<std::time::Duration>,
+) -> eyre::Result<Handoff> {
+    let _ = std::fs::remove_file(path);
+    let listener = std::os::unix::net::UnixListener::bind(path)
+        .map_err(|e| eyre::eyre!("failed to listen on {}: {e}", path.display()))?;
+    let accepted = match wait_readable(listener.as_raw_fd(), timeout) {
+        Ok(true) => listener.accept().map_err(eyre::Report::from),
+        Ok(false) => Err(eyre::eyre!("no session arrived on {}", path.display())),
+        Err(e) => Err(e.into()),
+    };
+    let _ = std::fs::remove_file(path);
+    let (mut stream, _) = accepted?;
+    stream.set_read_timeout(Some(TRANSFER_TIMEOUT))?;
+    stream.set_write_timeout(Some(TRANSFER_TIMEOUT))?;
+
+    let mut byte = [0u8; 1];
+    let mut iov = [std::io::IoSliceMut::new(&mut byte)];
+    let mut cmsg = nix::cmsg_space!([std::os::fd::RawFd; 1]);
+    let msg = nix::sys::socket::recvmsg::<()>(
+        stream.as_raw_fd(),
+        &mut iov,
+        Some(&mut cmsg),
+        nix::sys::socket::MsgFlags::empty(),
+    )
+    .map_err(|e| eyre::eyre!("failed to receive PTY: {e}"))?;
+    let master = msg
+        .cmsgs()
+        .map_err(|e| eyre::eyre!("failed to receive PTY: {e}"))?
+        .find_map(|cmsg| match cmsg {
+            nix::sys::socket::ControlMessageOwned::ScmRights(fds) => fds.first().copied(),
+            _ => None,
+        })
+        .ok_or_else(|| eyre::eyre!("no PTY in handoff"))?;
+    // SAFETY: the descriptor was just received and nothing else owns it
+    let master = unsafe { std::os::fd::OwnedFd::from_raw_fd(master) };
+
+    let mut payload = Vec::new();
+    stream.read_to_end(&mut payload)?;
+    let state: SessionState = serde_json::from_slice(&payload)?;
+    stream.write_all(ACK)?;
+
+    Ok(Handoff {
+        state,
+        master: std::sync::Arc::new(master),
+        listener: None,
+    })
+}
+
+/// Wait until `fd` is readable or `timeout` passes, returning whether it
+/// became readable.
+fn wait_readable(
+    fd: std::os::fd::RawFd,
+    timeout: Option<std::time::Duration>,
+) -> std::io::Result<bool> {
+    let timeout_ms = timeout.map_or(-1, |timeout| {
+        i32::try_from(timeout.as_millis()).unwrap_or(i32::MAX)
+    });
+    let mut pollfd = nix::libc::pollfd {
+        fd,
+        events: nix::libc::POLLIN,
+        revents: 0,
+    };
+    loop {
+        match unsafe { nix::libc::poll(&mut pollfd, 1, timeout_ms) } {
+            -1 => {
+                let e = std::io::Error::last_os_error();
+                if e.kind() != std::io::ErrorKind::Interrupted {
+                    return Err(e);
+                }
+            }
+            ready => return Ok(ready > 0),
+        }
+    }
+}
+
+/// Replace this process with `exe`, which picks the session up with
+/// [`from_fds`]. Only returns on failure.
+pub(crate) fn exec(
+    exe: &std::path::Path,
+    state: &SessionState,
+    master_fd: i32,
+    listener_fd: i32,
+) -> eyre::Report {
+    let result = (|| -> eyre::Result<std::convert::Infallible> {
+        let mut state_file = tempfile::tempfile()?;
+        serde_json::to_writer(&mut state_file, state)?;
+        state_file.rewind()?;
+        let state_fd = state_file.as_raw_fd();
+
+        for fd in [master_fd, listener_fd, state_fd] {
+            if unsafe { nix::libc::fcntl(fd, nix::libc::F_SETFD, 0) } == -1 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+        }
+
+        let args = [
+            exe.as_os_str().to_string_lossy().into_owned(),
+            "resume-handoff".to_string(),
+            format!("--master-fd={master_fd}"),
+            format!("--listener-fd={listener_fd}"),
+            format!("--state-fd={state_fd}"),
+        ]
+        .map(|arg| std::ffi::CString::new(arg).expect("no NUL in exec arguments"));
+        // The TCP token isn't in the state file, so it goes in the environment
+        let env = std::env::vars_os()
+            .filter(|(key, _)| key != tap_protocol::AUTH_TOKEN_ENV)
+            .map(|(mut var, value)| {
+                var.push("=");
+                var.push(value);
+                var
+            })
+            .chain(
+                crate::remote::token()
+                    .map(|token| format!("{}={token}", tap_protocol::AUTH_TOKEN_ENV).into()),
+            )
+            .map(|var| std::ffi::CString::new(var.into_encoded_bytes()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let exe = std::ffi::CString::new(exe.as_os_str().as_encoded_bytes())?;
+        Ok(nix::unistd::execve(&exe, &args, &env)?)
+    })();
+    let Err(e) = result;
+    e.wrap_err(format!("failed to exec {}", exe.display()))
+}
+
+/// Pick up a session from descriptors inherited across [`exec`].
+pub fn from_fds(master_fd: i32, listener_fd: i32, state_fd: i32) -> eyre::Result<Handoff> {
+    for fd in [master_fd, listener_fd, state_fd] {
+        if unsafe { nix::libc::fcntl(fd, nix::libc::F_GETFD) } == -1 {
+            eyre::bail!("descriptor {fd} isn't open");
+        }
+    }
+    // Don't leak the session into processes spawned from here on
+    for fd in [master_fd, listener_fd] {
+        unsafe {
+            nix::libc::fcntl(fd, nix::libc::F_SETFD, nix::libc::FD_CLOEXEC);
+        }
+    }
+
+    // SAFETY: the descriptors were inherited from the previous server and
+    // nothing else in this process owns them
+    let (master, listener, state_file) = unsafe {
+        (
+            std::os::fd::OwnedFd::from_raw_fd(master_fd),
+            std::os::fd::OwnedFd::from_raw_fd(listener_fd),
+            std::fs::File::from_raw_fd(state_fd),
+        )
+    };
+    let state: SessionState = serde_json::from_reader(std::io::BufReader::new(state_file))
+        .map_err(|e| eyre::eyre!("invalid session state: {e}"))?;
+
+    Ok(Handoff {
+        state,
+        master: std::sync::Arc::new(master),
+        listener: Some(std::sync::Arc::new(listener)),
+    })
+}
+
+/// Signals the detached I/O loop that the session now lives elsewhere.
+pub(crate) static HANDED_OFF: tokio::sync::Notify = tokio::sync::Notify::const_new();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fd_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("migrate.sock");
+
+        let receiver = {
+            let path = path.clone();
+            std::thread::spawn(move || receive(&path, None).unwrap())
+        };
+        while !path.exists() {
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+
+        let (read_end, write_end) = nix::unistd::pipe().unwrap();
+        let state = SessionState {
+            entry: serde_json::json!({ "id": "s", "pid": 1, "command": ["sh"] }),
+            child_pid: 42,
+            scrollback: "hello".to_string(),
+            listen: Some(([127, 0, 0, 1], 4000).into()),
+            adopted: false,
+        };
+        send(&path, &state, write_end.as_raw_fd()).unwrap();
+        drop(write_end);
+
+        let handoff = receiver.join().unwrap();
+        assert_eq!(handoff.session_id(), "s");
+        assert_eq!(handoff.child_pid().as_raw(), 42);
+        assert_eq!(handoff.command(), vec!["sh".to_string()]);
+        assert_eq!(handoff.entry()["pid"], std::process::id());
+        assert_eq!(handoff.scrollback(), "hello");
+        assert_eq!(handoff.listen(), Some(([127, 0, 0, 1], 4000).into()));
+        assert!(handoff.adopted());
+
+        // The received descriptor is the same pipe
+        let mut master = std::fs::File::from(handoff.master().unwrap());
+        drop(handoff);
+        master.write_all(b"x").unwrap();
+        drop(master);
+        let mut read_end = std::fs::File::from(read_end);
+        let mut out = String::new();
+        read_end.read_to_string(&mut out).unwrap();
+        assert_eq!(out, "x");
+    }
+
+    #[test]
+    fn test_receive_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("migrate.sock");
+        let timeout = std::time::Duration::from_millis(50);
+        assert!(receive(&path, Some(timeout)).is_err());
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,198 @@
+//! Desktop notifications for things worth looking up from another window
+//! for: the bell, the child exiting, and output matching configured patterns.
+//!
+//! Notifications go through `osascript` on macOS and `notify-send` elsewhere.
+
+/// Bytes of earlier output kept so patterns split across chunks still match.
+const PATTERN_TAIL_BYTES: usize = 256;
+/// Minimum time between notifications for the same reason.
+const THROTTLE: std::time::Duration = std::time::Duration::from_secs(10);
+
+struct Notifier {
+    exit: bool,
+}
+
+static NOTIFIER: std::sync::OnceLock<Notifier> = std::sync::OnceLock::new();
+
+/// Start notifying as configured. Does nothing unless `notify.enabled` is set.
+pub fn init(
+    config: &tap_config::NotifyConfig,
+    output_rx: tokio::sync::broadcast::Receiver<Vec<u8>>,
+) -> eyre::Result<()> {
+    if !config.enabled {
+        return Ok(());
+    }
+    let patterns = config
+        .patterns
+        .iter()
+        .map(|pattern| {
+            regex::Regex::new(pattern)
+                .map_err(|e| eyre::eyre!("invalid notify pattern '{pattern}': {e}"))
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+    let _ = NOTIFIER.set(Notifier { exit: config.exit });
+
+    let kinds = if config.bell {
+        vec![tap_protocol::EventKind::Bell]
+    } else {
+        Vec::new()
+    };
+    tokio::spawn(watch(
+        crate::events::Subscription::new(kinds.clone()),
+        !kinds.is_empty(),
+        PatternMatcher::new(patterns),
+        output_rx,
+    ));
+    Ok(())
+}
+
+/// Notify that the child exited, if configured. Called directly rather than
+/// from the event stream since the process exits right after.
+pub fn exited(exit_code: Option<i32>) {
+    if let Some(notifier) = NOTIFIER.get()
+        && notifier.exit
+    {
+        match exit_code {
+            Some(code) => send(&format!("exited with status {code}")),
+            None => send("exited"),
+        }
+    }
+}
+
+async fn watch(
+    mut events: crate::events::Subscription,
+    bell: bool,
+    mut patterns: PatternMatcher,
+    mut output_rx: tokio::sync::broadcast::Receiver<Vec<u8>>,
+) {
+    let mut last_bell = None;
+    loop {
+        tokio::select! {
+            _ = crate::events::next(Some(&mut events)), if bell => {
+                if throttled(&mut last_bell) {
+                    continue;
+                }
+                send("bell");
+            }
+            result = output_rx.recv() => match result {
+                Ok(data) => {
+                    if let Some(matched) = patterns.push(&data) {
+                        send(&matched);
+                    }
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {}
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            },
+        }
+    }
+}
+
+/// Whether a notification sent at `last` is too recent for another;
+/// otherwise records now as the last one.
+fn throttled(last: &mut Option<std::time::Instant>) -> bool {
+    let now = std::time::Instant::now();
+    if last.is_some_and(|last| now.duration_since(last) < THROTTLE) {
+        return true;
+    }
+    *last = Some(now);
+    false
+}
+
+/// Finds configured patterns in output, each at most once per `THROTTLE`.
+struct PatternMatcher {
+    patterns: Vec<(regex::Regex, Option<std::time::Instant>)>,
+    tail: Vec<u8>,
+}
+
+impl PatternMatcher {
+    fn new(patterns: Vec<regex::Regex>) -> Self {
+        Self {
+            patterns: patterns
+                .into_iter()
+                .map(|pattern| (pattern, None))
+                .collect(),
+            tail: Vec::new(),
+        }
+    }
+
+    /// Handle a chunk of output, returning the text of a new match.
+    fn push(&mut self, data: &[u8]) -> Option<String> {
+        if self.patterns.is_empty() {
+            return None;
+        }
+        self.tail.extend_from_slice(data);
+        let text = String::from_utf8_lossy(&self.tail).into_owned();
+        let excess = self.tail.len().saturating_sub(PATTERN_TAIL_BYTES);
+        self.tail.drain(..excess);
+
+        for (pattern, last) in &mut self.patterns {
+            if let Some(found) = pattern.find(&text) {
+                // Don't match the same output again on the next chunk
+                self.tail.clear();
+                if !throttled(last) {
+                    return Some(found.as_str().to_string());
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Show a desktop notification titled with the session, in the background.
+/// The session's name is read now, as `tap rename` may have changed it.
+fn send(message: &str) {
+    let title = format!("tap: {}", crate::SESSION_ID.read());
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = std::process::Command::new("osascript");
+        command.arg("-e").arg(format!(
+            "display notification {} with title {}",
+            applescript_string(message),
+            applescript_string(&title)
+        ));
+        command
+    } else {
+        let mut command = std::process::Command::new("notify-send");
+        command.arg(&title).arg(message);
+        command
+    };
+    command
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null());
+    match command.spawn() {
+        Ok(mut child) => {
+            std::thread::spawn(move || child.wait());
+        }
+        Err(e) => tracing::warn!("failed to send notification: {e}"),
+    }
+}
+
+/// Quote `s` as an AppleScript string literal.
+fn applescript_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern_split_across_chunks() {
+        let mut matcher = PatternMatcher::new(vec![regex::Regex::new(r"error\[E\d+\]").unwrap()]);
+        assert_eq!(matcher.push(b"   Compiling foo\r\nerror[E0"), None);
+        assert_eq!(
+            matcher.push(b"308]: mismatched types"),
+            Some("error[E0308]".to_string())
+        );
+        // Throttled until a while has passed
+        assert_eq!(matcher.push(b"error[E0599]: no method"), None);
+    }
+
+    #[test]
+    fn test_applescript_string() {
+        assert_eq!(
+            applescript_string(r#"say "hi" \o/"#),
+            r#""say \"hi\" \\o/""#
+        );
+    }
+}
@@ -0,0 +1,117 @@
+//! OSC 10/11 foreground/background color query handling.
+//!
+//! While a terminal is attached, queries from the inner app reach it through
+//! the normal output path and its replies come back as input. We remember
+//! those replies so that while detached, when nothing would answer, queries
+//! can be answered from the cache instead of leaving theme-aware TUIs (e.g.
+//! neovim detecting dark/light background) waiting.
+
+const OSC_START: &[u8] = b"\x1b]";
+const BEL: u8 = 0x07;
+const ST: &[u8] = b"\x1b\\";
+
+/// Dynamic colors we cache: OSC 10 (foreground) and OSC 11 (background).
+const COLOR_CODES: [u8; 2] = [10, 11];
+
+/// Last color values reported by an outer terminal.
+static COLOR_CACHE: parking_lot::Mutex<[Option<String>; 2]> = parking_lot::Mutex::new([None, None]);
+
+/// Iterate over the OSC sequences in `data`, yielding (code, payload).
+/// Sequences split across reads are not recognized.
+fn osc_sequences(data: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    let mut rest = data;
+    std::iter::from_fn(move || {
+        loop {
+            let start = rest.windows(OSC_START.len()).position(|w| w == OSC_START)?;
+            let body = &rest[start + OSC_START.len()..];
+            let (end, terminator_len) = match (
+                body.iter().position(|&b| b == BEL),
+                body.windows(ST.len()).position(|w| w == ST),
+            ) {
+                (Some(bel), Some(st)) if st < bel => (st, ST.len()),
+                (Some(bel), _) => (bel, 1),
+                (None, Some(st)) => (st, ST.len()),
+                (None, None) => return None,
+            };
+            let sequence = &body[..end];
+            rest = &body[end + terminator_len..];
+
+            let Some(semicolon) = sequence.iter().position(|&b| b == b';') else {
+                continue;
+            };
+            let Some(code) = std::str::from_utf8(&sequence[..semicolon])
+                .ok()
+                .and_then(|code| code.parse().ok())
+            else {
+                continue;
+            };
+            return Some((code, &sequence[semicolon + 1..]));
+        }
+    })
+}
+
+/// Remember OSC 10/11 replies (e.g. `ESC ] 11 ; rgb:0000/0000/0000 ST`)
+/// found in input from an attached terminal.
+pub fn cache_color_replies(input: &[u8]) {
+    for (code, payload) in osc_sequences(input) {
+        let Some(index) = COLOR_CODES.iter().position(|&c| c == code) else {
+            continue;
+        };
+        if payload == b"?" {
+            continue;
+        }
+        if let Ok(value) = std::str::from_utf8(payload) {
+            tracing::debug!("caching OSC {code} color: {value}");
+            COLOR_CACHE.lock()[index] = Some(value.to_string());
+        }
+    }
+}
+
+/// Build replies for OSC 10/11 queries in child output that can be answered
+/// from the cache.
+pub fn answer_color_queries(output: &[u8]) -> Vec<u8> {
+    let cache = COLOR_CACHE.lock();
+    let mut replies = Vec::new();
+    for (code, payload) in osc_sequences(output) {
+        if payload != b"?" {
+            continue;
+        }
+        let Some(index) = COLOR_CODES.iter().position(|&c| c == code) else {
+            continue;
+        };
+        if let Some(value) = &cache[index] {
+            replies.extend_from_slice(format!("\x1b]{code};{value}\x1b\\").as_bytes());
+        }
+    }
+    replies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_osc_sequences_terminators() {
+        let data = b"a\x1b]11;?\x07b\x1b]10;rgb:ffff/ffff/ffff\x1b\\c";
+        let found: Vec<_> = osc_sequences(data).collect();
+        assert_eq!(
+            found,
+            vec![(11, &b"?"[..]), (10, &b"rgb:ffff/ffff/ffff"[..])]
+        );
+    }
+
+    #[test]
+    fn test_osc_sequences_ignores_unterminated() {
+        assert_eq!(osc_sequences(b"\x1b]11;?").count(), 0);
+    }
+
+    #[test]
+    fn test_cache_and_answer() {
+        cache_color_replies(b"\x1b]11;rgb:1111/2222/3333\x1b\\");
+        let replies = answer_color_queries(b"\x1b]11;?\x07");
+        assert_eq!(replies, b"\x1b]11;rgb:1111/2222/3333\x1b\\");
+
+        // Title sequences are left alone
+        assert!(answer_color_queries(b"\x1b]0;title\x07").is_empty());
+    }
+}
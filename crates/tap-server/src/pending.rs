@@ -0,0 +1,100 @@
+//! Holding risky injected input so watchers can cancel it.
+//!
+//! Injected text matching a configured pattern is announced to subscribed
+//! and attached clients and only forwarded after a delay, unless someone
+//! cancels it first. A softer check than refusing the input outright, for
+//! supervised agent sessions.
+
+/// Injected input waiting to be forwarded.
+#[derive(Debug, Clone)]
+pub struct PendingInput {
+    pub id: u64,
+    pub data: String,
+    pub delay_ms: u64,
+}
+
+struct Guard {
+    patterns: Vec<regex::Regex>,
+    delay: std::time::Duration,
+}
+
+static GUARD: std::sync::OnceLock<Guard> = std::sync::OnceLock::new();
+static NEXT_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1);
+/// Announcements of held input.
+static ANNOUNCE: std::sync::LazyLock<tokio::sync::broadcast::Sender<PendingInput>> =
+    std::sync::LazyLock::new(|| tokio::sync::broadcast::channel(16).0);
+/// Cancel handles for held input, by ID.
+static CANCELS: std::sync::LazyLock<
+    parking_lot::Mutex<std::collections::HashMap<u64, tokio::sync::oneshot::Sender<()>>>,
+> = std::sync::LazyLock::new(Default::default);
+
+/// Configure which injected input is held. Does nothing without patterns.
+pub fn init(config: &tap_config::InjectConfig) -> eyre::Result<()> {
+    if config.risky_patterns.is_empty() {
+        return Ok(());
+    }
+    let patterns = config
+        .risky_patterns
+        .iter()
+        .map(|pattern| {
+            regex::Regex::new(pattern)
+                .map_err(|e| eyre::eyre!("invalid risky input pattern '{pattern}': {e}"))
+        })
+        .collect::<eyre::Result<_>>()?;
+    let _ = GUARD.set(Guard {
+        patterns,
+        delay: std::time::Duration::from_secs(config.confirm_delay_secs),
+    });
+    Ok(())
+}
+
+/// Receive announcements of held input.
+pub fn subscribe() -> tokio::sync::broadcast::Receiver<PendingInput> {
+    ANNOUNCE.subscribe()
+}
+
+/// Hold `data` if it looks risky. Returns whether it may be forwarded:
+/// immediately for ordinary input, after the delay for risky input, or
+/// `false` if it was cancelled.
+pub async fn hold(data: &str) -> bool {
+    let Some(guard) = GUARD.get() else {
+        return true;
+    };
+    if !guard.patterns.iter().any(|pattern| pattern.is_match(data)) {
+        return true;
+    }
+
+    let id = NEXT_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let (cancel_tx, cancel_rx) = tokio::sync::oneshot::channel();
+    CANCELS.lock().insert(id, cancel_tx);
+    tracing::info!("holding risky input #{id} for {:?}", guard.delay);
+    let _ = ANNOUNCE.send(PendingInput {
+        id,
+        data: data.to_string(),
+        delay_ms: guard.delay.as_millis() as u64,
+    });
+
+    let cancelled = tokio::select! {
+        _ = tokio::time::sleep(guard.delay) => false,
+        result = cancel_rx => result.is_ok(),
+    };
+    CANCELS.lock().remove(&id);
+    !cancelled
+}
+
+/// Cancel held input by ID, or all of it. Returns how many were cancelled.
+pub fn cancel(id: Option<u64>) -> usize {
+    let mut cancels = CANCELS.lock();
+    let ids: Vec<u64> = match id {
+        Some(id) => cancels
+            .contains_key(&id)
+            .then_some(id)
+            .into_iter()
+            .collect(),
+        None => cancels.keys().copied().collect(),
+    };
+    ids.iter()
+        .filter_map(|id| cancels.remove(id))
+        .filter(|cancel| cancel.send(()).is_ok())
+        .count()
+}
@@ -0,0 +1,239 @@
+//! Saving what's needed to bring a session back after a reboot: `tap
+//! restore` relaunches the same command in the same directory and
+//! environment, with the old screen shown above its new output.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Variables tied to the login, display or terminal the session was started
+/// from rather than to the session itself.
+const VOLATILE_ENV: &[&str] = &[
+    "COLORTERM",
+    "DBUS_SESSION_BUS_ADDRESS",
+    "DISPLAY",
+    "ITERM_SESSION_ID",
+    "KITTY_WINDOW_ID",
+    "OLDPWD",
+    "PWD",
+    "SHLVL",
+    "SSH_AGENT_PID",
+    "SSH_AUTH_SOCK",
+    "SSH_CLIENT",
+    "SSH_CONNECTION",
+    "SSH_TTY",
+    "TAP_SESSION",
+    "TAP_SOCKET",
+    "TERM",
+    "TERM_SESSION_ID",
+    "TMUX",
+    "TMUX_PANE",
+    "WAYLAND_DISPLAY",
+    "WEZTERM_PANE",
+    "WINDOWID",
+    "XDG_SESSION_ID",
+    "_",
+];
+
+/// Directory this session saves to, once [`init`] has run with persistence
+/// enabled.
+static DIR: std::sync::OnceLock<PathBuf> = std::sync::OnceLock::new();
+
+/// Set when the server is being shut down from outside (e.g. at reboot), so
+/// the saved state outlives the child.
+static KEEP: AtomicBool = AtomicBool::new(false);
+
+/// A session as saved to disk.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct SavedSession {
+    pub id: String,
+    pub command: Vec<String>,
+    /// The child's working directory, where it could be read.
+    #[serde(default)]
+    pub cwd: Option<PathBuf>,
+    /// The child's environment, minus [`VOLATILE_ENV`].
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// The config profile the session was started with.
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Address the session was also served on over TCP. Its token comes
+    /// from the environment of whoever restores it.
+    #[serde(default)]
+    pub listen: Option<std::net::SocketAddr>,
+    /// Text of the screen when it was saved.
+    pub screen: String,
+    pub rows: u16,
+    pub cols: u16,
+    /// When it was saved (RFC 3339).
+    pub saved: String,
+}
+
+/// Start saving this session every `[persist] interval_secs`, if enabled.
+pub fn init(config: &tap_config::Config, child_pid: i32) {
+    if !config.persist.enabled {
+        return;
+    }
+    let dir = tap_config::saved_sessions_dir(config);
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        tracing::warn!("failed to create {}: {e}", dir.display());
+        return;
+    }
+    let _ = DIR.set(dir.clone());
+    let interval = std::time::Duration::from_secs(config.persist.interval_secs.max(1));
+    tokio::spawn(save_periodically(dir, interval, child_pid));
+}
+
+async fn save_periodically(dir: PathBuf, interval: std::time::Duration, child_pid: i32) {
+    let mut ticker = tokio::time::interval(interval);
+    let mut saved_as: Option<String> = None;
+    loop {
+        ticker.tick().await;
+        if crate::EXIT_CODE.get().is_some() {
+            return;
+        }
+        let saved = snapshot(child_pid);
+        // A renamed session drops the file saved under its old name
+        if let Some(old) = saved_as.as_deref()
+            && old != saved.id
+        {
+            let _ = std::fs::remove_file(saved_path(&dir, old));
+        }
+        if let Err(e) = save(&dir, &saved) {
+            tracing::warn!("failed to save session: {e}");
+        }
+        saved_as = Some(saved.id);
+    }
+}
+
+/// The session's current state.
+fn snapshot(child_pid: i32) -> SavedSession {
+    let id = crate::SESSION_ID.read().clone();
+    let entry = std::fs::read(tap_protocol::sessions_file())
+        .ok()
+        .and_then(|data| serde_json::from_slice::<Vec<tap_protocol::Session>>(&data).ok())
+        .and_then(|sessions| sessions.into_iter().find(|session| session.id == id));
+    let env = crate::procenv::read_process_env(child_pid)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(key, _)| !VOLATILE_ENV.contains(&key.as_str()) && !key.starts_with("TAP_"))
+        .collect();
+    let (screen, (rows, cols)) = {
+        let scrollback = crate::SCROLLBACK.read();
+        (scrollback.get_lines(None), scrollback.size())
+    };
+    SavedSession {
+        command: entry
+            .as_ref()
+            .map(|session| session.command.clone())
+            .unwrap_or_default(),
+        profile: entry.as_ref().and_then(|session| session.profile.clone()),
+        tags: entry.map(|session| session.tags).unwrap_or_default(),
+        id,
+        cwd: crate::procenv::read_process_cwd(child_pid).ok(),
+        env,
+        listen: crate::remote::addr(),
+        screen,
+        rows,
+        cols,
+        saved: chrono::Utc::now().to_rfc3339(),
+    }
+}
+
+fn saved_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join(format!("{id}.json"))
+}
+
+/// Write atomically, so a reboot mid-save leaves the previous copy.
+fn save(dir: &Path, saved: &SavedSession) -> std::io::Result<()> {
+    let path = saved_path(dir, &saved.id);
+    let tmp = path.with_extension("json.tmp");
+    std::fs::write(&tmp, serde_json::to_vec(saved)?)?;
+    std::fs::rename(tmp, path)
+}
+
+/// Keep the saved state when the child exits, as it's being killed by a
+/// shutdown rather than ending on its own.
+pub fn keep() {
+    KEEP.store(true, Ordering::Relaxed);
+}
+
+/// Forget this session's saved state now that it has ended.
+pub fn discard() {
+    if KEEP.load(Ordering::Relaxed) {
+        return;
+    }
+    if let Some(dir) = DIR.get() {
+        let _ = std::fs::remove_file(saved_path(dir, &crate::SESSION_ID.read()));
+    }
+}
+
+/// Read a saved session.
+pub fn load(path: &Path) -> eyre::Result<SavedSession> {
+    let data = std::fs::read(path)?;
+    serde_json::from_slice(&data).map_err(|e| eyre::eyre!("invalid saved session: {e}"))
+}
+
+/// Saved sessions in `dir` and the files they're in, oldest first.
+pub fn list(dir: &Path) -> Vec<(PathBuf, SavedSession)> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut saved: Vec<(PathBuf, SavedSession)> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|path| match load(&path) {
+            Ok(session) => Some((path, session)),
+            Err(e) => {
+                tracing::warn!("skipping {}: {e}", path.display());
+                None
+            }
+        })
+        .collect();
+    saved.sort_by(|a, b| a.1.saved.cmp(&b.1.saved));
+    saved
+}
+
+/// Output that shows the saved screen, followed by a line marking where
+/// the restored session starts.
+pub fn seed(saved: &SavedSession) -> Vec<u8> {
+    let screen = saved.screen.trim_end_matches('\n');
+    let mut out = screen.replace('\n', "\r\n");
+    if !screen.is_empty() {
+        out.push_str("\r\n");
+    }
+    out.push_str(&format!(
+        "\x1b[2m[tap: restored from {}]\x1b[0m\r\n",
+        saved.saved
+    ));
+    out.into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seed() {
+        let saved = SavedSession {
+            id: "calm-fox".to_string(),
+            command: vec!["bash".to_string()],
+            cwd: None,
+            env: BTreeMap::new(),
+            tags: Vec::new(),
+            profile: None,
+            listen: None,
+            screen: "$ make\nok\n\n".to_string(),
+            rows: 24,
+            cols: 80,
+            saved: "2024-01-01T00:00:00+00:00".to_string(),
+        };
+        assert_eq!(
+            String::from_utf8(seed(&saved)).unwrap(),
+            "$ make\r\nok\r\n\x1b[2m[tap: restored from 2024-01-01T00:00:00+00:00]\x1b[0m\r\n"
+        );
+    }
+}
@@ -0,0 +1,228 @@
+//! Probing the outer terminal's capabilities on attached start.
+//!
+//! Terminals answer queries in order, and all of them answer DA1 (primary
+//! device attributes), so DA1 is sent last: once its reply arrives, any
+//! earlier query still unanswered isn't supported. A terminal that doesn't
+//! answer at all within the timeout is assumed capable, as tap did before
+//! probing. Truecolor support has no reliable query, so it's guessed from the
+//! environment.
+
+use std::os::fd::AsRawFd as _;
+
+/// Kitty keyboard flags query, then DA1.
+const QUERY: &[u8] = b"\x1b[?u\x1b[c";
+/// How long to wait for the DA1 reply (kept short so slow links don't stall startup).
+const TIMEOUT: std::time::Duration = std::time::Duration::from_millis(300);
+/// DA1 attribute for clipboard access via OSC 52.
+const DA1_CLIPBOARD: &str = "52";
+
+/// Terminals known to handle OSC 52 without listing it in DA1.
+const OSC52_TERMINALS: &[&str] = &[
+    "kitty",
+    "ghostty",
+    "wezterm",
+    "iterm",
+    "alacritty",
+    "foot",
+    "contour",
+];
+/// Terminals known to render 24-bit color.
+const TRUECOLOR_TERMINALS: &[&str] = &[
+    "kitty",
+    "ghostty",
+    "wezterm",
+    "iterm",
+    "alacritty",
+    "foot",
+    "contour",
+    "vscode",
+];
+
+/// What the outer terminal supports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// Answers the kitty keyboard protocol query, so flags can be pushed.
+    pub kitty_keyboard: bool,
+    /// Can answer OSC 52 clipboard queries.
+    pub osc52: bool,
+}
+
+static CAPABILITIES: std::sync::OnceLock<Capabilities> = std::sync::OnceLock::new();
+
+/// Capabilities found by the last [`probe`], if one ran.
+pub fn cached() -> Option<Capabilities> {
+    CAPABILITIES.get().copied()
+}
+
+/// Whether the terminal tap was started in likely renders 24-bit color.
+pub fn truecolor() -> bool {
+    let env = |name| std::env::var(name).unwrap_or_default();
+    truecolor_hint(&env("COLORTERM"), &[env("TERM"), env("TERM_PROGRAM")])
+}
+
+/// Query the terminal on `stdin`/stdout, which must be in raw mode, and
+/// cache the results. Returns them along with any other input read while
+/// waiting for replies, e.g. early keystrokes.
+pub fn probe(stdin: std::os::fd::BorrowedFd<'_>) -> (Capabilities, Vec<u8>) {
+    let env = |name| std::env::var(name).unwrap_or_default();
+    let names = [env("TERM"), env("TERM_PROGRAM")];
+
+    let mut input = Vec::new();
+    let mut stdout = std::io::stdout();
+    let replies = match std::io::Write::write_all(&mut stdout, QUERY)
+        .and_then(|()| std::io::Write::flush(&mut stdout))
+    {
+        Ok(()) => read_replies(stdin, &mut input),
+        Err(e) => {
+            tracing::debug!("failed to send terminal queries: {e}");
+            None
+        }
+    };
+
+    let capabilities = match replies {
+        Some(replies) => Capabilities {
+            kitty_keyboard: replies.kitty_keyboard,
+            osc52: replies.da1.iter().any(|attr| attr == DA1_CLIPBOARD)
+                || known_terminal(&names, OSC52_TERMINALS),
+        },
+        None => {
+            tracing::debug!("terminal didn't answer DA1, assuming full support");
+            Capabilities {
+                kitty_keyboard: true,
+                osc52: true,
+            }
+        }
+    };
+    tracing::debug!("terminal capabilities: {capabilities:?}");
+    let _ = CAPABILITIES.set(capabilities);
+    (capabilities, input)
+}
+
+/// Replies to [`QUERY`].
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Replies {
+    kitty_keyboard: bool,
+    /// DA1 attributes, e.g. `["62", "22", "52"]`.
+    da1: Vec<String>,
+}
+
+/// Read from `stdin` until the DA1 reply or the timeout, leaving input that
+/// isn't a reply in `input`.
+fn read_replies(stdin: std::os::fd::BorrowedFd<'_>, input: &mut Vec<u8>) -> Option<Replies> {
+    let deadline = std::time::Instant::now() + TIMEOUT;
+    let mut replies = Replies::default();
+    let mut buf = [0u8; 256];
+    loop {
+        let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+        if remaining.is_zero() {
+            return None;
+        }
+        let mut pollfd = nix::libc::pollfd {
+            fd: stdin.as_raw_fd(),
+            events: nix::libc::POLLIN,
+            revents: 0,
+        };
+        let timeout_ms = i32::try_from(remaining.as_millis()).unwrap_or(i32::MAX);
+        if unsafe { nix::libc::poll(&mut pollfd, 1, timeout_ms) } <= 0 {
+            continue;
+        }
+        let n = match nix::unistd::read(stdin, &mut buf) {
+            Ok(0) | Err(_) => return None,
+            Ok(n) => n,
+        };
+        input.extend_from_slice(&buf[..n]);
+        if take_replies(input, &mut replies) {
+            return Some(replies);
+        }
+    }
+}
+
+/// Remove complete replies from `input` into `replies`, returning whether
+/// the DA1 reply was among them.
+fn take_replies(input: &mut Vec<u8>, replies: &mut Replies) -> bool {
+    let mut i = 0;
+    while let Some(offset) = input[i..].windows(3).position(|w| w == b"\x1b[?") {
+        let start = i + offset;
+        let params_start = start + 3;
+        let Some(len) = input[params_start..]
+            .iter()
+            .position(|b| (0x40..=0x7e).contains(b))
+        else {
+            break;
+        };
+        let end = params_start + len;
+        let params = String::from_utf8_lossy(&input[params_start..end]).into_owned();
+        match input[end] {
+            b'u' => replies.kitty_keyboard = true,
+            b'c' => replies.da1 = params.split(';').map(str::to_string).collect(),
+            _ => {
+                i = end + 1;
+                continue;
+            }
+        }
+        let done = input[end] == b'c';
+        input.drain(start..=end);
+        if done {
+            return true;
+        }
+        i = start;
+    }
+    false
+}
+
+/// Whether COLORTERM or the terminal's name suggests 24-bit color.
+fn truecolor_hint(colorterm: &str, names: &[String]) -> bool {
+    matches!(colorterm, "truecolor" | "24bit")
+        || names.iter().any(|name| name.ends_with("-direct"))
+        || known_terminal(names, TRUECOLOR_TERMINALS)
+}
+
+/// Whether TERM or TERM_PROGRAM (in `names`) mention one of `terminals`.
+fn known_terminal(names: &[String], terminals: &[&str]) -> bool {
+    names.iter().any(|name| {
+        let name = name.to_ascii_lowercase();
+        terminals.iter().any(|terminal| name.contains(terminal))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_replies() {
+        let mut replies = Replies::default();
+        let mut input = b"a\x1b[?0u".to_vec();
+        assert!(!take_replies(&mut input, &mut replies));
+        assert!(replies.kitty_keyboard);
+
+        input.extend_from_slice(b"b\x1b[?62;22;52");
+        assert!(!take_replies(&mut input, &mut replies));
+        input.extend_from_slice(b"cd");
+        assert!(take_replies(&mut input, &mut replies));
+        assert_eq!(replies.da1, ["62", "22", "52"]);
+        assert_eq!(input, b"abd");
+    }
+
+    #[test]
+    fn test_da1_without_kitty() {
+        let mut replies = Replies::default();
+        let mut input = b"\x1b[?1;2c".to_vec();
+        assert!(take_replies(&mut input, &mut replies));
+        assert!(!replies.kitty_keyboard);
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn test_truecolor_hint() {
+        let names = |term: &str, program: &str| [term.to_string(), program.to_string()];
+        assert!(truecolor_hint("truecolor", &names("xterm-256color", "")));
+        assert!(truecolor_hint("", &names("xterm-kitty", "")));
+        assert!(truecolor_hint("", &names("xterm-256color", "iTerm.app")));
+        assert!(truecolor_hint("", &names("xterm-direct", "")));
+        assert!(!truecolor_hint(
+            "",
+            &names("xterm-256color", "Apple_Terminal")
+        ));
+    }
+}
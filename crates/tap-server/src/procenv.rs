@@ -0,0 +1,233 @@
+//! Reading the environment and working directory of the child process.
+//!
+//! Linux exposes it through `/proc/<pid>/environ`; macOS through the
+//! `KERN_PROCARGS2` sysctl, which packs argc, the exec path, argv and envp
+//! into a single buffer.
+
+use std::collections::BTreeMap;
+
+/// Read the environment of the process with the given PID.
+#[cfg(target_os = "linux")]
+pub fn read_process_env(pid: i32) -> std::io::Result<BTreeMap<String, String>> {
+    let raw = std::fs::read(format!("/proc/{pid}/environ"))?;
+    Ok(parse_env_block(&raw))
+}
+
+/// Read the environment of the process with the given PID.
+#[cfg(target_os = "macos")]
+pub fn read_process_env(pid: i32) -> std::io::Result<BTreeMap<String, String>> {
+    let mut mib = [nix::libc::CTL_KERN, nix::libc::KERN_PROCARGS2, pid];
+    let mut size: nix::libc::size_t = 0;
+
+    // First call reports the required buffer size
+    let ret = unsafe {
+        nix::libc::sysctl(
+            mib.as_mut_ptr(),
+            mib.len() as u32,
+            std::ptr::null_mut(),
+            &mut size,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    let mut buf = vec![0u8; size];
+    let ret = unsafe {
+        nix::libc::sysctl(
+            mib.as_mut_ptr(),
+            mib.len() as u32,
+            buf.as_mut_ptr().cast(),
+            &mut size,
+            std::ptr::null_mut(),
+            0,
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    buf.truncate(size);
+
+    Ok(parse_env_block(skip_procargs_header(&buf)))
+}
+
+/// Read the environment of the process with the given PID.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn read_process_env(_pid: i32) -> std::io::Result<BTreeMap<String, String>> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "reading process environment is not supported on this platform",
+    ))
+}
+
+/// Read the working directory of the process with the given PID.
+#[cfg(target_os = "linux")]
+pub fn read_process_cwd(pid: i32) -> std::io::Result<std::path::PathBuf> {
+    std::fs::read_link(format!("/proc/{pid}/cwd"))
+}
+
+/// Read the working directory of the process with the given PID.
+#[cfg(target_os = "macos")]
+pub fn read_process_cwd(pid: i32) -> std::io::Result<std::path::PathBuf> {
+    use std::os::unix::ffi::OsStrExt as _;
+
+    let mut info: nix::libc::proc_vnodepathinfo = unsafe { std::mem::zeroed() };
+    let size = std::mem::size_of::<nix::libc::proc_vnodepathinfo>() as i32;
+    let ret = unsafe {
+        nix::libc::proc_pidinfo(
+            pid,
+            nix::libc::PROC_PIDVNODEPATHINFO,
+            0,
+            (&raw mut info).cast(),
+            size,
+        )
+    };
+    if ret != size {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    let path = unsafe { std::ffi::CStr::from_ptr(info.pvi_cdir.vip_path.as_ptr().cast()) };
+    Ok(std::ffi::OsStr::from_bytes(path.to_bytes()).into())
+}
+
+/// Read the working directory of the process with the given PID.
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+pub fn read_process_cwd(_pid: i32) -> std::io::Result<std::path::PathBuf> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "reading process working directory is not supported on this platform",
+    ))
+}
+
+/// Read the user and system CPU time of the process with the given PID,
+/// including children it has waited for.
+#[cfg(target_os = "linux")]
+pub fn read_process_cpu(pid: i32) -> std::io::Result<(std::time::Duration, std::time::Duration)> {
+    let stat = std::fs::read_to_string(format!("/proc/{pid}/stat"))?;
+    let ticks_per_sec = unsafe { nix::libc::sysconf(nix::libc::_SC_CLK_TCK) };
+    parse_stat_cpu(&stat, u64::try_from(ticks_per_sec).unwrap_or(100))
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed /proc stat"))
+}
+
+/// Read the user and system CPU time of the process with the given PID,
+/// including children it has waited for.
+#[cfg(not(target_os = "linux"))]
+pub fn read_process_cpu(_pid: i32) -> std::io::Result<(std::time::Duration, std::time::Duration)> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "reading another process's CPU time is not supported on this platform",
+    ))
+}
+
+/// User and system time (`utime + cutime`, `stime + cstime`) from a
+/// `/proc/<pid>/stat` line.
+#[cfg(any(target_os = "linux", test))]
+fn parse_stat_cpu(
+    stat: &str,
+    ticks_per_sec: u64,
+) -> Option<(std::time::Duration, std::time::Duration)> {
+    // The command name can contain spaces and parentheses; fields resume
+    // after the last `)`, starting with the state (field 3)
+    let fields: Vec<u64> = stat[stat.rfind(')')? + 1..]
+        .split_whitespace()
+        .skip(11)
+        .take(4)
+        .map(|field| field.parse().ok())
+        .collect::<Option<_>>()?;
+    let [utime, stime, cutime, cstime] = fields[..] else {
+        return None;
+    };
+    let to_duration =
+        |ticks: u64| std::time::Duration::from_millis(ticks * 1000 / ticks_per_sec.max(1));
+    Some((to_duration(utime + cutime), to_duration(stime + cstime)))
+}
+
+/// Skip argc, the exec path, its NUL padding and argv in a `KERN_PROCARGS2`
+/// buffer, returning the envp block.
+#[cfg(any(target_os = "macos", test))]
+fn skip_procargs_header(buf: &[u8]) -> &[u8] {
+    const ARGC_LEN: usize = 4;
+
+    let Some(argc_bytes) = buf.get(..ARGC_LEN) else {
+        return &[];
+    };
+    let argc = i32::from_ne_bytes(argc_bytes.try_into().unwrap()).max(0) as usize;
+    let mut rest = &buf[ARGC_LEN..];
+
+    // Exec path, then NUL padding up to the first argument
+    let path_end = rest.iter().position(|&b| b == 0).unwrap_or(rest.len());
+    rest = &rest[path_end..];
+    let args_start = rest.iter().position(|&b| b != 0).unwrap_or(rest.len());
+    rest = &rest[args_start..];
+
+    for _ in 0..argc {
+        let arg_end = rest.iter().position(|&b| b == 0).unwrap_or(rest.len());
+        rest = &rest[(arg_end + 1).min(rest.len())..];
+    }
+
+    rest
+}
+
+/// Parse a block of NUL-separated `KEY=VALUE` entries.
+/// Stops at the first empty entry, which terminates envp.
+fn parse_env_block(raw: &[u8]) -> BTreeMap<String, String> {
+    raw.split(|&b| b == 0)
+        .take_while(|entry| !entry.is_empty())
+        .filter_map(|entry| {
+            let entry = String::from_utf8_lossy(entry);
+            let (key, value) = entry.split_once('=')?;
+            Some((key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_env_block() {
+        let vars = parse_env_block(b"HOME=/root\0DISPLAY=:0\0EMPTY=\0");
+        assert_eq!(vars.get("HOME").map(String::as_str), Some("/root"));
+        assert_eq!(vars.get("DISPLAY").map(String::as_str), Some(":0"));
+        assert_eq!(vars.get("EMPTY").map(String::as_str), Some(""));
+        assert_eq!(vars.len(), 3);
+    }
+
+    #[test]
+    fn test_parse_env_block_value_with_equals() {
+        let vars = parse_env_block(b"OPTS=a=b\0");
+        assert_eq!(vars.get("OPTS").map(String::as_str), Some("a=b"));
+    }
+
+    #[test]
+    fn test_skip_procargs_header() {
+        let mut buf = 2i32.to_ne_bytes().to_vec();
+        buf.extend_from_slice(b"/bin/zsh\0\0\0\0zsh\0-i\0TERM=xterm\0\0junk");
+        let vars = parse_env_block(skip_procargs_header(&buf));
+        assert_eq!(vars.len(), 1);
+        assert_eq!(vars.get("TERM").map(String::as_str), Some("xterm"));
+    }
+
+    #[test]
+    fn test_parse_stat_cpu() {
+        let stat = "1234 (my (cmd)) S 1 1234 1234 0 -1 4194560 100 0 0 0 250 50 10 5 20 0 1 0";
+        assert_eq!(
+            parse_stat_cpu(stat, 100),
+            Some((
+                std::time::Duration::from_millis(2600),
+                std::time::Duration::from_millis(550)
+            ))
+        );
+        assert_eq!(parse_stat_cpu("1234 (sh) S 1", 100), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_read_own_env() {
+        let vars = read_process_env(std::process::id() as i32).unwrap();
+        assert!(!vars.is_empty());
+    }
+}
@@ -0,0 +1,164 @@
+//! Collapsing progress bars and spinners in stored scrollback.
+//!
+//! Tools redraw progress with a bare carriage return. The emulator already
+//! overwrites such a line in place, but once the line is wider than the
+//! terminal it wraps, the return only goes back to the start of the last
+//! row, and every redraw leaves its earlier rows behind. This filter rewrites
+//! such returns to move up to the start of the wrapped line and clear it, so
+//! only the final state is kept. It only touches what's stored; clients still
+//! get the output as the child wrote it.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Escape {
+    None,
+    /// After ESC.
+    Start,
+    /// Inside a CSI sequence.
+    Csi,
+    /// Inside an OSC/DCS/APC string, up to BEL or ST.
+    String,
+    /// ESC inside a string, maybe the start of ST.
+    StringEsc,
+}
+
+/// Streaming rewriter for carriage-return redraws of wrapped lines.
+#[derive(Debug)]
+pub struct ProgressFilter {
+    /// Columns printed since the start of the line. Counts characters, so
+    /// wide characters are undercounted.
+    width: usize,
+    escape: Escape,
+    /// A carriage return ending the last chunk, not yet known to be bare.
+    pending_cr: bool,
+}
+
+impl ProgressFilter {
+    pub const fn new() -> Self {
+        Self {
+            width: 0,
+            escape: Escape::None,
+            pending_cr: false,
+        }
+    }
+
+    /// Rewrite a chunk of output for a terminal `cols` wide.
+    pub fn process(&mut self, data: &[u8], cols: u16) -> Vec<u8> {
+        let cols = usize::from(cols.max(1));
+        let mut out = Vec::with_capacity(data.len());
+
+        let mut i = 0;
+        if std::mem::take(&mut self.pending_cr) {
+            match data.first() {
+                None => {
+                    self.pending_cr = true;
+                    return out;
+                }
+                Some(&b'\n') => out.push(b'\r'),
+                Some(_) => self.carriage_return(&mut out, cols),
+            }
+        }
+
+        while i < data.len() {
+            let byte = data[i];
+            i += 1;
+            match self.escape {
+                Escape::Start => {
+                    self.escape = match byte {
+                        b'[' => Escape::Csi,
+                        b']' | b'P' | b'_' | b'^' | b'X' => Escape::String,
+                        _ => Escape::None,
+                    };
+                }
+                Escape::Csi => {
+                    if (0x40..=0x7e).contains(&byte) {
+                        self.escape = Escape::None;
+                    }
+                }
+                Escape::String => match byte {
+                    0x07 => self.escape = Escape::None,
+                    0x1b => self.escape = Escape::StringEsc,
+                    _ => {}
+                },
+                Escape::StringEsc => {
+                    self.escape = if byte == b'\\' {
+                        Escape::None
+                    } else {
+                        Escape::String
+                    };
+                }
+                Escape::None => match byte {
+                    0x1b => self.escape = Escape::Start,
+                    b'\n' => self.width = 0,
+                    b'\r' => match data.get(i) {
+                        None => {
+                            self.pending_cr = true;
+                            return out;
+                        }
+                        Some(&b'\n') => {}
+                        Some(_) => {
+                            self.carriage_return(&mut out, cols);
+                            continue;
+                        }
+                    },
+                    0x08 => self.width = self.width.saturating_sub(1),
+                    b'\t' => self.width = (self.width / 8 + 1) * 8,
+                    // UTF-8 continuation bytes and other controls take no space
+                    0x80..=0xbf => {}
+                    0x00..=0x1f | 0x7f => {}
+                    _ => self.width += 1,
+                },
+            }
+            out.push(byte);
+        }
+        out
+    }
+
+    /// A bare carriage return: back to the start of the line, even if it
+    /// wrapped onto several rows.
+    fn carriage_return(&mut self, out: &mut Vec<u8>, cols: usize) {
+        let rows_up = self.width.saturating_sub(1) / cols;
+        if rows_up > 0 {
+            out.extend_from_slice(format!("\r\x1b[{rows_up}A\x1b[J").as_bytes());
+        } else {
+            out.push(b'\r');
+        }
+        self.width = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_short_lines_untouched() {
+        let mut filter = ProgressFilter::new();
+        let data = b"\x1b[32m 10%\x1b[0m\r 20%\r\n";
+        assert_eq!(filter.process(data, 80), data);
+    }
+
+    #[test]
+    fn test_wrapped_redraw_moves_up() {
+        let mut filter = ProgressFilter::new();
+        let bar = "#".repeat(25);
+        let out = filter.process(format!("{bar}\r{bar}\n").as_bytes(), 10);
+        assert_eq!(out, format!("{bar}\r\x1b[2A\x1b[J{bar}\n").into_bytes());
+    }
+
+    #[test]
+    fn test_carriage_return_split_across_chunks() {
+        let mut filter = ProgressFilter::new();
+        let bar = "#".repeat(15);
+        assert_eq!(
+            filter.process(format!("{bar}\r").as_bytes(), 10),
+            bar.as_bytes()
+        );
+        assert_eq!(filter.process(b"\n", 10), b"\r\n");
+
+        assert_eq!(
+            filter.process(format!("{bar}\r").as_bytes(), 10),
+            bar.as_bytes()
+        );
+        assert_eq!(filter.process(b"x", 10), b"\r\x1b[1A\x1b[Jx");
+    }
+}
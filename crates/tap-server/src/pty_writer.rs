@@ -0,0 +1,89 @@
+//! Serialized writes to the PTY master.
+//!
+//! Local keystrokes, injected input and attached-client input all go through
+//! one queue drained by a dedicated thread, so writes never interleave and
+//! arrive in the order they were sent.
+
+use std::os::fd::BorrowedFd;
+
+/// Channel for sending input to the PTY.
+pub type InputSender = tokio::sync::mpsc::UnboundedSender<Vec<u8>>;
+
+/// Spawn the thread that owns all writes to the PTY master.
+pub fn spawn(master_fd: i32) -> std::io::Result<InputSender> {
+    let (input_tx, mut input_rx) = tokio::sync::mpsc::unbounded_channel::<Vec<u8>>();
+
+    std::thread::Builder::new()
+        .name("tap-pty-writer".to_string())
+        .spawn(move || {
+            while let Some(data) = input_rx.blocking_recv() {
+                trace_pipeline!(PTY_WRITE, data.len());
+                if let Err(e) = write_all(master_fd, &data) {
+                    tracing::debug!("PTY write error: {e}");
+                    break;
+                }
+            }
+        })?;
+
+    Ok(input_tx)
+}
+
+/// Write all of `data`, retrying short writes, `EINTR` and `EAGAIN`.
+fn write_all(fd: i32, mut data: &[u8]) -> nix::Result<()> {
+    let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+    while !data.is_empty() {
+        match nix::unistd::write(borrowed, data) {
+            Ok(0) => return Err(nix::errno::Errno::EIO),
+            Ok(n) => data = &data[n..],
+            Err(nix::errno::Errno::EINTR) => continue,
+            Err(nix::errno::Errno::EAGAIN) => wait_writable(fd),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Block until the fd is writable again (the child's input queue drained).
+fn wait_writable(fd: i32) {
+    let mut pollfd = nix::libc::pollfd {
+        fd,
+        events: nix::libc::POLLOUT,
+        revents: 0,
+    };
+    unsafe {
+        nix::libc::poll(&mut pollfd, 1, -1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read as _;
+    use std::os::fd::{AsRawFd as _, FromRawFd as _, IntoRawFd as _};
+
+    use super::*;
+
+    #[test]
+    fn test_write_all_larger_than_pipe_buffer() {
+        let (read_end, write_end) = nix::unistd::pipe().unwrap();
+        let write_fd = write_end.as_raw_fd();
+
+        // Make the write end non-blocking so short writes and EAGAIN occur
+        unsafe {
+            let flags = nix::libc::fcntl(write_fd, nix::libc::F_GETFL);
+            nix::libc::fcntl(write_fd, nix::libc::F_SETFL, flags | nix::libc::O_NONBLOCK);
+        }
+
+        let data = vec![b'x'; 1024 * 1024];
+        let reader = std::thread::spawn(move || {
+            let mut file = unsafe { std::fs::File::from_raw_fd(read_end.into_raw_fd()) };
+            let mut received = Vec::new();
+            file.read_to_end(&mut received).unwrap();
+            received.len()
+        });
+
+        write_all(write_fd, &data).unwrap();
+        drop(write_end);
+
+        assert_eq!(reader.join().unwrap(), data.len());
+    }
+}
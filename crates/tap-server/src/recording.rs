@@ -0,0 +1,321 @@
+//! Recording session output as asciicast v2 (`.cast`) files.
+//!
+//! A recording can be started explicitly (`tap start --record` or the
+//! `StartRecording` request) and runs until stopped or the session ends.
+//!
+//! Auto-recording keeps the last few seconds of output in memory and starts
+//! a recording, pre-buffer included, when output matches a configured
+//! trigger, so intermittent failures in long sessions get captured without
+//! recording everything.
+
+use std::io::Write as _;
+
+/// Bytes of earlier output kept so triggers split across chunks still match.
+const TRIGGER_TAIL_BYTES: usize = 256;
+
+/// Writes output events to an asciicast v2 file.
+pub struct Recorder {
+    out: std::io::BufWriter<std::fs::File>,
+    start: std::time::Instant,
+}
+
+impl Recorder {
+    /// Create `path` and write the header. Event times are relative to `start`.
+    pub fn create(
+        path: &std::path::Path,
+        (rows, cols): (u16, u16),
+        start: std::time::Instant,
+    ) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+        let header = serde_json::json!({
+            "version": 2,
+            "width": cols,
+            "height": rows,
+            "timestamp": chrono::Utc::now().timestamp(),
+        });
+        writeln!(out, "{header}")?;
+        Ok(Self { out, start })
+    }
+
+    /// Append an output event that happened at `at`.
+    pub fn write_output(&mut self, at: std::time::Instant, data: &[u8]) -> std::io::Result<()> {
+        let time = at.saturating_duration_since(self.start).as_secs_f64();
+        let event = serde_json::json!([time, "o", String::from_utf8_lossy(data)]);
+        writeln!(self.out, "{event}")?;
+        self.out.flush()
+    }
+
+    /// Append a marker event labelled `label` that happened at `at`.
+    pub fn write_marker(&mut self, at: std::time::Instant, label: &str) -> std::io::Result<()> {
+        let time = at.saturating_duration_since(self.start).as_secs_f64();
+        let event = serde_json::json!([time, "m", label]);
+        writeln!(self.out, "{event}")?;
+        self.out.flush()
+    }
+}
+
+/// Trigger-based recording state for a session.
+pub struct AutoRecord {
+    triggers: Vec<regex::Regex>,
+    pre_buffer: std::collections::VecDeque<(std::time::Instant, Vec<u8>)>,
+    pre_buffer_window: std::time::Duration,
+    /// How long to keep recording after a trigger; `None` records until the
+    /// session ends.
+    post_trigger: Option<std::time::Duration>,
+    tail: Vec<u8>,
+    dir: std::path::PathBuf,
+    active: Option<(Recorder, Option<std::time::Instant>)>,
+}
+
+impl AutoRecord {
+    /// Build from config. Returns `None` when no triggers are configured.
+    pub fn new(
+        config: &tap_config::RecordConfig,
+        dir: std::path::PathBuf,
+    ) -> eyre::Result<Option<Self>> {
+        if config.triggers.is_empty() {
+            return Ok(None);
+        }
+        let triggers = config
+            .triggers
+            .iter()
+            .map(|pattern| {
+                regex::Regex::new(pattern)
+                    .map_err(|e| eyre::eyre!("invalid record trigger '{pattern}': {e}"))
+            })
+            .collect::<eyre::Result<_>>()?;
+        Ok(Some(Self {
+            triggers,
+            pre_buffer: std::collections::VecDeque::new(),
+            pre_buffer_window: std::time::Duration::from_secs(config.pre_buffer_secs),
+            post_trigger: (config.post_trigger_secs > 0)
+                .then(|| std::time::Duration::from_secs(config.post_trigger_secs)),
+            tail: Vec::new(),
+            dir,
+            active: None,
+        }))
+    }
+
+    /// Handle a chunk of output. `size` is the terminal size for a new recording.
+    pub fn push(&mut self, data: &[u8], now: std::time::Instant, size: (u16, u16)) {
+        if let Some((recorder, stop_at)) = &mut self.active {
+            if let Err(e) = recorder.write_output(now, data) {
+                tracing::warn!("failed to write recording: {e}");
+                self.active = None;
+            } else if stop_at.is_some_and(|stop_at| now >= stop_at) {
+                tracing::debug!("auto-recording finished");
+                self.active = None;
+            }
+            return;
+        }
+
+        self.pre_buffer.push_back((now, data.to_vec()));
+        while let Some((at, _)) = self.pre_buffer.front()
+            && now.saturating_duration_since(*at) > self.pre_buffer_window
+        {
+            self.pre_buffer.pop_front();
+        }
+
+        self.tail.extend_from_slice(data);
+        let text = String::from_utf8_lossy(&self.tail);
+        let triggered = self.triggers.iter().any(|trigger| trigger.is_match(&text));
+        let excess = self.tail.len().saturating_sub(TRIGGER_TAIL_BYTES);
+        self.tail.drain(..excess);
+
+        if triggered {
+            self.tail.clear();
+            self.start(now, size);
+        }
+    }
+
+    /// Start recording, beginning with the buffered output.
+    fn start(&mut self, now: std::time::Instant, size: (u16, u16)) {
+        let start = self.pre_buffer.front().map_or(now, |(at, _)| *at);
+        let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+        let path = self
+            .dir
+            .join(format!("{}-{stamp}.cast", crate::SESSION_ID.read()));
+
+        let recorder = Recorder::create(&path, size, start).and_then(|mut recorder| {
+            for (at, chunk) in self.pre_buffer.drain(..) {
+                recorder.write_output(at, &chunk)?;
+            }
+            Ok(recorder)
+        });
+        match recorder {
+            Ok(recorder) => {
+                tracing::info!("trigger matched, recording to {}", path.display());
+                self.active = Some((recorder, self.post_trigger.map(|d| now + d)));
+            }
+            Err(e) => tracing::warn!("failed to start recording {}: {e}", path.display()),
+        }
+    }
+}
+
+/// Auto-recording for this session, if configured.
+static AUTO_RECORD: parking_lot::Mutex<Option<AutoRecord>> = parking_lot::Mutex::new(None);
+
+/// Explicitly requested recording, if one is running.
+static MANUAL: parking_lot::Mutex<Option<(Recorder, std::path::PathBuf)>> =
+    parking_lot::Mutex::new(None);
+
+/// Enable auto-recording for the session.
+pub fn init(auto_record: Option<AutoRecord>) {
+    *AUTO_RECORD.lock() = auto_record;
+}
+
+fn pty_size() -> (u16, u16) {
+    crate::MASTER_FD
+        .get()
+        .map_or((24, 80), |&fd| crate::resize::current_size(fd))
+}
+
+/// Start recording all further output to `path`.
+pub fn start(path: &std::path::Path) -> eyre::Result<()> {
+    let mut manual = MANUAL.lock();
+    if let Some((_, current)) = manual.as_ref() {
+        eyre::bail!("already recording to {}", current.display());
+    }
+    let recorder = Recorder::create(path, pty_size(), std::time::Instant::now())
+        .map_err(|e| eyre::eyre!("failed to create {}: {e}", path.display()))?;
+    tracing::info!("recording to {}", path.display());
+    *manual = Some((recorder, path.to_path_buf()));
+    Ok(())
+}
+
+/// Stop the explicit recording, returning the file it was written to.
+pub fn stop() -> Option<std::path::PathBuf> {
+    MANUAL.lock().take().map(|(_, path)| path)
+}
+
+/// Feed a chunk of PTY output to any active recording.
+pub fn record_output(data: &[u8]) {
+    let now = std::time::Instant::now();
+
+    let mut manual = MANUAL.lock();
+    if let Some((recorder, path)) = manual.as_mut()
+        && let Err(e) = recorder.write_output(now, data)
+    {
+        tracing::warn!("failed to write recording {}: {e}", path.display());
+        *manual = None;
+    }
+    drop(manual);
+
+    let mut auto_record = AUTO_RECORD.lock();
+    if let Some(auto_record) = auto_record.as_mut() {
+        auto_record.push(data, now, pty_size());
+    }
+}
+
+/// Add a marker labelled `label` to any active recording.
+pub fn annotate(label: &str) {
+    let now = std::time::Instant::now();
+
+    let mut manual = MANUAL.lock();
+    if let Some((recorder, path)) = manual.as_mut()
+        && let Err(e) = recorder.write_marker(now, label)
+    {
+        tracing::warn!("failed to write recording {}: {e}", path.display());
+        *manual = None;
+    }
+    drop(manual);
+
+    let mut auto_record = AUTO_RECORD.lock();
+    if let Some(AutoRecord {
+        active: Some((recorder, _)),
+        ..
+    }) = auto_record.as_mut()
+        && let Err(e) = recorder.write_marker(now, label)
+    {
+        tracing::warn!("failed to write recording: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(triggers: &[&str]) -> tap_config::RecordConfig {
+        tap_config::RecordConfig {
+            triggers: triggers.iter().map(|t| t.to_string()).collect(),
+            ..tap_config::RecordConfig::default()
+        }
+    }
+
+    fn recordings(dir: &std::path::Path) -> Vec<String> {
+        std::fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .map(|e| std::fs::read_to_string(e.unwrap().path()).unwrap())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_no_triggers_disables() {
+        let dir = tempfile::tempdir().unwrap();
+        let auto = AutoRecord::new(&config(&[]), dir.path().to_path_buf()).unwrap();
+        assert!(auto.is_none());
+    }
+
+    #[test]
+    fn test_trigger_flushes_pre_buffer() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut auto = AutoRecord::new(&config(&["panicked at"]), dir.path().to_path_buf())
+            .unwrap()
+            .unwrap();
+        let now = std::time::Instant::now();
+
+        auto.push(b"running tests\r\n", now, (24, 80));
+        assert!(recordings(dir.path()).is_empty());
+
+        // Trigger text split across chunks still matches
+        auto.push(b"thread 'main' pan", now, (24, 80));
+        auto.push(b"icked at src/lib.rs", now, (24, 80));
+        auto.push(b"after\r\n", now, (24, 80));
+
+        let recordings = recordings(dir.path());
+        assert_eq!(recordings.len(), 1);
+        let lines: Vec<&str> = recordings[0].lines().collect();
+        assert!(lines[0].contains(r#""version":2"#));
+        assert!(lines[1].contains("running tests"));
+        assert!(lines.last().unwrap().contains("after"));
+        assert_eq!(lines.len(), 5);
+    }
+
+    #[test]
+    fn test_recorder_timestamps() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("nested/out.cast");
+        let start = std::time::Instant::now();
+        let mut recorder = Recorder::create(&path, (30, 100), start).unwrap();
+        recorder.write_output(start, b"a").unwrap();
+        recorder
+            .write_output(start + std::time::Duration::from_millis(1500), b"\x1b[1mb")
+            .unwrap();
+        recorder
+            .write_marker(start + std::time::Duration::from_secs(2), "bisect")
+            .unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = content
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines[0]["width"], 100);
+        assert_eq!(lines[0]["height"], 30);
+        assert_eq!(lines[1], serde_json::json!([0.0, "o", "a"]));
+        assert_eq!(lines[2], serde_json::json!([1.5, "o", "\x1b[1mb"]));
+        assert_eq!(lines[3], serde_json::json!([2.0, "m", "bisect"]));
+    }
+
+    #[test]
+    fn test_invalid_trigger() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(AutoRecord::new(&config(&["("]), dir.path().to_path_buf()).is_err());
+    }
+}
@@ -0,0 +1,121 @@
+//! Serving a session over TCP (`tap start --listen`), for clients on other
+//! machines. Only its owner can open the Unix socket, but anyone who can
+//! reach the port can connect, so each connection has to open with a
+//! `Hello` carrying the session's token. The address is carried across
+//! upgrades, migrations and restores; the token only ever lives in
+//! `$TAP_AUTH_TOKEN`.
+
+/// How long a TCP client has to send its `Hello`.
+pub(crate) const HELLO_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+/// TCP clients served at once; connections beyond this are closed.
+pub(crate) const MAX_CLIENTS: usize = 32;
+/// Pause after a failed accept (e.g. out of descriptors) before the next.
+pub(crate) const ACCEPT_BACKOFF: std::time::Duration = std::time::Duration::from_millis(100);
+/// How long to keep retrying an address the previous server may still hold.
+const REBIND_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
+
+/// Where this session is served, once it's listening.
+static SERVING: std::sync::OnceLock<Listen> = std::sync::OnceLock::new();
+
+/// Where to accept TCP connections, and the token they must present.
+#[derive(Clone)]
+pub struct Listen {
+    pub addr: std::net::SocketAddr,
+    pub token: String,
+}
+
+impl std::fmt::Debug for Listen {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Listen")
+            .field("addr", &self.addr)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Listen {
+    /// Listen on `addr` with the token from `$TAP_AUTH_TOKEN`, for sessions
+    /// carried over from another server. The token is never written to disk,
+    /// so this is `None` if it isn't set.
+    pub fn from_env(addr: std::net::SocketAddr) -> Option<Self> {
+        let token = std::env::var(tap_protocol::AUTH_TOKEN_ENV)
+            .ok()
+            .filter(|token| !token.is_empty())?;
+        Some(Self { addr, token })
+    }
+}
+
+/// The address this session is served on over TCP, if it is.
+pub(crate) fn addr() -> Option<std::net::SocketAddr> {
+    SERVING.get().map(|listen| listen.addr)
+}
+
+/// The token TCP clients present, if the session is served over TCP.
+pub(crate) fn token() -> Option<&'static str> {
+    SERVING.get().map(|listen| listen.token.as_str())
+}
+
+/// Bind `listen`'s address. A server taking over from another retries while
+/// the old one may still be holding it.
+pub(crate) async fn bind(
+    listen: &Listen,
+    taking_over: bool,
+) -> std::io::Result<tokio::net::TcpListener> {
+    let deadline = tokio::time::Instant::now() + REBIND_TIMEOUT;
+    loop {
+        match tokio::net::TcpListener::bind(listen.addr).await {
+            Ok(listener) => {
+                let _ = SERVING.set(Listen {
+                    addr: listener.local_addr()?,
+                    token: listen.token.clone(),
+                });
+                return Ok(listener);
+            }
+            Err(e)
+                if taking_over
+                    && e.kind() == std::io::ErrorKind::AddrInUse
+                    && tokio::time::Instant::now() < deadline =>
+            {
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Whether the first request on a TCP connection presents `token`. The
+/// comparison takes as long wherever the first difference is.
+pub(crate) fn authenticates(request: &tap_protocol::Request, token: &str) -> bool {
+    let tap_protocol::Request::Hello {
+        token: Some(given), ..
+    } = request
+    else {
+        return false;
+    };
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hello(token: Option<&str>) -> tap_protocol::Request {
+        tap_protocol::Request::Hello {
+            version: tap_protocol::PROTOCOL_VERSION.to_string(),
+            token: token.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_authenticates() {
+        assert!(authenticates(&hello(Some("s3cret")), "s3cret"));
+        assert!(!authenticates(&hello(Some("s3cres")), "s3cret"));
+        assert!(!authenticates(&hello(Some("s3cret!")), "s3cret"));
+        assert!(!authenticates(&hello(None), "s3cret"));
+        assert!(!authenticates(&tap_protocol::Request::Ping, "s3cret"));
+    }
+}
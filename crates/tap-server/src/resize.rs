@@ -0,0 +1,108 @@
+//! Coalescing PTY resizes.
+//!
+//! Dragging a window edge fires a burst of resize requests, and applying each
+//! one makes full-screen apps redraw over and over. Requests are queued here
+//! and only the latest is applied, once none have arrived for a short while.
+
+/// Sizes waiting to be applied, as (rows, cols).
+static RESIZE_TX: std::sync::OnceLock<tokio::sync::mpsc::UnboundedSender<(u16, u16)>> =
+    std::sync::OnceLock::new();
+/// Sizes actually applied to the PTY, for subscribers.
+static RESIZED: std::sync::LazyLock<tokio::sync::broadcast::Sender<(u16, u16)>> =
+    std::sync::LazyLock::new(|| tokio::sync::broadcast::channel(16).0);
+
+/// Start applying queued resizes to the PTY at `master_fd`, waiting for
+/// `quiescence` without new requests before each one.
+pub fn spawn(master_fd: i32, quiescence: std::time::Duration) {
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    if RESIZE_TX.set(tx).is_err() {
+        return;
+    }
+    tokio::spawn(run(master_fd, quiescence, rx));
+}
+
+/// Queue a resize of the PTY. Empty sizes are ignored.
+pub fn request(rows: u16, cols: u16) {
+    if rows == 0 || cols == 0 {
+        tracing::debug!("ignoring resize to {rows}x{cols}");
+        return;
+    }
+    match RESIZE_TX.get() {
+        Some(tx) => {
+            let _ = tx.send((rows, cols));
+        }
+        None => {
+            if let Some(&master_fd) = crate::MASTER_FD.get() {
+                crate::set_window_size_raw(master_fd, rows, cols);
+                crate::SCROLLBACK.write().resize(rows, cols);
+                crate::events::emit(tap_protocol::Event::Resize { rows, cols });
+            }
+        }
+    }
+}
+
+/// Receive each size applied to the PTY.
+pub fn subscribe() -> tokio::sync::broadcast::Receiver<(u16, u16)> {
+    RESIZED.subscribe()
+}
+
+async fn run(
+    master_fd: i32,
+    quiescence: std::time::Duration,
+    mut rx: tokio::sync::mpsc::UnboundedReceiver<(u16, u16)>,
+) {
+    while let Some((rows, cols)) = next_settled(&mut rx, quiescence).await {
+        if current_size(master_fd) == (rows, cols) {
+            continue;
+        }
+        tracing::debug!("applying resize to {rows}x{cols}");
+        crate::set_window_size_raw(master_fd, rows, cols);
+        crate::SCROLLBACK.write().resize(rows, cols);
+        let _ = RESIZED.send((rows, cols));
+        crate::events::emit(tap_protocol::Event::Resize { rows, cols });
+    }
+}
+
+/// Wait for a burst of requests and return the last one once no more have
+/// arrived for `quiescence`. Returns `None` once the queue is closed.
+async fn next_settled(
+    rx: &mut tokio::sync::mpsc::UnboundedReceiver<(u16, u16)>,
+    quiescence: std::time::Duration,
+) -> Option<(u16, u16)> {
+    let mut latest = rx.recv().await?;
+    loop {
+        match tokio::time::timeout(quiescence, rx.recv()).await {
+            Ok(Some(size)) => latest = size,
+            Ok(None) | Err(_) => return Some(latest),
+        }
+    }
+}
+
+/// Current size of the PTY as (rows, cols).
+pub fn current_size(fd: i32) -> (u16, u16) {
+    let mut ws: nix::pty::Winsize = unsafe { std::mem::zeroed() };
+    unsafe {
+        nix::libc::ioctl(fd, nix::libc::TIOCGWINSZ, &mut ws);
+    }
+    (ws.ws_row, ws.ws_col)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_next_settled_keeps_latest() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        for cols in [80, 90, 100] {
+            tx.send((24, cols)).unwrap();
+        }
+        let quiescence = std::time::Duration::from_millis(10);
+        assert_eq!(next_settled(&mut rx, quiescence).await, Some((24, 100)));
+
+        tx.send((30, 120)).unwrap();
+        drop(tx);
+        assert_eq!(next_settled(&mut rx, quiescence).await, Some((30, 120)));
+        assert_eq!(next_settled(&mut rx, quiescence).await, None);
+    }
+}
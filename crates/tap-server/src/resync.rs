@@ -0,0 +1,46 @@
+//! Recovering when the emulated screen no longer matches what the app drew,
+//! e.g. after binary output or a truncated escape sequence.
+
+use crate::pty_writer::InputSender;
+
+/// Input typed after a resync (`[output] resync_input`).
+static INPUT: std::sync::OnceLock<Vec<u8>> = std::sync::OnceLock::new();
+
+/// Configure the input typed into the session after a resync.
+pub fn init(config: &tap_config::OutputConfig) {
+    if let Some(input) = &config.resync_input {
+        let _ = INPUT.set(input.clone().into_bytes());
+    }
+}
+
+/// Rebuild the emulator from a clean state and ask the app to redraw: the
+/// foreground process group gets SIGWINCH, as on a resize, and the
+/// configured input (e.g. Ctrl-L) is typed.
+pub fn resync(input_tx: &InputSender) -> Result<(), String> {
+    crate::SCROLLBACK.write().resync();
+
+    let Some(&master_fd) = crate::MASTER_FD.get() else {
+        return Err("session has no terminal".to_string());
+    };
+    let fd = unsafe { std::os::fd::BorrowedFd::borrow_raw(master_fd) };
+    let signalled = match nix::unistd::tcgetpgrp(fd) {
+        Ok(group) => nix::sys::signal::killpg(group, nix::sys::signal::Signal::SIGWINCH),
+        Err(_) => match crate::CHILD_PID.get() {
+            Some(&pid) => crate::signal_child(
+                nix::unistd::Pid::from_raw(pid),
+                nix::sys::signal::Signal::SIGWINCH,
+            ),
+            None => Err(nix::errno::Errno::ESRCH),
+        },
+    };
+    if let Err(e) = signalled {
+        tracing::debug!("resync: failed to send SIGWINCH: {e}");
+    }
+
+    if let Some(input) = INPUT.get()
+        && input_tx.send(input.clone()).is_err()
+    {
+        return Err("session ended".to_string());
+    }
+    Ok(())
+}
@@ -0,0 +1,179 @@
+//! Rendering the visible screen to SVG.
+//!
+//! Each cell is laid out on a fixed monospace grid so the image matches the
+//! terminal exactly, regardless of which monospace font the viewer picks.
+
+use std::fmt::Write as _;
+
+use crate::backend::{CellColor, StyledCell};
+
+const CELL_WIDTH: f32 = 8.4;
+const CELL_HEIGHT: f32 = 17.0;
+const FONT_SIZE: f32 = 14.0;
+/// Distance from the top of a cell to the text baseline.
+const BASELINE: f32 = 13.0;
+const PADDING: f32 = 8.0;
+
+const DEFAULT_FG: (u8, u8, u8) = (0xd0, 0xd0, 0xd0);
+const DEFAULT_BG: (u8, u8, u8) = (0x1c, 0x1c, 0x1c);
+
+/// The 16 base colors, as xterm draws them.
+const ANSI_COLORS: [(u8, u8, u8); 16] = [
+    (0x00, 0x00, 0x00),
+    (0xcd, 0x00, 0x00),
+    (0x00, 0xcd, 0x00),
+    (0xcd, 0xcd, 0x00),
+    (0x00, 0x00, 0xee),
+    (0xcd, 0x00, 0xcd),
+    (0x00, 0xcd, 0xcd),
+    (0xe5, 0xe5, 0xe5),
+    (0x7f, 0x7f, 0x7f),
+    (0xff, 0x00, 0x00),
+    (0x00, 0xff, 0x00),
+    (0xff, 0xff, 0x00),
+    (0x5c, 0x5c, 0xff),
+    (0xff, 0x00, 0xff),
+    (0x00, 0xff, 0xff),
+    (0xff, 0xff, 0xff),
+];
+
+/// Resolve a cell color to RGB, using `default` for the terminal default.
+fn rgb(color: CellColor, default: (u8, u8, u8)) -> (u8, u8, u8) {
+    match color {
+        CellColor::Default => default,
+        CellColor::Rgb(r, g, b) => (r, g, b),
+        CellColor::Indexed(idx @ 0..=15) => ANSI_COLORS[usize::from(idx)],
+        CellColor::Indexed(idx @ 16..=231) => {
+            // 6x6x6 color cube
+            let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+            let idx = idx - 16;
+            (level(idx / 36), level(idx / 6 % 6), level(idx % 6))
+        }
+        CellColor::Indexed(idx) => {
+            // Grayscale ramp
+            let v = 8 + (idx - 232) * 10;
+            (v, v, v)
+        }
+    }
+}
+
+fn hex((r, g, b): (u8, u8, u8)) -> String {
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Render screen rows (as from [`crate::scrollback::ScrollbackBuffer::styled_rows`]) to an SVG document.
+pub fn render_svg(rows: &[Vec<StyledCell>]) -> String {
+    let cols = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let width = cols as f32 * CELL_WIDTH + 2.0 * PADDING;
+    let height = rows.len() as f32 * CELL_HEIGHT + 2.0 * PADDING;
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}">"#
+    );
+    let _ = writeln!(
+        svg,
+        r#"<rect width="100%" height="100%" fill="{}"/>"#,
+        hex(DEFAULT_BG)
+    );
+    let _ = writeln!(
+        svg,
+        r#"<g font-family="monospace" font-size="{FONT_SIZE}" xml:space="preserve">"#
+    );
+
+    for (row_idx, row) in rows.iter().enumerate() {
+        let y = PADDING + row_idx as f32 * CELL_HEIGHT;
+        for (col_idx, cell) in row.iter().enumerate() {
+            let x = PADDING + col_idx as f32 * CELL_WIDTH;
+            let cell_width = if cell.wide { 2.0 } else { 1.0 } * CELL_WIDTH;
+
+            let (mut fg, mut bg) = (rgb(cell.fg, DEFAULT_FG), rgb(cell.bg, DEFAULT_BG));
+            if cell.inverse {
+                std::mem::swap(&mut fg, &mut bg);
+            }
+
+            if bg != DEFAULT_BG {
+                let _ = writeln!(
+                    svg,
+                    r#"<rect x="{x}" y="{y}" width="{cell_width}" height="{CELL_HEIGHT}" fill="{}"/>"#,
+                    hex(bg)
+                );
+            }
+
+            if cell.contents.trim().is_empty() {
+                continue;
+            }
+
+            let mut attrs = format!(r#"fill="{}""#, hex(fg));
+            if cell.bold {
+                attrs.push_str(r#" font-weight="bold""#);
+            }
+            if cell.italic {
+                attrs.push_str(r#" font-style="italic""#);
+            }
+            if cell.underline {
+                attrs.push_str(r#" text-decoration="underline""#);
+            }
+            let _ = writeln!(
+                svg,
+                r#"<text x="{x}" y="{}" textLength="{cell_width}" {attrs}>{}</text>"#,
+                y + BASELINE,
+                escape_xml(&cell.contents)
+            );
+        }
+    }
+
+    svg.push_str("</g>\n</svg>\n");
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cell(contents: &str) -> StyledCell {
+        StyledCell {
+            contents: contents.to_string(),
+            ..StyledCell::default()
+        }
+    }
+
+    #[test]
+    fn test_palette() {
+        assert_eq!(rgb(CellColor::Indexed(1), DEFAULT_FG), (0xcd, 0x00, 0x00));
+        assert_eq!(rgb(CellColor::Indexed(16), DEFAULT_FG), (0, 0, 0));
+        assert_eq!(rgb(CellColor::Indexed(231), DEFAULT_FG), (255, 255, 255));
+        assert_eq!(rgb(CellColor::Indexed(255), DEFAULT_FG), (238, 238, 238));
+    }
+
+    #[test]
+    fn test_render_svg_escapes_text() {
+        let svg = render_svg(&[vec![cell("<"), cell("&"), cell(" ")]]);
+        assert!(svg.contains(">&lt;</text>"));
+        assert!(svg.contains(">&amp;</text>"));
+        assert_eq!(svg.matches("<text").count(), 2);
+    }
+
+    #[test]
+    fn test_render_svg_background() {
+        let mut highlighted = cell("x");
+        highlighted.bg = CellColor::Rgb(0x12, 0x34, 0x56);
+        let svg = render_svg(&[vec![highlighted]]);
+        assert!(svg.contains(r##"fill="#123456""##));
+    }
+}
@@ -1,29 +1,395 @@
+use crate::backend::{Backend, StyledCell, TerminalBackend as _};
+use crate::clipboard::AppClipboard;
+use crate::commands::CommandLog;
+use crate::cursor_style::CursorStyle;
+use crate::cwd::WorkingDir;
+use crate::filters::Pipeline;
+use crate::keys::KeyModes;
+use crate::kitty::KeyboardFlags;
+
 const DEFAULT_SCROLLBACK_LINES: usize = 10000;
 const DEFAULT_TERMINAL_ROWS: u16 = 24;
 const DEFAULT_TERMINAL_COLS: u16 = 80;
+/// Rough memory used by one emulator cell, for `max_bytes`.
+const ESTIMATED_CELL_BYTES: usize = 40;
+/// Raw output kept for rendering text since a mark.
+const MAX_MARK_LOG_BYTES: usize = 4 * 1024 * 1024;
+/// Marks kept at once; marking past this drops the oldest.
+const MAX_MARKS: usize = 64;
+/// Upper bound on rows used when rendering raw output as text.
+const MAX_RENDER_ROWS: usize = 10000;
+/// Leaves the alternate screen and restores the main screen's cursor.
+pub const LEAVE_ALT_SCREEN: &[u8] = b"\x1b[?1049l";
+/// Clears a terminal's scrollback, leaving the screen as it is.
+pub const CLEAR_SAVED_LINES: &[u8] = b"\x1b[3J";
+
+/// Plain text of raw output rendered `cols` wide, as it would read on a
+/// screen tall enough that nothing scrolls off.
+pub fn render_text(raw: &[u8], cols: u16) -> String {
+    let line_count = raw.iter().filter(|&&b| b == b'\n').count() + 1;
+    let rows = line_count.clamp(DEFAULT_TERMINAL_ROWS as usize, MAX_RENDER_ROWS) as u16;
+
+    let mut parser = Backend::new(rows, cols.max(1), 0);
+    parser.process(raw);
+    parser.contents().trim_end_matches('\n').to_string()
+}
+
+/// Output with only what draws kept, for replaying to a terminal long after
+/// the app wrote it. Queries the terminal would answer (DSR, DA, DECRQM and
+/// the like) would have their replies typed into whatever runs now, and
+/// titles, clipboard writes, notifications and bells would act again, so
+/// they're dropped along with OSC, DCS and APC strings other than
+/// hyperlinks. A sequence cut off at the end is dropped too.
+fn replayable(raw: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(raw.len());
+    let mut i = 0;
+    while i < raw.len() {
+        match raw[i] {
+            // ENQ asks for the answerback message
+            0x05 | 0x07 => i += 1,
+            0x1b => {
+                let Some(len) = escape_len(&raw[i..]) else {
+                    break;
+                };
+                let sequence = &raw[i..i + len];
+                if draws(sequence) {
+                    out.extend_from_slice(sequence);
+                }
+                i += len;
+            }
+            byte => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    out
+}
 
-/// A scrollback buffer backed by vt100 terminal emulator.
+/// Length of the escape sequence `data` starts with, or `None` if it's cut
+/// off.
+fn escape_len(data: &[u8]) -> Option<usize> {
+    match data.get(1)? {
+        b'[' => data[2..]
+            .iter()
+            .position(|b| (0x40..=0x7e).contains(b))
+            .map(|end| end + 3),
+        b']' | b'P' | b'_' | b'^' | b'X' => {
+            (2..data.len()).find_map(|j| match (data[j], data.get(j + 1)) {
+                (0x07, _) => Some(j + 1),
+                (0x1b, Some(b'\\')) => Some(j + 2),
+                _ => None,
+            })
+        }
+        _ => data[1..]
+            .iter()
+            .position(|b| !(0x20..=0x2f).contains(b))
+            .map(|end| end + 2),
+    }
+}
+
+/// Whether replaying the complete escape `sequence` only affects the screen.
+fn draws(sequence: &[u8]) -> bool {
+    match sequence[1] {
+        b'[' => {
+            let params = &sequence[2..sequence.len() - 1];
+            match sequence[sequence.len() - 1] {
+                // Status and device attribute reports, window reports
+                b'n' | b'c' | b't' => false,
+                // Kitty keyboard flags query
+                b'u' => !params.starts_with(b"?"),
+                // XTVERSION
+                b'q' => !params.starts_with(b">"),
+                // DECRQM
+                b'p' => !params.ends_with(b"$"),
+                _ => true,
+            }
+        }
+        b']' => sequence.starts_with(b"\x1b]8;"),
+        b'P' | b'_' | b'^' | b'X' => false,
+        // DECID and full reset
+        b'Z' | b'c' => false,
+        _ => true,
+    }
+}
+
+/// A scrollback buffer backed by a terminal emulator (vt100 by default).
 pub struct ScrollbackBuffer {
-    parser: Option<vt100::Parser>,
+    parser: Option<Backend>,
+    /// Size for the emulator once it's created, as (rows, cols).
+    size: (u16, u16),
     max_lines: usize,
+    /// Approximate memory cap for history, if any.
+    max_bytes: Option<usize>,
+    /// Raw output since the oldest mark, up to `MAX_MARK_LOG_BYTES` of it
+    /// (empty while there are no marks).
+    mark_log: std::collections::VecDeque<u8>,
+    /// Position of `mark_log`'s first byte in all the output it has logged.
+    mark_log_start: usize,
+    /// Mark labels and their positions in the logged output, oldest first.
+    marks: Vec<(String, usize)>,
+    cursor_style: CursorStyle,
+    keyboard_flags: KeyboardFlags,
+    /// Filters applied to output before it's stored.
+    filters: Pipeline,
+    /// Output of recent commands, split by shell integration marks.
+    commands: CommandLog,
+    /// Most recent raw output, replayed to attaching terminals.
+    tail: Vec<u8>,
+    /// Bytes of `tail` to keep.
+    max_tail: usize,
+    /// Whether `tail` has lost the start of the output.
+    tail_trimmed: bool,
+    cwd: WorkingDir,
+    clipboard: AppClipboard,
 }
 
 impl ScrollbackBuffer {
     pub const fn new() -> Self {
+        Self::with_limits(DEFAULT_SCROLLBACK_LINES, None)
+    }
+
+    /// A buffer keeping at most `max_lines` of history, and fewer if they
+    /// would take more than about `max_bytes`.
+    pub const fn with_limits(max_lines: usize, max_bytes: Option<usize>) -> Self {
         Self {
             parser: None,
-            max_lines: DEFAULT_SCROLLBACK_LINES,
+            size: (DEFAULT_TERMINAL_ROWS, DEFAULT_TERMINAL_COLS),
+            max_lines,
+            max_bytes,
+            mark_log: std::collections::VecDeque::new(),
+            mark_log_start: 0,
+            marks: Vec::new(),
+            cursor_style: CursorStyle::new(),
+            keyboard_flags: KeyboardFlags::new(),
+            filters: Pipeline::new(Vec::new()),
+            commands: CommandLog::new(),
+            tail: Vec::new(),
+            max_tail: 0,
+            tail_trimmed: false,
+            cwd: WorkingDir::new(),
+            clipboard: AppClipboard::new(),
         }
     }
 
-    fn ensure_parser(&mut self) -> &mut vt100::Parser {
-        self.parser.get_or_insert_with(|| {
-            vt100::Parser::new(DEFAULT_TERMINAL_ROWS, DEFAULT_TERMINAL_COLS, self.max_lines)
-        })
+    /// Keep the last `bytes` of output for [`Self::recent_output`].
+    pub fn set_tail_limit(&mut self, bytes: usize) {
+        self.max_tail = bytes;
     }
 
+    /// Keep what the app copies with OSC 52 (`[clipboard] keep`).
+    pub fn set_keep_clipboard(&mut self, keep: bool) {
+        self.clipboard.set_keep(keep);
+    }
+
+    /// Filter output before it's stored.
+    pub fn set_filters(&mut self, filters: Pipeline) {
+        self.filters = filters;
+    }
+
+    /// Screen size as (rows, cols).
+    pub const fn size(&self) -> (u16, u16) {
+        self.size
+    }
+
+    fn ensure_parser(&mut self) -> &mut Backend {
+        let (rows, cols) = self.size;
+        let lines = match self.max_bytes {
+            Some(max_bytes) => {
+                let line_bytes = usize::from(cols.max(1)) * ESTIMATED_CELL_BYTES;
+                self.max_lines.min(max_bytes / line_bytes)
+            }
+            None => self.max_lines,
+        };
+        self.parser
+            .get_or_insert_with(|| Backend::new(rows, cols, lines))
+    }
+
+    /// Match the emulator to the PTY's new size. The emulator can't have an
+    /// empty screen, so a zero dimension is treated as one.
+    pub fn resize(&mut self, rows: u16, cols: u16) {
+        let (rows, cols) = (rows.max(1), cols.max(1));
+        self.size = (rows, cols);
+        if let Some(parser) = &mut self.parser {
+            parser.set_size(rows, cols);
+        }
+    }
+
+    #[cfg_attr(
+        feature = "trace",
+        tracing::instrument(level = "debug", skip_all, fields(bytes = data.len()))
+    )]
     pub fn push(&mut self, data: &[u8]) {
-        self.ensure_parser().process(data);
+        self.cursor_style.process(data);
+        self.keyboard_flags.process(data);
+        self.cwd.process(data);
+        self.clipboard.process(data);
+        let data = self.filters.process(data, self.size.1);
+        let data = &*data;
+        let parser = self.ensure_parser();
+        let (title, bells, alternate) = (
+            parser.title().to_string(),
+            parser.bell_count(),
+            parser.alternate_screen(),
+        );
+        parser.process(data);
+        if parser.bell_count() != bells {
+            crate::events::emit(tap_protocol::Event::Bell);
+        }
+        if parser.title() != title {
+            crate::events::emit(tap_protocol::Event::Title {
+                title: parser.title().to_string(),
+            });
+            crate::SESSION_INFO_CHANGED.notify_one();
+        }
+        if parser.alternate_screen() != alternate {
+            crate::events::emit(tap_protocol::Event::AltScreen {
+                active: parser.alternate_screen(),
+            });
+        }
+        self.commands.process(data);
+
+        if self.max_tail > 0 {
+            self.tail.extend_from_slice(data);
+            // Trim in batches rather than on every chunk
+            if self.tail.len() > self.max_tail * 2 {
+                let excess = self.tail.len() - self.max_tail;
+                self.tail.drain(..excess);
+                self.tail_trimmed = true;
+            }
+        }
+
+        if !self.marks.is_empty() {
+            self.mark_log.extend(data);
+            if let Some(excess) = self.mark_log.len().checked_sub(MAX_MARK_LOG_BYTES) {
+                self.mark_log.drain(..excess);
+                self.mark_log_start += excess;
+            }
+        }
+    }
+
+    /// Replace the emulator with a fresh one, for when binary output or a
+    /// truncated sequence left it out of step with the app. Its history is
+    /// dropped; marks and command output, kept as raw bytes, remain.
+    pub fn resync(&mut self) {
+        self.parser = None;
+        self.ensure_parser();
+    }
+
+    /// Drop the history above the screen, keeping what's on it, along with
+    /// the output kept for replaying to attaching terminals.
+    pub fn clear_history(&mut self) {
+        let Some(parser) = &self.parser else {
+            return;
+        };
+        let screen = parser.state_formatted();
+        self.parser = None;
+        self.ensure_parser().process(&screen);
+        self.tail.clear();
+        self.tail_trimmed = false;
+    }
+
+    /// Up to the configured amount of recent raw output, starting at a line
+    /// boundary so it doesn't open mid-sequence, with only what draws kept
+    /// (see [`replayable`]). Empty on the alternate screen, whose redraws
+    /// aren't history.
+    pub fn recent_output(&self) -> Vec<u8> {
+        if self
+            .parser
+            .as_ref()
+            .is_some_and(|parser| parser.alternate_screen())
+        {
+            return Vec::new();
+        }
+        let tail = &self.tail[self.tail.len().saturating_sub(self.max_tail)..];
+        if !self.tail_trimmed && tail.len() == self.tail.len() {
+            return replayable(tail);
+        }
+        match tail.iter().position(|&b| b == b'\n') {
+            Some(newline) => replayable(&tail[newline + 1..]),
+            None => Vec::new(),
+        }
+    }
+
+    /// Leave the alternate screen as if the app had, bringing back the main
+    /// screen after a TUI dies without restoring it. Returns whether the
+    /// alternate screen was active.
+    pub fn reset_alt(&mut self) -> bool {
+        let Some(parser) = &mut self.parser else {
+            return false;
+        };
+        if !parser.alternate_screen() {
+            return false;
+        }
+        parser.process(LEAVE_ALT_SCREEN);
+        crate::events::emit(tap_protocol::Event::AltScreen { active: false });
+        true
+    }
+
+    /// Insert an invisible bookmark at the current point in the output.
+    /// Marking again with the same label moves the mark.
+    pub fn mark(&mut self, label: &str) {
+        self.marks.retain(|(existing, _)| existing != label);
+        if self.marks.len() >= MAX_MARKS {
+            self.marks.remove(0);
+        }
+        let end = self.mark_log_start + self.mark_log.len();
+        self.marks.push((label.to_string(), end));
+
+        // Output from before the oldest mark left is never read
+        let unmarked = self.marks[0].1.saturating_sub(self.mark_log_start);
+        self.mark_log.drain(..unmarked);
+        self.mark_log_start += unmarked;
+    }
+
+    /// Text output since the given mark, or `None` if there is no such mark.
+    ///
+    /// Marks whose output exceeded the retained log return only the most
+    /// recent part.
+    pub fn get_lines_since_mark(&self, label: &str, count: Option<usize>) -> Option<String> {
+        let &(_, offset) = self.marks.iter().find(|(existing, _)| existing == label)?;
+        let start = offset.saturating_sub(self.mark_log_start);
+        let raw: Vec<u8> = self.mark_log.range(start..).copied().collect();
+
+        let cols = self
+            .parser
+            .as_ref()
+            .map_or(DEFAULT_TERMINAL_COLS, |parser| parser.size().1);
+        let contents = render_text(&raw, cols);
+
+        Some(match count {
+            Some(n) => {
+                let lines: Vec<&str> = contents.lines().collect();
+                let start = lines.len().saturating_sub(n);
+                lines[start..].join("\n")
+            }
+            None => contents,
+        })
+    }
+
+    /// Text and exit code of the `nth` most recently finished command
+    /// (1 for the last), if shell integration marked it.
+    pub fn command_output(&self, nth: usize) -> Option<(String, Option<i32>)> {
+        let command = self.commands.nth_last(nth)?;
+        Some((render_text(&command.output, self.size.1), command.exit_code))
+    }
+
+    /// Window title the app last set (OSC 0/2).
+    pub fn title(&self) -> String {
+        self.parser
+            .as_ref()
+            .map(|parser| parser.title().to_string())
+            .unwrap_or_default()
+    }
+
+    /// Working directory last reported by the shell (OSC 7).
+    pub fn cwd(&self) -> Option<&str> {
+        self.cwd.get()
+    }
+
+    /// Clipboard contents the app last set with OSC 52, if kept.
+    pub fn app_clipboard(&self) -> Option<&[u8]> {
+        self.clipboard.get()
     }
 
     pub fn get_lines(&self, count: Option<usize>) -> String {
@@ -31,10 +397,8 @@ impl ScrollbackBuffer {
             return String::new();
         };
 
-        let screen = parser.screen();
-
-        // Just return current screen contents - vt100 handles alternate screen internally
-        let all_contents = screen.contents();
+        // Just return current screen contents - the backend handles alternate screen internally
+        let all_contents = parser.contents();
 
         match count {
             Some(n) => {
@@ -46,16 +410,132 @@ impl ScrollbackBuffer {
         }
     }
 
+    /// Lines for copy mode, a row each: the whole history followed by the
+    /// screen. Also the (line, column) of the cursor, to start at.
+    pub fn copy_text(&mut self) -> (Vec<String>, (usize, usize)) {
+        let Some(parser) = &mut self.parser else {
+            return (Vec::new(), (0, 0));
+        };
+        let (lines, history) = parser.rows_with_history();
+        let (row, _) = self.cursor_position();
+        (lines, (history + row, self.cursor_char_col()))
+    }
+
     pub fn cursor_position(&self) -> (usize, usize) {
         let Some(parser) = &self.parser else {
             return (0, 0);
         };
 
-        let screen = parser.screen();
-        (
-            screen.cursor_position().0 as usize,
-            screen.cursor_position().1 as usize,
-        )
+        let (row, col) = parser.cursor_position();
+        (row as usize, col as usize)
+    }
+
+    /// Escape sequences that redraw the visible screen, cursor and modes from scratch.
+    pub fn screen_formatted(&self) -> Vec<u8> {
+        let Some(parser) = &self.parser else {
+            return Vec::new();
+        };
+
+        let mut formatted = parser.state_formatted();
+        formatted.extend_from_slice(&self.cursor_style.sequence());
+        formatted
+    }
+
+    /// Cursor shape last selected by the app, as a DECSCUSR parameter (0 for
+    /// the terminal's default).
+    pub fn cursor_style(&self) -> u8 {
+        self.cursor_style.style()
+    }
+
+    /// Cells of the visible screen with their colors and attributes.
+    pub fn styled_rows(&self) -> Vec<Vec<StyledCell>> {
+        self.parser
+            .as_ref()
+            .map(|parser| parser.styled_rows())
+            .unwrap_or_default()
+    }
+
+    /// The visible screen with cell attributes, cursor, mode and title.
+    pub fn screen(&self) -> tap_protocol::Screen {
+        let (rows, cols) = self.size;
+        let Some(parser) = &self.parser else {
+            return tap_protocol::Screen {
+                rows,
+                cols,
+                cells: vec![vec![tap_protocol::ScreenCell::default(); cols.into()]; rows.into()],
+                cursor_row: 0,
+                cursor_col: 0,
+                alternate_screen: false,
+                title: String::new(),
+            };
+        };
+
+        let (rows, cols) = parser.size();
+        let (cursor_row, cursor_col) = parser.cursor_position();
+        tap_protocol::Screen {
+            rows,
+            cols,
+            cells: parser
+                .styled_rows()
+                .into_iter()
+                .map(|row| row.into_iter().map(Into::into).collect())
+                .collect(),
+            cursor_row,
+            cursor_col,
+            alternate_screen: parser.alternate_screen(),
+            title: parser.title().to_string(),
+        }
+    }
+
+    /// Whether the app in the session enabled bracketed paste mode.
+    pub fn bracketed_paste(&self) -> bool {
+        self.parser
+            .as_ref()
+            .is_some_and(|parser| parser.bracketed_paste())
+    }
+
+    /// Modes the app set that change how keys sent to it are encoded.
+    pub(crate) fn key_modes(&self) -> KeyModes {
+        KeyModes {
+            application_cursor: self
+                .parser
+                .as_ref()
+                .is_some_and(|parser| parser.application_cursor()),
+            kitty_flags: self.keyboard_flags.flags(),
+        }
+    }
+
+    /// Cursor column as a character index into its line rather than a cell offset.
+    ///
+    /// Wide characters (CJK, emoji) occupy two cells but one character, so the
+    /// two diverge on lines with non-ASCII content.
+    pub fn cursor_char_col(&self) -> usize {
+        let Some(parser) = &self.parser else {
+            return 0;
+        };
+
+        let (row, col) = parser.cursor_position();
+        parser.char_col_at(row, col)
+    }
+
+    /// Cursor column as a byte offset into its line, for editors that count
+    /// columns in bytes.
+    pub fn cursor_byte_col(&self) -> usize {
+        let Some(parser) = &self.parser else {
+            return 0;
+        };
+
+        let (row, col) = parser.cursor_position();
+        parser.byte_col_at(row, col)
+    }
+
+    /// Convert a cell column on the given screen row to a character index.
+    pub fn char_col_at(&self, row: u16, col: u16) -> usize {
+        let Some(parser) = &self.parser else {
+            return 0;
+        };
+
+        parser.char_col_at(row, col)
     }
 }
 
@@ -88,6 +568,100 @@ mod tests {
         assert!(last_two.contains("line3") || last_two.contains("line4"));
     }
 
+    #[test]
+    fn test_resize_changes_wrap_width() {
+        let line = "a".repeat(100);
+
+        let mut buf = ScrollbackBuffer::new();
+        buf.push(line.as_bytes());
+        assert_eq!(buf.get_lines(None).lines().next().unwrap().len(), 80);
+
+        let mut buf = ScrollbackBuffer::with_limits(100, Some(1024));
+        buf.resize(24, 120);
+        buf.push(line.as_bytes());
+        assert_eq!(buf.get_lines(None).lines().next().unwrap(), line);
+    }
+
+    #[test]
+    fn test_screen_cells() {
+        let mut buf = ScrollbackBuffer::new();
+        buf.resize(4, 10);
+        buf.push(b"\x1b]2;build\x07ok \x1b[1;7;31mFAIL\x1b[0m");
+        let screen = buf.screen();
+        assert_eq!((screen.rows, screen.cols), (4, 10));
+        assert_eq!((screen.cursor_row, screen.cursor_col), (0, 7));
+        assert_eq!(screen.title, "build");
+        assert!(!screen.alternate_screen);
+
+        let plain = &screen.cells[0][0];
+        assert_eq!(plain.text, "o");
+        assert!(!plain.bold && !plain.inverse);
+        let failed = &screen.cells[0][3];
+        assert_eq!(failed.text, "F");
+        assert!(failed.bold && failed.inverse);
+        assert_eq!(failed.fg, tap_protocol::Color::Indexed(1));
+    }
+
+    #[test]
+    fn test_cursor_position_after_resize() {
+        let mut buf = ScrollbackBuffer::new();
+        buf.resize(24, 120);
+        buf.push("a".repeat(100).as_bytes());
+        assert_eq!(buf.cursor_position(), (0, 100));
+    }
+
+    #[tokio::test]
+    async fn test_emits_screen_events() {
+        let mut events = crate::events::Subscription::new(vec![
+            tap_protocol::EventKind::Title,
+            tap_protocol::EventKind::AltScreen,
+        ]);
+        let mut buf = ScrollbackBuffer::new();
+        buf.push(b"\x1b]2;build\x07\x1b[?1049h");
+        buf.push(b"\x1b[?1049l");
+
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            received.push(crate::events::next(Some(&mut events)).await);
+        }
+        assert_eq!(
+            received,
+            [
+                tap_protocol::Event::Title {
+                    title: "build".to_string()
+                },
+                tap_protocol::Event::AltScreen { active: true },
+                tap_protocol::Event::AltScreen { active: false },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_collapse_progress() {
+        let mut buf = ScrollbackBuffer::new();
+        buf.set_filters(Pipeline::new(vec![Box::new(
+            crate::progress::ProgressFilter::new(),
+        )]));
+        for percent in [10, 50, 100] {
+            buf.push(format!("\r{}{percent}%", "=".repeat(150)).as_bytes());
+        }
+        buf.push(b"\r\ndone\r\n");
+
+        let lines = buf.get_lines(None);
+        let lines: Vec<&str> = lines.lines().filter(|line| !line.is_empty()).collect();
+        assert_eq!(lines, [format!("{}100%", "=".repeat(150)).as_str(), "done"]);
+    }
+
+    #[test]
+    fn test_resize_to_zero() {
+        let mut buf = ScrollbackBuffer::new();
+        buf.push(b"hello\r\nworld");
+        buf.resize(0, 0);
+        buf.push(b"more");
+        let _ = buf.get_lines(None);
+        let _ = buf.screen_formatted();
+    }
+
     #[test]
     fn test_cursor_position() {
         let mut buf = ScrollbackBuffer::new();
@@ -97,6 +671,101 @@ mod tests {
         assert_eq!(col, 5);
     }
 
+    #[test]
+    fn test_cursor_char_col_ascii() {
+        let mut buf = ScrollbackBuffer::new();
+        buf.push(b"hello");
+        assert_eq!(buf.cursor_position().1, 5);
+        assert_eq!(buf.cursor_char_col(), 5);
+    }
+
+    #[test]
+    fn test_cursor_char_col_wide_chars() {
+        let mut buf = ScrollbackBuffer::new();
+        buf.push("日本語x".as_bytes());
+        // Three wide characters take six cells
+        assert_eq!(buf.cursor_position().1, 7);
+        assert_eq!(buf.cursor_char_col(), 4);
+        // Each takes three bytes in UTF-8
+        assert_eq!(buf.cursor_byte_col(), 10);
+    }
+
+    #[test]
+    fn test_screen_formatted_round_trip() {
+        let mut buf = ScrollbackBuffer::new();
+        buf.push(b"\x1b[31mred\x1b[0m plain\r\nsecond");
+
+        let mut replay = ScrollbackBuffer::new();
+        replay.push(&buf.screen_formatted());
+        assert_eq!(replay.get_lines(None), buf.get_lines(None));
+        assert_eq!(replay.cursor_position(), buf.cursor_position());
+    }
+
+    #[test]
+    fn test_lines_since_mark() {
+        let mut buf = ScrollbackBuffer::new();
+        buf.push(b"$ before\r\n");
+        buf.mark("cmd");
+        buf.push(b"after 1\r\nafter 2\r\n");
+
+        let since = buf.get_lines_since_mark("cmd", None).unwrap();
+        assert!(since.contains("after 1"));
+        assert!(since.contains("after 2"));
+        assert!(!since.contains("before"));
+        assert_eq!(buf.get_lines_since_mark("cmd", Some(1)).unwrap(), "after 2");
+    }
+
+    #[test]
+    fn test_lines_since_mark_survives_scrolling() {
+        let mut buf = ScrollbackBuffer::new();
+        buf.mark("start");
+        for i in 1..=100 {
+            buf.push(format!("line {i}\r\n").as_bytes());
+        }
+
+        let since = buf.get_lines_since_mark("start", None).unwrap();
+        assert!(since.contains("line 1\n"));
+        assert!(since.contains("line 100"));
+    }
+
+    #[test]
+    fn test_unknown_mark() {
+        let buf = ScrollbackBuffer::new();
+        assert!(buf.get_lines_since_mark("missing", None).is_none());
+    }
+
+    #[test]
+    fn test_remark_moves_mark() {
+        let mut buf = ScrollbackBuffer::new();
+        buf.mark("m");
+        buf.push(b"first\r\n");
+        buf.mark("m");
+        buf.push(b"second\r\n");
+
+        let since = buf.get_lines_since_mark("m", None).unwrap();
+        assert!(!since.contains("first"));
+        assert!(since.contains("second"));
+    }
+
+    #[test]
+    fn test_oldest_mark_dropped() {
+        let mut buf = ScrollbackBuffer::new();
+        buf.mark("first");
+        buf.push(b"early\r\n");
+        for i in 1..MAX_MARKS {
+            buf.mark(&format!("m{i}"));
+        }
+        buf.push(b"late\r\n");
+        assert!(buf.get_lines_since_mark("first", None).is_some());
+
+        buf.mark("last");
+        assert!(buf.get_lines_since_mark("first", None).is_none());
+        // Nothing is left marking the earlier output
+        let since = buf.get_lines_since_mark("m1", None).unwrap();
+        assert!(!since.contains("early"));
+        assert_eq!(buf.mark_log, b"late\r\n");
+    }
+
     #[test]
     fn test_strips_ansi_escapes() {
         let mut buf = ScrollbackBuffer::new();
@@ -143,6 +812,81 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_recent_output() {
+        let mut buf = ScrollbackBuffer::new();
+        buf.set_tail_limit(64);
+        buf.push(b"one\r\ntwo");
+        assert_eq!(buf.recent_output(), b"one\r\ntwo".as_slice());
+
+        // Cut back to a line boundary once older output is dropped
+        let mut buf = ScrollbackBuffer::new();
+        buf.set_tail_limit(16);
+        buf.push(b"first line\r\n\x1b[1msecond\x1b[0m\r\nthird");
+        assert_eq!(buf.recent_output(), b"third".as_slice());
+
+        buf.push(b"\r\n\x1b[?1049hvim");
+        assert!(buf.recent_output().is_empty());
+    }
+
+    #[test]
+    fn test_copy_text_includes_history() {
+        let mut buf = ScrollbackBuffer::new();
+        buf.resize(5, 20);
+        for i in 0..30 {
+            buf.push(format!("line {i}\r\n").as_bytes());
+        }
+        buf.push(b"$ ");
+        let (lines, cursor) = buf.copy_text();
+        assert_eq!(lines[0], "line 0");
+        assert_eq!(lines[29], "line 29");
+        assert_eq!(cursor, (30, 2));
+        // Reading the history leaves the screen where it was
+        assert!(buf.get_lines(None).starts_with("line 26"));
+    }
+
+    #[test]
+    fn test_recent_output_only_draws() {
+        let mut buf = ScrollbackBuffer::new();
+        buf.set_tail_limit(1024);
+        buf.push(
+            b"\x1b[6n\x1b[c\x1b]11;?\x07\x1b]0;title\x1b\\\x1b]52;c;aGk=\x07\x07\
+              \x1b[1mbold\x1b[0m \x1b]8;;https://example.com\x1b\\link\x1b]8;;\x1b\\\x1b]2;cut",
+        );
+        assert_eq!(
+            buf.recent_output(),
+            b"\x1b[1mbold\x1b[0m \x1b]8;;https://example.com\x1b\\link\x1b]8;;\x1b\\".as_slice()
+        );
+    }
+
+    #[test]
+    fn test_reset_alt() {
+        let mut buf = ScrollbackBuffer::new();
+        buf.push(b"$ make\r\nok\r\n$ htop\r\n\x1b[?1049h\x1b[2Jhtop screen");
+        assert!(buf.reset_alt());
+        let content = buf.get_lines(None);
+        assert!(content.contains("$ make"));
+        assert!(!content.contains("htop screen"));
+        assert!(!buf.screen().alternate_screen);
+        assert!(!buf.reset_alt());
+    }
+
+    #[test]
+    fn test_resync() {
+        let mut buf = ScrollbackBuffer::new();
+        buf.resize(10, 40);
+        buf.push(b"\x1b[?1049h\x1b[5;3Hgarbage\x1b[1;7");
+        buf.resync();
+        assert_eq!(buf.get_lines(None), "");
+        assert_eq!(buf.cursor_position(), (0, 0));
+        assert_eq!(buf.size(), (10, 40));
+        buf.push(b"redrawn");
+        let screen = buf.screen();
+        assert!(!screen.alternate_screen);
+        assert_eq!(screen.rows, 10);
+        assert_eq!(buf.get_lines(None), "redrawn");
+    }
+
     /// Simulates vim workflow: enter alternate, show file, exit, restore main
     #[test]
     fn test_vim_like_workflow() {
@@ -0,0 +1,97 @@
+//! Sequence numbers for the output stream subscribers see: the count of
+//! bytes broadcast so far. `Inject` answers with the current number, and a
+//! client that subscribes from it gets everything printed since, so it can't
+//! miss the response to its own input.
+
+/// Recent broadcast output kept for subscribing from an earlier sequence.
+const MAX_REPLAY_BYTES: usize = 1024 * 1024;
+
+static LOG: parking_lot::Mutex<OutputLog> = parking_lot::Mutex::new(OutputLog::new());
+
+/// The most recent broadcast output and where it ends.
+struct OutputLog {
+    recent: Vec<u8>,
+    /// Sequence number just past the last byte of `recent`.
+    end: u64,
+}
+
+impl OutputLog {
+    const fn new() -> Self {
+        Self {
+            recent: Vec::new(),
+            end: 0,
+        }
+    }
+
+    fn push(&mut self, data: &[u8]) {
+        self.recent.extend_from_slice(data);
+        self.end += data.len() as u64;
+        if self.recent.len() > MAX_REPLAY_BYTES {
+            let excess = self.recent.len() - MAX_REPLAY_BYTES / 2;
+            self.recent.drain(..excess);
+        }
+    }
+
+    /// Output from `seq` on, or an error if it's no longer kept.
+    fn since(&self, seq: u64) -> Result<&[u8], String> {
+        let start = self.end - self.recent.len() as u64;
+        if seq < start {
+            return Err(format!(
+                "output from sequence {seq} is no longer available (oldest is {start})"
+            ));
+        }
+        let skip = usize::try_from(seq.min(self.end) - start).unwrap_or(usize::MAX);
+        Ok(&self.recent[skip..])
+    }
+}
+
+/// Broadcast output to subscribers, numbering it.
+pub(crate) fn broadcast(output_tx: &tokio::sync::broadcast::Sender<Vec<u8>>, data: Vec<u8>) {
+    // Held while sending so `subscribe_from` sees each chunk exactly once
+    let mut log = LOG.lock();
+    log.push(&data);
+    let _ = output_tx.send(data);
+}
+
+/// Sequence number of the next output to be broadcast.
+pub(crate) fn current() -> u64 {
+    LOG.lock().end
+}
+
+/// A fresh receiver for live output and the sequence number it starts at.
+pub(crate) fn subscribe(
+    output_rx: &tokio::sync::broadcast::Receiver<Vec<u8>>,
+) -> (tokio::sync::broadcast::Receiver<Vec<u8>>, u64) {
+    let log = LOG.lock();
+    (output_rx.resubscribe(), log.end)
+}
+
+/// A fresh receiver for live output along with everything broadcast from
+/// `seq` up to where it starts.
+pub(crate) fn subscribe_from(
+    output_rx: &tokio::sync::broadcast::Receiver<Vec<u8>>,
+    seq: u64,
+) -> Result<(tokio::sync::broadcast::Receiver<Vec<u8>>, Vec<u8>), String> {
+    let log = LOG.lock();
+    let replay = log.since(seq)?.to_vec();
+    Ok((output_rx.resubscribe(), replay))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_since() {
+        let mut log = OutputLog::new();
+        log.push(b"hello ");
+        log.push(b"world");
+        assert_eq!(log.since(6).unwrap(), b"world");
+        assert_eq!(log.since(0).unwrap(), b"hello world");
+        assert_eq!(log.since(99).unwrap(), b"");
+
+        log.push(&vec![b'x'; MAX_REPLAY_BYTES]);
+        assert!(log.since(0).is_err());
+        assert_eq!(log.since(log.end - 1).unwrap(), b"x");
+    }
+}
@@ -0,0 +1,149 @@
+//! Appending session output to a log file with size-based rotation.
+//!
+//! Unlike scrollback, the log keeps everything, so a detached session's
+//! output can still be read after it scrolled out of the emulator's history.
+//! Once the file reaches its size limit it's renamed to `<file>.1` (older
+//! logs shift up to `<file>.<keep>`, and beyond that are deleted) and a new
+//! file is started.
+
+use std::io::Write as _;
+
+use crate::filters::OutputFilter as _;
+
+pub struct SessionLog {
+    path: std::path::PathBuf,
+    out: std::fs::File,
+    /// Bytes in the current file.
+    written: u64,
+    max_bytes: u64,
+    keep: usize,
+    strip: Option<crate::filters::StripAnsi>,
+    timestamps: Option<crate::filters::Timestamp>,
+}
+
+static LOG: parking_lot::Mutex<Option<SessionLog>> = parking_lot::Mutex::new(None);
+
+impl SessionLog {
+    /// Open `path` for appending.
+    pub fn open(
+        path: &std::path::Path,
+        config: &tap_config::LoggingConfig,
+    ) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let out = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        let written = out.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            out,
+            written,
+            max_bytes: config.max_bytes.max(1),
+            keep: config.keep,
+            strip: config.strip_ansi.then(crate::filters::StripAnsi::default),
+            timestamps: config.timestamps.then(crate::filters::Timestamp::new),
+        })
+    }
+
+    /// Append a chunk of output, rotating first if it would overflow the file.
+    pub fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        let mut data = std::borrow::Cow::Borrowed(data);
+        if let Some(strip) = &mut self.strip {
+            data = std::borrow::Cow::Owned(strip.process(&data, 0));
+        }
+        if let Some(timestamps) = &mut self.timestamps {
+            data = std::borrow::Cow::Owned(timestamps.process(&data, 0));
+        }
+        if data.is_empty() {
+            return Ok(());
+        }
+        if self.written > 0 && self.written + data.len() as u64 > self.max_bytes {
+            self.rotate()?;
+        }
+        self.out.write_all(&data)?;
+        self.written += data.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        let rotated = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{n}"));
+            std::path::PathBuf::from(name)
+        };
+        if self.keep == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            let _ = std::fs::remove_file(rotated(self.keep));
+            for n in (1..self.keep).rev() {
+                let _ = std::fs::rename(rotated(n), rotated(n + 1));
+            }
+            std::fs::rename(&self.path, rotated(1))?;
+        }
+        self.out = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+/// Start logging the session's output to `path`, or to `[logging] file`
+/// if no path is given.
+pub fn init(
+    path: Option<&std::path::Path>,
+    config: &tap_config::LoggingConfig,
+    session_id: &str,
+) -> eyre::Result<()> {
+    let path = match (path, &config.file) {
+        (Some(path), _) => path.to_path_buf(),
+        (None, Some(file)) => std::path::PathBuf::from(file.replace("{session}", session_id)),
+        (None, None) => return Ok(()),
+    };
+    let log = SessionLog::open(&path, config)
+        .map_err(|e| eyre::eyre!("failed to open log file {}: {e}", path.display()))?;
+    *LOG.lock() = Some(log);
+    Ok(())
+}
+
+/// Append output to the session log, if there is one.
+pub fn record_output(data: &[u8]) {
+    let mut log = LOG.lock();
+    if let Some(session_log) = log.as_mut()
+        && let Err(e) = session_log.write(data)
+    {
+        tracing::warn!("failed to write session log, stopping: {e}");
+        *log = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotates_by_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.log");
+        let config = tap_config::LoggingConfig {
+            strip_ansi: true,
+            max_bytes: 8,
+            keep: 2,
+            ..Default::default()
+        };
+        let mut log = SessionLog::open(&path, &config).unwrap();
+        for line in ["one\r\n", "\x1b[1mtwo\x1b[0m\r\n", "three\r\n", "four\r\n"] {
+            log.write(line.as_bytes()).unwrap();
+        }
+
+        let read = |path: &std::path::Path| std::fs::read_to_string(path).unwrap();
+        assert_eq!(read(&path), "four\n");
+        assert_eq!(read(&dir.path().join("session.log.1")), "three\n");
+        assert_eq!(read(&dir.path().join("session.log.2")), "one\ntwo\n");
+        assert!(!dir.path().join("session.log.3").exists());
+    }
+}
@@ -0,0 +1,76 @@
+//! Tap's own status lines (`[tap: …]` banners, `[detached]`), kept out of
+//! pipelines and free of escape codes when `NO_COLOR` is set.
+
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set by `tap --raw`: no status lines and no escape codes at all.
+static RAW: AtomicBool = AtomicBool::new(false);
+
+/// Guarantee pristine output for scripting.
+pub fn set_raw(raw: bool) {
+    RAW.store(raw, Ordering::Relaxed);
+}
+
+/// Whether `--raw` is in effect.
+pub fn raw() -> bool {
+    RAW.load(Ordering::Relaxed)
+}
+
+/// Whether `NO_COLOR` is set to a non-empty value (see no-color.org).
+pub fn no_color() -> bool {
+    std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty())
+}
+
+/// Whether to use color in output going to `stream`.
+pub fn color(stream: &impl IsTerminal) -> bool {
+    !raw() && !no_color() && stream.is_terminal()
+}
+
+/// A status line as it should appear on `stream`: dimmed on a terminal,
+/// plain under `NO_COLOR`, and `None` when piped or under `--raw`.
+pub fn styled(text: &str, stream: &impl IsTerminal) -> Option<String> {
+    style(text, !raw() && stream.is_terminal(), no_color())
+}
+
+fn style(text: &str, show: bool, no_color: bool) -> Option<String> {
+    if !show {
+        None
+    } else if no_color {
+        Some(text.to_string())
+    } else {
+        Some(format!("\x1b[2m{text}\x1b[0m"))
+    }
+}
+
+/// Print a status line to stdout, if it belongs there (see [`styled`]).
+pub fn print(text: &str) {
+    if let Some(line) = styled(text, &std::io::stdout()) {
+        println!("{line}");
+    }
+}
+
+/// Print a status line to stderr, if it belongs there (see [`styled`]).
+pub fn eprint(text: &str) {
+    if let Some(line) = styled(text, &std::io::stderr()) {
+        eprintln!("{line}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_style() {
+        assert_eq!(style("[detached]", false, false), None);
+        assert_eq!(
+            style("[detached]", true, false).as_deref(),
+            Some("\x1b[2m[detached]\x1b[0m")
+        );
+        assert_eq!(
+            style("[detached]", true, true).as_deref(),
+            Some("[detached]")
+        );
+    }
+}
@@ -0,0 +1,125 @@
+//! Forwarding PTY output to the outer terminal.
+//!
+//! Writes happen on their own task so a slow terminal doesn't stall the PTY
+//! loop. With a backlog limit configured, output beyond the limit is dropped
+//! and the latest screen is redrawn once the terminal has caught up, trading
+//! intermediate frames for bounded latency.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tokio::io::AsyncWriteExt as _;
+
+const RETRY_DELAY: std::time::Duration = std::time::Duration::from_millis(5);
+
+pub struct StdoutForwarder {
+    tx: tokio::sync::mpsc::UnboundedSender<Vec<u8>>,
+    task: tokio::task::JoinHandle<()>,
+    /// Bytes queued but not yet written.
+    backlog: Arc<AtomicUsize>,
+    max_backlog: Option<usize>,
+    /// Output is being dropped until the terminal catches up.
+    resyncing: bool,
+}
+
+impl StdoutForwarder {
+    /// Spawn the writer task. `max_backlog` of `None` never drops output.
+    pub fn spawn(max_backlog: Option<usize>) -> Self {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let backlog = Arc::new(AtomicUsize::new(0));
+        let task = tokio::spawn(write_loop(rx, backlog.clone()));
+        Self {
+            tx,
+            task,
+            backlog,
+            max_backlog,
+            resyncing: false,
+        }
+    }
+
+    /// Queue output for the terminal.
+    /// Returns false if the terminal can no longer be written to.
+    pub fn send(&mut self, data: Vec<u8>) -> bool {
+        if self.resyncing {
+            return !self.tx.is_closed();
+        }
+        if let Some(max_backlog) = self.max_backlog
+            && self.backlog.load(Ordering::Relaxed) + data.len() > max_backlog
+        {
+            tracing::debug!("terminal too slow, dropping output until it catches up");
+            self.resyncing = true;
+            return !self.tx.is_closed();
+        }
+        self.enqueue(data)
+    }
+
+    #[must_use]
+    pub fn is_resyncing(&self) -> bool {
+        self.resyncing
+    }
+
+    /// Once the terminal has drained its backlog, queue a redraw of the
+    /// current screen and resume forwarding.
+    pub fn try_resync(&mut self, redraw: impl FnOnce() -> Vec<u8>) -> bool {
+        if !self.resyncing || self.backlog.load(Ordering::Relaxed) > 0 {
+            return !self.tx.is_closed();
+        }
+        tracing::debug!("terminal caught up, redrawing screen");
+        self.resyncing = false;
+        self.enqueue(redraw())
+    }
+
+    /// Write out everything still queued.
+    pub async fn finish(self) {
+        drop(self.tx);
+        let _ = self.task.await;
+    }
+
+    fn enqueue(&mut self, data: Vec<u8>) -> bool {
+        self.backlog.fetch_add(data.len(), Ordering::Relaxed);
+        self.tx.send(data).is_ok()
+    }
+}
+
+async fn write_loop(
+    mut rx: tokio::sync::mpsc::UnboundedReceiver<Vec<u8>>,
+    backlog: Arc<AtomicUsize>,
+) {
+    let mut stdout = tokio::io::stdout();
+    while let Some(data) = rx.recv().await {
+        if let Err(e) = write_all_retrying(&mut stdout, &data).await {
+            tracing::debug!("stdout write error: {e}");
+            break;
+        }
+        backlog.fetch_sub(data.len(), Ordering::Relaxed);
+    }
+}
+
+/// Like `write_all`, but retries `EINTR` and `EAGAIN` instead of failing.
+async fn write_all_retrying(
+    stdout: &mut tokio::io::Stdout,
+    mut data: &[u8],
+) -> std::io::Result<()> {
+    while !data.is_empty() {
+        match stdout.write(data).await {
+            Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+            Ok(n) => data = &data[n..],
+            Err(e) if is_retryable(&e) => tokio::time::sleep(RETRY_DELAY).await,
+            Err(e) => return Err(e),
+        }
+    }
+    loop {
+        match stdout.flush().await {
+            Ok(()) => return Ok(()),
+            Err(e) if is_retryable(&e) => tokio::time::sleep(RETRY_DELAY).await,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn is_retryable(e: &std::io::Error) -> bool {
+    matches!(
+        e.kind(),
+        std::io::ErrorKind::Interrupted | std::io::ErrorKind::WouldBlock
+    )
+}
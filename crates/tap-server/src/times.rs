@@ -0,0 +1,68 @@
+//! CPU and wall-clock time used by the session's command, for `tap time`.
+
+/// When the command started.
+static STARTED: std::sync::OnceLock<chrono::DateTime<chrono::Utc>> = std::sync::OnceLock::new();
+
+/// Times recorded when the command exited.
+static FINAL: std::sync::OnceLock<tap_protocol::SessionTimes> = std::sync::OnceLock::new();
+
+/// Start the wall clock at `started`.
+pub(crate) fn start(started: chrono::DateTime<chrono::Utc>) {
+    let _ = STARTED.set(started);
+}
+
+/// Record the final times from the exited command's resource usage.
+pub(crate) fn finish(usage: &nix::libc::rusage) {
+    let _ = FINAL.set(tap_protocol::SessionTimes {
+        wall_ms: wall_ms(),
+        user_ms: timeval_ms(&usage.ru_utime),
+        sys_ms: timeval_ms(&usage.ru_stime),
+        exited: true,
+    });
+}
+
+/// Times so far. While the command runs, CPU time covers the child and
+/// the commands it has finished waiting for (e.g. a shell's completed jobs).
+pub(crate) fn current() -> tap_protocol::SessionTimes {
+    if let Some(&times) = FINAL.get() {
+        return times;
+    }
+    let (user, sys) = crate::CHILD_PID
+        .get()
+        .and_then(|&pid| crate::procenv::read_process_cpu(pid).ok())
+        .unwrap_or_default();
+    tap_protocol::SessionTimes {
+        wall_ms: wall_ms(),
+        user_ms: duration_ms(user),
+        sys_ms: duration_ms(sys),
+        exited: false,
+    }
+}
+
+fn wall_ms() -> u64 {
+    STARTED.get().map_or(0, |started| {
+        u64::try_from((chrono::Utc::now() - *started).num_milliseconds()).unwrap_or(0)
+    })
+}
+
+fn timeval_ms(tv: &nix::libc::timeval) -> u64 {
+    u64::try_from(tv.tv_sec).unwrap_or(0) * 1000 + u64::try_from(tv.tv_usec).unwrap_or(0) / 1000
+}
+
+fn duration_ms(duration: std::time::Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timeval_ms() {
+        let tv = nix::libc::timeval {
+            tv_sec: 3,
+            tv_usec: 250_999,
+        };
+        assert_eq!(timeval_ms(&tv), 3250);
+    }
+}
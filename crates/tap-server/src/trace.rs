@@ -0,0 +1,50 @@
+//! Counters behind pipeline tracing (the `trace` feature). Every event gets
+//! a sequence number shared across stages and each stage keeps a running
+//! byte total, so `tap --debug` logs show the order output, keystrokes and
+//! requests were handled in, and where bytes went missing.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// A pipeline stage's running byte count.
+pub(crate) struct Stage(AtomicU64);
+
+impl Stage {
+    const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    /// Count `bytes` more, returning the new total.
+    pub(crate) fn add(&self, bytes: usize) -> u64 {
+        let bytes = bytes as u64;
+        self.0.fetch_add(bytes, Ordering::Relaxed) + bytes
+    }
+}
+
+/// Output read from the PTY.
+pub(crate) static PTY_READ: Stage = Stage::new();
+/// Keystrokes read from the local terminal.
+pub(crate) static STDIN: Stage = Stage::new();
+/// Requests read from socket clients.
+pub(crate) static REQUEST: Stage = Stage::new();
+/// Input written to the PTY.
+pub(crate) static PTY_WRITE: Stage = Stage::new();
+
+/// The next event's sequence number.
+pub(crate) fn next_seq() -> u64 {
+    SEQ.fetch_add(1, Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stage_totals() {
+        let stage = Stage::new();
+        assert_eq!(stage.add(3), 3);
+        assert_eq!(stage.add(4), 7);
+        assert!(next_seq() < next_seq());
+    }
+}
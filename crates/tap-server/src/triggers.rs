@@ -0,0 +1,285 @@
+//! Running commands when output matches a pattern.
+//!
+//! Output is matched a line at a time, as the line reads on screen: colors,
+//! cursor movement and carriage-return redraws are rendered away first. The
+//! line still being written is matched too, so prompts like `Password:` fire
+//! without waiting for a newline. Each trigger fires at most once per line,
+//! and output matching over and over can't pile up commands: a trigger that
+//! just ran doesn't run again for a moment, and only so many run at once.
+
+/// Longest line kept for matching; the start of longer lines is dropped.
+const MAX_LINE_BYTES: usize = 4096;
+/// Width lines are rendered at, wide enough that they don't wrap.
+const RENDER_COLS: u16 = 512;
+/// How soon a trigger can run again after it last ran.
+const DEBOUNCE: std::time::Duration = std::time::Duration::from_secs(1);
+/// Trigger commands running at once; matches beyond this are dropped.
+const MAX_RUNNING: usize = 8;
+
+/// A fired trigger's index, its command and the environment to run it with.
+type Fired = (usize, String, Vec<(String, String)>);
+
+struct Trigger {
+    regex: regex::Regex,
+    command: String,
+}
+
+/// Start running `[triggers]` commands on matching output.
+pub fn init(
+    triggers: &std::collections::BTreeMap<String, String>,
+    output_rx: tokio::sync::broadcast::Receiver<Vec<u8>>,
+) -> eyre::Result<()> {
+    if triggers.is_empty() {
+        return Ok(());
+    }
+    let triggers = triggers
+        .iter()
+        .map(|(pattern, command)| {
+            let regex = regex::Regex::new(pattern)
+                .map_err(|e| eyre::eyre!("invalid trigger pattern '{pattern}': {e}"))?;
+            Ok(Trigger {
+                regex,
+                command: command.clone(),
+            })
+        })
+        .collect::<eyre::Result<Vec<_>>>()?;
+    tokio::spawn(watch(LineMatcher::new(triggers), output_rx));
+    Ok(())
+}
+
+async fn watch(mut matcher: LineMatcher, mut output_rx: tokio::sync::broadcast::Receiver<Vec<u8>>) {
+    let mut limiter = Limiter::new(MAX_RUNNING);
+    loop {
+        match output_rx.recv().await {
+            Ok(data) => {
+                for (trigger, command, env) in matcher.push(&data) {
+                    if let Some(permit) = limiter.permit(trigger, &command) {
+                        run(&command, env, permit);
+                    }
+                }
+            }
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::debug!("trigger matcher lagged, skipped {skipped} chunks");
+            }
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Decides which fired triggers actually run.
+struct Limiter {
+    /// When each trigger, by index, last ran.
+    last_run: std::collections::HashMap<usize, std::time::Instant>,
+    running: std::sync::Arc<tokio::sync::Semaphore>,
+}
+
+impl Limiter {
+    fn new(max_running: usize) -> Self {
+        Self {
+            last_run: std::collections::HashMap::new(),
+            running: std::sync::Arc::new(tokio::sync::Semaphore::new(max_running)),
+        }
+    }
+
+    /// A slot to run `trigger`'s command in, held until it exits, unless it
+    /// ran too recently or too many commands are running.
+    fn permit(
+        &mut self,
+        trigger: usize,
+        command: &str,
+    ) -> Option<tokio::sync::OwnedSemaphorePermit> {
+        let now = std::time::Instant::now();
+        if self
+            .last_run
+            .get(&trigger)
+            .is_some_and(|&last| now.duration_since(last) < DEBOUNCE)
+        {
+            tracing::debug!("trigger '{command}' ran less than {DEBOUNCE:?} ago, skipping");
+            return None;
+        }
+        let Ok(permit) = self.running.clone().try_acquire_owned() else {
+            tracing::warn!("too many trigger commands running, skipping '{command}'");
+            return None;
+        };
+        self.last_run.insert(trigger, now);
+        Some(permit)
+    }
+}
+
+/// Run a trigger's command with its match in the environment, holding
+/// `permit` until it exits.
+fn run(command: &str, env: Vec<(String, String)>, permit: tokio::sync::OwnedSemaphorePermit) {
+    tracing::debug!("running trigger: {command}");
+    let spawned = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        // Read now, as `tap rename` may have renamed the session since
+        .env("TAP_SESSION", crate::SESSION_ID.read().as_str())
+        .envs(env)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn();
+    match spawned {
+        Ok(mut child) => {
+            tokio::spawn(async move {
+                let _ = child.wait().await;
+                drop(permit);
+            });
+        }
+        Err(e) => tracing::warn!("failed to run trigger '{command}': {e}"),
+    }
+}
+
+/// Splits output into lines and matches triggers against them.
+struct LineMatcher {
+    triggers: Vec<Trigger>,
+    /// Raw output of the current line.
+    line: Vec<u8>,
+    /// Which triggers already fired on the current line.
+    fired: Vec<bool>,
+}
+
+impl LineMatcher {
+    fn new(triggers: Vec<Trigger>) -> Self {
+        let fired = vec![false; triggers.len()];
+        Self {
+            triggers,
+            line: Vec::new(),
+            fired,
+        }
+    }
+
+    /// Handle a chunk of output, returning the command and environment of
+    /// each trigger that fires.
+    fn push(&mut self, data: &[u8]) -> Vec<Fired> {
+        let mut fired = Vec::new();
+        for piece in data.split_inclusive(|&b| b == b'\n') {
+            self.line.extend_from_slice(piece);
+            if self.line.len() > MAX_LINE_BYTES {
+                let excess = self.line.len() - MAX_LINE_BYTES;
+                self.line.drain(..excess);
+            }
+            if piece.ends_with(b"\n") {
+                self.check(&mut fired);
+                self.line.clear();
+                self.fired.fill(false);
+            }
+        }
+        // The line still being written, e.g. a prompt
+        if !self.line.is_empty() {
+            self.check(&mut fired);
+        }
+        fired
+    }
+
+    fn check(&mut self, fired: &mut Vec<Fired>) {
+        if self.fired.iter().all(|&f| f) {
+            return;
+        }
+        let text = line_text(&self.line);
+        for (index, (trigger, already)) in self.triggers.iter().zip(&mut self.fired).enumerate() {
+            if *already {
+                continue;
+            }
+            if let Some(captures) = trigger.regex.captures(&text) {
+                *already = true;
+                fired.push((
+                    index,
+                    trigger.command.clone(),
+                    match_env(&trigger.regex, &captures),
+                ));
+            }
+        }
+    }
+}
+
+/// A raw line as it reads on screen.
+fn line_text(raw: &[u8]) -> String {
+    let raw = raw
+        .strip_suffix(b"\r\n")
+        .or_else(|| raw.strip_suffix(b"\n"))
+        .unwrap_or(raw);
+    if raw.iter().any(|&b| matches!(b, 0x1b | b'\r' | 0x08)) {
+        crate::scrollback::render_text(raw, RENDER_COLS)
+    } else {
+        String::from_utf8_lossy(raw).into_owned()
+    }
+}
+
+/// Environment for a trigger's command: `TAP_MATCH` for the whole match,
+/// `TAP_MATCH_<N>` for numbered groups and `TAP_MATCH_<NAME>` for named ones.
+fn match_env(regex: &regex::Regex, captures: &regex::Captures<'_>) -> Vec<(String, String)> {
+    let mut env = Vec::new();
+    for (i, name) in regex.capture_names().enumerate() {
+        let Some(group) = captures.get(i) else {
+            continue;
+        };
+        let value = group.as_str().to_string();
+        if i == 0 {
+            env.push(("TAP_MATCH".to_string(), value));
+            continue;
+        }
+        env.push((format!("TAP_MATCH_{i}"), value.clone()));
+        if let Some(name) = name {
+            env.push((format!("TAP_MATCH_{}", name.to_ascii_uppercase()), value));
+        }
+    }
+    env
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matcher(pattern: &str) -> LineMatcher {
+        LineMatcher::new(vec![Trigger {
+            regex: regex::Regex::new(pattern).unwrap(),
+            command: "true".to_string(),
+        }])
+    }
+
+    #[test]
+    fn test_prompt_fires_once_per_line() {
+        let mut matcher = matcher(r"^\[sudo\] password for (?<user>\w+):");
+        assert!(matcher.push(b"$ sudo ls\r\n[sudo] pass").is_empty());
+
+        let fired = matcher.push(b"word for ada: ");
+        assert_eq!(fired.len(), 1);
+        let env = &fired[0].2;
+        assert!(env.contains(&("TAP_MATCH_1".to_string(), "ada".to_string())));
+        assert!(env.contains(&("TAP_MATCH_USER".to_string(), "ada".to_string())));
+
+        // Still the same line
+        assert!(matcher.push(b"\x08 ").is_empty());
+        assert!(matcher.push(b"\r\n").is_empty());
+        assert_eq!(matcher.push(b"[sudo] password for ada: ").len(), 1);
+    }
+
+    #[test]
+    fn test_matches_rendered_text() {
+        let mut matcher = matcher(r"^error: build failed$");
+        let fired = matcher.push(b"\x1b[31merror\x1b[0m: build \x1b[1mfailed\x1b[0m\r\n");
+        assert_eq!(fired.len(), 1);
+        assert_eq!(
+            matcher.push(b" 50%\r100%\r\nerror: build failed\n").len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_limiter() {
+        let mut limiter = Limiter::new(2);
+        let first = limiter.permit(0, "true");
+        assert!(first.is_some());
+        // Too soon for the same trigger
+        assert!(limiter.permit(0, "true").is_none());
+
+        let second = limiter.permit(1, "true");
+        assert!(second.is_some());
+        // Both slots are taken
+        assert!(limiter.permit(2, "true").is_none());
+        drop(first);
+        assert!(limiter.permit(2, "true").is_some());
+    }
+}
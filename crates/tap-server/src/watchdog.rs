@@ -0,0 +1,154 @@
+//! Noticing children that have wedged while someone waits on them.
+//!
+//! While a client blocks in `Request::Wait` with a stall timeout, a child
+//! that produces no output for that long is nudged with SIGWINCH, which makes
+//! most interactive programs redraw. If that doesn't get any output out of it
+//! either, the waiter is told the session stalled and the configured hook runs.
+
+/// How long a nudged child gets to produce output before it counts as stalled.
+const PROBE_GRACE: std::time::Duration = std::time::Duration::from_secs(2);
+
+struct Watchdog {
+    stall_after: Option<std::time::Duration>,
+    hook: Option<String>,
+}
+
+static WATCHDOG: std::sync::OnceLock<Watchdog> = std::sync::OnceLock::new();
+/// Reference point for `LAST_OUTPUT_MS`.
+static START: std::sync::LazyLock<std::time::Instant> =
+    std::sync::LazyLock::new(std::time::Instant::now);
+/// Milliseconds after `START` that the child last produced output.
+static LAST_OUTPUT_MS: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+/// Configure the default stall timeout and the hook run on stalls.
+pub fn init(config: &tap_config::WatchdogConfig) {
+    std::sync::LazyLock::force(&START);
+    let _ = WATCHDOG.set(Watchdog {
+        stall_after: config.stall_secs.map(std::time::Duration::from_secs),
+        hook: config.hook.clone(),
+    });
+}
+
+/// Note that the child just produced output.
+pub fn record_output() {
+    LAST_OUTPUT_MS.store(now_ms(), std::sync::atomic::Ordering::Relaxed);
+}
+
+fn now_ms() -> u64 {
+    u64::try_from(START.elapsed().as_millis()).unwrap_or(u64::MAX)
+}
+
+fn idle() -> std::time::Duration {
+    let last = LAST_OUTPUT_MS.load(std::sync::atomic::Ordering::Relaxed);
+    std::time::Duration::from_millis(now_ms().saturating_sub(last))
+}
+
+/// The stall timeout for a waiter: its own, else the configured one.
+pub fn stall_after(requested: Option<u64>) -> Option<std::time::Duration> {
+    requested
+        .map(std::time::Duration::from_secs)
+        .or_else(|| WATCHDOG.get().and_then(|watchdog| watchdog.stall_after))
+}
+
+/// Resolve once the child has been silent for `stall_after` and ignored a
+/// nudge, returning how long it has been silent. Each silence is reported
+/// once; the next stall needs fresh output first.
+pub async fn next_stall(stall_after: std::time::Duration, reported: &mut Option<u64>) -> u64 {
+    loop {
+        let last = LAST_OUTPUT_MS.load(std::sync::atomic::Ordering::Relaxed);
+        let idle = idle();
+        if !due(last, idle, stall_after, *reported) {
+            let remaining = stall_after.saturating_sub(idle);
+            tokio::time::sleep(remaining.max(PROBE_GRACE)).await;
+            continue;
+        }
+
+        nudge();
+        tokio::time::sleep(PROBE_GRACE).await;
+        if LAST_OUTPUT_MS.load(std::sync::atomic::Ordering::Relaxed) == last {
+            *reported = Some(last);
+            let idle_secs = idle().as_secs();
+            tracing::info!("child stalled: no output for {idle_secs}s");
+            run_hook(idle_secs);
+            return idle_secs;
+        }
+    }
+}
+
+/// Whether the silence since `last`, now `idle` long, is worth probing.
+fn due(
+    last: u64,
+    idle: std::time::Duration,
+    stall_after: std::time::Duration,
+    reported: Option<u64>,
+) -> bool {
+    reported != Some(last) && idle >= stall_after
+}
+
+/// Send SIGWINCH to the PTY's foreground process group without resizing it.
+fn nudge() {
+    let Some(&master_fd) = crate::MASTER_FD.get() else {
+        return;
+    };
+    let master = unsafe { std::os::fd::BorrowedFd::borrow_raw(master_fd) };
+    match nix::unistd::tcgetpgrp(master) {
+        Ok(pgrp) => {
+            let _ = nix::sys::signal::killpg(pgrp, nix::sys::signal::Signal::SIGWINCH);
+        }
+        Err(_) => {
+            if let Some(&child_pid) = crate::CHILD_PID.get() {
+                let _ = crate::signal_child(
+                    nix::unistd::Pid::from_raw(child_pid),
+                    nix::sys::signal::Signal::SIGWINCH,
+                );
+            }
+        }
+    }
+}
+
+/// Run the configured hook in the background, if there is one.
+fn run_hook(idle_secs: u64) {
+    let Some(watchdog) = WATCHDOG.get() else {
+        return;
+    };
+    let Some(hook) = &watchdog.hook else {
+        return;
+    };
+    let spawned = tokio::process::Command::new("sh")
+        .arg("-c")
+        .arg(hook)
+        .env("TAP_SESSION", crate::SESSION_ID.read().as_str())
+        .env("TAP_IDLE_SECS", idle_secs.to_string())
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn();
+    match spawned {
+        Ok(mut child) => {
+            tokio::spawn(async move {
+                let _ = child.wait().await;
+            });
+        }
+        Err(e) => tracing::warn!("failed to run stall hook: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_each_silence_reported_once() {
+        let stall_after = std::time::Duration::from_secs(10);
+        let idle = std::time::Duration::from_secs(12);
+        assert!(!due(
+            5,
+            std::time::Duration::from_secs(3),
+            stall_after,
+            None
+        ));
+        assert!(due(5, idle, stall_after, None));
+        assert!(!due(5, idle, stall_after, Some(5)));
+        assert!(due(9, idle, stall_after, Some(5)));
+    }
+}
@@ -0,0 +1,195 @@
+//! `tap config check`: finding mistakes in the config file before a
+//! session trips over them.
+
+/// Something wrong with the config, and the line it's on when known.
+pub struct Problem {
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+/// Check the config file's `content`: that it parses, and that its
+/// keybinds, patterns and editor command are usable.
+pub fn check(content: &str) -> Vec<Problem> {
+    let config: tap_config::Config = match toml::from_str(content) {
+        Ok(config) => config,
+        Err(e) => {
+            return vec![Problem {
+                line: e.span().map(|span| line_at(content, span.start)),
+                message: e.message().trim_end().to_string(),
+            }];
+        }
+    };
+
+    let mut problems = Vec::new();
+    let mut report = |needle: &str, message: String| {
+        problems.push(Problem {
+            line: find_line(content, needle),
+            message,
+        });
+    };
+
+    let keybinds = &config.keybinds;
+    let named = [
+        ("editor", Some(&keybinds.editor)),
+        ("detach", Some(&keybinds.detach)),
+        ("paste", keybinds.paste.as_ref()),
+        ("copy_mode", keybinds.copy_mode.as_ref()),
+        ("prefix", keybinds.prefix.as_ref()),
+    ];
+    let tables = keybinds.prefix_keys.keys().chain(keybinds.custom.keys());
+    let keys = named
+        .into_iter()
+        .filter_map(|(name, key)| Some((name, key?)))
+        .chain(tables.map(|key| ("keybind", key)));
+    for (name, key) in keys {
+        if let Err(e) = tap_config::Keybind::parse(key) {
+            report(key, format!("keybinds: {name} '{key}': {e}"));
+        }
+    }
+
+    let patterns = [
+        ("triggers", config.triggers.keys().collect::<Vec<_>>()),
+        ("notify.patterns", config.notify.patterns.iter().collect()),
+        (
+            "inject.risky_patterns",
+            config.inject.risky_patterns.iter().collect(),
+        ),
+        ("record.triggers", config.record.triggers.iter().collect()),
+    ];
+    for (section, patterns) in patterns {
+        for pattern in patterns {
+            if let Err(e) = regex::Regex::new(pattern) {
+                report(
+                    pattern,
+                    format!("{section}: invalid pattern '{pattern}': {e}"),
+                );
+            }
+        }
+    }
+    // These match raw bytes, which allows a little more
+    let byte_patterns = [
+        ("filters.redact", &config.filters.redact),
+        ("input_filters.deny", &config.input_filters.deny),
+    ];
+    for (section, patterns) in byte_patterns {
+        for pattern in patterns {
+            if let Err(e) = regex::bytes::Regex::new(pattern) {
+                report(
+                    pattern,
+                    format!("{section}: invalid pattern '{pattern}': {e}"),
+                );
+            }
+        }
+    }
+
+    for name in config.profiles.keys() {
+        if let Err(e) = tap_config::with_profile(config.clone(), name) {
+            report(&format!("profiles.{name}"), format!("{e:#}"));
+        }
+    }
+
+    let editor = tap_config::get_editor(&config);
+    let program = editor.split_whitespace().next().unwrap_or_default();
+    if program.is_empty() {
+        report("editor", "editor: the editor command is empty".to_string());
+    } else if !is_executable(program) {
+        let source = if config.editor.is_some() {
+            ""
+        } else {
+            " (from $EDITOR, $VISUAL or the default)"
+        };
+        report(program, format!("editor: '{program}' not found{source}"));
+    }
+
+    // What the checks above can't see, such as prefix keys without a prefix
+    if problems.is_empty() {
+        let pipelines = [
+            tap_server::input::InputProcessor::new(&config).err(),
+            tap_server::input::InputProcessor::injected(&config).err(),
+        ];
+        for e in pipelines.into_iter().flatten() {
+            problems.push(Problem {
+                line: None,
+                message: format!("{e:#}"),
+            });
+        }
+    }
+    problems
+}
+
+/// 1-based line holding byte `offset`.
+fn line_at(content: &str, offset: usize) -> usize {
+    content[..offset.min(content.len())].matches('\n').count() + 1
+}
+
+/// 1-based line of the first mention of `needle`, as written or as it
+/// would be escaped in a TOML string.
+fn find_line(content: &str, needle: &str) -> Option<usize> {
+    let escaped = needle.replace('\\', "\\\\").replace('"', "\\\"");
+    [needle, escaped.as_str()]
+        .into_iter()
+        .find_map(|needle| content.find(needle))
+        .map(|offset| line_at(content, offset))
+}
+
+/// Whether `program` names an executable file, directly or on `$PATH`.
+fn is_executable(program: &str) -> bool {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    let runnable = |path: &std::path::Path| {
+        path.metadata()
+            .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+    };
+    if program.contains('/') {
+        return runnable(std::path::Path::new(program));
+    }
+    std::env::var_os("PATH")
+        .is_some_and(|path| std::env::split_paths(&path).any(|dir| runnable(&dir.join(program))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(content: &str) -> Vec<(Option<usize>, String)> {
+        check(content)
+            .into_iter()
+            .map(|problem| (problem.line, problem.message))
+            .collect()
+    }
+
+    #[test]
+    fn test_check() {
+        assert!(lines("editor = \"sh\"\n").is_empty());
+
+        let problems = lines("editor = \"sh\"\n\n[scrollback]\nmax_lines = \"many\"\n");
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].0, Some(4));
+
+        let content = "editor = \"sh\"\nnotify.patterns = [\"ok\", \"(unclosed\"]\n\n[keybinds]\ndetach = \"Hyper-x\"\n";
+        let problems = lines(content);
+        assert_eq!(problems.len(), 2);
+        assert_eq!(problems[0].0, Some(5));
+        assert!(problems[0].1.starts_with("keybinds: detach 'Hyper-x'"));
+        assert_eq!(problems[1].0, Some(2));
+        assert!(
+            problems[1]
+                .1
+                .starts_with("notify.patterns: invalid pattern '(unclosed'")
+        );
+
+        let problems =
+            lines("editor = \"sh\"\n\n[profiles.agent]\nscrollback.max_lines = \"many\"\n");
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].0, Some(3));
+        assert!(
+            problems[0]
+                .1
+                .starts_with("invalid settings in profile 'agent'")
+        );
+
+        let problems = lines("editor = \"no-such-editor --wait\"\n");
+        assert_eq!(problems[0].0, Some(1));
+        assert_eq!(problems[0].1, "editor: 'no-such-editor' not found");
+    }
+}
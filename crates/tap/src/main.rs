@@ -1,10 +1,20 @@
 //! Unified CLI for tap terminal sessions.
 
+mod config_check;
+mod picker;
+mod play;
+mod prefix;
+mod push;
+mod share;
+
 use std::os::fd::BorrowedFd;
 
 use eyre::WrapErr as _;
 use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
 
+const DEFAULT_START_WAIT_MS: u64 = 10_000;
+const DEFAULT_PING_TIMEOUT_MS: u64 = 1_000;
+
 #[derive(clap::Parser)]
 #[command(name = "tap", about = "Terminal session manager for tiling WM users")]
 struct Args {
@@ -12,6 +22,15 @@ struct Args {
     #[arg(long, global = true)]
     debug: bool,
 
+    /// Pristine output for scripting: no status lines, colors or table
+    /// headers.
+    #[arg(long, global = true)]
+    raw: bool,
+
+    /// Print `list`, `cursor`, `size` and `events` output as JSON.
+    #[arg(long, global = true)]
+    json: bool,
+
     #[command(subcommand)]
     command: Option<Command>,
 }
@@ -23,17 +42,210 @@ enum Command {
         /// Command to run (defaults to $SHELL).
         #[arg(trailing_var_arg = true)]
         command: Vec<String>,
-        /// Start detached (in background).
+        /// Start detached, in a background process that outlives this
+        /// terminal; prints the session ID once it's up.
         #[arg(short, long)]
         detached: bool,
+        /// With --detached, serve the session from this process instead of
+        /// a background one (e.g. under a service manager).
+        #[arg(long, requires = "detached")]
+        no_fork: bool,
+        /// With --detached, block until output matches this regex, then print
+        /// the session ID and exit (1 if it didn't appear in time).
+        #[arg(long, requires = "detached")]
+        wait_for: Option<regex::Regex>,
+        /// With --detached, how long to wait for readiness in milliseconds.
+        /// Without --wait-for, waits for the first output.
+        #[arg(long, requires = "detached")]
+        wait_ms: Option<u64>,
+        /// Name the session instead of generating an ID.
+        #[arg(long, alias = "session-id", value_parser = parse_session_name)]
+        name: Option<String>,
+        /// Tag the session for bulk selection (repeatable).
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        /// Record the session's output to this asciicast (.cast) file.
+        #[arg(long, value_name = "FILE")]
+        record: Option<std::path::PathBuf>,
+        /// Append the session's output to this log file, rotated by size
+        /// (see `[logging]` in the config).
+        #[arg(long, value_name = "FILE")]
+        log_file: Option<std::path::PathBuf>,
+        /// Don't print the `[tap: …]` banner (see `banner` in the config).
+        #[arg(short, long)]
+        quiet: bool,
+        /// Start in the working directory of this session.
+        #[arg(long, value_name = "SESSION")]
+        cwd_of: Option<String>,
+        /// Relaunch the session saved in this file (used by `tap restore`).
+        #[arg(long, hide = true, requires = "no_fork", value_name = "FILE")]
+        restore: Option<std::path::PathBuf>,
+        /// Use this profile from the config (`[profiles.<name>]`) instead of
+        /// one chosen by the command.
+        #[arg(long, value_name = "NAME")]
+        profile: Option<String>,
+        /// Set an environment variable for the command (repeatable).
+        #[arg(short, long = "env", value_name = "KEY=VALUE", value_parser = parse_env_var)]
+        env: Vec<(String, String)>,
+        /// Start a session from inside another one, recording it as nested
+        /// (see `tap list --tree`) without a warning.
+        #[arg(long)]
+        allow_nested: bool,
+        /// Also serve the session over TCP on this address, for
+        /// `tap attach tcp://HOST:PORT` from other machines.
+        #[arg(long, value_name = "ADDR")]
+        listen: Option<std::net::SocketAddr>,
+        /// Token TCP clients must present (defaults to $TAP_AUTH_TOKEN).
+        #[arg(long, requires = "listen", value_name = "TOKEN")]
+        auth_token: Option<String>,
     },
     /// Attach to a running session.
     Attach {
+        /// Session ID (picks from a list if several are running).
+        session: Option<String>,
+        /// View the session at its current size instead of resizing it to this terminal.
+        #[arg(long)]
+        no_resize: bool,
+        /// Watch without sending keystrokes (implies --no-resize).
+        #[arg(long)]
+        read_only: bool,
+        /// Detach every other client first, e.g. one left attached by a
+        /// terminal that died (like `tmux attach -d`).
+        #[arg(short = 'f', long)]
+        detach_others: bool,
+    },
+    /// Copy a local file into the session by typing it into its shell.
+    Push {
+        /// Session ID.
+        session: String,
+        /// Local file to send.
+        local: std::path::PathBuf,
+        /// Destination path in the session (defaults to the file's name).
+        remote_path: Option<String>,
+    },
+    /// Cancel risky injected input that is being held.
+    Cancel {
+        /// Session ID (uses latest if not specified).
+        #[arg(short, long)]
+        session: Option<String>,
+        /// ID of the held input (cancels all if not specified).
+        id: Option<u64>,
+    },
+    /// Check whether a session is alive. Exits 0 if it answers, 1 if its
+    /// socket exists but it doesn't, 2 if the session is unknown.
+    Ping {
+        /// Session ID (uses latest if not specified).
+        session: Option<String>,
+        /// How long to wait for an answer, in milliseconds.
+        #[arg(long, default_value_t = DEFAULT_PING_TIMEOUT_MS)]
+        timeout_ms: u64,
+    },
+    /// Disconnect clients attached to or subscribed to a session.
+    Detach {
+        /// Session ID (uses latest if not specified).
+        session: Option<String>,
+    },
+    /// Hand a detached session over to another tap process, e.g. a newer
+    /// version started with `tap receive`.
+    Migrate {
+        /// Session ID.
+        session: String,
+        /// Socket the receiving tap process is listening on.
+        #[arg(long)]
+        to: std::path::PathBuf,
+    },
+    /// Wait for a session sent with `tap migrate` and keep serving it.
+    /// The child's exit status can't be observed after a migration.
+    Receive {
+        /// Socket to listen on.
+        socket: std::path::PathBuf,
+        /// Give up if no session arrives within this many seconds.
+        #[arg(long, value_name = "SECS")]
+        timeout: Option<u64>,
+    },
+    /// Restart detached session servers as this tap binary, keeping their
+    /// sessions running. Use after installing a new release.
+    SelfUpgradeHandoff {
+        /// Session ID (upgrades every detached session if not specified).
+        session: Option<String>,
+        /// Binary to restart as (defaults to this one).
+        #[arg(long)]
+        exe: Option<std::path::PathBuf>,
+    },
+    /// Continue serving a session after `self-upgrade-handoff`.
+    #[command(hide = true)]
+    ResumeHandoff {
+        #[arg(long)]
+        master_fd: i32,
+        #[arg(long)]
+        listener_fd: i32,
+        #[arg(long)]
+        state_fd: i32,
+    },
+    /// Relay stdin and stdout to a session's socket, for clients reaching
+    /// it over SSH.
+    #[command(hide = true)]
+    Proxy {
         /// Session ID (uses latest if not specified).
         session: Option<String>,
     },
+    /// Replay a recording (.cast or ttyrec). Space pauses, the arrow keys
+    /// seek and q quits.
+    Play {
+        /// Recording to play.
+        file: std::path::PathBuf,
+        /// Playback speed multiplier.
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
+        /// Cap pauses between output at this many seconds.
+        #[arg(long)]
+        max_idle: Option<f64>,
+        /// Play into a new detached session instead, so it can be attached
+        /// to and its scrollback queried during playback.
+        #[arg(long)]
+        new_session: bool,
+    },
+    /// Record a session's output to an asciicast (.cast) file.
+    Record {
+        /// Session ID (uses latest if not specified).
+        #[arg(short, long)]
+        session: Option<String>,
+        /// File to record to.
+        #[arg(required_unless_present = "stop")]
+        path: Option<std::path::PathBuf>,
+        /// Stop the current recording instead.
+        #[arg(long, conflicts_with = "path")]
+        stop: bool,
+    },
     /// List all active sessions.
-    List,
+    List {
+        #[command(flatten)]
+        filter: FilterArgs,
+        /// Also show each session's notes.
+        #[arg(short, long)]
+        long: bool,
+        /// Show sessions started inside others under them.
+        #[arg(long)]
+        tree: bool,
+        /// List the sessions on another machine, over SSH.
+        #[arg(long, value_name = "[USER@]HOST")]
+        host: Option<String>,
+    },
+    /// Stop sessions by ID or in bulk with filters.
+    Kill {
+        /// Session ID to kill.
+        #[arg(conflicts_with_all = ["tags", "detached_only", "older_than"])]
+        session: Option<String>,
+        /// Kill every session matching the filters (all sessions if none given).
+        #[arg(long, conflicts_with = "session")]
+        all: bool,
+        /// Signal to send, by name or number (e.g. INT, SIGKILL, 9). By
+        /// default the child is hung up and killed if it doesn't exit.
+        #[arg(long, value_parser = parse_signal)]
+        signal: Option<i32>,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
     /// Get scrollback buffer from a session.
     Scrollback {
         /// Session ID (uses latest if not specified).
@@ -42,6 +254,73 @@ enum Command {
         /// Number of lines to retrieve.
         #[arg(short, long)]
         lines: Option<usize>,
+        /// Only show output since this mark (see `tap mark`).
+        #[arg(long)]
+        since_mark: Option<String>,
+    },
+    /// Copy a session's scrollback to the system clipboard, with pbcopy,
+    /// wl-copy, xclip or xsel, or else OSC 52 through this terminal.
+    Copy {
+        /// Session ID (uses latest if not specified).
+        #[arg(short, long)]
+        session: Option<String>,
+        /// Number of lines to copy (all scrollback if not specified).
+        #[arg(short, long)]
+        lines: Option<usize>,
+        /// Copy what the app last copied with OSC 52 instead (needs
+        /// `[clipboard] keep`).
+        #[arg(long, conflicts_with = "lines")]
+        app: bool,
+    },
+    /// Upload a session's scrollback to a paste service and print the link.
+    ShareOutput {
+        /// Session ID (uses latest if not specified).
+        #[arg(short, long)]
+        session: Option<String>,
+        /// Number of lines to upload (all scrollback if not specified).
+        #[arg(short, long)]
+        lines: Option<usize>,
+    },
+    /// Relaunch sessions saved with `[persist]`, e.g. after a reboot,
+    /// showing their old screens above the new output.
+    Restore {
+        /// Saved sessions to restore (all of them if none are given).
+        sessions: Vec<String>,
+        /// List saved sessions instead of restoring them.
+        #[arg(short, long)]
+        list: bool,
+    },
+    /// Rebuild sessions.json from the sessions still running, e.g. after
+    /// it was lost or damaged.
+    RepairIndex,
+    /// Give a session a new name.
+    Rename {
+        /// Current session ID.
+        session: String,
+        /// New name.
+        #[arg(value_parser = parse_session_name)]
+        name: String,
+    },
+    /// Add a timestamped note to a session (see `tap history`), also
+    /// marked in any recording in progress.
+    Note {
+        /// Session ID.
+        session: String,
+        /// Note text.
+        text: String,
+    },
+    /// Show when a session started and the notes added to it since.
+    History {
+        /// Session ID (uses latest if not specified).
+        session: Option<String>,
+    },
+    /// Set an invisible bookmark in a session's output history.
+    Mark {
+        /// Session ID (uses latest if not specified).
+        #[arg(short, long)]
+        session: Option<String>,
+        /// Mark label.
+        label: String,
     },
     /// Get cursor position.
     Cursor {
@@ -49,6 +328,18 @@ enum Command {
         #[arg(short, long)]
         session: Option<String>,
     },
+    /// Print the window title the app in a session last set.
+    Title {
+        /// Session ID (uses latest if not specified).
+        #[arg(short, long)]
+        session: Option<String>,
+    },
+    /// Print a session's working directory, e.g. `cd "$(tap cwd -s web)"`.
+    Cwd {
+        /// Session ID (uses latest if not specified).
+        #[arg(short, long)]
+        session: Option<String>,
+    },
     /// Get terminal size.
     Size {
         /// Session ID (uses latest if not specified).
@@ -62,15 +353,430 @@ enum Command {
         session: Option<String>,
         /// Text to inject.
         text: String,
+        /// Print the output that follows, up to and including a match of this regex.
+        #[arg(long, value_name = "REGEX", group = "capture")]
+        until: Option<String>,
+        /// Print the output that follows, up to the shell's next prompt
+        /// (needs shell integration emitting OSC 133).
+        #[arg(long, group = "capture")]
+        until_prompt: bool,
+        /// Print the output that follows for this many milliseconds.
+        #[arg(long, value_name = "MS", group = "capture")]
+        capture_ms: Option<u64>,
+        /// Give up on --until/--until-prompt after this many seconds.
+        #[arg(long, value_name = "SECS")]
+        timeout: Option<u64>,
+    },
+    /// Send keys to a session by name: Enter, Tab, Escape, Space, BSpace,
+    /// Up/Down/Left/Right, Home/End, PageUp/PageDown, Insert/Delete, F1-F12,
+    /// with C- (ctrl), M- (alt) and S- (shift) prefixes, e.g. `C-c` or
+    /// `M-x`. Other arguments are typed as literal text.
+    SendKeys {
+        /// Session ID (uses latest if not specified).
+        #[arg(short, long)]
+        session: Option<String>,
+        /// Keys to send, in order.
+        #[arg(required = true)]
+        keys: Vec<String>,
+    },
+    /// Stream session events: bell, title, exit, resize, attach, detach,
+    /// alt_screen and editor_failed.
+    Events {
+        /// Session ID (uses latest if not specified).
+        #[arg(short, long)]
+        session: Option<String>,
+        /// Only these kinds of events (repeatable; all if not given).
+        #[arg(long = "kind", value_name = "KIND", value_parser = parse_event_kind)]
+        kinds: Vec<tap_client::EventKind>,
+    },
+    /// Print the output of the last command run in a session's shell (needs
+    /// shell integration marking commands with OSC 133).
+    LastOutput {
+        /// Session ID (uses latest if not specified).
+        #[arg(short, long)]
+        session: Option<String>,
+        /// Which command, counting back from the last (1).
+        #[arg(short, default_value_t = 1)]
+        n: usize,
+    },
+    /// Run a command in a session's shell, print its output and exit with
+    /// its status. Needs a POSIX-style shell (sh, bash, zsh).
+    Exec {
+        /// Session ID (uses latest if not specified).
+        #[arg(short, long)]
+        session: Option<String>,
+        /// Give up if the command hasn't finished after this many seconds.
+        #[arg(long, value_name = "SECS")]
+        timeout: Option<u64>,
+        /// When the command stops at a prompt for input (a password, a y/N
+        /// confirmation), show the prompt and send it the line typed here.
+        #[arg(short, long)]
+        interactive: bool,
+        /// Command line to run.
+        command: String,
     },
     /// Subscribe to live output stream.
     Subscribe {
+        /// Session ID (uses latest if not specified).
+        #[arg(short, long, conflicts_with = "all")]
+        session: Option<String>,
+        /// Follow every matching session, labelling each line with its session ID.
+        #[arg(long)]
+        all: bool,
+        /// With --all, also pick up sessions started later.
+        #[arg(long, requires = "all")]
+        follow: bool,
+        /// Start from this output sequence number (as printed by `tap
+        /// inject`), replaying what was printed since.
+        #[arg(long, value_name = "SEQ", conflicts_with = "all")]
+        from_seq: Option<u64>,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Resize a session's terminal, no bigger than its smallest attached
+    /// terminal.
+    Resize {
+        /// Session ID (uses latest if not specified).
+        #[arg(short, long)]
+        session: Option<String>,
+        /// New size as <rows>x<cols>, e.g. 24x80.
+        #[arg(value_parser = parse_size)]
+        size: (u16, u16),
+    },
+    /// Show the wall-clock and CPU time a session's command has used, like
+    /// time(1).
+    Time {
+        /// Session ID (uses latest if not specified).
+        session: Option<String>,
+    },
+    /// Wait for a session's command to exit and exit with its status.
+    Wait {
+        /// Session ID (uses latest if not specified).
+        session: Option<String>,
+        /// Report on stderr whenever the command goes this many seconds
+        /// without output and ignores a redraw nudge (defaults to the
+        /// session's `watchdog.stall_secs`).
+        #[arg(long, value_name = "SECS")]
+        stall_after: Option<u64>,
+    },
+    /// Wait until a session prints a line matching a regex and print that
+    /// line. Exits 1 on timeout and 2 if the session ends first.
+    WaitFor {
+        /// Session ID (uses latest if not specified).
+        #[arg(short, long)]
+        session: Option<String>,
+        /// Give up after this many seconds.
+        #[arg(long, value_name = "SECS")]
+        timeout: Option<u64>,
+        /// Print only the matched text rather than its whole line.
+        #[arg(short = 'o', long)]
+        only_matching: bool,
+        /// Regex to wait for in the output printed from now on.
+        pattern: String,
+    },
+    /// Open a session's scrollback in the editor (same as the editor keybind).
+    /// Prints the path of a scrollback file if the session is detached.
+    Edit {
+        /// Session ID (uses latest if not specified).
+        #[arg(short, long)]
+        session: Option<String>,
+    },
+    /// Drive a REPL in a session line by line from a local prompt.
+    Repl {
+        /// Session ID (uses latest if not specified).
+        session: Option<String>,
+        /// Wrap each line in bracketed paste sequences.
+        #[arg(long)]
+        bracketed_paste: bool,
+        /// Milliseconds of output silence after which a command is considered done.
+        #[arg(long, default_value_t = 300)]
+        idle_ms: u64,
+    },
+    /// Mirror one session's output into another session's input.
+    Mirror {
+        /// Session to read output from.
+        src: String,
+        /// Session to inject the output into (prints to this terminal if not specified).
+        dst: Option<String>,
+    },
+    /// Store text in a named paste buffer.
+    SetBuffer {
+        /// Session ID used to reach a server (uses latest if not specified).
+        #[arg(short, long)]
+        session: Option<String>,
+        /// Buffer name.
+        name: String,
+        /// Text to store (reads stdin if not given).
+        text: Option<String>,
+    },
+    /// Print the contents of a named paste buffer.
+    GetBuffer {
+        /// Session ID used to reach a server (uses latest if not specified).
+        #[arg(short, long)]
+        session: Option<String>,
+        /// Buffer name.
+        name: String,
+    },
+    /// Paste text into a session as a terminal would, in bracketed paste
+    /// markers if the app enabled them, so editors and REPLs don't
+    /// auto-indent it or run it line by line.
+    Paste {
         /// Session ID (uses latest if not specified).
         #[arg(short, long)]
         session: Option<String>,
+        /// Text to paste (reads stdin if not given).
+        text: Option<String>,
+    },
+    /// Paste a named buffer into a session.
+    PasteBuffer {
+        /// Session ID (uses latest if not specified).
+        #[arg(short, long)]
+        session: Option<String>,
+        /// Buffer name.
+        name: String,
+    },
+    /// Show the environment the session's process is running with.
+    Env {
+        /// Session ID (uses latest if not specified).
+        #[arg(short, long)]
+        session: Option<String>,
+        /// Print only this variable's value.
+        name: Option<String>,
+    },
+    /// Save an image of the session's screen.
+    Screenshot {
+        /// Session ID (uses latest if not specified).
+        #[arg(short, long)]
+        session: Option<String>,
+        /// Output file; `.svg` is written as-is, anything else as PNG.
+        #[arg(short, long)]
+        output: std::path::PathBuf,
+    },
+    /// Print the screen as JSON: every cell with its colors and attributes,
+    /// the cursor, whether the alternate screen is active and the title.
+    Screen {
+        /// Session ID (uses latest if not specified).
+        #[arg(short, long)]
+        session: Option<String>,
+    },
+    /// Rebuild a session's emulated screen when it no longer matches the
+    /// app's (e.g. after binary output), and have the app redraw.
+    Resync {
+        /// Session ID (uses latest if not specified).
+        #[arg(short, long)]
+        session: Option<String>,
+    },
+    /// Switch a session back to its main screen after a full-screen app
+    /// crashed without leaving the alternate screen.
+    ResetAlt {
+        /// Session ID (uses latest if not specified).
+        #[arg(short, long)]
+        session: Option<String>,
+    },
+    /// Check or print the config file.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
     },
 }
 
+#[derive(clap::Subcommand)]
+enum ConfigCommand {
+    /// Check the config: that it parses, and that keybinds, patterns and
+    /// the editor command are valid. Exits 1 if there are problems.
+    Check,
+    /// Print the config file.
+    Show {
+        /// Print the configuration in effect instead: defaults, the file and
+        /// environment overrides, merged.
+        #[arg(long)]
+        effective: bool,
+        /// With --effective, apply this profile too.
+        #[arg(long, requires = "effective", value_name = "NAME")]
+        profile: Option<String>,
+    },
+}
+
+/// Session selectors shared by `list`, `kill` and `subscribe --all`.
+#[derive(clap::Args)]
+struct FilterArgs {
+    /// Only sessions with this tag (repeatable; all must match).
+    #[arg(long = "tag")]
+    tags: Vec<String>,
+    /// Only sessions with no attached client.
+    #[arg(long)]
+    detached_only: bool,
+    /// Only sessions older than this, e.g. 30m, 6h, 2d.
+    #[arg(long, value_parser = tap_client::parse_age)]
+    older_than: Option<std::time::Duration>,
+}
+
+impl From<FilterArgs> for tap_client::SessionFilter {
+    fn from(args: FilterArgs) -> Self {
+        Self {
+            tags: args.tags,
+            detached_only: args.detached_only,
+            older_than: args.older_than,
+        }
+    }
+}
+
+/// Parse a terminal size in `<rows>x<cols>` form.
+fn parse_size(s: &str) -> Result<(u16, u16), String> {
+    let (rows, cols) = s
+        .split_once('x')
+        .ok_or_else(|| format!("invalid size '{s}' — expected <rows>x<cols>, e.g. 24x80"))?;
+    let rows: u16 = rows
+        .parse()
+        .map_err(|_| format!("invalid row count '{rows}'"))?;
+    let cols: u16 = cols
+        .parse()
+        .map_err(|_| format!("invalid column count '{cols}'"))?;
+    if rows == 0 || cols == 0 {
+        return Err(format!(
+            "invalid size '{s}' — rows and columns must be non-zero"
+        ));
+    }
+    Ok((rows, cols))
+}
+
+/// Exit status for `tap ping`: 0 if the session answers within `timeout`,
+/// 1 if its socket exists but it doesn't, 2 if there's no such session. A
+/// remote session that can't be reached counts as not answering.
+async fn ping(session: Option<String>, timeout: std::time::Duration) -> i32 {
+    let id = match session {
+        Some(id) => id,
+        None => match tap_client::list_sessions()
+            .ok()
+            .and_then(|s| s.last().cloned())
+        {
+            Some(session) => session.id,
+            None => return 2,
+        },
+    };
+    let remote = tap_client::is_remote(&id);
+    if !remote && !tap_client::socket_path(&id).exists() {
+        return 2;
+    }
+
+    let answered = tokio::time::timeout(timeout, async {
+        let mut client = if remote {
+            tap_client::Client::connect(&id).await?
+        } else {
+            tap_client::Client::connect_unchecked(&id).await?
+        };
+        client.ping().await
+    })
+    .await;
+    match answered {
+        Ok(Ok(())) => 0,
+        _ => 1,
+    }
+}
+
+/// Refuse `session` if it's on another machine, for commands that only work
+/// with sessions on this one.
+fn require_local(session: &str, command: &str) -> eyre::Result<()> {
+    if tap_client::is_remote(session) {
+        eyre::bail!("`tap {command}` is not supported for remote sessions");
+    }
+    Ok(())
+}
+
+/// `tap proxy`: pass bytes between stdio and a session's socket until
+/// either side closes.
+async fn run_proxy(session: Option<String>) -> eyre::Result<()> {
+    let id = match session {
+        Some(id) => id,
+        None => {
+            let sessions = tap_client::list_sessions()?;
+            sessions
+                .last()
+                .ok_or(tap_client::Error::NoSessions)?
+                .id
+                .clone()
+        }
+    };
+    let path = tap_client::socket_path(&id);
+    if !path.exists() {
+        return Err(tap_client::Error::SessionNotFound(id).into());
+    }
+    let (mut from_session, mut to_session) =
+        tokio::net::UnixStream::connect(&path).await?.into_split();
+    let relayed = tokio::select! {
+        result = tokio::io::copy(&mut tokio::io::stdin(), &mut to_session) => result,
+        result = tokio::io::copy(&mut from_session, &mut tokio::io::stdout()) => result,
+    };
+    // Exit rather than return, since the runtime would wait on stdin's
+    // blocking read before shutting down
+    std::process::exit(i32::from(relayed.is_err()));
+}
+
+/// Parse an event kind as named in the protocol (`bell`, `alt_screen`).
+fn parse_session_name(s: &str) -> Result<String, String> {
+    tap_client::validate_session_name(s)?;
+    Ok(s.to_string())
+}
+
+fn parse_env_var(s: &str) -> Result<(String, String), String> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected KEY=VALUE, got '{s}'"))?;
+    if key.is_empty() || s.contains('\0') {
+        return Err(format!("invalid environment variable '{s}'"));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+fn parse_event_kind(s: &str) -> Result<tap_client::EventKind, String> {
+    serde_json::from_value(serde_json::Value::String(s.replace('-', "_"))).map_err(|_| {
+        format!(
+            "unknown event kind '{s}' — expected bell, title, exit, resize, attach, detach, alt_screen or editor_failed"
+        )
+    })
+}
+
+/// One-line description of an event for `tap events`.
+fn describe_event(event: &tap_client::Event) -> String {
+    match event {
+        tap_client::Event::Bell => "bell".to_string(),
+        tap_client::Event::Title { title } => format!("title {title}"),
+        tap_client::Event::Exit {
+            exit_code: Some(exit_code),
+        } => format!("exit {exit_code}"),
+        tap_client::Event::Exit { exit_code: None } => "exit".to_string(),
+        tap_client::Event::Resize { rows, cols } => format!("resize {rows}x{cols}"),
+        tap_client::Event::Attach { clients } => format!("attach ({clients} attached)"),
+        tap_client::Event::Detach { clients } => format!("detach ({clients} attached)"),
+        tap_client::Event::AltScreen { active: true } => "alt_screen enter".to_string(),
+        tap_client::Event::AltScreen { active: false } => "alt_screen exit".to_string(),
+        tap_client::Event::EditorFailed { message, .. } => format!("editor_failed {message}"),
+    }
+}
+
+/// A duration as time(1) prints it, e.g. `1m2.345s`.
+fn format_duration_ms(ms: u64) -> String {
+    format!("{}m{}.{:03}s", ms / 60_000, ms / 1000 % 60, ms % 1000)
+}
+
+/// Parse a signal given by name (`TERM`, `SIGTERM`) or number (`15`).
+fn parse_signal(s: &str) -> Result<i32, String> {
+    if let Ok(number) = s.parse::<i32>() {
+        return nix::sys::signal::Signal::try_from(number)
+            .map(|signal| signal as i32)
+            .map_err(|_| format!("unknown signal number {number}"));
+    }
+    let name = s.to_ascii_uppercase();
+    let name = if name.starts_with("SIG") {
+        name
+    } else {
+        format!("SIG{name}")
+    };
+    name.parse::<nix::sys::signal::Signal>()
+        .map(|signal| signal as i32)
+        .map_err(|_| format!("unknown signal '{s}'"))
+}
+
 async fn get_client(session: Option<String>) -> eyre::Result<tap_client::Client> {
     match session {
         Some(id) => tap_client::Client::connect(&id)
@@ -82,21 +788,16 @@ async fn get_client(session: Option<String>) -> eyre::Result<tap_client::Client>
     }
 }
 
-async fn run_start(command: Vec<String>, detached: bool) -> eyre::Result<()> {
-    let config = tap_server::ServerConfig {
-        command,
-        session_id: None,
-        detached,
-    };
+async fn run_start(config: tap_server::ServerConfig) -> eyre::Result<()> {
+    let detached = config.detached;
     match tap_server::run(config).await? {
         tap_server::RunResult::Exited(code) => std::process::exit(code),
         tap_server::RunResult::Detached { session_id } => {
             if detached {
-                // Started detached - keep the process running
-                // Wait forever (the PTY loop runs in a background task)
-                loop {
-                    tokio::time::sleep(std::time::Duration::from_secs(3600)).await;
-                }
+                // Started detached - keep the process running until the
+                // session ends (the PTY loop runs in a background task)
+                let exit_code = tap_server::wait_for_exit().await;
+                std::process::exit(exit_code.unwrap_or(0));
             } else {
                 // User detached interactively
                 println!("Use `tap attach {session_id}` to reattach");
@@ -106,6 +807,340 @@ async fn run_start(command: Vec<String>, detached: bool) -> eyre::Result<()> {
     }
 }
 
+/// Serve a detached session from `tap start --detached --no-fork` in a
+/// background process of its own session, so it survives this process and
+/// the terminal it ran in.
+fn spawn_background(
+    config: &tap_server::ServerConfig,
+    session_id: &str,
+    restore: Option<(&std::path::Path, &tap_server::persist::SavedSession)>,
+) -> eyre::Result<std::process::Child> {
+    use std::os::unix::process::CommandExt as _;
+
+    let exe = std::env::current_exe().wrap_err("failed to locate tap executable")?;
+
+    let mut launcher = std::process::Command::new(exe);
+    launcher.args(["start", "--detached", "--no-fork", "--name", session_id]);
+    for tag in &config.tags {
+        launcher.args(["--tag", tag]);
+    }
+    if let Some(record) = &config.record {
+        launcher.arg("--record").arg(record);
+    }
+    if let Some(log_file) = &config.log_file {
+        launcher.arg("--log-file").arg(log_file);
+    }
+    if let Some(profile) = &config.profile {
+        launcher.args(["--profile", profile]);
+    }
+    // The background process finds its parent the same way
+    if config.parent.is_some() {
+        launcher.arg("--allow-nested");
+    } else {
+        launcher.env_remove("TAP_SESSION_ID");
+    }
+    for (key, value) in &config.env {
+        launcher.arg("--env").arg(format!("{key}={value}"));
+    }
+    // In the environment rather than the arguments, where `ps` would show it
+    if let Some(listen) = &config.listen {
+        launcher
+            .arg("--listen")
+            .arg(listen.addr.to_string())
+            .env(tap_protocol::AUTH_TOKEN_ENV, &listen.token);
+    }
+    if let Some((path, saved)) = restore {
+        launcher.arg("--restore").arg(path).envs(&saved.env);
+        if let Some(cwd) = saved.cwd.as_ref().filter(|cwd| cwd.is_dir()) {
+            launcher.current_dir(cwd);
+        }
+    }
+    launcher
+        .arg("--")
+        .args(&config.command)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null());
+    // New session so the background process survives this terminal closing
+    unsafe {
+        launcher.pre_exec(|| {
+            nix::unistd::setsid()
+                .map(|_| ())
+                .map_err(std::io::Error::from)
+        });
+    }
+    launcher
+        .spawn()
+        .wrap_err("failed to start background session")
+}
+
+/// Start a detached session in a background process and return once it
+/// answers, printing its ID.
+async fn run_start_background(config: tap_server::ServerConfig) -> eyre::Result<()> {
+    let session_id = config
+        .session_id
+        .clone()
+        .unwrap_or_else(tap_server::generate_session_id);
+    let mut server = spawn_background(&config, &session_id, None)?;
+    wait_until_up(&session_id, &mut server).await?;
+    println!("{session_id}");
+    Ok(())
+}
+
+/// `tap config check` and `tap config show`.
+fn run_config(command: ConfigCommand) -> eyre::Result<()> {
+    let path = tap_config::config_path();
+    let content = if path.exists() {
+        Some(
+            std::fs::read_to_string(&path)
+                .wrap_err_with(|| format!("failed to read {}", path.display()))?,
+        )
+    } else {
+        None
+    };
+    match command {
+        ConfigCommand::Check => {
+            let Some(content) = content else {
+                println!("{} doesn't exist; using defaults", path.display());
+                return Ok(());
+            };
+            let problems = config_check::check(&content);
+            for problem in &problems {
+                match problem.line {
+                    Some(line) => eprintln!("{}:{line}: {}", path.display(), problem.message),
+                    None => eprintln!("{}: {}", path.display(), problem.message),
+                }
+            }
+            if !problems.is_empty() {
+                std::process::exit(1);
+            }
+            println!("{}: ok", path.display());
+        }
+        ConfigCommand::Show {
+            effective: true,
+            profile,
+        } => {
+            let (config, _) = tap_config::load_for_session(profile.as_deref(), &[])?;
+            print!("{}", toml::to_string_pretty(&config)?);
+        }
+        ConfigCommand::Show {
+            effective: false, ..
+        } => match content {
+            Some(content) => print!("{content}"),
+            None => eprintln!("{} doesn't exist; using defaults", path.display()),
+        },
+    }
+    Ok(())
+}
+
+/// Have every server with a socket put its entry back in sessions.json,
+/// then drop entries for sessions that aren't running.
+async fn run_repair_index() -> eyre::Result<()> {
+    let dir = tap_protocol::socket_dir();
+    let entries =
+        std::fs::read_dir(&dir).wrap_err_with(|| format!("failed to read {}", dir.display()))?;
+    let mut live = Vec::new();
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if path.extension().is_none_or(|ext| ext != "sock") {
+            continue;
+        }
+        let Some(id) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        let registered = async {
+            let mut client = tap_client::Client::connect(id).await?;
+            client.reregister().await
+        };
+        match registered.await {
+            Ok(()) => live.push(id.to_string()),
+            Err(e) => eprintln!("{id}: {e}"),
+        }
+    }
+    let dropped = tap_server::index::retain_live(&live)?;
+    println!(
+        "{} running session(s) in the index, {dropped} stale entr{} dropped",
+        live.len(),
+        if dropped == 1 { "y" } else { "ies" }
+    );
+    Ok(())
+}
+
+/// Run a keybind's shell command in the background, with `TAP_SESSION` set
+/// to the session.
+fn run_keybind_command(command: &str, session: &str) {
+    let mut child = tokio::process::Command::new("sh");
+    child
+        .arg("-c")
+        .arg(command)
+        .env("TAP_SESSION", session)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null());
+    match child.spawn() {
+        Ok(mut child) => {
+            tokio::spawn(async move {
+                let _ = child.wait().await;
+            });
+        }
+        Err(e) => tracing::warn!("failed to run keybind command '{command}': {e}"),
+    }
+}
+
+/// Write a session's scrollback to a file that's kept, returning its path.
+/// Paste into `session` over a connection of its own, since the attached
+/// one only carries keystrokes.
+async fn paste_into(session: &str, contents: &[u8]) -> eyre::Result<()> {
+    get_client(Some(session.to_string()))
+        .await?
+        .paste(&String::from_utf8_lossy(contents))
+        .await?;
+    Ok(())
+}
+
+async fn save_scrollback(session: &str) -> eyre::Result<std::path::PathBuf> {
+    use std::io::Write as _;
+
+    let content = get_client(Some(session.to_string()))
+        .await?
+        .get_scrollback(None)
+        .await?;
+    let mut file = tempfile::Builder::new()
+        .prefix("tap-scrollback-")
+        .suffix(".txt")
+        .tempfile()
+        .wrap_err("failed to create scrollback file")?;
+    file.write_all(content.as_bytes())
+        .wrap_err("failed to write scrollback file")?;
+    let (_, path) = file.keep().wrap_err("failed to keep scrollback file")?;
+    Ok(path)
+}
+
+/// Set the clipboard through the terminal running tap with OSC 52, for
+/// when there's no clipboard command (e.g. over SSH).
+fn copy_via_terminal(contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write as _;
+
+    let mut tty = std::fs::OpenOptions::new().write(true).open("/dev/tty")?;
+    tty.write_all(&tap_server::clipboard::osc52_copy(contents))?;
+    tty.flush()
+}
+
+/// Relaunch each saved session in `ids` (all if empty) that isn't still
+/// running, printing the IDs restored.
+async fn run_restore(ids: &[String], list: bool) -> eyre::Result<()> {
+    let tap_config = tap_config::load().wrap_err("failed to load tap configuration")?;
+    let saved = tap_server::persist::list(&tap_config::saved_sessions_dir(&tap_config));
+    let running = tap_client::list_sessions()?;
+
+    for (path, session) in saved {
+        if !ids.is_empty() && !ids.contains(&session.id) {
+            continue;
+        }
+        if list {
+            println!(
+                "{}\t{}\t{}",
+                session.id,
+                session.saved,
+                session.command.join(" ")
+            );
+            continue;
+        }
+        if running.iter().any(|s| s.id == session.id) {
+            eprintln!("{} is still running", session.id);
+            continue;
+        }
+        let listen = session
+            .listen
+            .and_then(tap_server::remote::Listen::from_env);
+        if let Some(addr) = session.listen
+            && listen.is_none()
+        {
+            eprintln!(
+                "{}: not serving on {addr}: ${} isn't set",
+                session.id,
+                tap_protocol::AUTH_TOKEN_ENV
+            );
+        }
+        let restored = async {
+            let config = tap_server::ServerConfig {
+                command: session.command.clone(),
+                tags: session.tags.clone(),
+                listen,
+                ..tap_server::ServerConfig::default()
+            };
+            let mut server = spawn_background(&config, &session.id, Some((&path, &session)))?;
+            wait_until_up(&session.id, &mut server).await
+        };
+        match restored.await {
+            Ok(()) => println!("{}", session.id),
+            Err(e) => eprintln!("failed to restore {}: {e}", session.id),
+        }
+    }
+    Ok(())
+}
+
+/// Wait for a session started by [`spawn_background`] to answer.
+async fn wait_until_up(session_id: &str, server: &mut std::process::Child) -> eyre::Result<()> {
+    const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(20);
+
+    let deadline =
+        tokio::time::Instant::now() + std::time::Duration::from_millis(DEFAULT_START_WAIT_MS);
+    loop {
+        if let Ok(mut client) = tap_client::Client::connect(session_id).await
+            && client.ping().await.is_ok()
+        {
+            return Ok(());
+        }
+        if let Some(status) = server.try_wait()? {
+            eyre::bail!("session {session_id} failed to start ({status})");
+        }
+        if tokio::time::Instant::now() >= deadline {
+            eyre::bail!("session {session_id} didn't come up in time");
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+/// Start a detached session in a background process and block until it is
+/// ready: its screen matches `pattern`, or it prints anything if no pattern
+/// is given. Prints the session ID and exits 0 when ready, 1 on timeout.
+async fn run_start_and_wait(
+    config: tap_server::ServerConfig,
+    pattern: Option<regex::Regex>,
+    timeout: std::time::Duration,
+) -> eyre::Result<()> {
+    const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(50);
+
+    let session_id = config
+        .session_id
+        .clone()
+        .unwrap_or_else(tap_server::generate_session_id);
+    let mut launcher = spawn_background(&config, &session_id, None)?;
+
+    let deadline = tokio::time::Instant::now() + timeout;
+    let ready = loop {
+        if let Ok(mut client) = tap_client::Client::connect(&session_id).await
+            && let Ok(content) = client.get_scrollback(None).await
+        {
+            let matched = match &pattern {
+                Some(pattern) => pattern.is_match(&content),
+                None => !content.trim().is_empty(),
+            };
+            if matched {
+                break true;
+            }
+        }
+        if launcher.try_wait()?.is_some() || tokio::time::Instant::now() >= deadline {
+            break false;
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    };
+
+    println!("{session_id}");
+    std::process::exit(if ready { 0 } else { 1 });
+}
+
 fn get_window_size() -> (u16, u16) {
     let mut ws: nix::pty::Winsize = unsafe { std::mem::zeroed() };
     unsafe {
@@ -126,15 +1161,119 @@ fn restore_terminal(fd: BorrowedFd<'_>, termios: &nix::sys::termios::Termios) {
     let _ = nix::sys::termios::tcsetattr(fd, nix::sys::termios::SetArg::TCSANOW, termios);
 }
 
-async fn run_attach(session: Option<String>) -> eyre::Result<()> {
-    let mut client = get_client(session.clone()).await?;
+/// The running session this process is inside of, from `$TAP_SESSION_ID`.
+fn enclosing_session() -> Option<String> {
+    let id = std::env::var("TAP_SESSION_ID")
+        .ok()
+        .filter(|id| !id.is_empty())?;
+    let sessions = tap_client::list_sessions().ok()?;
+    sessions
+        .iter()
+        .any(|s| s.id == id && s.ended.is_none())
+        .then_some(id)
+}
+
+/// The config with the profile `session` (the latest if `None`) was started
+/// with, or without one if it no longer exists.
+fn session_config(session: Option<&str>) -> eyre::Result<tap_config::Config> {
+    let sessions = tap_client::list_sessions().unwrap_or_default();
+    let entry = match session {
+        Some(id) => sessions.iter().find(|s| s.id == id),
+        None => sessions.last(),
+    };
+    match entry.and_then(|s| s.profile.as_deref()) {
+        Some(profile) => tap_config::load_for_session(Some(profile), &[])
+            .map(|(config, _)| config)
+            .or_else(|_| tap_config::load()),
+        None => tap_config::load(),
+    }
+}
+
+/// Describe a COLORTERM difference between this terminal and the one the
+/// session was started from, so the user can re-export it inside.
+fn colorterm_mismatch(session: Option<&str>) -> Option<String> {
+    let sessions = tap_client::list_sessions().ok()?;
+    let session = match session {
+        Some(id) => sessions.into_iter().find(|s| s.id == id)?,
+        None => sessions.into_iter().last()?,
+    };
+    let current = std::env::var("COLORTERM").ok().filter(|c| !c.is_empty());
+    if current.is_none() || current == session.colorterm {
+        return None;
+    }
+    let current = current?;
+    let started = session.colorterm.as_deref().unwrap_or("unset");
+    Some(format!(
+        "session has COLORTERM={started}; this terminal has {current} — `export COLORTERM={current}` to update"
+    ))
+}
+
+/// Outcome of [`choose_session`].
+enum Choice {
+    /// Attach to this session, or the latest if `None`.
+    Session(Option<String>),
+    Cancelled,
+}
+
+/// Offer a picker when several sessions are running and we're on a
+/// terminal; otherwise fall back to the latest session.
+async fn choose_session() -> eyre::Result<Choice> {
+    use std::io::IsTerminal as _;
+
+    let sessions = tap_client::list_sessions()?;
+    if sessions.len() < 2 || !std::io::stdin().is_terminal() || !std::io::stderr().is_terminal() {
+        return Ok(Choice::Session(None));
+    }
+    Ok(match picker::pick(sessions).await? {
+        Some(id) => Choice::Session(Some(id)),
+        None => Choice::Cancelled,
+    })
+}
+
+async fn run_attach(
+    session: Option<String>,
+    no_resize: bool,
+    read_only: bool,
+    detach_others: bool,
+) -> eyre::Result<()> {
+    let no_resize = no_resize || read_only;
+    // Settle which session "latest" is now, so the connections keybinds open
+    // later reach this one even if a newer session starts meanwhile
+    let session = match session {
+        Some(id) => id,
+        None => {
+            let sessions = tap_client::list_sessions()?;
+            sessions
+                .last()
+                .ok_or(tap_client::Error::NoSessions)?
+                .id
+                .clone()
+        }
+    };
+    let mut client = get_client(Some(session.clone())).await?;
+
+    // Load config for keybinds
+    let tap_config = session_config(Some(&session)).wrap_err("failed to load tap configuration")?;
+    let colorterm_hint = if tap_config.terminal.colorterm_hint {
+        colorterm_mismatch(Some(&session))
+    } else {
+        None
+    };
 
     // Get current terminal size
     let (rows, cols) = get_window_size();
 
+    // The session's own size is shown when viewing it letterboxed. Query it
+    // before attaching, since the connection stops answering requests after.
+    let session_size = if no_resize {
+        client.get_size().await.ok()
+    } else {
+        None
+    };
+
     // Attach to the session
-    let scrollback = client
-        .attach(rows, cols)
+    let attachment = client
+        .attach(rows, cols, no_resize, read_only, detach_others)
         .await
         .wrap_err("failed to attach to session")?;
 
@@ -142,35 +1281,125 @@ async fn run_attach(session: Option<String>) -> eyre::Result<()> {
     let stdin_fd = unsafe { BorrowedFd::borrow_raw(nix::libc::STDIN_FILENO) };
     let orig_termios = setup_terminal(stdin_fd).ok();
 
+    // Replay recent output so this terminal's own scrollback has history,
+    // then scroll it off the screen before drawing the current state
+    if !attachment.tail.is_empty() {
+        let mut stdout = std::io::stdout();
+        let _ = std::io::Write::write_all(&mut stdout, b"\x1b[2J\x1b[H");
+        let _ = std::io::Write::write_all(&mut stdout, &attachment.tail);
+        let _ = std::io::Write::write_all(&mut stdout, b"\x1b[0m\r");
+        let _ = std::io::Write::write_all(&mut stdout, "\n".repeat(usize::from(rows)).as_bytes());
+    }
+
     // Clear screen and print scrollback
     print!("\x1b[2J\x1b[H"); // Clear screen and move to top-left
-    print!("{scrollback}");
+    print!("{}", attachment.scrollback);
+    // Match the cursor shape the app in the session selected
+    print!("\x1b[{} q", attachment.cursor_style);
+    // Save this terminal's title and show the session's
+    let set_title = tap_config.terminal.set_title;
+    if set_title {
+        print!("\x1b[22;0t");
+        if !attachment.title.is_empty() {
+            print!("\x1b]2;{}\x07", attachment.title);
+        }
+    }
     let _ = std::io::Write::flush(&mut std::io::stdout());
 
-    let session_name = session.as_deref().unwrap_or("latest");
-    eprintln!("\x1b[2m[attached to {session_name}]\x1b[0m");
+    let mode = if read_only { ", read-only" } else { "" };
+    if let Some((session_rows, session_cols)) = session_size {
+        tap_server::status::eprint(&format!(
+            "[attached to {session} at {session_rows}x{session_cols}{mode}]"
+        ));
+    } else {
+        tap_server::status::eprint(&format!("[attached to {session}{mode}]"));
+    }
+    if let Some(hint) = colorterm_hint {
+        tap_server::status::eprint(&format!("[{hint}]"));
+    }
 
-    // Load config for keybinds
-    let tap_config = tap_config::load().wrap_err("failed to load tap configuration")?;
     let mut input_processor = tap_server::input::InputProcessor::new(&tap_config)
         .wrap_err("failed to initialize input processor")?;
+    // The server applies these to attached input, where they can't be skipped
+    input_processor.remove(tap_config::InputFilter::RateLimit);
+    input_processor.remove(tap_config::InputFilter::Deny);
 
     // Main I/O loop
     let mut stdin = tokio::io::stdin();
     let mut stdout = tokio::io::stdout();
 
     let mut stdin_buf = vec![0u8; 4096];
+    let mut detached_reason = None;
+    // So the server can tell this client from one that vanished
+    let mut heartbeat = tokio::time::interval(tap_protocol::HEARTBEAT_INTERVAL);
+    // While set, output is kept from the screen, which shows copy mode
+    let mut copy_mode: Option<tap_server::copy_mode::CopyMode> = None;
+    // The local clipboard, read off the loop since paste tools can be slow
+    let (clipboard_tx, mut clipboard_rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut awaiting_clipboard = false;
+    let mut sigwinch =
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::window_change())?;
 
     let exit_code = loop {
+        // Heartbeats and then stdin come first so neither is starved by an
+        // output flood, which would otherwise get this client dropped or
+        // delay keystrokes behind it
         tokio::select! {
+            biased;
+
+            _ = heartbeat.tick() => {
+                if let Err(e) = client.heartbeat().await {
+                    tracing::debug!("heartbeat error: {e}");
+                    break 1;
+                }
+            }
             result = stdin.read(&mut stdin_buf) => {
                 match result {
                     Ok(0) => break 0,
                     Ok(n) => {
-                        let input_bytes = &stdin_buf[..n];
+                        let mut input_bytes = &stdin_buf[..n];
+
+                        // Take the terminal's answer to our OSC 52 query as the paste
+                        let remaining;
+                        if awaiting_clipboard
+                            && let Some((contents, rest)) = tap_server::clipboard::take_osc52_reply(input_bytes)
+                        {
+                            awaiting_clipboard = false;
+                            if let Err(e) = paste_into(&session, &contents).await {
+                                tracing::debug!("paste error: {e}");
+                            }
+                            remaining = rest;
+                            if remaining.is_empty() {
+                                continue;
+                            }
+                            input_bytes = &remaining;
+                        }
+
+                        if let Some(mode) = &mut copy_mode {
+                            let screen = match mode.handle(input_bytes) {
+                                tap_server::copy_mode::Outcome::Stay => mode.render(),
+                                outcome => {
+                                    copy_mode = None;
+                                    if let Err(e) = client.redraw().await {
+                                        tracing::debug!("redraw error: {e}");
+                                        break 1;
+                                    }
+                                    match outcome {
+                                        tap_server::copy_mode::Outcome::Yank(text) => tap_server::clipboard::osc52_copy(text.as_bytes()),
+                                        _ => Vec::new(),
+                                    }
+                                }
+                            };
+                            if stdout.write_all(&screen).await.is_err() {
+                                break 1;
+                            }
+                            let _ = stdout.flush().await;
+                            continue;
+                        }
                         match input_processor.process(input_bytes) {
                             tap_server::input::InputResult::Passthrough(bytes) => {
-                                if !bytes.is_empty() {
+                                // Keybinds (e.g. detach) still work when read-only
+                                if !bytes.is_empty() && !read_only {
                                     if let Err(e) = client.send_input(bytes).await {
                                         tracing::debug!("send_input error: {e}");
                                         break 1;
@@ -180,9 +1409,83 @@ async fn run_attach(session: Option<String>) -> eyre::Result<()> {
                             tap_server::input::InputResult::Action(tap_server::input::KeybindAction::Detach) => {
                                 break 0;
                             }
+                            tap_server::input::InputResult::Action(tap_server::input::KeybindAction::Inject(data)) => {
+                                if !read_only && let Err(e) = client.send_input(data).await {
+                                    tracing::debug!("send_input error: {e}");
+                                    break 1;
+                                }
+                            }
+                            tap_server::input::InputResult::Action(tap_server::input::KeybindAction::Run(command)) => {
+                                run_keybind_command(&command, &session);
+                            }
+                            tap_server::input::InputResult::Action(tap_server::input::KeybindAction::ScrollbackToFile) => {
+                                let message = match save_scrollback(&session).await {
+                                    Ok(path) => format!("[tap: scrollback saved to {}]", path.display()),
+                                    Err(e) => format!("[tap: {e:#}]"),
+                                };
+                                if let Some(line) = tap_server::status::styled(&message, &std::io::stderr()) {
+                                    eprint!("\r\n{line}\r\n");
+                                }
+                            }
+                            tap_server::input::InputResult::Action(tap_server::input::KeybindAction::ClearScrollback) => {
+                                // Watching doesn't extend to wiping the history
+                                if !read_only {
+                                    let cleared = async {
+                                        get_client(Some(session.clone())).await?.clear_scrollback().await?;
+                                        eyre::Ok(())
+                                    };
+                                    match cleared.await {
+                                        Ok(()) => {
+                                            let _ = stdout.write_all(tap_server::scrollback::CLEAR_SAVED_LINES).await;
+                                            let _ = stdout.flush().await;
+                                        }
+                                        Err(e) => tracing::debug!("clear scrollback error: {e}"),
+                                    }
+                                }
+                            }
+                            tap_server::input::InputResult::Action(tap_server::input::KeybindAction::Kill) => {
+                                // The attached connection only carries input, so ask on another
+                                if !read_only {
+                                    let killed = async {
+                                        get_client(Some(session.clone())).await?.kill(None).await?;
+                                        eyre::Ok(())
+                                    };
+                                    if let Err(e) = killed.await {
+                                        tracing::debug!("kill error: {e}");
+                                    }
+                                }
+                            }
+                            tap_server::input::InputResult::Action(tap_server::input::KeybindAction::CopyMode) => {
+                                let text = async {
+                                    let text = get_client(Some(session.clone())).await?.get_copy_text().await?;
+                                    eyre::Ok(text)
+                                };
+                                match text.await {
+                                    Ok((lines, cursor)) => {
+                                        let mode = tap_server::copy_mode::CopyMode::new(lines, cursor, get_window_size());
+                                        if stdout.write_all(&mode.render()).await.is_err() {
+                                            break 1;
+                                        }
+                                        let _ = stdout.flush().await;
+                                        copy_mode = Some(mode);
+                                    }
+                                    Err(e) => tracing::debug!("copy mode error: {e}"),
+                                }
+                            }
+                            tap_server::input::InputResult::Action(tap_server::input::KeybindAction::PasteClipboard) => {
+                                if !read_only {
+                                    let clipboard_tx = clipboard_tx.clone();
+                                    tokio::task::spawn_blocking(move || {
+                                        let _ = clipboard_tx.send(tap_server::clipboard::read_system_clipboard());
+                                    });
+                                }
+                            }
                             tap_server::input::InputResult::Action(tap_server::input::KeybindAction::OpenEditor) => {
                                 // Not supported in attach mode
                             }
+                            tap_server::input::InputResult::Rejected(reason) => {
+                                tracing::debug!("dropped input: {reason}");
+                            }
                             tap_server::input::InputResult::NeedMore => {
                                 // Wait for timeout or more input
                             }
@@ -194,27 +1497,73 @@ async fn run_attach(session: Option<String>) -> eyre::Result<()> {
                     }
                 }
             }
-            result = client.read_output() => {
+            Some(contents) = clipboard_rx.recv() => {
+                match contents {
+                    Some(contents) => {
+                        if let Err(e) = paste_into(&session, &contents).await {
+                            tracing::debug!("paste error: {e}");
+                        }
+                    }
+                    None => {
+                        // No paste tool; ask the outer terminal instead
+                        awaiting_clipboard = true;
+                        if stdout.write_all(tap_server::clipboard::OSC52_QUERY).await.is_err() {
+                            break 1;
+                        }
+                        let _ = stdout.flush().await;
+                    }
+                }
+            }
+            result = client.read_event() => {
                 match result {
-                    Ok(Some(data)) => {
+                    Ok(Some(tap_client::OutputEvent::Output(data))) => {
+                        if copy_mode.is_some() {
+                            continue;
+                        }
                         if stdout.write_all(&data).await.is_err() {
                             break 1;
                         }
                         let _ = stdout.flush().await;
                     }
+                    Ok(Some(tap_client::OutputEvent::PendingInput { id, data, delay_ms })) => {
+                        let notice = format!(
+                            "[tap: injecting {data:?} in {}s — `tap cancel {id}` to stop]",
+                            delay_ms.div_ceil(1000)
+                        );
+                        if let Some(line) = tap_server::status::styled(&notice, &std::io::stderr()) {
+                            eprint!("\r\n{line}\r\n");
+                        }
+                    }
+                    Ok(Some(
+                        tap_client::OutputEvent::Resized { .. } | tap_client::OutputEvent::Event(_),
+                    )) => {}
+                    Ok(Some(tap_client::OutputEvent::Detached { reason })) => {
+                        detached_reason = Some(reason);
+                        break 0;
+                    }
                     Ok(None) => {
                         // Session ended
                         break 0;
                     }
                     Err(e) => {
-                        tracing::debug!("read_output error: {e}");
+                        tracing::debug!("read_event error: {e}");
                         break 0;
                     }
                 }
             }
+            _ = sigwinch.recv(), if copy_mode.is_some() => {
+                if let Some(mode) = &mut copy_mode {
+                    mode.resize(get_window_size());
+                    if stdout.write_all(&mode.render()).await.is_err() {
+                        break 1;
+                    }
+                    let _ = stdout.flush().await;
+                }
+            }
             _ = tokio::time::sleep(input_processor.escape_timeout()), if input_processor.has_pending_escape() => {
                 if let tap_server::input::InputResult::Passthrough(bytes) = input_processor.timeout_escape()
                     && !bytes.is_empty()
+                    && !read_only
                 {
                     let _ = client.send_input(bytes).await;
                 }
@@ -222,22 +1571,215 @@ async fn run_attach(session: Option<String>) -> eyre::Result<()> {
         }
     };
 
-    // Restore terminal
+    // Restore terminal and its own cursor shape
     if let Some(ref termios) = orig_termios {
         restore_terminal(stdin_fd, termios);
     }
+    print!("\x1b[0 q");
+    if set_title {
+        print!("\x1b[23;0t");
+    }
+    let _ = std::io::Write::flush(&mut std::io::stdout());
 
-    eprintln!("\n\x1b[2m[detached]\x1b[0m");
+    match detached_reason {
+        Some(reason) => tap_server::status::eprint(&format!("\n[detached: {reason}]")),
+        None => tap_server::status::eprint("\n[detached]"),
+    }
 
     std::process::exit(exit_code);
 }
 
+/// Rasterize an SVG screenshot to a PNG file, in a font built into tap so
+/// it looks the same everywhere. Italics fall back to the upright face.
+fn write_png(svg: &str, path: &std::path::Path) -> eyre::Result<()> {
+    let mut options = resvg::usvg::Options::default();
+    let fonts = options.fontdb_mut();
+    fonts.load_font_data(include_bytes!("../fonts/DejaVuSansMono.ttf").to_vec());
+    fonts.load_font_data(include_bytes!("../fonts/DejaVuSansMono-Bold.ttf").to_vec());
+    fonts.set_monospace_family("DejaVu Sans Mono");
+    let tree =
+        resvg::usvg::Tree::from_str(svg, &options).wrap_err("failed to parse screenshot SVG")?;
+
+    let size = tree.size().to_int_size();
+    let mut pixmap = resvg::tiny_skia::Pixmap::new(size.width(), size.height())
+        .ok_or_else(|| eyre::eyre!("screenshot is empty"))?;
+    resvg::render(
+        &tree,
+        resvg::tiny_skia::Transform::default(),
+        &mut pixmap.as_mut(),
+    );
+    pixmap
+        .save_png(path)
+        .wrap_err_with(|| format!("failed to write {}", path.display()))?;
+    Ok(())
+}
+
+/// Follow the output of all matching sessions, one labelled line at a time.
+async fn run_subscribe_all(filter: tap_client::SessionFilter, follow: bool) -> eyre::Result<()> {
+    let mut multi = tap_client::MultiSubscriber::new(filter, follow).await?;
+    if multi.active() == 0 && !follow {
+        return Err(tap_client::Error::NoSessions.into());
+    }
+
+    let color = tap_server::status::color(&std::io::stdout());
+    let mut prefixers = std::collections::HashMap::new();
+    let mut stdout = tokio::io::stdout();
+    while let Some((id, event)) = multi.next().await? {
+        let prefixer = prefixers
+            .entry(id)
+            .or_insert_with_key(|id| prefix::LinePrefixer::new(id, color));
+        let data = match event {
+            tap_client::SessionEvent::Output(data) => prefixer.prefix(&data),
+            tap_client::SessionEvent::Ended => prefixer.line("[session ended]"),
+        };
+        stdout.write_all(&data).await?;
+        stdout.flush().await?;
+    }
+    Ok(())
+}
+
+async fn run_repl(
+    session: Option<String>,
+    bracketed_paste: bool,
+    idle: std::time::Duration,
+) -> eyre::Result<()> {
+    use tokio::io::AsyncBufReadExt as _;
+
+    let mut injector = get_client(session.clone()).await?;
+    let mut watcher = get_client(session).await?;
+    watcher.subscribe().await?;
+
+    let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    let mut stdout = tokio::io::stdout();
+
+    loop {
+        stdout.write_all(b"tap> ").await?;
+        stdout.flush().await?;
+
+        let Some(line) = lines.next_line().await? else {
+            break;
+        };
+
+        let payload = if bracketed_paste {
+            format!("\x1b[200~{line}\x1b[201~\r")
+        } else {
+            format!("{line}\r")
+        };
+        injector.inject(&payload).await?;
+
+        // Print output until the session goes quiet
+        loop {
+            match tokio::time::timeout(idle, watcher.read_output()).await {
+                Ok(Ok(Some(data))) => {
+                    stdout.write_all(&data).await?;
+                    stdout.flush().await?;
+                }
+                Ok(Ok(None)) => return Ok(()),
+                Ok(Err(e)) => return Err(e.into()),
+                Err(_) => break,
+            }
+        }
+        stdout.write_all(b"\n").await?;
+    }
+
+    Ok(())
+}
+
+/// Run `command` like `tap exec`, but whenever it stops at a prompt for
+/// input, show the prompt and pass the next line typed here to the session,
+/// then go back to capturing.
+async fn run_exec_interactive(
+    session: Option<String>,
+    command: &str,
+    timeout: Option<std::time::Duration>,
+) -> eyre::Result<tap_client::CommandOutput> {
+    use tokio::io::AsyncBufReadExt as _;
+
+    let mut runner = get_client(session.clone()).await?;
+    let mut answerer = get_client(session).await?;
+    runner.start_interactive_command(command, timeout).await?;
+
+    let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    // Whether the session echoes the answer, while a prompt awaits one
+    let mut awaiting: Option<bool> = None;
+    loop {
+        let event = match awaiting {
+            None => runner.read_command_event().await?,
+            Some(echo) => tokio::select! {
+                event = runner.read_command_event() => event?,
+                line = lines.next_line() => {
+                    if !echo {
+                        set_local_echo(true);
+                        eprintln!();
+                    }
+                    let Some(line) = line? else {
+                        eyre::bail!("stdin closed while the command was waiting for input");
+                    };
+                    answerer.write_input(format!("{line}\r").into_bytes()).await?;
+                    awaiting = None;
+                    continue;
+                }
+            },
+        };
+        match event {
+            tap_client::CommandEvent::Prompt { prompt, echo } => {
+                eprint!("{prompt} ");
+                set_local_echo(echo);
+                awaiting = Some(echo);
+            }
+            tap_client::CommandEvent::Finished(output) => {
+                if awaiting == Some(false) {
+                    set_local_echo(true);
+                    eprintln!();
+                }
+                return Ok(output);
+            }
+        }
+    }
+}
+
+/// Turn echo of typed input on this terminal on or off (for answering
+/// password prompts). Does nothing when stdin isn't a terminal.
+fn set_local_echo(enabled: bool) {
+    let stdin_fd = unsafe { BorrowedFd::borrow_raw(nix::libc::STDIN_FILENO) };
+    let Ok(mut termios) = nix::sys::termios::tcgetattr(stdin_fd) else {
+        return;
+    };
+    termios
+        .local_flags
+        .set(nix::sys::termios::LocalFlags::ECHO, enabled);
+    let _ = nix::sys::termios::tcsetattr(stdin_fd, nix::sys::termios::SetArg::TCSANOW, &termios);
+}
+
+/// Relocate sockets and sessions.json as configured, unless
+/// `$TAP_STATE_DIR` already does.
+fn install_resolver() -> eyre::Result<()> {
+    if std::env::var_os(tap_protocol::STATE_DIR_ENV).is_some_and(|dir| !dir.is_empty()) {
+        return Ok(());
+    }
+    let Ok(tap_config) = tap_config::load() else {
+        // Reported by whichever command needs the config
+        return Ok(());
+    };
+    let paths = tap_config.paths;
+    if let Some(dir) = paths.state_dir {
+        tap_protocol::set_resolver(tap_protocol::DirResolver(dir));
+    } else if let Some(command) = paths.state_dir_command {
+        let resolver = tap_protocol::DirResolver::from_command(&command)
+            .wrap_err("failed to get the state directory from `state_dir_command`")?;
+        tap_protocol::set_resolver(resolver);
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> eyre::Result<()> {
     color_eyre::install()?;
 
     let args = <Args as clap::Parser>::parse();
 
+    tap_server::status::set_raw(args.raw);
+
     // Setup logging
     if args.debug {
         let log_dir = dirs::home_dir()
@@ -264,70 +1806,838 @@ async fn main() -> eyre::Result<()> {
             .init();
     }
 
+    install_resolver()?;
+
     // Default to Start if no command given
     let command = args.command.unwrap_or(Command::Start {
         command: vec![],
         detached: false,
+        no_fork: false,
+        wait_for: None,
+        wait_ms: None,
+        name: None,
+        tags: vec![],
+        record: None,
+        log_file: None,
+        quiet: false,
+        cwd_of: None,
+        restore: None,
+        profile: None,
+        env: vec![],
+        allow_nested: false,
     });
 
     match command {
-        Command::Start { command, detached } => {
-            run_start(command, detached).await?;
+        Command::Start {
+            command,
+            detached,
+            no_fork,
+            wait_for,
+            wait_ms,
+            name,
+            tags,
+            record,
+            log_file,
+            quiet,
+            cwd_of,
+            restore,
+            profile,
+            env,
+            allow_nested,
+            listen,
+            auth_token,
+        } => {
+            let record = record.map(std::path::absolute).transpose()?;
+            let log_file = log_file.map(std::path::absolute).transpose()?;
+            if let Some(session) = cwd_of {
+                let cwd = get_client(Some(session)).await?.get_cwd().await?;
+                std::env::set_current_dir(&cwd)
+                    .wrap_err_with(|| format!("failed to change to {cwd}"))?;
+            }
+            if let Some(name) = &name
+                && tap_client::list_sessions()?.iter().any(|s| &s.id == name)
+            {
+                eyre::bail!("session '{name}' already exists");
+            }
+            if let Some(profile) = &profile
+                && !tap_config::load()?.profiles.contains_key(profile)
+            {
+                eyre::bail!("no profile named '{profile}' in the config");
+            }
+            let parent = enclosing_session();
+            if let Some(parent) = &parent
+                && !allow_nested
+            {
+                // Its keybinds would be caught by the outer session first
+                if !detached {
+                    eyre::bail!(
+                        "already inside session '{parent}'; detach first, or pass --allow-nested"
+                    );
+                }
+                eprintln!("tap: starting a session inside '{parent}' (--allow-nested to silence)");
+            }
+            let listen = match listen {
+                Some(addr) => {
+                    let token = auth_token
+                        .or_else(|| std::env::var(tap_protocol::AUTH_TOKEN_ENV).ok())
+                        .filter(|token| !token.is_empty())
+                        .ok_or_else(|| {
+                            eyre::eyre!(
+                                "--listen needs a token; pass --auth-token or set ${}",
+                                tap_protocol::AUTH_TOKEN_ENV
+                            )
+                        })?;
+                    Some(tap_server::remote::Listen { addr, token })
+                }
+                None => None,
+            };
+            let config = tap_server::ServerConfig {
+                command,
+                session_id: name,
+                detached,
+                tags,
+                record,
+                log_file,
+                adopt: None,
+                quiet,
+                restore: restore
+                    .as_deref()
+                    .map(tap_server::persist::load)
+                    .transpose()?,
+                profile,
+                env,
+                parent,
+                listen,
+            };
+            if wait_for.is_some() || wait_ms.is_some() {
+                let timeout =
+                    std::time::Duration::from_millis(wait_ms.unwrap_or(DEFAULT_START_WAIT_MS));
+                run_start_and_wait(config, wait_for, timeout).await?;
+            } else if detached && !no_fork {
+                run_start_background(config).await?;
+            } else {
+                run_start(config).await?;
+            }
         }
-        Command::Attach { session } => {
-            run_attach(session).await?;
+        Command::Attach {
+            session,
+            no_resize,
+            read_only,
+            detach_others,
+        } => {
+            let session = match session {
+                Some(id) => Some(id),
+                None => match choose_session().await? {
+                    Choice::Session(id) => id,
+                    Choice::Cancelled => return Ok(()),
+                },
+            };
+            run_attach(session, no_resize, read_only, detach_others).await?;
         }
-        Command::List => {
-            let sessions = tap_client::list_sessions()?;
-            if sessions.is_empty() {
+        Command::List {
+            filter,
+            long,
+            tree,
+            host,
+        } => {
+            let filter: tap_client::SessionFilter = filter.into();
+            let sessions = match host {
+                Some(host) => {
+                    let now = chrono::Utc::now();
+                    let mut sessions = tap_client::list_remote_sessions(&host).await?;
+                    sessions.retain(|s| filter.matches(s, now));
+                    sessions
+                }
+                None => tap_client::list_sessions_filtered(&filter)?,
+            };
+            if args.json {
+                println!("{}", serde_json::to_string(&sessions)?);
+            } else if args.raw {
+                for session in sessions {
+                    println!(
+                        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                        session.id,
+                        session.pid,
+                        session.attached,
+                        session.started,
+                        session.tags.join(","),
+                        session.command.join(" "),
+                        session.cwd.as_deref().unwrap_or(""),
+                        session.title.as_deref().unwrap_or("")
+                    );
+                }
+            } else if sessions.is_empty() {
                 println!("No active sessions");
             } else {
                 println!(
-                    "{:<25} {:<8} {:<10} {:<25} COMMAND",
-                    "ID", "PID", "ATTACHED", "STARTED"
+                    "{:<25} {:<8} {:<10} {:<25} {:<15} {:<30} {:<20} COMMAND",
+                    "ID", "PID", "ATTACHED", "STARTED", "TAGS", "CWD", "TITLE"
                 );
-                for session in sessions {
+                let rows = if tree {
+                    tap_client::session_tree(&sessions)
+                } else {
+                    sessions.iter().map(|session| (0, session)).collect()
+                };
+                for (depth, session) in rows {
                     let attached_str = if session.attached { "yes" } else { "no" };
+                    let id = match depth {
+                        0 => session.id.clone(),
+                        depth => format!("{}└ {}", "  ".repeat(depth - 1), session.id),
+                    };
                     println!(
-                        "{:<25} {:<8} {:<10} {:<25} {}",
-                        session.id,
+                        "{:<25} {:<8} {:<10} {:<25} {:<15} {:<30} {:<20} {}",
+                        id,
                         session.pid,
                         attached_str,
                         session.started,
+                        session.tags.join(","),
+                        session.cwd.as_deref().unwrap_or("-"),
+                        session
+                            .title
+                            .as_deref()
+                            .filter(|t| !t.is_empty())
+                            .unwrap_or("-"),
                         session.command.join(" ")
                     );
+                    if long {
+                        for note in &session.notes {
+                            println!("    {}  {}", note.time, note.text);
+                        }
+                    }
+                }
+            }
+        }
+        Command::Kill {
+            session,
+            all,
+            signal,
+            filter,
+        } => {
+            let filter: tap_client::SessionFilter = filter.into();
+            let sessions = match session {
+                // Sessions elsewhere aren't in this machine's list
+                Some(id) if tap_client::is_remote(&id) => {
+                    tap_client::Client::connect(&id).await?.kill(signal).await?;
+                    println!("Killed {id}");
+                    return Ok(());
+                }
+                Some(id) => {
+                    let sessions = tap_client::list_sessions()?;
+                    let session = sessions
+                        .into_iter()
+                        .find(|s| s.id == id)
+                        .ok_or(tap_client::Error::SessionNotFound(id))?;
+                    vec![session]
+                }
+                None if all || !filter.is_empty() => tap_client::list_sessions_filtered(&filter)?,
+                None => eyre::bail!("specify a session ID, --all, or a filter such as --tag"),
+            };
+            let mut failed = 0;
+            for session in sessions {
+                let result = match tap_client::Client::connect(&session.id).await {
+                    Ok(mut client) => client.kill(signal).await,
+                    Err(e) => Err(e),
+                };
+                match result {
+                    Ok(()) => println!("Killed {}", session.id),
+                    Err(e) => {
+                        eprintln!("failed to kill {}: {e}", session.id);
+                        failed += 1;
+                    }
                 }
             }
+            if failed > 0 {
+                eyre::bail!("failed to kill {failed} session(s)");
+            }
         }
-        Command::Scrollback { session, lines } => {
+        Command::Scrollback {
+            session,
+            lines,
+            since_mark,
+        } => {
+            use std::io::IsTerminal as _;
+
             let mut client = get_client(session).await?;
-            let content = client.get_scrollback(lines).await?;
+            let content = match since_mark {
+                Some(mark) => client.get_scrollback_since(&mark, lines).await?,
+                None => client.get_scrollback(lines).await?,
+            };
             print!("{content}");
+            // Keep the shell prompt off the last line when a person is reading
+            if !args.raw && !content.ends_with('\n') && std::io::stdout().is_terminal() {
+                println!();
+            }
+        }
+        Command::Copy {
+            session,
+            lines,
+            app,
+        } => {
+            let mut client = get_client(session).await?;
+            let content = if app {
+                client.get_clipboard().await?
+            } else {
+                client.get_scrollback(lines).await?
+            };
+            let bytes = content.into_bytes();
+            let len = bytes.len();
+            let copied = tokio::task::spawn_blocking(move || {
+                tap_server::clipboard::write_system_clipboard(&bytes)
+                    || copy_via_terminal(&bytes).is_ok()
+            })
+            .await?;
+            if !copied {
+                eyre::bail!("no clipboard command found and no terminal to copy through");
+            }
+            tap_server::status::eprint(&format!("[copied {len} bytes]"));
+        }
+        Command::ShareOutput { session, lines } => {
+            let mut client = get_client(session).await?;
+            let content = client.get_scrollback(lines).await?;
+            let tap_config = tap_config::load().wrap_err("failed to load tap configuration")?;
+            let link =
+                tokio::task::spawn_blocking(move || share::upload(&tap_config.share, &content))
+                    .await??;
+            println!("{link}");
+        }
+        Command::Restore { sessions, list } => {
+            for session in &sessions {
+                require_local(session, "restore")?;
+            }
+            run_restore(&sessions, list).await?;
+        }
+        Command::RepairIndex => run_repair_index().await?,
+        Command::Rename { session, name } => {
+            let mut client = tap_client::Client::connect(&session).await?;
+            client.rename(&name).await?;
+        }
+        Command::Note { session, text } => {
+            let mut client = tap_client::Client::connect(&session).await?;
+            client.note(&text).await?;
+        }
+        Command::History { session } => {
+            if session
+                .as_deref()
+                .is_some_and(|address| address.starts_with(tap_client::TCP_SCHEME))
+            {
+                eyre::bail!("`tap history` is not supported for sessions served over TCP");
+            }
+            // A session on another machine is found in its list, over SSH
+            let (sessions, id, prefix) = match session.as_deref().and_then(tap_client::parse_remote)
+            {
+                Some((destination, id)) => (
+                    tap_client::list_remote_sessions(destination).await?,
+                    id.map(str::to_string),
+                    format!("{destination}:"),
+                ),
+                None => (tap_client::list_sessions()?, session, String::new()),
+            };
+            let session = match id {
+                Some(id) => sessions
+                    .into_iter()
+                    .find(|s| s.id == id)
+                    .ok_or(tap_client::Error::SessionNotFound(id))?,
+                None => sessions
+                    .into_iter()
+                    .last()
+                    .ok_or(tap_client::Error::NoSessions)?,
+            };
+            println!(
+                "{}  started: {}",
+                session.started,
+                session.command.join(" ")
+            );
+            for note in &session.notes {
+                println!("{}  {}", note.time, note.text);
+            }
+            if let Ok(times) = async {
+                let mut client =
+                    tap_client::Client::connect(&format!("{prefix}{}", session.id)).await?;
+                client.get_times().await
+            }
+            .await
+            {
+                println!(
+                    "running {}, cpu {} user {} sys",
+                    format_duration_ms(times.wall_ms),
+                    format_duration_ms(times.user_ms),
+                    format_duration_ms(times.sys_ms)
+                );
+            }
+        }
+        Command::Time { session } => {
+            let mut client = get_client(session).await?;
+            let times = client.get_times().await?;
+            if args.json {
+                println!("{}", serde_json::to_string(&times)?);
+            } else {
+                println!("real\t{}", format_duration_ms(times.wall_ms));
+                println!("user\t{}", format_duration_ms(times.user_ms));
+                println!("sys\t{}", format_duration_ms(times.sys_ms));
+            }
+        }
+        Command::Mark { session, label } => {
+            let mut client = get_client(session).await?;
+            client.mark(&label).await?;
         }
         Command::Cursor { session } => {
             let mut client = get_client(session).await?;
-            let (row, col) = client.get_cursor().await?;
-            println!("Row: {row}, Col: {col}");
+            let cursor = client.get_cursor().await?;
+            if args.json {
+                println!("{}", serde_json::to_string(&cursor)?);
+            } else if cursor.char_col == cursor.col {
+                println!("Row: {}, Col: {}", cursor.row, cursor.col);
+            } else {
+                println!(
+                    "Row: {}, Col: {} (char {})",
+                    cursor.row, cursor.col, cursor.char_col
+                );
+            }
+        }
+        Command::Title { session } => {
+            let mut client = get_client(session).await?;
+            println!("{}", client.get_title().await?);
+        }
+        Command::Cwd { session } => {
+            let mut client = get_client(session).await?;
+            println!("{}", client.get_cwd().await?);
         }
         Command::Size { session } => {
             let mut client = get_client(session).await?;
             let (rows, cols) = client.get_size().await?;
-            println!("{rows}x{cols}");
+            if args.json {
+                println!("{}", serde_json::json!({ "rows": rows, "cols": cols }));
+            } else {
+                println!("{rows}x{cols}");
+            }
+        }
+        Command::Inject {
+            session,
+            text,
+            until,
+            until_prompt,
+            capture_ms,
+            timeout,
+        } => {
+            let mut client = get_client(session).await?;
+            let until = match (until, until_prompt, capture_ms) {
+                (Some(pattern), _, _) => Some(tap_client::CaptureEnd::Regex { pattern }),
+                (_, true, _) => Some(tap_client::CaptureEnd::PromptMark),
+                (_, _, Some(ms)) => Some(tap_client::CaptureEnd::Timeout { ms }),
+                _ => None,
+            };
+            match until {
+                Some(until) => {
+                    let timeout = timeout.map(std::time::Duration::from_secs);
+                    let output = client.inject_and_capture(&text, until, timeout).await?;
+                    println!("{output}");
+                }
+                None => {
+                    let seq = client.inject(&text).await?;
+                    println!("Injected (output sequence {seq})");
+                }
+            }
+        }
+        Command::SendKeys { session, keys } => {
+            let mut client = get_client(session).await?;
+            client.send_keys(&keys).await?;
+        }
+        Command::Events { session, kinds } => {
+            let mut client = get_client(session).await?;
+            client.subscribe_events(kinds).await?;
+            while let Some(event) = client.read_event().await? {
+                let tap_client::OutputEvent::Event(event) = event else {
+                    continue;
+                };
+                if args.json {
+                    println!("{}", serde_json::to_string(&event)?);
+                } else {
+                    println!("{}", describe_event(&event));
+                }
+            }
         }
-        Command::Inject { session, text } => {
+        Command::LastOutput { session, n } => {
             let mut client = get_client(session).await?;
-            client.inject(&text).await?;
-            println!("Injected");
+            let (output, _) = client.command_output(n).await?;
+            if !output.is_empty() {
+                println!("{output}");
+            }
+        }
+        Command::Exec {
+            session,
+            timeout,
+            interactive,
+            command,
+        } => {
+            let timeout = timeout.map(std::time::Duration::from_secs);
+            let result = if interactive {
+                run_exec_interactive(session, &command, timeout).await?
+            } else {
+                let mut client = get_client(session).await?;
+                client.run_command(&command, timeout).await?
+            };
+            if !result.output.is_empty() {
+                println!("{}", result.output);
+            }
+            std::process::exit(result.exit_code);
         }
-        Command::Subscribe { session } => {
+        Command::Subscribe {
+            all: true,
+            follow,
+            filter,
+            ..
+        } => run_subscribe_all(filter.into(), follow).await?,
+        Command::Subscribe {
+            session, from_seq, ..
+        } => {
             let mut client = get_client(session).await?;
-            client.subscribe().await?;
+            match from_seq {
+                Some(seq) => client.subscribe_from(seq).await?,
+                None => client.subscribe().await?,
+            }
             let mut stdout = tokio::io::stdout();
             while let Some(data) = client.read_output().await? {
                 stdout.write_all(&data).await?;
                 stdout.flush().await?;
             }
         }
+        Command::Resize {
+            session,
+            size: (rows, cols),
+        } => {
+            let mut client = get_client(session).await?;
+            let (applied_rows, applied_cols) = client.set_size(rows, cols).await?;
+            if (applied_rows, applied_cols) != (rows, cols) {
+                eprintln!("capped to fit the smallest attached terminal");
+            }
+            println!("{applied_rows}x{applied_cols}");
+        }
+        Command::Push {
+            session,
+            local,
+            remote_path,
+        } => {
+            let data = std::fs::read(&local)
+                .wrap_err_with(|| format!("failed to read {}", local.display()))?;
+            let remote_path = match remote_path {
+                Some(path) => path,
+                None => local
+                    .file_name()
+                    .ok_or_else(|| eyre::eyre!("{} has no file name", local.display()))?
+                    .to_string_lossy()
+                    .into_owned(),
+            };
+            let mut client = get_client(Some(session)).await?;
+            let commands = push::push_commands(&data, &remote_path);
+            let total = commands.len();
+            for (i, command) in commands.into_iter().enumerate() {
+                client.write_input(command.into_bytes()).await?;
+                eprint!("\r{}/{total}", i + 1);
+            }
+            eprintln!("\rpushed {} bytes to {remote_path}", data.len());
+        }
+        Command::Cancel { session, id } => {
+            let mut client = get_client(session).await?;
+            let count = client.cancel_input(id).await?;
+            println!("Cancelled {count}");
+        }
+        Command::Ping {
+            session,
+            timeout_ms,
+        } => {
+            let code = ping(session, std::time::Duration::from_millis(timeout_ms)).await;
+            std::process::exit(code);
+        }
+        Command::Detach { session } => {
+            let mut client = get_client(session).await?;
+            client.detach_clients().await?;
+        }
+        Command::Migrate { session, to } => {
+            require_local(&session, "migrate")?;
+            let mut client = tap_client::Client::connect_unchecked(&session).await?;
+            client.migrate(&to).await?;
+        }
+        Command::Receive { socket, timeout } => {
+            let timeout = timeout.map(std::time::Duration::from_secs);
+            let handoff =
+                tokio::task::spawn_blocking(move || tap_server::migrate::receive(&socket, timeout))
+                    .await??;
+            run_start(tap_server::ServerConfig {
+                adopt: Some(handoff),
+                detached: true,
+                ..tap_server::ServerConfig::default()
+            })
+            .await?;
+        }
+        Command::SelfUpgradeHandoff { session, exe } => {
+            let exe = match exe {
+                Some(exe) => std::path::absolute(exe)?,
+                None => std::env::current_exe().wrap_err("failed to locate tap executable")?,
+            };
+            let ids = match session {
+                Some(session) => {
+                    require_local(&session, "self-upgrade-handoff")?;
+                    vec![session]
+                }
+                None => tap_client::list_sessions()?
+                    .into_iter()
+                    .filter(|s| !s.attached)
+                    .map(|s| s.id)
+                    .collect(),
+            };
+            let mut failed = false;
+            for id in ids {
+                let result = async {
+                    let mut client = tap_client::Client::connect_unchecked(&id).await?;
+                    client.upgrade(&exe).await
+                }
+                .await;
+                match result {
+                    Ok(()) => println!("{id}: upgraded"),
+                    Err(e) => {
+                        eprintln!("{id}: {e}");
+                        failed = true;
+                    }
+                }
+            }
+            if failed {
+                std::process::exit(1);
+            }
+        }
+        Command::ResumeHandoff {
+            master_fd,
+            listener_fd,
+            state_fd,
+        } => {
+            let handoff = tap_server::migrate::from_fds(master_fd, listener_fd, state_fd)?;
+            run_start(tap_server::ServerConfig {
+                adopt: Some(handoff),
+                detached: true,
+                ..tap_server::ServerConfig::default()
+            })
+            .await?;
+        }
+        Command::Proxy { session } => run_proxy(session).await?,
+        Command::Play {
+            file,
+            speed,
+            max_idle,
+            new_session,
+        } => {
+            if speed <= 0.0 {
+                eyre::bail!("--speed must be positive");
+            }
+            let frames = play::load(&file)?;
+            if new_session {
+                let exe = std::env::current_exe().wrap_err("failed to locate tap executable")?;
+                let mut command = vec![
+                    exe.display().to_string(),
+                    "play".to_string(),
+                    std::path::absolute(&file)?.display().to_string(),
+                    "--speed".to_string(),
+                    speed.to_string(),
+                ];
+                if let Some(max_idle) = max_idle {
+                    command.extend(["--max-idle".to_string(), max_idle.to_string()]);
+                }
+                run_start(tap_server::ServerConfig {
+                    command,
+                    detached: true,
+                    ..tap_server::ServerConfig::default()
+                })
+                .await?;
+            } else {
+                play::play(&frames, play::Options { speed, max_idle }).await?;
+            }
+        }
+        Command::Record {
+            session,
+            path,
+            stop,
+        } => {
+            let mut client = get_client(session).await?;
+            if stop {
+                let path = client.stop_recording().await?;
+                println!("Saved {path}");
+            } else if let Some(path) = path {
+                client.start_recording(&path).await?;
+            }
+        }
+        Command::Wait {
+            session,
+            stall_after,
+        } => {
+            let waited = async {
+                let mut client = get_client(session.clone()).await?;
+                let exit_code = client
+                    .wait_watched(stall_after.map(std::time::Duration::from_secs), |idle| {
+                        eprintln!("tap: session stalled, no output for {}s", idle.as_secs());
+                    })
+                    .await?;
+                eyre::Ok(exit_code)
+            };
+            let exit_code = match waited.await {
+                Ok(exit_code) => exit_code,
+                // It may have ended before (or while) we asked
+                Err(e) => match session.as_deref().map(tap_client::ended_exit_code) {
+                    Some(Ok(Some(exit_code))) => Some(exit_code),
+                    _ => return Err(e),
+                },
+            };
+            let Some(exit_code) = exit_code else {
+                eprintln!(
+                    "tap: session ended; its exit status can't be observed after a migration"
+                );
+                std::process::exit(0);
+            };
+            std::process::exit(exit_code);
+        }
+        Command::WaitFor {
+            session,
+            timeout,
+            only_matching,
+            pattern,
+        } => {
+            let mut client = get_client(session).await?;
+            let timeout = timeout.map(std::time::Duration::from_secs);
+            match client.wait_for_pattern(&pattern, timeout).await {
+                Ok(Some(found)) if only_matching => println!("{}", found.matched),
+                Ok(Some(found)) => println!("{}", found.line),
+                Ok(None) => {
+                    eprintln!("tap: session ended before '{pattern}' appeared");
+                    std::process::exit(2);
+                }
+                Err(tap_client::Error::TimedOut(_)) => {
+                    eprintln!("tap: timed out waiting for '{pattern}'");
+                    std::process::exit(1);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Command::Edit { session } => {
+            let mut client = get_client(session).await?;
+            if let Some(path) = client.open_editor().await? {
+                println!("{path}");
+            }
+        }
+        Command::Repl {
+            session,
+            bracketed_paste,
+            idle_ms,
+        } => {
+            run_repl(
+                session,
+                bracketed_paste,
+                std::time::Duration::from_millis(idle_ms),
+            )
+            .await?;
+        }
+        Command::Mirror { src, dst } => {
+            let mut source = get_client(Some(src)).await?;
+            source.subscribe().await?;
+            match dst {
+                Some(dst) => {
+                    let mut target = get_client(Some(dst.clone())).await?;
+                    while let Some(data) = source.read_output().await? {
+                        target
+                            .write_input(data)
+                            .await
+                            .wrap_err_with(|| format!("failed to mirror into session '{dst}'"))?;
+                    }
+                }
+                None => {
+                    let mut stdout = tokio::io::stdout();
+                    while let Some(data) = source.read_output().await? {
+                        stdout.write_all(&data).await?;
+                        stdout.flush().await?;
+                    }
+                }
+            }
+        }
+        Command::SetBuffer {
+            session,
+            name,
+            text,
+        } => {
+            let text = match text {
+                Some(text) => text,
+                None => {
+                    let mut text = String::new();
+                    tokio::io::stdin()
+                        .read_to_string(&mut text)
+                        .await
+                        .wrap_err("failed to read buffer contents from stdin")?;
+                    text
+                }
+            };
+            let mut client = get_client(session).await?;
+            client.set_buffer(&name, &text).await?;
+        }
+        Command::GetBuffer { session, name } => {
+            let mut client = get_client(session).await?;
+            let data = client.get_buffer(&name).await?;
+            print!("{data}");
+        }
+        Command::Paste { session, text } => {
+            let text = match text {
+                Some(text) => text,
+                None => {
+                    let mut text = String::new();
+                    tokio::io::stdin()
+                        .read_to_string(&mut text)
+                        .await
+                        .wrap_err("failed to read text to paste from stdin")?;
+                    text
+                }
+            };
+            let mut client = get_client(session).await?;
+            client.paste(&text).await?;
+        }
+        Command::PasteBuffer { session, name } => {
+            let mut client = get_client(session).await?;
+            client.paste_buffer(&name).await?;
+        }
+        Command::Env { session, name } => {
+            let mut client = get_client(session).await?;
+            let vars = client.get_env().await?;
+            match name {
+                Some(name) => {
+                    let value = vars
+                        .get(&name)
+                        .ok_or_else(|| eyre::eyre!("variable '{name}' is not set in session"))?;
+                    println!("{value}");
+                }
+                None => {
+                    for (key, value) in vars {
+                        println!("{key}={value}");
+                    }
+                }
+            }
+        }
+        Command::Screenshot { session, output } => {
+            let mut client = get_client(session).await?;
+            let svg = client.screenshot().await?;
+            if output.extension().is_some_and(|ext| ext == "svg") {
+                std::fs::write(&output, svg)
+                    .wrap_err_with(|| format!("failed to write {}", output.display()))?;
+            } else {
+                write_png(&svg, &output)?;
+            }
+        }
+        Command::Screen { session } => {
+            let mut client = get_client(session).await?;
+            let screen = client.get_screen().await?;
+            println!("{}", serde_json::to_string(&screen)?);
+        }
+        Command::Resync { session } => {
+            let mut client = get_client(session).await?;
+            client.resync_screen().await?;
+        }
+        Command::ResetAlt { session } => {
+            let mut client = get_client(session).await?;
+            client.reset_alt_screen().await?;
+        }
+        Command::Config { command } => run_config(command)?,
     }
 
     Ok(())
@@ -0,0 +1,231 @@
+//! Choosing a session for `tap attach` when several are running.
+
+use std::io::Write as _;
+
+use crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::{cursor, execute, queue, style, terminal};
+
+/// How often the last-line previews are refreshed.
+const PREVIEW_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Lines fetched from the end of each session's output for its preview,
+/// enough to reach past blank rows at the bottom of a tall screen.
+const PREVIEW_LINES: usize = 200;
+
+/// How long to wait for one session's preview before leaving it blank.
+const PREVIEW_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(200);
+
+/// A session as listed in the picker.
+struct Entry {
+    session: tap_client::Session,
+    /// Last non-blank line of its screen.
+    preview: String,
+}
+
+/// Let the user pick one of `sessions` (newest first), fuzzy-filtering by
+/// typing. Returns the chosen session's ID, or `None` if cancelled.
+pub async fn pick(sessions: Vec<tap_client::Session>) -> eyre::Result<Option<String>> {
+    let mut entries: Vec<Entry> = sessions
+        .into_iter()
+        .rev()
+        .map(|session| Entry {
+            session,
+            preview: String::new(),
+        })
+        .collect();
+    refresh_previews(&mut entries).await;
+
+    let runtime = tokio::runtime::Handle::current();
+    tokio::task::spawn_blocking(move || {
+        terminal::enable_raw_mode()?;
+        let mut stderr = std::io::stderr();
+        execute!(stderr, terminal::EnterAlternateScreen, cursor::Hide)?;
+        let result = run(&mut entries, &runtime, &mut stderr);
+        let _ = execute!(stderr, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+        result
+    })
+    .await?
+}
+
+/// The picker's event loop.
+fn run(
+    entries: &mut [Entry],
+    runtime: &tokio::runtime::Handle,
+    out: &mut impl std::io::Write,
+) -> eyre::Result<Option<String>> {
+    let mut query = String::new();
+    let mut selected = 0;
+    loop {
+        let visible: Vec<usize> = (0..entries.len())
+            .filter(|&i| fuzzy_match(&query, &search_text(&entries[i].session)))
+            .collect();
+        selected = selected.min(visible.len().saturating_sub(1));
+        draw(out, entries, &visible, selected, &query)?;
+
+        if !crossterm::event::poll(PREVIEW_INTERVAL)? {
+            runtime.block_on(refresh_previews(entries));
+            continue;
+        }
+        let Event::Key(KeyEvent {
+            code,
+            modifiers,
+            kind: KeyEventKind::Press,
+            ..
+        }) = crossterm::event::read()?
+        else {
+            continue;
+        };
+        let ctrl = modifiers.contains(KeyModifiers::CONTROL);
+        match code {
+            KeyCode::Esc => return Ok(None),
+            KeyCode::Char('c' | 'd') if ctrl => return Ok(None),
+            KeyCode::Enter => {
+                return Ok(visible
+                    .get(selected)
+                    .map(|&i| entries[i].session.id.clone()));
+            }
+            KeyCode::Up => selected = selected.saturating_sub(1),
+            KeyCode::Char('p' | 'k') if ctrl => selected = selected.saturating_sub(1),
+            KeyCode::Down => selected += 1,
+            KeyCode::Char('n' | 'j') if ctrl => selected += 1,
+            KeyCode::Backspace => {
+                query.pop();
+            }
+            KeyCode::Char('u') if ctrl => query.clear(),
+            KeyCode::Char(c) if !ctrl => {
+                query.push(c);
+                selected = 0;
+            }
+            _ => {}
+        }
+    }
+}
+
+fn draw(
+    out: &mut impl std::io::Write,
+    entries: &[Entry],
+    visible: &[usize],
+    selected: usize,
+    query: &str,
+) -> std::io::Result<()> {
+    let (cols, rows) = terminal::size()?;
+    let width = usize::from(cols);
+    let now = chrono::Utc::now();
+
+    queue!(
+        out,
+        terminal::Clear(terminal::ClearType::All),
+        cursor::MoveTo(0, 0)
+    )?;
+    write!(out, "attach> {query}")?;
+    let list_rows = usize::from(rows.saturating_sub(2));
+    // Scroll so the selection stays on screen
+    let first = selected.saturating_sub(list_rows.saturating_sub(1));
+    for (row, &index) in visible.iter().skip(first).take(list_rows).enumerate() {
+        let entry = &entries[index];
+        let session = &entry.session;
+        let line = format!(
+            "{:<22} {:<6} {:<4} {:<20} {}",
+            session.id,
+            age(&session.started, now),
+            if session.attached { "att" } else { "" },
+            session.command.join(" "),
+            entry.preview
+        );
+        let line: String = line.chars().take(width).collect();
+        queue!(out, cursor::MoveTo(0, row as u16 + 2))?;
+        if first + row == selected {
+            queue!(
+                out,
+                style::SetAttribute(style::Attribute::Reverse),
+                style::Print(format!("{line:<width$}")),
+                style::SetAttribute(style::Attribute::Reset)
+            )?;
+        } else {
+            queue!(out, style::Print(line))?;
+        }
+    }
+    out.flush()
+}
+
+/// Refetch the last line of each session's screen.
+async fn refresh_previews(entries: &mut [Entry]) {
+    for entry in entries {
+        if let Some(line) = last_line(&entry.session.id).await {
+            entry.preview = line;
+        }
+    }
+}
+
+async fn last_line(id: &str) -> Option<String> {
+    let fetch = async {
+        let mut client = tap_client::Client::connect(id).await.ok()?;
+        client.get_scrollback(Some(PREVIEW_LINES)).await.ok()
+    };
+    let content = tokio::time::timeout(PREVIEW_TIMEOUT, fetch).await.ok()??;
+    content
+        .lines()
+        .rev()
+        .find(|line| !line.trim().is_empty())
+        .map(|line| line.trim().to_string())
+}
+
+/// What the query is matched against.
+fn search_text(session: &tap_client::Session) -> String {
+    format!(
+        "{} {} {}",
+        session.id,
+        session.command.join(" "),
+        session.tags.join(" ")
+    )
+}
+
+/// Whether the characters of `query` appear in order in `text`, ignoring case.
+fn fuzzy_match(query: &str, text: &str) -> bool {
+    let mut text = text.chars().flat_map(char::to_lowercase);
+    query
+        .chars()
+        .flat_map(char::to_lowercase)
+        .all(|wanted| text.any(|c| c == wanted))
+}
+
+/// How long ago an RFC 3339 `started` time was, e.g. `5m` or `3d`.
+fn age(started: &str, now: chrono::DateTime<chrono::Utc>) -> String {
+    let Ok(started) = chrono::DateTime::parse_from_rfc3339(started) else {
+        return "?".to_string();
+    };
+    let secs = (now - started.with_timezone(&chrono::Utc))
+        .num_seconds()
+        .max(0);
+    match secs {
+        0..60 => format!("{secs}s"),
+        60..3600 => format!("{}m", secs / 60),
+        3600..86400 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_match() {
+        assert!(fuzzy_match("", "anything"));
+        assert!(fuzzy_match("npmdev", "brave-otter npm run dev"));
+        assert!(fuzzy_match("HTOP", "calm-fox htop"));
+        assert!(!fuzzy_match("devnpm", "brave-otter npm run dev"));
+    }
+
+    #[test]
+    fn test_age() {
+        let now = chrono::DateTime::parse_from_rfc3339("2024-01-02T00:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        assert_eq!(age("2024-01-01T23:59:30+00:00", now), "30s");
+        assert_eq!(age("2024-01-01T23:00:00+00:00", now), "1h");
+        assert_eq!(age("2023-12-30T00:00:00+00:00", now), "3d");
+        assert_eq!(age("not a time", now), "?");
+    }
+}
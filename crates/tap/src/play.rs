@@ -0,0 +1,251 @@
+//! Replaying recorded sessions (asciicast v2 or ttyrec) to the terminal.
+
+use std::os::fd::BorrowedFd;
+
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+/// How far the arrow keys seek.
+const SEEK_STEP: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Clears the screen and resets terminal modes before redrawing after a seek.
+const RESET: &[u8] = b"\x1bc";
+
+/// A chunk of output and when it was written, in seconds from the start.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub time: f64,
+    pub data: Vec<u8>,
+}
+
+/// Playback timing options.
+#[derive(Debug, Clone, Copy)]
+pub struct Options {
+    /// Playback speed multiplier.
+    pub speed: f64,
+    /// Longest pause between frames, in seconds of playback.
+    pub max_idle: Option<f64>,
+}
+
+/// Read a recording, detecting its format from its contents.
+pub fn load(path: &std::path::Path) -> eyre::Result<Vec<Frame>> {
+    let bytes =
+        std::fs::read(path).map_err(|e| eyre::eyre!("failed to read {}: {e}", path.display()))?;
+    if bytes.first() == Some(&b'{') {
+        parse_cast(&String::from_utf8_lossy(&bytes))
+    } else {
+        parse_ttyrec(&bytes)
+    }
+}
+
+/// Parse output events from an asciicast v2 file.
+fn parse_cast(content: &str) -> eyre::Result<Vec<Frame>> {
+    let mut lines = content.lines();
+    let header: serde_json::Value = serde_json::from_str(lines.next().unwrap_or_default())
+        .map_err(|e| eyre::eyre!("invalid asciicast header: {e}"))?;
+    if header["version"] != 2 {
+        eyre::bail!("unsupported asciicast version {}", header["version"]);
+    }
+
+    let mut frames = Vec::new();
+    for (n, line) in lines.enumerate().filter(|(_, l)| !l.trim().is_empty()) {
+        let (time, kind, data): (f64, String, String) = serde_json::from_str(line)
+            .map_err(|e| eyre::eyre!("invalid asciicast event on line {}: {e}", n + 2))?;
+        if kind == "o" {
+            frames.push(Frame {
+                time,
+                data: data.into_bytes(),
+            });
+        }
+    }
+    Ok(frames)
+}
+
+/// Parse a ttyrec file: records of seconds, microseconds and length (all
+/// little-endian u32) followed by that many bytes of output.
+fn parse_ttyrec(bytes: &[u8]) -> eyre::Result<Vec<Frame>> {
+    let mut frames = Vec::new();
+    let mut start = None;
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let Some((header, body)) = rest.split_first_chunk::<12>() else {
+            eyre::bail!("truncated ttyrec header");
+        };
+        let field = |i: usize| u32::from_le_bytes(header[i * 4..i * 4 + 4].try_into().unwrap());
+        let time = f64::from(field(0)) + f64::from(field(1)) / 1_000_000.0;
+        let len = field(2) as usize;
+        if body.len() < len {
+            eyre::bail!("truncated ttyrec frame");
+        }
+        let start = *start.get_or_insert(time);
+        frames.push(Frame {
+            time: time - start,
+            data: body[..len].to_vec(),
+        });
+        rest = &body[len..];
+    }
+    Ok(frames)
+}
+
+/// When each frame should be shown, from the start of playback.
+fn schedule(frames: &[Frame], options: Options) -> Vec<std::time::Duration> {
+    let mut at = 0.0;
+    let mut previous = 0.0;
+    frames
+        .iter()
+        .map(|frame| {
+            let mut gap = (frame.time - previous).max(0.0) / options.speed;
+            if let Some(max_idle) = options.max_idle {
+                gap = gap.min(max_idle);
+            }
+            previous = frame.time;
+            at += gap;
+            std::time::Duration::from_secs_f64(at)
+        })
+        .collect()
+}
+
+/// Play `frames` to stdout. Space pauses, the arrow keys seek and `q` quits.
+pub async fn play(frames: &[Frame], options: Options) -> eyre::Result<()> {
+    let schedule = schedule(frames, options);
+
+    let stdin_fd = unsafe { BorrowedFd::borrow_raw(nix::libc::STDIN_FILENO) };
+    let orig_termios = crate::setup_terminal(stdin_fd).ok();
+
+    let mut stdin = tokio::io::stdin();
+    let mut stdout = tokio::io::stdout();
+    let mut keys = [0u8; 16];
+    let mut keys_open = orig_termios.is_some();
+
+    let mut next = 0;
+    let mut base = tokio::time::Instant::now();
+    let mut paused_at: Option<std::time::Duration> = None;
+
+    while next < frames.len() {
+        tokio::select! {
+            () = tokio::time::sleep_until(base + schedule[next]), if paused_at.is_none() => {
+                stdout.write_all(&frames[next].data).await?;
+                stdout.flush().await?;
+                next += 1;
+            }
+            result = stdin.read(&mut keys), if keys_open => {
+                let n = result.unwrap_or(0);
+                let position = paused_at.unwrap_or_else(|| base.elapsed());
+                let target = match &keys[..n] {
+                    [] => {
+                        keys_open = false;
+                        continue;
+                    }
+                    b"q" | b"\x03" => break,
+                    b" " => {
+                        match paused_at.take() {
+                            Some(position) => base = tokio::time::Instant::now() - position,
+                            None => paused_at = Some(position),
+                        }
+                        continue;
+                    }
+                    b"\x1b[C" => position + SEEK_STEP,
+                    b"\x1b[D" => position.saturating_sub(SEEK_STEP),
+                    _ => continue,
+                };
+
+                // Redraw from the start up to the new position
+                next = schedule.partition_point(|&at| at <= target);
+                stdout.write_all(RESET).await?;
+                for frame in &frames[..next] {
+                    stdout.write_all(&frame.data).await?;
+                }
+                stdout.flush().await?;
+                match &mut paused_at {
+                    Some(paused_at) => *paused_at = target,
+                    None => base = tokio::time::Instant::now() - target,
+                }
+            }
+        }
+    }
+
+    if let Some(termios) = &orig_termios {
+        crate::restore_terminal(stdin_fd, termios);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cast() {
+        let content = concat!(
+            r#"{"version": 2, "width": 80, "height": 24}"#,
+            "\n",
+            r#"[0.5, "o", "hello"]"#,
+            "\n",
+            r#"[0.7, "i", "x"]"#,
+            "\n",
+            r#"[1.25, "o", "\u001b[1mworld"]"#,
+            "\n",
+        );
+        let frames = parse_cast(content).unwrap();
+        assert_eq!(
+            frames,
+            vec![
+                Frame {
+                    time: 0.5,
+                    data: b"hello".to_vec()
+                },
+                Frame {
+                    time: 1.25,
+                    data: b"\x1b[1mworld".to_vec()
+                },
+            ]
+        );
+        assert!(parse_cast(r#"{"version": 1}"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_ttyrec() {
+        let mut bytes = Vec::new();
+        for (sec, usec, data) in [(100u32, 0u32, &b"ab"[..]), (101, 500_000, b"c")] {
+            bytes.extend_from_slice(&sec.to_le_bytes());
+            bytes.extend_from_slice(&usec.to_le_bytes());
+            bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(data);
+        }
+        let frames = parse_ttyrec(&bytes).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].time, 0.0);
+        assert_eq!(frames[1].time, 1.5);
+        assert_eq!(frames[1].data, b"c");
+
+        assert!(parse_ttyrec(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_schedule() {
+        let frames: Vec<Frame> = [0.0, 1.0, 11.0]
+            .into_iter()
+            .map(|time| Frame { time, data: vec![] })
+            .collect();
+        let secs = |options| -> Vec<f64> {
+            schedule(&frames, options)
+                .iter()
+                .map(std::time::Duration::as_secs_f64)
+                .collect()
+        };
+
+        assert_eq!(
+            secs(Options {
+                speed: 2.0,
+                max_idle: None
+            }),
+            vec![0.0, 0.5, 5.5]
+        );
+        assert_eq!(
+            secs(Options {
+                speed: 1.0,
+                max_idle: Some(2.0)
+            }),
+            vec![0.0, 1.0, 3.0]
+        );
+    }
+}
@@ -0,0 +1,88 @@
+//! Labelling merged output from several sessions.
+
+/// Foreground colors cycled through for session labels (red..cyan).
+const LABEL_COLORS: [u8; 6] = [31, 32, 33, 34, 35, 36];
+
+/// Prefixes each line of a session's output with its ID.
+///
+/// Output arrives in arbitrary chunks, so this remembers whether the last
+/// chunk ended mid-line to avoid labelling continuations.
+pub struct LinePrefixer {
+    label: Vec<u8>,
+    at_line_start: bool,
+}
+
+impl LinePrefixer {
+    /// Create a prefixer for `session_id`, colorizing the label if `color`.
+    pub fn new(session_id: &str, color: bool) -> Self {
+        let label = if color {
+            let code = LABEL_COLORS[label_color_index(session_id)];
+            format!("\x1b[0;{code}m[{session_id}]\x1b[0m ")
+        } else {
+            format!("[{session_id}] ")
+        };
+        Self {
+            label: label.into_bytes(),
+            at_line_start: true,
+        }
+    }
+
+    /// Label the lines in a chunk of output.
+    pub fn prefix(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len() + self.label.len());
+        for &b in data {
+            if self.at_line_start {
+                out.extend_from_slice(&self.label);
+                self.at_line_start = false;
+            }
+            out.push(b);
+            if b == b'\n' {
+                self.at_line_start = true;
+            }
+        }
+        out
+    }
+
+    /// A labelled line of our own, starting a new line if output left off
+    /// mid-line.
+    pub fn line(&mut self, text: &str) -> Vec<u8> {
+        let mut out = Vec::new();
+        if !self.at_line_start {
+            out.push(b'\n');
+            self.at_line_start = true;
+        }
+        out.extend(self.prefix(text.as_bytes()));
+        out.extend(self.prefix(b"\n"));
+        out
+    }
+}
+
+/// Pick a stable color for a session from its ID.
+fn label_color_index(session_id: &str) -> usize {
+    let hash = session_id.bytes().fold(0usize, |hash, b| {
+        hash.wrapping_mul(31).wrapping_add(usize::from(b))
+    });
+    hash % LABEL_COLORS.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefix_lines_across_chunks() {
+        let mut prefixer = LinePrefixer::new("web", false);
+        assert_eq!(prefixer.prefix(b"one\ntw"), b"[web] one\n[web] tw");
+        assert_eq!(prefixer.prefix(b"o\n"), b"o\n");
+        assert_eq!(prefixer.prefix(b"three"), b"[web] three");
+        assert_eq!(prefixer.line("done"), b"\n[web] done\n");
+    }
+
+    #[test]
+    fn test_color_is_stable() {
+        let a = LinePrefixer::new("calm-otter", true);
+        let b = LinePrefixer::new("calm-otter", true);
+        assert_eq!(a.label, b.label);
+        assert!(a.label.starts_with(b"\x1b[0;3"));
+    }
+}
@@ -0,0 +1,55 @@
+//! Typing a file into a session's shell.
+//!
+//! The file is base64-encoded and injected as a series of
+//! `printf … | base64 -d >> path` commands, which work in POSIX shells and
+//! fish alike and need nothing on the other end beyond `base64`.
+
+use base64::Engine as _;
+
+/// Raw bytes per injected command. Encodes to 3200 characters, keeping each
+/// command line well under the 4096-byte canonical-mode line limit.
+const CHUNK_BYTES: usize = 2400;
+
+/// Quote a string for POSIX shells and fish.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Commands that recreate `data` at `remote_path`, each ending in a carriage
+/// return. A leading space keeps them out of shell history where supported.
+pub fn push_commands(data: &[u8], remote_path: &str) -> Vec<String> {
+    let path = shell_quote(remote_path);
+    let mut commands = vec![format!(" printf '' > {path}\r")];
+    for chunk in data.chunks(CHUNK_BYTES) {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(chunk);
+        commands.push(format!(" printf %s '{encoded}' | base64 -d >> {path}\r"));
+    }
+    commands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    fn test_push_commands() {
+        let commands = push_commands(b"hello", "out.txt");
+        assert_eq!(
+            commands,
+            vec![
+                " printf '' > 'out.txt'\r".to_string(),
+                " printf %s 'aGVsbG8=' | base64 -d >> 'out.txt'\r".to_string(),
+            ]
+        );
+
+        let commands = push_commands(&vec![0u8; CHUNK_BYTES + 1], "big");
+        assert_eq!(commands.len(), 3);
+        assert!(commands.iter().all(|c| c.len() < 4096));
+    }
+}
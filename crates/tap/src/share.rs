@@ -0,0 +1,103 @@
+//! Uploading scrollback to a paste service.
+//!
+//! Uploads go through `curl` so the CLI doesn't carry its own HTTP and TLS
+//! stack for this one command.
+
+use eyre::WrapErr as _;
+
+const ZERO_X_ZERO_URL: &str = "https://0x0.st";
+const GIST_API_URL: &str = "https://api.github.com/gists";
+const GIST_FILE_NAME: &str = "tap-output.txt";
+
+/// Upload `content` to the configured service and return its link.
+pub fn upload(config: &tap_config::ShareConfig, content: &str) -> eyre::Result<String> {
+    match config.service {
+        tap_config::ShareService::ZeroXZero => upload_form(ZERO_X_ZERO_URL, content),
+        tap_config::ShareService::Url => {
+            let url = config
+                .url
+                .as_deref()
+                .ok_or_else(|| eyre::eyre!("share.url must be set for the url service"))?;
+            upload_form(url, content)
+        }
+        tap_config::ShareService::Gist => {
+            let token = config
+                .github_token
+                .clone()
+                .or_else(|| std::env::var("GITHUB_TOKEN").ok())
+                .ok_or_else(|| {
+                    eyre::eyre!("share.github_token or $GITHUB_TOKEN must be set for gists")
+                })?;
+            upload_gist(&token, content)
+        }
+    }
+}
+
+/// POST `content` as the multipart field `file`; the response is the link.
+fn upload_form(url: &str, content: &str) -> eyre::Result<String> {
+    let response = curl(&["-F", "file=@-;filename=tap-output.txt", url], content)?;
+    Ok(response.trim().to_string())
+}
+
+fn upload_gist(token: &str, content: &str) -> eyre::Result<String> {
+    let body = serde_json::json!({
+        "description": "tap session output",
+        "public": false,
+        "files": { GIST_FILE_NAME: { "content": content } },
+    });
+    // The token goes in via stdin rather than argv, where other users could
+    // see it, so the body goes via a file
+    let mut body_file = tempfile::NamedTempFile::new().wrap_err("failed to create temp file")?;
+    std::io::Write::write_all(&mut body_file, body.to_string().as_bytes())
+        .wrap_err("failed to write gist body")?;
+    let body_arg = format!("@{}", body_file.path().display());
+    let response = curl(
+        &[
+            "-H",
+            "@-",
+            "-H",
+            "Accept: application/vnd.github+json",
+            "--data-binary",
+            &body_arg,
+            GIST_API_URL,
+        ],
+        &format!("Authorization: Bearer {token}\n"),
+    )?;
+
+    let response: serde_json::Value =
+        serde_json::from_str(&response).wrap_err("unexpected response from GitHub")?;
+    response["html_url"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| eyre::eyre!("GitHub did not return a gist URL: {response}"))
+}
+
+/// Run curl with `stdin` piped to it, returning the response.
+fn curl(args: &[&str], stdin: &str) -> eyre::Result<String> {
+    use std::io::Write as _;
+
+    let mut child = std::process::Command::new("curl")
+        .args(["--silent", "--show-error", "--fail-with-body"])
+        .args(args)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .wrap_err("failed to run curl — is it installed?")?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(stdin.as_bytes())
+        .wrap_err("failed to send output to curl")?;
+
+    let output = child.wait_with_output().wrap_err("failed to run curl")?;
+    if !output.status.success() {
+        eyre::bail!(
+            "upload failed: {}{}",
+            String::from_utf8_lossy(&output.stderr).trim(),
+            String::from_utf8_lossy(&output.stdout).trim()
+        );
+    }
+    String::from_utf8(output.stdout).wrap_err("upload response is not UTF-8")
+}
@@ -0,0 +1,608 @@
+//! End-to-end tests driving real detached sessions over their sockets.
+//!
+//! Each test starts `tap start --detached` with the built binary and talks to
+//! it with `tap-client`. All sessions share one temporary state directory
+//! (`TAP_STATE_DIR`) and home, so the user's own sessions and config are
+//! never touched.
+
+use std::io::Write as _;
+use std::os::unix::process::CommandExt as _;
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The state directory shared by every test, with this process resolving
+/// socket and sessions paths inside it.
+fn state_dir() -> &'static Path {
+    static DIR: OnceLock<tempfile::TempDir> = OnceLock::new();
+    DIR.get_or_init(|| {
+        let dir = tempfile::tempdir().expect("failed to create state dir");
+        tap_protocol::set_resolver(tap_protocol::DirResolver(dir.path().to_path_buf()));
+        dir
+    })
+    .path()
+}
+
+/// A `tap` command isolated in the test state directory.
+fn tap() -> std::process::Command {
+    let dir = state_dir();
+    let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_tap"));
+    command
+        .env(tap_protocol::STATE_DIR_ENV, dir)
+        .env("HOME", dir)
+        .env("XDG_CONFIG_HOME", dir.join("config"))
+        .env("SHELL", "/bin/sh")
+        .env_remove("TAP_SESSION")
+        .env_remove("TAP_SESSION_ID")
+        .env_remove("TAP_SOCKET");
+    command
+}
+
+/// A detached session, killed when dropped.
+struct Session {
+    id: String,
+}
+
+impl Session {
+    /// Start `sh` in a detached session named after the test.
+    async fn start(name: &str) -> Self {
+        Self::start_with(name, &[]).await
+    }
+
+    /// Start `sh` in a detached session with extra `tap start` arguments.
+    async fn start_with(name: &str, args: &[&str]) -> Self {
+        let id = format!("{name}-{}", std::process::id());
+        let output = tokio::process::Command::from(tap())
+            .args(["start", "--detached", "--quiet", "--name", &id])
+            .args(args)
+            .args(["--", "sh"])
+            .output()
+            .await
+            .expect("failed to run tap start");
+        assert!(
+            output.status.success(),
+            "tap start failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert_eq!(String::from_utf8_lossy(&output.stdout).trim(), id);
+        Self { id }
+    }
+
+    async fn client(&self) -> tap_client::Client {
+        tap_client::Client::connect(&self.id)
+            .await
+            .expect("failed to connect")
+    }
+
+    /// Wait until the scrollback contains `text`.
+    async fn wait_for_text(&self, text: &str) {
+        let mut client = self.client().await;
+        let found = poll(async || {
+            client
+                .get_scrollback(None)
+                .await
+                .is_ok_and(|content| content.contains(text))
+        })
+        .await;
+        assert!(found, "{text:?} never appeared in {}", self.id);
+    }
+
+    /// Whether sessions.json shows a client attached.
+    fn attached(&self) -> bool {
+        tap_client::list_sessions()
+            .unwrap_or_default()
+            .iter()
+            .any(|s| s.id == self.id && s.attached)
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let _ = tap()
+            .args(["kill", &self.id])
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status();
+    }
+}
+
+/// Check `condition` until it holds, returning false after [`TIMEOUT`].
+async fn poll(mut condition: impl AsyncFnMut() -> bool) -> bool {
+    let deadline = tokio::time::Instant::now() + TIMEOUT;
+    while tokio::time::Instant::now() < deadline {
+        if condition().await {
+            return true;
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+    false
+}
+
+#[tokio::test]
+async fn test_inject_echo_round_trip() {
+    let session = Session::start("inject").await;
+    let mut client = session.client().await;
+
+    // Only the shell's output has the sum; the echoed input doesn't
+    client.inject("echo tap-$((6 * 7))\r").await.unwrap();
+    session.wait_for_text("tap-42").await;
+
+    client
+        .send_keys(&["echo keys-$((1 + 1))".to_string(), "Enter".to_string()])
+        .await
+        .unwrap();
+    session.wait_for_text("keys-2").await;
+
+    client.paste("echo pasted-$((2 + 2))\n").await.unwrap();
+    session.wait_for_text("pasted-4").await;
+}
+
+/// A request bigger than one read still arrives whole.
+#[tokio::test]
+async fn test_large_paste() {
+    let session = Session::start("large-paste").await;
+    let mut client = session.client().await;
+
+    // Many short lines, as the tty caps how long one line can be
+    let mut text = format!(": {}\n", "x".repeat(100)).repeat(64);
+    text.push_str("echo large-$((8 * 8))\n");
+    assert!(text.len() > 4096);
+    client.paste(&text).await.unwrap();
+    session.wait_for_text("large-64").await;
+}
+
+#[tokio::test]
+async fn test_hello() {
+    let session = Session::start("hello").await;
+    let version = session.client().await.hello().await.unwrap();
+    assert_eq!(version, tap_protocol::PROTOCOL_VERSION);
+}
+
+/// Output streamed to a subscribed connection isn't lost to a query made
+/// on it meanwhile.
+#[tokio::test]
+async fn test_query_while_subscribed() {
+    let session = Session::start("multiplex").await;
+    let mut client = session.client().await;
+    client.subscribe().await.unwrap();
+
+    client.inject("echo multi-$((5 * 5))\r").await.unwrap();
+    session.wait_for_text("multi-25").await;
+    let scrollback = client.get_scrollback(None).await.unwrap();
+    assert!(scrollback.contains("multi-25"));
+
+    let streamed = tokio::time::timeout(TIMEOUT, async {
+        let mut output = Vec::new();
+        while let Ok(Some(data)) = client.read_output().await {
+            output.extend(data);
+            if String::from_utf8_lossy(&output).contains("multi-25") {
+                return true;
+            }
+        }
+        false
+    })
+    .await;
+    assert_eq!(streamed, Ok(true), "output was lost to the query");
+}
+
+#[tokio::test]
+async fn test_session_env() {
+    let session = Session::start_with("env", &["-e", "GREETING=hello there"]).await;
+    let mut client = session.client().await;
+
+    client
+        .inject("echo \"[$GREETING|$TAP_SESSION_ID|$TAP_SOCKET]\"\r")
+        .await
+        .unwrap();
+    let socket = tap_protocol::socket_path(&session.id);
+    session
+        .wait_for_text(&format!(
+            "[hello there|{}|{}]",
+            session.id,
+            socket.display()
+        ))
+        .await;
+}
+
+/// A session served over TCP only talks to clients with its token.
+#[tokio::test]
+async fn test_tcp_listen() {
+    use tokio::io::AsyncReadExt as _;
+
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .expect("failed to find a free port")
+        .port();
+    let addr = format!("127.0.0.1:{port}");
+    let session = Session::start_with("tcp", &["--listen", &addr, "--auth-token", "s3cret"]).await;
+
+    let mut client = tap_client::Client::connect(&format!("tcp://s3cret@{addr}"))
+        .await
+        .unwrap();
+    client.inject("echo remote-$((4 * 4))\r").await.unwrap();
+    session.wait_for_text("remote-16").await;
+
+    let denied = tap_client::Client::connect(&format!("tcp://guess@{addr}")).await;
+    assert!(matches!(denied, Err(tap_client::Error::Denied(_))));
+
+    // A connection that never says hello is closed
+    let mut silent = tokio::net::TcpStream::connect(&addr).await.unwrap();
+    let mut buf = [0u8; 64];
+    let read = tokio::time::timeout(TIMEOUT, silent.read(&mut buf)).await;
+    assert!(matches!(read, Ok(Ok(0))), "{read:?}");
+}
+
+/// A session upgraded in place keeps running under the new server.
+#[tokio::test]
+async fn test_self_upgrade_handoff() {
+    let session = Session::start("upgrade").await;
+    session
+        .client()
+        .await
+        .inject("echo before-$((2 + 2))\r")
+        .await
+        .unwrap();
+    session.wait_for_text("before-4").await;
+
+    let output = tokio::process::Command::from(tap())
+        .args(["self-upgrade-handoff", &session.id])
+        .output()
+        .await
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "tap self-upgrade-handoff failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let answered = poll(async || {
+        tap()
+            .args(["ping", &session.id])
+            .status()
+            .is_ok_and(|status| status.success())
+    });
+    assert!(answered.await, "{} stopped answering", session.id);
+    let mut client = session.client().await;
+    client.inject("echo after-$((3 + 3))\r").await.unwrap();
+    session.wait_for_text("after-6").await;
+    // The scrollback came along
+    session.wait_for_text("before-4").await;
+}
+
+/// `tap proxy`, which SSH runs for clients on other machines, relays
+/// requests and replies until its stdin closes.
+#[tokio::test]
+async fn test_proxy() {
+    use tokio::io::{AsyncBufReadExt as _, AsyncWriteExt as _};
+
+    let session = Session::start("proxy").await;
+    let mut proxy = tokio::process::Command::from(tap())
+        .args(["proxy", &session.id])
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .unwrap();
+    let mut stdin = proxy.stdin.take().unwrap();
+    let mut stdout = tokio::io::BufReader::new(proxy.stdout.take().unwrap());
+
+    let ping = tap_protocol::Envelope {
+        request_id: Some(7),
+        message: tap_protocol::Request::Ping,
+    };
+    let mut line = serde_json::to_vec(&ping).unwrap();
+    line.push(b'\n');
+    stdin.write_all(&line).await.unwrap();
+    let mut reply = String::new();
+    tokio::time::timeout(TIMEOUT, stdout.read_line(&mut reply))
+        .await
+        .expect("no reply through the proxy")
+        .unwrap();
+    let reply: tap_protocol::Envelope<tap_protocol::Response> =
+        serde_json::from_str(&reply).unwrap();
+    assert_eq!(reply.request_id, Some(7));
+    assert!(matches!(reply.message, tap_protocol::Response::Pong));
+
+    drop(stdin);
+    let status = tokio::time::timeout(TIMEOUT, proxy.wait())
+        .await
+        .expect("proxy outlived its stdin")
+        .unwrap();
+    assert!(status.success());
+}
+
+/// A session started from inside another records it as its parent.
+#[tokio::test]
+async fn test_nested_session() {
+    let exe = format!("TAP={}", env!("CARGO_BIN_EXE_tap"));
+    let outer = Session::start_with("outer", &["-e", &exe]).await;
+    let inner = Session {
+        id: format!("inner-{}", std::process::id()),
+    };
+
+    outer
+        .client()
+        .await
+        .inject(&format!("\"$TAP\" start -d -q --name {} -- sh\r", inner.id))
+        .await
+        .unwrap();
+    let nested = poll(async || {
+        tap_client::list_sessions()
+            .unwrap_or_default()
+            .iter()
+            .any(|s| s.id == inner.id && s.parent.as_ref() == Some(&outer.id))
+    });
+    assert!(
+        nested.await,
+        "{} wasn't recorded inside {}",
+        inner.id,
+        outer.id
+    );
+    outer.wait_for_text("(--allow-nested to silence)").await;
+}
+
+#[tokio::test]
+async fn test_resize() {
+    let session = Session::start("resize").await;
+    let mut client = session.client().await;
+
+    assert_eq!(client.set_size(30, 100).await.unwrap(), (30, 100));
+    assert_eq!(client.get_size().await.unwrap(), (30, 100));
+    client.inject("stty size\r").await.unwrap();
+    session.wait_for_text("30 100").await;
+
+    // An attached terminal's size wins
+    let mut attached = session.client().await;
+    attached.attach(20, 70, false, false, false).await.unwrap();
+    let resized = poll(async || client.get_size().await.is_ok_and(|size| size == (20, 70)));
+    assert!(resized.await, "attaching didn't resize the session");
+    // And caps resizes from elsewhere, so it can still show the whole screen
+    assert_eq!(client.set_size(40, 60).await.unwrap(), (20, 60));
+}
+
+#[tokio::test]
+async fn test_attach_and_detach() {
+    let session = Session::start("attach").await;
+
+    let mut attached = session.client().await;
+    attached.attach(24, 80, false, false, false).await.unwrap();
+    assert!(poll(async || session.attached()).await);
+
+    // Input from the attached client reaches the shell, even sent right
+    // behind a heartbeat
+    attached.heartbeat().await.unwrap();
+    attached
+        .send_input(b"echo from-$((3 * 3))\r".to_vec())
+        .await
+        .unwrap();
+    session.wait_for_text("from-9").await;
+
+    session.client().await.detach_clients().await.unwrap();
+    let closed = tokio::time::timeout(TIMEOUT, async {
+        while let Ok(Some(_)) = attached.read_output().await {}
+    })
+    .await;
+    assert!(closed.is_ok(), "attached client wasn't disconnected");
+    assert!(poll(async || !session.attached()).await);
+
+    // The session outlives its clients
+    session.client().await.ping().await.unwrap();
+    let mut again = session.client().await;
+    again.attach(24, 80, false, false, false).await.unwrap();
+}
+
+/// A subscribed connection can go on to attach, and then drives the session.
+#[tokio::test]
+async fn test_subscribe_then_attach() {
+    let session = Session::start("subscribe-attach").await;
+    let mut client = session.client().await;
+    client.subscribe().await.unwrap();
+    client.attach(24, 80, false, false, false).await.unwrap();
+    assert!(poll(async || session.attached()).await);
+
+    client
+        .send_input(b"echo via-$((4 * 4))\r".to_vec())
+        .await
+        .unwrap();
+    let streamed = tokio::time::timeout(TIMEOUT, async {
+        let mut output = Vec::new();
+        while let Ok(Some(data)) = client.read_output().await {
+            output.extend(data);
+            if String::from_utf8_lossy(&output).contains("via-16") {
+                return true;
+            }
+        }
+        false
+    })
+    .await;
+    assert_eq!(streamed, Ok(true), "attached output didn't arrive");
+}
+
+#[tokio::test]
+async fn test_detach_others() {
+    let session = Session::start("steal").await;
+
+    let mut first = session.client().await;
+    first.attach(24, 80, false, false, false).await.unwrap();
+    let mut second = session.client().await;
+    second.attach(30, 100, false, false, true).await.unwrap();
+
+    let detached = tokio::time::timeout(TIMEOUT, async {
+        loop {
+            match first.read_event().await {
+                Ok(Some(tap_client::OutputEvent::Detached { .. })) => return true,
+                Ok(Some(_)) => {}
+                _ => return false,
+            }
+        }
+    })
+    .await;
+    assert_eq!(
+        detached,
+        Ok(true),
+        "first client wasn't told it was detached"
+    );
+
+    // Only the new client sizes the PTY now
+    let mut client = session.client().await;
+    let resized = poll(async || client.get_size().await.is_ok_and(|size| size == (30, 100)));
+    assert!(resized.await, "the detached client still sizes the session");
+}
+
+/// Run `tap attach` on `session` in a terminal of its own, whose output is
+/// drained. Returns the process and the terminal's keyboard.
+fn attach_in_terminal(session: &Session) -> (std::process::Child, std::fs::File) {
+    let winsize = nix::pty::Winsize {
+        ws_row: 24,
+        ws_col: 80,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    let pty = nix::pty::openpty(Some(&winsize), None).expect("openpty failed");
+    let mut attach = tap();
+    attach
+        .args(["attach", &session.id])
+        .stdin(pty.slave.try_clone().unwrap())
+        .stdout(pty.slave.try_clone().unwrap())
+        .stderr(pty.slave.try_clone().unwrap());
+    // Make the PTY the controlling terminal, as a terminal emulator would
+    unsafe {
+        attach.pre_exec(|| {
+            nix::unistd::setsid().map_err(std::io::Error::from)?;
+            if nix::libc::ioctl(0, nix::libc::TIOCSCTTY as _, 0) < 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let child = attach.spawn().expect("failed to run tap attach");
+    drop(pty.slave);
+
+    // Drain the terminal so tap never blocks writing to it
+    let mut master = std::fs::File::from(pty.master);
+    let keyboard = master.try_clone().unwrap();
+    std::thread::spawn(move || std::io::copy(&mut master, &mut std::io::sink()));
+    (child, keyboard)
+}
+
+/// `tap attach` in a terminal of its own, detaching with the default
+/// keybind (`Ctrl-\`).
+#[tokio::test]
+async fn test_detach_keybind() {
+    let session = Session::start("keybind").await;
+    let (mut child, mut keyboard) = attach_in_terminal(&session);
+
+    assert!(
+        poll(async || session.attached()).await,
+        "tap attach didn't attach"
+    );
+    keyboard.write_all(b"\x1c").unwrap();
+
+    let exited = poll(async || child.try_wait().unwrap().is_some()).await;
+    if !exited {
+        let _ = child.kill();
+    }
+    assert!(exited, "tap attach didn't exit on the detach keybind");
+    assert!(child.wait().unwrap().success());
+    assert!(poll(async || !session.attached()).await);
+    session.client().await.ping().await.unwrap();
+}
+
+/// An attached terminal keeps its heartbeat going under a flood of output,
+/// so the server doesn't take it for a client that vanished.
+#[tokio::test]
+async fn test_attached_under_output_flood() {
+    let session = Session::start("flood").await;
+    let (mut child, mut keyboard) = attach_in_terminal(&session);
+    assert!(
+        poll(async || session.attached()).await,
+        "tap attach didn't attach"
+    );
+
+    session.client().await.inject("yes\r").await.unwrap();
+    // Longer than the server waits for a heartbeat
+    tokio::time::sleep(tap_protocol::HEARTBEAT_INTERVAL * 4).await;
+    assert!(
+        child.try_wait().unwrap().is_none(),
+        "tap attach was dropped"
+    );
+    assert!(session.attached());
+
+    keyboard.write_all(b"\x1c").unwrap();
+    let exited = poll(async || child.try_wait().unwrap().is_some()).await;
+    if !exited {
+        let _ = child.kill();
+    }
+    assert!(exited, "tap attach didn't exit on the detach keybind");
+    session.client().await.inject("\x03").await.unwrap();
+}
+
+/// Sessions started and ended together each leave a readable entry with
+/// their own exit code, and no socket.
+#[tokio::test]
+async fn test_concurrent_cleanup() {
+    const SESSIONS: i32 = 8;
+
+    // Start and end them all at once, so their sessions.json writes race
+    let mut tasks = tokio::task::JoinSet::new();
+    for code in 0..SESSIONS {
+        tasks.spawn(async move {
+            let session = Session::start(&format!("cleanup-{code}")).await;
+            let mut client = session.client().await;
+            client.inject(&format!("exit {code}\r")).await.unwrap();
+            (session, code)
+        });
+    }
+
+    while let Some(result) = tasks.join_next().await {
+        let (session, code) = result.unwrap();
+        let ended = poll(async || {
+            tap_client::ended_exit_code(&session.id).is_ok_and(|code| code.is_some())
+        });
+        assert!(ended.await, "{} didn't record its exit", session.id);
+        assert_eq!(
+            tap_client::ended_exit_code(&session.id).unwrap(),
+            Some(code)
+        );
+        let socket = tap_protocol::socket_path(&session.id);
+        assert!(
+            poll(async || !socket.exists()).await,
+            "{} left its socket behind",
+            session.id
+        );
+        assert!(
+            !tap_client::list_sessions()
+                .unwrap()
+                .iter()
+                .any(|s| s.id == session.id)
+        );
+    }
+}
+
+/// Killing a session cleans it up once its child exits, even while a
+/// process it started keeps the PTY open.
+#[tokio::test]
+async fn test_kill_cleans_up_with_pty_held_open() {
+    let session = Session::start("kill-held").await;
+    let mut client = session.client().await;
+    // Split so the echoed command line doesn't match
+    client
+        .inject("(trap '' HUP; exec sleep 20) & echo hel''d\r")
+        .await
+        .unwrap();
+    session.wait_for_text("held").await;
+
+    client.kill(None).await.unwrap();
+    let socket = tap_protocol::socket_path(&session.id);
+    assert!(
+        poll(async || !socket.exists()).await,
+        "{} left its socket behind",
+        session.id
+    );
+    let ended =
+        poll(async || tap_client::ended_exit_code(&session.id).is_ok_and(|code| code.is_some()));
+    assert!(ended.await, "{} didn't record its exit", session.id);
+}
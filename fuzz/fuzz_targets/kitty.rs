@@ -0,0 +1,22 @@
+//! Keyboard input: CSI u translation and keybind matching.
+
+#![no_main]
+
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    let _ = tap_server::kitty::translate_all_csi_u(data);
+
+    for keybind in [
+        tap_config::Keybind::Alt('e'),
+        tap_config::Keybind::Ctrl('\\'),
+    ] {
+        if let Some(consumed) = keybind.matches(data) {
+            assert!(consumed <= data.len());
+        }
+    }
+
+    let mut input = tap_server::input::InputProcessor::new(&tap_config::Config::default()).unwrap();
+    for chunk in data.chunks(7) {
+        let _ = input.process(chunk);
+    }
+    let _ = input.timeout_escape();
+});
@@ -0,0 +1,12 @@
+//! Requests as read from a session socket, which any local process can write to.
+
+#![no_main]
+
+type Envelope = tap_protocol::Envelope<tap_protocol::Request>;
+
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    if let Ok(envelope) = serde_json::from_slice::<Envelope>(data) {
+        let encoded = serde_json::to_vec(&envelope).unwrap();
+        serde_json::from_slice::<Envelope>(&encoded).unwrap();
+    }
+});
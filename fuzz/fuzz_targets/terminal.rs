@@ -0,0 +1,23 @@
+//! PTY output fed to the scrollback emulator, with a resize from the first
+//! bytes since clients choose the size.
+
+#![no_main]
+
+libfuzzer_sys::fuzz_target!(|data: &[u8]| {
+    let Some((&[rows, cols], output)) = data.split_first_chunk::<2>() else {
+        return;
+    };
+
+    let mut buf = tap_server::scrollback::ScrollbackBuffer::new();
+    buf.resize(u16::from(rows), u16::from(cols));
+    for chunk in output.chunks(13) {
+        buf.push(chunk);
+    }
+
+    let _ = buf.get_lines(Some(10));
+    let _ = buf.screen_formatted();
+    let _ = buf.styled_rows();
+    let (row, col) = buf.cursor_position();
+    let _ = buf.char_col_at(row as u16, col as u16);
+    let _ = buf.cursor_style();
+});